@@ -0,0 +1,135 @@
+import sys
+import time
+import struct
+import asyncio
+import hashlib
+import argparse
+
+try:
+    from bleak import BleakClient, BleakScanner
+    from bleak.exc import BleakError
+except ImportError:
+    print("Error: 'bleak' library not found.")
+    print("Please install it using: pip install bleak")
+    sys.exit(1)
+
+# DFU service of the firmware (see src/ota/dfu.rs)
+SERVICE_UUID = "5e1b0001-6a3c-4f4e-9d2a-8b5c7e0f1a20"
+CONTROL_UUID = "5e1b0002-6a3c-4f4e-9d2a-8b5c7e0f1a20"
+DATA_UUID = "5e1b0003-6a3c-4f4e-9d2a-8b5c7e0f1a20"
+STATUS_UUID = "5e1b0004-6a3c-4f4e-9d2a-8b5c7e0f1a20"
+
+OP_BEGIN, OP_QUERY, OP_COMMIT, OP_ABORT = 0x01, 0x02, 0x03, 0x04
+STATUS_READY, STATUS_INSTALLED, STATUS_MALFORMED = 0x00, 0x01, 0xFF
+ERRORS = {
+    0x81: "ota_disabled", 0x82: "ota_busy", 0x83: "no_transfer", 0x84: "too_large",
+    0x85: "size_mismatch", 0x86: "not_an_image", 0x87: "no_ota_slot", 0x88: "flash",
+    0x89: "digest_mismatch", 0x8A: "bad_signature", 0xFF: "malformed",
+}
+
+STATUS_TIMEOUT = 10.0
+MAX_ATTEMPTS = 10
+
+
+class DfuError(Exception):
+    pass
+
+
+class Status:
+    def __init__(self, data):
+        self.code, self.offset, self.max_data, self.window = struct.unpack("<BIHH", bytes(data))
+
+
+async def wait_status(queue):
+    status = Status(await asyncio.wait_for(queue.get(), STATUS_TIMEOUT))
+    if status.code not in (STATUS_READY, STATUS_INSTALLED):
+        raise DfuError(ERRORS.get(status.code, f"error 0x{status.code:02x}"))
+    return status
+
+
+async def send_image(client, image, begin):
+    queue = asyncio.Queue()
+    await client.start_notify(STATUS_UUID, lambda _, data: queue.put_nowait(data))
+
+    # Begins, or resumes if the device already has part of this image
+    await client.write_gatt_char(CONTROL_UUID, begin, response=True)
+    status = await wait_status(queue)
+    if status.max_data == 0:
+        raise DfuError(f"ATT MTU {client.mtu_size} is too small")
+    print(f"📦 Starting at byte {status.offset} ({status.max_data} bytes per packet)")
+
+    offset = status.offset
+    started = time.monotonic()
+    while offset < len(image):
+        for _ in range(status.window):
+            if offset >= len(image):
+                break
+            packet = struct.pack("<I", offset) + image[offset:offset + status.max_data]
+            await client.write_gatt_char(DATA_UUID, packet, response=False)
+            offset += status.max_data
+        try:
+            status = await wait_status(queue)
+        except asyncio.TimeoutError:
+            # Lost a notification; ask where the device is
+            await client.write_gatt_char(CONTROL_UUID, bytes([OP_QUERY]), response=True)
+            status = await wait_status(queue)
+        # The device reports the offset it expects next, which may go back
+        offset = status.offset
+        rate = offset / max(time.monotonic() - started, 0.001) / 1024
+        print(f"\r⬆️  {offset}/{len(image)} bytes ({rate:.1f} KiB/s)", end="", flush=True)
+    print()
+
+    print("🔍 Verifying...")
+    await client.write_gatt_char(CONTROL_UUID, bytes([OP_COMMIT]), response=True)
+    while (await wait_status(queue)).code != STATUS_INSTALLED:
+        pass
+
+
+async def find_device(name):
+    print(f"Scanning for '{name}'...")
+    device = await BleakScanner.find_device_by_filter(
+        lambda d, adv: adv.local_name == name and SERVICE_UUID in adv.service_uuids, timeout=20.0
+    )
+    if device is None:
+        raise DfuError(f"device '{name}' not found")
+    return device
+
+
+async def run(args):
+    image = open(args.image, "rb").read()
+    signature = bytes.fromhex(args.signature)
+    if len(signature) != 64:
+        raise DfuError("the signature must be 64 bytes (128 hex digits)")
+    begin = bytes([OP_BEGIN]) + struct.pack("<I", len(image)) + hashlib.sha256(image).digest() + signature
+
+    device = await find_device(args.device)
+    for attempt in range(1, MAX_ATTEMPTS + 1):
+        try:
+            async with BleakClient(device) as client:
+                print(f"✅ Connected to {device.address} (MTU {client.mtu_size})")
+                await send_image(client, image, begin)
+                print("✅ Installed; the device restarts into the new image")
+                return
+        except (asyncio.TimeoutError, BleakError, EOFError, OSError) as e:
+            # The device keeps the partial image, so the next attempt resumes
+            print(f"\n❌ Connection lost ({str(e) or type(e).__name__}), retrying ({attempt}/{MAX_ATTEMPTS})...")
+            await asyncio.sleep(2)
+    raise DfuError("giving up")
+
+
+def main():
+    parser = argparse.ArgumentParser(description='Firmware update over BLE')
+    parser.add_argument('--device', required=True, help='Device id, as advertised (device_id in config.json)')
+    parser.add_argument('--image', required=True, help='Application image (espflash save-image)')
+    parser.add_argument('--signature', required=True, help='Ed25519 signature of the image SHA-256, in hex')
+    args = parser.parse_args()
+
+    try:
+        asyncio.run(run(args))
+    except DfuError as e:
+        print(f"\n❌ Update failed: {e}")
+        sys.exit(1)
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,75 @@
+import sys
+import json
+import asyncio
+import argparse
+
+try:
+    from bleak import BleakClient, BleakScanner
+except ImportError:
+    print("Error: 'bleak' library not found.")
+    print("Please install it using: pip install bleak")
+    sys.exit(1)
+
+# Provisioning service of the firmware in safe mode (see src/provision.rs)
+SERVICE_UUID = "5e1b0101-6a3c-4f4e-9d2a-8b5c7e0f1a20"
+CONFIG_UUID = "5e1b0102-6a3c-4f4e-9d2a-8b5c7e0f1a20"
+RESULT_UUID = "5e1b0103-6a3c-4f4e-9d2a-8b5c7e0f1a20"
+
+CONFIG_LEN = 244
+RESULT_TIMEOUT = 10.0
+
+
+class ProvisionError(Exception):
+    pass
+
+
+def split_update(update):
+    """One write per field, so long settings never share a write."""
+    for key, value in update.items():
+        data = json.dumps({key: value}, separators=(",", ":")).encode()
+        if len(data) > CONFIG_LEN:
+            raise ProvisionError(f"'{key}' does not fit one write ({len(data)} > {CONFIG_LEN} bytes)")
+        yield key, data
+
+
+async def run(args):
+    update = json.loads(args.config)
+    if not isinstance(update, dict) or not update:
+        raise ProvisionError("the config must be a non-empty JSON object")
+    writes = list(split_update(update))
+
+    print(f"Scanning for '{args.device}'...")
+    device = await BleakScanner.find_device_by_filter(
+        lambda d, adv: adv.local_name == args.device and SERVICE_UUID in adv.service_uuids, timeout=20.0
+    )
+    if device is None:
+        raise ProvisionError(f"device '{args.device}' not found (is it in safe mode, with BOOT pressed?)")
+
+    async with BleakClient(device) as client:
+        print(f"✅ Connected to {device.address}")
+        queue = asyncio.Queue()
+        await client.start_notify(RESULT_UUID, lambda _, data: queue.put_nowait(data))
+        for key, data in writes:
+            await client.write_gatt_char(CONFIG_UUID, data, response=True)
+            result = bytes(await asyncio.wait_for(queue.get(), RESULT_TIMEOUT)).rstrip(b"\0").decode()
+            if result != "ok":
+                raise ProvisionError(f"'{key}' rejected: {result}")
+            print(f"✅ {key} saved")
+    print("✅ Done; the device restarts with the new settings")
+
+
+def main():
+    parser = argparse.ArgumentParser(description='Fix the configuration of a device in safe mode over BLE')
+    parser.add_argument('--device', required=True, help='Device id, as advertised (device_id in config.json)')
+    parser.add_argument('config', help='set_config object, e.g. \'{"mqtt_host":"192.168.1.10"}\'')
+    args = parser.parse_args()
+
+    try:
+        asyncio.run(run(args))
+    except (ProvisionError, asyncio.TimeoutError, json.JSONDecodeError) as e:
+        print(f"\n❌ Provisioning failed: {str(e) or type(e).__name__}")
+        sys.exit(1)
+
+
+if __name__ == "__main__":
+    main()
@@ -18,6 +18,70 @@ fn main() {
         let mqtt_host = config.get("mqtt_host").and_then(|v| v.as_str()).unwrap_or("127.0.0.1");
         let mqtt_port = config.get("mqtt_port").and_then(|v| v.as_u64()).unwrap_or(1883);
         let device_id = config.get("device_id").and_then(|v| v.as_str()).unwrap_or("esp32");
+        let ipv6 = config.get("ipv6").and_then(|v| v.as_bool()).unwrap_or(false);
+        let dhcpv6 = config.get("dhcpv6").and_then(|v| v.as_bool()).unwrap_or(false);
+        let site = config.get("site").and_then(|v| v.as_str()).unwrap_or("default");
+        let topic_template = config.get("topic_template").and_then(|v| v.as_str()).unwrap_or("sensors/{channel}");
+        let publish_interval_secs = config.get("publish_interval_secs").and_then(|v| v.as_u64()).unwrap_or(2);
+        let sample_interval_ms = config.get("sample_interval_ms").and_then(|v| v.as_u64()).unwrap_or(500);
+        let deadband = config.get("deadband").and_then(|v| v.as_f64()).unwrap_or(0.0);
+        let heartbeat_secs = config.get("heartbeat_secs").and_then(|v| v.as_u64()).unwrap_or(300);
+        let health_interval_secs = config.get("health_interval_secs").and_then(|v| v.as_u64()).unwrap_or(60);
+        let payload_format = config.get("payload_format").and_then(|v| v.as_str()).unwrap_or("value");
+        let ha_discovery = config.get("ha_discovery").and_then(|v| v.as_bool()).unwrap_or(true);
+        let ota_confirm_secs = config.get("ota_confirm_secs").and_then(|v| v.as_u64()).unwrap_or(300);
+        let ntp_server = config.get("ntp_server").and_then(|v| v.as_str()).unwrap_or("pool.ntp.org");
+        let buffer_capacity = config.get("buffer_capacity").and_then(|v| v.as_u64()).unwrap_or(2000).clamp(1, u16::MAX as u64);
+        let buffer_max_age_secs = config.get("buffer_max_age_secs").and_then(|v| v.as_u64()).unwrap_or(86400).min(u32::MAX as u64);
+        let sleep_interval_secs = config.get("sleep_interval_secs").and_then(|v| v.as_u64()).unwrap_or(0);
+        let wifi_power_save = config.get("wifi_power_save").and_then(|v| v.as_str()).unwrap_or("none");
+        let wifi_tx_power_dbm = config.get("wifi_tx_power_dbm").and_then(|v| v.as_u64()).unwrap_or(20).clamp(2, 20);
+        let wifi_country = config.get("wifi_country").and_then(|v| v.as_str()).unwrap_or("CN");
+        let wifi_listen_interval = config.get("wifi_listen_interval").and_then(|v| v.as_u64()).unwrap_or(3).clamp(1, 100);
+        // Supply voltage divider on an ADC1 pin; no divider ratio disables it
+        let battery = config.get("battery");
+        let battery_u64 = |key: &str, default: u64| battery.and_then(|b| b.get(key)).and_then(|v| v.as_u64()).unwrap_or(default);
+        let battery_pin = battery_u64("pin", 3).min(255);
+        let battery_divider = battery.and_then(|b| b.get("divider")).and_then(|v| v.as_f64()).unwrap_or(0.0) as f32;
+        let battery_low_mv = battery_u64("low_mv", 3400).min(u16::MAX as u64);
+        let battery_cutoff_mv = battery_u64("cutoff_mv", 3200).min(u16::MAX as u64);
+        let battery_low_sleep_secs = battery_u64("low_sleep_secs", 3600);
+        let led_board = config.get("led_board").and_then(|v| v.as_str()).unwrap_or("gpio");
+        let led_brightness = config.get("led_brightness").and_then(|v| v.as_u64()).unwrap_or(20).clamp(1, 100);
+        let led_temp_cold = config.get("led_temp_cold").and_then(|v| v.as_f64()).unwrap_or(15.0) as f32;
+        let led_temp_hot = config.get("led_temp_hot").and_then(|v| v.as_f64()).unwrap_or(30.0) as f32;
+        // Hex Ed25519 public key; without one OTA updates are refused
+        let ota_public_key = config.get("ota_public_key").and_then(|v| v.as_str()).unwrap_or("");
+        let sensors: Vec<String> = config
+            .get("sensors")
+            .and_then(|v| v.as_array())
+            .map(|list| list.iter().filter_map(|v| v.as_str()).map(|name| format!("{:?}", name)).collect())
+            .unwrap_or_else(|| vec![format!("{:?}", "internal")]);
+        // (name, pin, attenuation, oversample, scale, unit)
+        let adc_channels: Vec<String> = config
+            .get("adc_channels")
+            .and_then(|v| v.as_array())
+            .map(|list| {
+                list.iter()
+                    .map(|ch| {
+                        let scale: Vec<String> = ch
+                            .get("scale")
+                            .and_then(|v| v.as_array())
+                            .map(|c| c.iter().filter_map(|v| v.as_f64()).map(|v| format!("{:?}", v as f32)).collect())
+                            .unwrap_or_default();
+                        format!(
+                            "({:?}, {}, {:?}, {}, &[{}], {:?})",
+                            ch.get("name").and_then(|v| v.as_str()).unwrap_or("adc"),
+                            ch.get("pin").and_then(|v| v.as_u64()).unwrap_or(0).min(255),
+                            ch.get("attenuation").and_then(|v| v.as_str()).unwrap_or("11db"),
+                            ch.get("oversample").and_then(|v| v.as_u64()).unwrap_or(1).clamp(1, 255),
+                            scale.join(", "),
+                            ch.get("unit").and_then(|v| v.as_str()).unwrap_or("")
+                        )
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
 
         let code = format!(
             r#"
@@ -26,8 +90,45 @@ fn main() {
             pub const DEFAULT_MQTT_HOST: &str = "{}";
             pub const DEFAULT_MQTT_PORT: u16 = {};
             pub const DEFAULT_DEVICE_ID: &str = "{}";
+            pub const DEFAULT_IPV6: bool = {};
+            pub const DEFAULT_DHCPV6: bool = {};
+            pub const DEFAULT_SITE: &str = "{}";
+            pub const DEFAULT_TOPIC_TEMPLATE: &str = "{}";
+            pub const DEFAULT_PUBLISH_INTERVAL_SECS: u32 = {};
+            pub const DEFAULT_SAMPLE_INTERVAL_MS: u32 = {};
+            pub const DEFAULT_DEADBAND: f32 = {:?};
+            pub const DEFAULT_HEARTBEAT_SECS: u32 = {};
+            pub const DEFAULT_HEALTH_INTERVAL_SECS: u32 = {};
+            pub const DEFAULT_PAYLOAD_FORMAT: &str = "{}";
+            pub const DEFAULT_HA_DISCOVERY: bool = {};
+            pub const DEFAULT_OTA_CONFIRM_SECS: u32 = {};
+            pub const DEFAULT_NTP_SERVER: &str = "{}";
+            pub const DEFAULT_BUFFER_CAPACITY: u16 = {};
+            pub const DEFAULT_BUFFER_MAX_AGE_SECS: u32 = {};
+            pub const DEFAULT_SLEEP_INTERVAL_SECS: u32 = {};
+            pub const DEFAULT_WIFI_POWER_SAVE: &str = "{}";
+            pub const DEFAULT_WIFI_TX_POWER_DBM: u8 = {};
+            pub const DEFAULT_WIFI_COUNTRY: &str = "{}";
+            pub const DEFAULT_WIFI_LISTEN_INTERVAL: u16 = {};
+            pub const DEFAULT_BATTERY_PIN: u8 = {};
+            pub const DEFAULT_BATTERY_DIVIDER: f32 = {:?};
+            pub const DEFAULT_BATTERY_LOW_MV: u16 = {};
+            pub const DEFAULT_BATTERY_CUTOFF_MV: u16 = {};
+            pub const DEFAULT_BATTERY_LOW_SLEEP_SECS: u32 = {};
+            pub const DEFAULT_LED_BOARD: &str = "{}";
+            pub const DEFAULT_LED_BRIGHTNESS: u8 = {};
+            pub const DEFAULT_LED_TEMP_COLD: f32 = {:?};
+            pub const DEFAULT_LED_TEMP_HOT: f32 = {:?};
+            pub const OTA_PUBLIC_KEY: &str = "{}";
+            pub const DEFAULT_SENSORS: &[&str] = &[{}];
+            #[allow(clippy::type_complexity)]
+            pub const DEFAULT_ADC_CHANNELS: &[(&str, u8, &str, u8, &[f32], &str)] = &[{}];
             "#,
-            ssid, password, mqtt_host, mqtt_port, device_id
+            ssid, password, mqtt_host, mqtt_port, device_id, ipv6, dhcpv6, site, topic_template, publish_interval_secs,
+            sample_interval_ms, deadband, heartbeat_secs, health_interval_secs, payload_format, ha_discovery, ota_confirm_secs,
+            ntp_server, buffer_capacity, buffer_max_age_secs, sleep_interval_secs,
+            wifi_power_save, wifi_tx_power_dbm, wifi_country, wifi_listen_interval, battery_pin, battery_divider, battery_low_mv,
+            battery_cutoff_mv, battery_low_sleep_secs, led_board, led_brightness, led_temp_cold, led_temp_hot, ota_public_key, sensors.join(", "), adc_channels.join(", ")
         );
         fs::write(&dest_path, code).unwrap();
     } else {
@@ -38,6 +139,39 @@ fn main() {
             pub const DEFAULT_MQTT_HOST: &str = "127.0.0.1";
             pub const DEFAULT_MQTT_PORT: u16 = 1883;
             pub const DEFAULT_DEVICE_ID: &str = "esp32";
+            pub const DEFAULT_IPV6: bool = false;
+            pub const DEFAULT_DHCPV6: bool = false;
+            pub const DEFAULT_SITE: &str = "default";
+            pub const DEFAULT_TOPIC_TEMPLATE: &str = "sensors/{channel}";
+            pub const DEFAULT_PUBLISH_INTERVAL_SECS: u32 = 2;
+            pub const DEFAULT_SAMPLE_INTERVAL_MS: u32 = 500;
+            pub const DEFAULT_DEADBAND: f32 = 0.0;
+            pub const DEFAULT_HEARTBEAT_SECS: u32 = 300;
+            pub const DEFAULT_HEALTH_INTERVAL_SECS: u32 = 60;
+            pub const DEFAULT_PAYLOAD_FORMAT: &str = "value";
+            pub const DEFAULT_HA_DISCOVERY: bool = true;
+            pub const DEFAULT_OTA_CONFIRM_SECS: u32 = 300;
+            pub const DEFAULT_NTP_SERVER: &str = "pool.ntp.org";
+            pub const DEFAULT_BUFFER_CAPACITY: u16 = 2000;
+            pub const DEFAULT_BUFFER_MAX_AGE_SECS: u32 = 86400;
+            pub const DEFAULT_SLEEP_INTERVAL_SECS: u32 = 0;
+            pub const DEFAULT_WIFI_POWER_SAVE: &str = "none";
+            pub const DEFAULT_WIFI_TX_POWER_DBM: u8 = 20;
+            pub const DEFAULT_WIFI_COUNTRY: &str = "CN";
+            pub const DEFAULT_WIFI_LISTEN_INTERVAL: u16 = 3;
+            pub const DEFAULT_BATTERY_PIN: u8 = 3;
+            pub const DEFAULT_BATTERY_DIVIDER: f32 = 0.0;
+            pub const DEFAULT_BATTERY_LOW_MV: u16 = 3400;
+            pub const DEFAULT_BATTERY_CUTOFF_MV: u16 = 3200;
+            pub const DEFAULT_BATTERY_LOW_SLEEP_SECS: u32 = 3600;
+            pub const DEFAULT_LED_BOARD: &str = "gpio";
+            pub const DEFAULT_LED_BRIGHTNESS: u8 = 20;
+            pub const DEFAULT_LED_TEMP_COLD: f32 = 15.0;
+            pub const DEFAULT_LED_TEMP_HOT: f32 = 30.0;
+            pub const OTA_PUBLIC_KEY: &str = "";
+            pub const DEFAULT_SENSORS: &[&str] = &["internal"];
+            #[allow(clippy::type_complexity)]
+            pub const DEFAULT_ADC_CHANNELS: &[(&str, u8, &str, u8, &[f32], &str)] = &[];
         "#;
         fs::write(&dest_path, code).unwrap();
     }
@@ -0,0 +1,197 @@
+// --- Sample Aggregation ---
+// Readings are collected at the sample rate into a `Window`. At the publish
+// rate the window is summarised (mean/min/max/stddev) and a `Deadband`
+// decides whether the summary is worth publishing.
+
+/// Statistics over one publish window.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Summary {
+    pub mean: f32,
+    pub min: f32,
+    pub max: f32,
+    /// Population standard deviation.
+    pub stddev: f32,
+    pub count: u16,
+}
+
+/// Running statistics (Welford's algorithm, so no samples are stored).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Window {
+    count: u16,
+    mean: f32,
+    m2: f32,
+    min: f32,
+    max: f32,
+}
+
+impl Window {
+    pub const fn new() -> Self {
+        Self { count: 0, mean: 0.0, m2: 0.0, min: 0.0, max: 0.0 }
+    }
+
+    /// Adds a reading. Non-finite values (sensor glitches) are ignored.
+    pub fn push(&mut self, value: f32) {
+        if !value.is_finite() || self.count == u16::MAX {
+            return;
+        }
+        if self.count == 0 {
+            self.min = value;
+            self.max = value;
+        } else {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f32;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    pub fn len(&self) -> u16 {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Summarises the window, or `None` if it holds no readings.
+    pub fn summary(&self) -> Option<Summary> {
+        if self.count == 0 {
+            return None;
+        }
+        Some(Summary {
+            mean: self.mean,
+            min: self.min,
+            max: self.max,
+            stddev: libm::sqrtf((self.m2 / self.count as f32).max(0.0)),
+            count: self.count,
+        })
+    }
+
+    /// Summarises and clears the window.
+    pub fn take(&mut self) -> Option<Summary> {
+        let summary = self.summary();
+        *self = Self::new();
+        summary
+    }
+}
+
+/// Publish-on-change filter: a value passes if it moved at least `threshold`
+/// away from the last published value, or if `heartbeat_ms` has elapsed since
+/// the last publish (so a flat signal still shows the device is alive).
+/// A threshold of 0 lets every value through.
+#[derive(Clone, Copy, Debug)]
+pub struct Deadband {
+    threshold: f32,
+    heartbeat_ms: u64,
+    last: Option<(f32, u64)>,
+}
+
+impl Deadband {
+    pub const fn new(threshold: f32, heartbeat_ms: u64) -> Self {
+        Self { threshold, heartbeat_ms, last: None }
+    }
+
+    /// Returns `true` if `value` should be published at `now_ms` and, if so,
+    /// remembers it as the last published value.
+    pub fn check(&mut self, value: f32, now_ms: u64) -> bool {
+        let publish = match self.last {
+            None => true,
+            Some(_) if self.threshold <= 0.0 => true,
+            Some((last, at)) => {
+                libm::fabsf(value - last) >= self.threshold || now_ms.saturating_sub(at) >= self.heartbeat_ms
+            }
+        };
+        if publish {
+            self.last = Some((value, now_ms));
+        }
+        publish
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(values: &[f32]) -> Window {
+        let mut window = Window::new();
+        for &v in values {
+            window.push(v);
+        }
+        window
+    }
+
+    #[test]
+    fn summary_over_a_window() {
+        let summary = window(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]).summary().unwrap();
+        assert_eq!(summary.count, 8);
+        assert_eq!(summary.mean, 5.0);
+        assert_eq!(summary.min, 2.0);
+        assert_eq!(summary.max, 9.0);
+        assert!((summary.stddev - 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn single_and_negative_readings() {
+        let summary = window(&[-12.5]).summary().unwrap();
+        assert_eq!((summary.mean, summary.min, summary.max, summary.stddev, summary.count), (-12.5, -12.5, -12.5, 0.0, 1));
+
+        let summary = window(&[-3.0, -1.0]).summary().unwrap();
+        assert_eq!((summary.min, summary.max, summary.mean), (-3.0, -1.0, -2.0));
+    }
+
+    #[test]
+    fn skips_non_finite_readings() {
+        let window = window(&[f32::NAN, 20.0, f32::INFINITY, 22.0, f32::NEG_INFINITY]);
+        assert_eq!(window.len(), 2);
+        let summary = window.summary().unwrap();
+        assert_eq!((summary.mean, summary.min, summary.max), (21.0, 20.0, 22.0));
+
+        assert!(self::window(&[f32::NAN]).summary().is_none());
+    }
+
+    #[test]
+    fn take_resets_the_window() {
+        let mut window = window(&[10.0, 30.0]);
+        assert_eq!(window.take().unwrap().mean, 20.0);
+        assert!(window.is_empty());
+        assert!(window.take().is_none());
+
+        // The old readings do not leak into the next window
+        window.push(5.0);
+        let summary = window.take().unwrap();
+        assert_eq!((summary.mean, summary.min, summary.max, summary.stddev, summary.count), (5.0, 5.0, 5.0, 0.0, 1));
+    }
+
+    #[test]
+    fn deadband_threshold_edges() {
+        let mut deadband = Deadband::new(0.5, 60_000);
+        assert!(deadband.check(20.0, 0), "the first value always passes");
+        assert!(!deadband.check(20.25, 1_000));
+        assert!(!deadband.check(19.75, 2_000));
+        assert!(deadband.check(20.5, 3_000), "a move of exactly the threshold passes");
+        // Measured from the last published value, not the last checked one
+        assert!(!deadband.check(20.75, 4_000));
+        assert!(deadband.check(20.0, 5_000));
+    }
+
+    #[test]
+    fn deadband_heartbeat_edges() {
+        let mut deadband = Deadband::new(1.0, 60_000);
+        assert!(deadband.check(20.0, 1_000));
+        assert!(!deadband.check(20.0, 60_999));
+        assert!(deadband.check(20.0, 61_000), "passes once the heartbeat has elapsed");
+        assert!(!deadband.check(20.0, 61_001), "the heartbeat restarts on publish");
+        // A clock going backwards does not trigger a publish
+        assert!(!deadband.check(20.0, 0));
+    }
+
+    #[test]
+    fn zero_threshold_passes_everything() {
+        let mut deadband = Deadband::new(0.0, 60_000);
+        assert!(deadband.check(20.0, 0));
+        assert!(deadband.check(20.0, 1));
+        assert!(deadband.check(20.0, 1));
+    }
+}
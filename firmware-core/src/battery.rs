@@ -0,0 +1,170 @@
+use serde::{Deserialize, Serialize};
+
+// --- Battery Monitoring ---
+// The supply is measured on an ADC1 pin behind a resistor divider. ADC1
+// belongs to the ADC sensor, so it takes this reading along with its channels
+// and records it here. Two thresholds turn the voltage into a level: below
+// `low_mv` the duty cycle sleeps longer, below `cutoff_mv` the device sends
+// a final alert and then only wakes to check whether the voltage came back,
+// without turning on Wi-Fi, until the battery is replaced or recharged.
+
+/// A level is only left once the voltage is this far above its threshold, so
+/// a battery recovering without load does not flip back and forth.
+pub const HYSTERESIS_MV: u32 = 100;
+
+/// Conversions averaged per reading.
+pub const OVERSAMPLE: u32 = 16;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct BatteryConfig {
+    /// ADC1 pin (GPIO0..=GPIO4) at the midpoint of the divider.
+    pub pin: u8,
+    /// Supply voltage over pin voltage, e.g. 2.0 for two equal resistors
+    /// (0 = no battery monitoring).
+    pub divider: f32,
+    /// Below this the battery is low.
+    pub low_mv: u16,
+    /// Below this the device stops before the brown-out detector resets it.
+    pub cutoff_mv: u16,
+    /// Sleep interval on a low or flat battery, if longer than `sleep_interval_secs`.
+    pub low_sleep_secs: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Level {
+    Ok,
+    Low,
+    Critical,
+}
+
+impl Level {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Ok => "ok",
+            Self::Low => "low",
+            Self::Critical => "critical",
+        }
+    }
+}
+
+impl BatteryConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.divider > 0.0
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.pin < 5
+            && self.divider.is_finite()
+            && self.divider >= 0.0
+            && self.cutoff_mv < self.low_mv
+            && self.low_sleep_secs > 0
+    }
+
+    /// Supply voltage for a voltage at the pin.
+    pub fn supply_mv(&self, pin_mv: f32) -> u32 {
+        (pin_mv * self.divider) as u32
+    }
+
+    /// The level at `mv`, coming from `previous`.
+    pub fn level(&self, mv: u32, previous: Level) -> Level {
+        let cutoff = self.cutoff_mv as u32 + if previous == Level::Critical { HYSTERESIS_MV } else { 0 };
+        let low = self.low_mv as u32 + if previous != Level::Ok { HYSTERESIS_MV } else { 0 };
+        if mv < cutoff {
+            Level::Critical
+        } else if mv < low {
+            Level::Low
+        } else {
+            Level::Ok
+        }
+    }
+
+    /// The sleep interval to use at `level`.
+    pub fn sleep_secs(&self, sleep_interval_secs: u32, level: Level) -> u32 {
+        match level {
+            Level::Ok => sleep_interval_secs,
+            Level::Low | Level::Critical => sleep_interval_secs.max(self.low_sleep_secs),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: BatteryConfig = BatteryConfig { pin: 3, divider: 2.0, low_mv: 3500, cutoff_mv: 3300, low_sleep_secs: 3600 };
+
+    #[test]
+    fn levels_going_down() {
+        assert_eq!(CONFIG.level(4100, Level::Ok), Level::Ok);
+        assert_eq!(CONFIG.level(3500, Level::Ok), Level::Ok);
+        assert_eq!(CONFIG.level(3499, Level::Ok), Level::Low);
+        assert_eq!(CONFIG.level(3300, Level::Low), Level::Low);
+        assert_eq!(CONFIG.level(3299, Level::Low), Level::Critical);
+        // A sudden drop skips `Low`
+        assert_eq!(CONFIG.level(3000, Level::Ok), Level::Critical);
+    }
+
+    #[test]
+    fn leaving_low_needs_the_hysteresis() {
+        assert_eq!(CONFIG.level(3550, Level::Low), Level::Low);
+        assert_eq!(CONFIG.level(3599, Level::Low), Level::Low);
+        assert_eq!(CONFIG.level(3600, Level::Low), Level::Ok);
+        // Coming from `Ok` the same voltage is fine
+        assert_eq!(CONFIG.level(3550, Level::Ok), Level::Ok);
+    }
+
+    #[test]
+    fn leaving_critical_needs_the_hysteresis() {
+        assert_eq!(CONFIG.level(3350, Level::Critical), Level::Critical);
+        assert_eq!(CONFIG.level(3399, Level::Critical), Level::Critical);
+        assert_eq!(CONFIG.level(3400, Level::Critical), Level::Low);
+        // Both thresholds apply when the battery is replaced
+        assert_eq!(CONFIG.level(3599, Level::Critical), Level::Low);
+        assert_eq!(CONFIG.level(3600, Level::Critical), Level::Ok);
+        // Coming from `Low` there is none
+        assert_eq!(CONFIG.level(3350, Level::Low), Level::Low);
+    }
+
+    #[test]
+    fn sleeps_longer_on_a_low_battery() {
+        assert_eq!(CONFIG.sleep_secs(300, Level::Ok), 300);
+        assert_eq!(CONFIG.sleep_secs(300, Level::Low), 3600);
+        assert_eq!(CONFIG.sleep_secs(300, Level::Critical), 3600);
+        // Never shorter than configured
+        assert_eq!(CONFIG.sleep_secs(7200, Level::Low), 7200);
+    }
+
+    #[test]
+    fn scales_the_pin_voltage() {
+        assert_eq!(CONFIG.supply_mv(1850.0), 3700);
+        assert_eq!(BatteryConfig { divider: 1.5, ..CONFIG }.supply_mv(2000.0), 3000);
+    }
+
+    #[test]
+    fn zero_divider_disables_monitoring() {
+        let disabled = BatteryConfig { divider: 0.0, ..CONFIG };
+        assert!(!disabled.is_enabled());
+        assert!(disabled.is_valid());
+        assert!(CONFIG.is_enabled());
+        assert!(CONFIG.is_valid());
+    }
+
+    #[test]
+    fn rejects_bad_thresholds_and_values() {
+        assert!(!BatteryConfig { cutoff_mv: 3500, ..CONFIG }.is_valid());
+        assert!(!BatteryConfig { cutoff_mv: 3600, ..CONFIG }.is_valid());
+        assert!(!BatteryConfig { divider: -1.0, ..CONFIG }.is_valid());
+        assert!(!BatteryConfig { divider: f32::NAN, ..CONFIG }.is_valid());
+        assert!(!BatteryConfig { divider: f32::INFINITY, ..CONFIG }.is_valid());
+        assert!(!BatteryConfig { pin: 5, ..CONFIG }.is_valid());
+        assert!(BatteryConfig { pin: 0, ..CONFIG }.is_valid());
+        assert!(!BatteryConfig { low_sleep_secs: 0, ..CONFIG }.is_valid());
+    }
+
+    #[test]
+    fn level_names() {
+        assert_eq!(Level::Ok.name(), "ok");
+        assert_eq!(Level::Low.name(), "low");
+        assert_eq!(Level::Critical.name(), "critical");
+    }
+}
@@ -0,0 +1,226 @@
+use serde::{Serialize, Deserialize};
+
+// --- Temperature Calibration ---
+// TSENS measures the die, not the room. A per-device linear correction maps
+// raw readings towards ambient, and an optional load term removes the
+// self-heating caused by the CPU and the radio.
+
+/// How quickly the die follows a change in load. The load estimate is smoothed
+/// with this time constant so the compensation tracks the die, not the tick.
+pub const THERMAL_TIME_CONSTANT_MS: u64 = 60_000;
+
+/// Per-device correction: `ambient = slope * raw + offset - cpu * cpu_load - radio * radio_load`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Calibration {
+    /// Added after scaling (°C).
+    pub offset: f32,
+    /// Multiplies the raw reading.
+    pub slope: f32,
+    /// Self-heating (°C) with the CPU busy all of the time.
+    pub cpu_coeff: f32,
+    /// Self-heating (°C) with the radio on all of the time.
+    pub radio_coeff: f32,
+}
+
+impl Calibration {
+    /// No correction: readings pass through unchanged.
+    pub const IDENTITY: Self = Self { offset: 0.0, slope: 1.0, cpu_coeff: 0.0, radio_coeff: 0.0 };
+
+    /// Applies the linear correction to a single raw reading.
+    pub fn apply(&self, raw: f32) -> f32 {
+        self.slope * raw + self.offset
+    }
+
+    /// Estimated self-heating (°C) to subtract for the given load.
+    pub fn compensation(&self, load: Load) -> f32 {
+        self.cpu_coeff * load.cpu.clamp(0.0, 1.0) + self.radio_coeff * load.radio.clamp(0.0, 1.0)
+    }
+
+    /// A zero or non-finite coefficient would turn every reading into garbage.
+    pub fn is_valid(&self) -> bool {
+        self.offset.is_finite()
+            && self.slope.is_finite()
+            && self.slope != 0.0
+            && self.cpu_coeff.is_finite()
+            && self.radio_coeff.is_finite()
+    }
+
+    /// Applies space-separated `key=value` pairs, e.g. `offset=-4.5 slope=1.02`.
+    /// Keys are `offset`, `slope`, `cpu` and `radio`. On error nothing is changed.
+    pub fn update(&mut self, args: &str) -> Result<(), CalibrationError> {
+        let mut updated = *self;
+        for pair in args.split_whitespace() {
+            let (key, value) = pair.split_once('=').ok_or(CalibrationError::Syntax)?;
+            let value: f32 = value.parse().map_err(|_| CalibrationError::InvalidValue)?;
+            match key {
+                "offset" => updated.offset = value,
+                "slope" => updated.slope = value,
+                "cpu" => updated.cpu_coeff = value,
+                "radio" => updated.radio_coeff = value,
+                _ => return Err(CalibrationError::UnknownKey),
+            }
+        }
+        if !updated.is_valid() {
+            return Err(CalibrationError::InvalidValue);
+        }
+        *self = updated;
+        Ok(())
+    }
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CalibrationError {
+    /// An argument is not of the form `key=value`.
+    Syntax,
+    UnknownKey,
+    /// Not a number, or the result would not be a usable calibration.
+    InvalidValue,
+}
+
+/// Fraction of time (0..1) the CPU was busy and the radio was on.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Load {
+    pub cpu: f32,
+    pub radio: f32,
+}
+
+/// Smoothed load estimate, fed once per sample tick.
+///
+/// CPU load is not measured directly: a tick that fires late found the
+/// executor busy with another task, so the fraction of late ticks estimates
+/// the fraction of time the CPU is busy.
+#[derive(Clone, Copy, Debug)]
+pub struct LoadMeter {
+    time_constant_ms: u64,
+    load: Load,
+}
+
+impl LoadMeter {
+    pub const fn new(time_constant_ms: u64) -> Self {
+        Self { time_constant_ms, load: Load { cpu: 0.0, radio: 0.0 } }
+    }
+
+    /// Records `elapsed_ms` of activity: whether the CPU was found busy and the radio on.
+    pub fn record(&mut self, elapsed_ms: u64, cpu_busy: bool, radio_on: bool) {
+        let alpha = elapsed_ms as f32 / (self.time_constant_ms + elapsed_ms).max(1) as f32;
+        let cpu = if cpu_busy { 1.0 } else { 0.0 };
+        let radio = if radio_on { 1.0 } else { 0.0 };
+        self.load.cpu += alpha * (cpu - self.load.cpu);
+        self.load.radio += alpha * (radio - self.load.radio);
+    }
+
+    pub fn load(&self) -> Load {
+        self.load
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_the_correction() {
+        let cal = Calibration { offset: -4.5, slope: 1.02, cpu_coeff: 2.0, radio_coeff: 1.0 };
+        assert!((cal.apply(30.0) - 26.1).abs() < 1e-4);
+        assert_eq!(cal.compensation(Load { cpu: 0.5, radio: 1.0 }), 2.0);
+        // Loads outside 0..1 are clamped
+        assert_eq!(cal.compensation(Load { cpu: 2.0, radio: -1.0 }), 2.0);
+        assert_eq!(Calibration::IDENTITY.apply(21.5), 21.5);
+    }
+
+    #[test]
+    fn updates_the_given_keys() {
+        let mut cal = Calibration::IDENTITY;
+        cal.update("offset=-4.5 slope=1.02").unwrap();
+        assert_eq!(cal, Calibration { offset: -4.5, slope: 1.02, cpu_coeff: 0.0, radio_coeff: 0.0 });
+        cal.update("  cpu=2.5   radio=0.8 ").unwrap();
+        assert_eq!(cal, Calibration { offset: -4.5, slope: 1.02, cpu_coeff: 2.5, radio_coeff: 0.8 });
+        // The last value for a key wins; no arguments change nothing
+        cal.update("offset=1 offset=2").unwrap();
+        assert_eq!(cal.offset, 2.0);
+        cal.update("").unwrap();
+        assert_eq!(cal.offset, 2.0);
+    }
+
+    #[test]
+    fn rejects_bad_syntax() {
+        let mut cal = Calibration::IDENTITY;
+        assert_eq!(cal.update("offset"), Err(CalibrationError::Syntax));
+        assert_eq!(cal.update("offset -4.5"), Err(CalibrationError::Syntax));
+        assert_eq!(cal.update("offset= -4.5"), Err(CalibrationError::InvalidValue));
+        assert_eq!(cal.update("offset=abc"), Err(CalibrationError::InvalidValue));
+        assert_eq!(cal.update("offset="), Err(CalibrationError::InvalidValue));
+        assert_eq!(cal, Calibration::IDENTITY);
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        let mut cal = Calibration::IDENTITY;
+        assert_eq!(cal.update("gain=2"), Err(CalibrationError::UnknownKey));
+        assert_eq!(cal.update("Offset=1"), Err(CalibrationError::UnknownKey));
+        assert_eq!(cal, Calibration::IDENTITY);
+    }
+
+    #[test]
+    fn rejects_unusable_values_without_partial_update() {
+        let mut cal = Calibration::IDENTITY;
+        assert_eq!(cal.update("offset=-4.5 slope=0"), Err(CalibrationError::InvalidValue));
+        assert_eq!(cal.update("offset=-4.5 slope=NaN"), Err(CalibrationError::InvalidValue));
+        assert_eq!(cal.update("offset=inf"), Err(CalibrationError::InvalidValue));
+        assert_eq!(cal.update("cpu=-inf"), Err(CalibrationError::InvalidValue));
+        // An error after valid pairs keeps the earlier ones out too
+        assert_eq!(cal.update("offset=-4.5 gain=2"), Err(CalibrationError::UnknownKey));
+        assert_eq!(cal, Calibration::IDENTITY);
+    }
+
+    #[test]
+    fn load_converges() {
+        let mut meter = LoadMeter::new(1000);
+        assert_eq!(meter.load(), Load::default());
+        for _ in 0..100 {
+            meter.record(100, true, true);
+        }
+        assert!(meter.load().cpu > 0.99 && meter.load().cpu <= 1.0);
+        assert!(meter.load().radio > 0.99 && meter.load().radio <= 1.0);
+        for _ in 0..100 {
+            meter.record(100, false, true);
+        }
+        assert!(meter.load().cpu < 0.01 && meter.load().cpu >= 0.0);
+        assert!(meter.load().radio > 0.99);
+    }
+
+    #[test]
+    fn load_follows_the_time_constant() {
+        // One step as long as the time constant moves halfway
+        let mut meter = LoadMeter::new(1000);
+        meter.record(1000, true, false);
+        assert_eq!(meter.load(), Load { cpu: 0.5, radio: 0.0 });
+        // A busy fraction settles at that fraction
+        let mut meter = LoadMeter::new(1000);
+        for i in 0..1000 {
+            meter.record(10, i % 4 == 0, false);
+        }
+        assert!((meter.load().cpu - 0.25).abs() < 0.05);
+    }
+
+    #[test]
+    fn zero_elapsed_changes_nothing() {
+        let mut meter = LoadMeter::new(1000);
+        meter.record(500, true, false);
+        let before = meter.load();
+        meter.record(0, false, true);
+        assert_eq!(meter.load(), before);
+        // Even without smoothing there is no division by zero
+        let mut meter = LoadMeter::new(0);
+        meter.record(0, true, true);
+        assert_eq!(meter.load(), Load::default());
+        meter.record(10, true, true);
+        assert_eq!(meter.load(), Load { cpu: 1.0, radio: 1.0 });
+    }
+}
@@ -0,0 +1,843 @@
+use core::fmt::Write;
+use core::net::IpAddr;
+use heapless::{String, Vec};
+use serde::Deserialize;
+
+use crate::battery::BatteryConfig;
+use crate::config::AppConfig;
+use crate::led::{self, LedBoard};
+use crate::ota::chunk::DEFAULT_CHUNK_SIZE;
+use crate::ota::http::{self, MAX_URL_LEN};
+use crate::ota::image::{Manifest, OtaError};
+use crate::sensor::{SensorKind, MAX_SENSORS};
+use crate::telemetry::{push_json_str, EncodeError, PayloadFormat};
+use crate::topic;
+use crate::wifi::{self, PowerSave};
+
+// --- Remote Commands ---
+// Commands arrive on `devices/<id>/cmd/<name>` with a JSON object of arguments
+// (an empty payload means no arguments). The result is published on
+// `devices/<id>/resp/<name>`; an `"id"` given in the arguments is echoed so
+// the caller can match responses to requests. Parsing and formatting live
+// here, the effects (reboot, LED, flash) are carried out by the caller.
+
+/// Largest command packet (topic + arguments) accepted from the broker.
+pub const MAX_COMMAND_PACKET: usize = 1024;
+
+/// Longest request id echoed in the response.
+pub const MAX_REQUEST_ID: usize = 32;
+
+/// Scratch space for unescaping JSON strings; fits the longest argument (an OTA URL).
+pub const UNESCAPE_BUF_SIZE: usize = MAX_URL_LEN;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    /// Restart the device after responding.
+    Reboot,
+    /// Change the sample and/or publish interval, optionally saving it.
+    SetInterval,
+    /// Blink the LED so the device can be found.
+    Identify,
+    /// Return the stored configuration without secrets.
+    GetConfig,
+    /// Change stored configuration fields; applied on the next boot.
+    SetConfig,
+    /// Download a signed firmware image over HTTP and boot it.
+    Ota,
+    /// Start (or resume) receiving a signed image in chunks over MQTT.
+    OtaBegin,
+    /// Change Wi-Fi power save and/or TX power, optionally saving them.
+    SetWifi,
+}
+
+impl Command {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "reboot" => Some(Self::Reboot),
+            "set_interval" => Some(Self::SetInterval),
+            "identify" => Some(Self::Identify),
+            "get_config" => Some(Self::GetConfig),
+            "set_config" => Some(Self::SetConfig),
+            "ota" => Some(Self::Ota),
+            "ota_begin" => Some(Self::OtaBegin),
+            "set_wifi" => Some(Self::SetWifi),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CommandError {
+    UnknownCommand,
+    /// Not a JSON object, a field has the wrong type, or a field is unknown.
+    InvalidArgs,
+    /// A field is well-formed but out of range (e.g. an interval of 0).
+    InvalidValue,
+    /// The configuration could not be written to flash.
+    Storage,
+    /// A firmware update was refused or failed.
+    Update(OtaError),
+}
+
+impl CommandError {
+    /// Error code sent in the response.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::UnknownCommand => "unknown_command",
+            Self::InvalidArgs => "invalid_args",
+            Self::InvalidValue => "invalid_value",
+            Self::Storage => "storage",
+            Self::Update(e) => e.name(),
+        }
+    }
+}
+
+/// `devices/<id>/cmd/+`
+pub fn subscription<const N: usize>(device_id: &str) -> Result<String<N>, EncodeError> {
+    let mut filter = String::new();
+    write!(filter, "devices/{}/cmd/+", device_id)?;
+    Ok(filter)
+}
+
+/// Returns the command name if `topic` is `devices/<device_id>/cmd/<name>`.
+pub fn command_name<'a>(topic: &'a str, device_id: &str) -> Option<&'a str> {
+    let rest = topic.strip_prefix("devices/")?.strip_prefix(device_id)?.strip_prefix("/cmd/")?;
+    (!rest.is_empty() && !rest.contains('/')).then_some(rest)
+}
+
+/// `devices/<id>/resp/<name>`
+pub fn response_topic<const N: usize>(device_id: &str, name: &str) -> Result<String<N>, EncodeError> {
+    let mut topic = String::new();
+    write!(topic, "devices/{}/resp/{}", device_id, name)?;
+    Ok(topic)
+}
+
+/// Parses the arguments of a command; an empty payload is treated as `{}`.
+pub fn parse_args<T: for<'de> Deserialize<'de>>(payload: &[u8]) -> Result<T, CommandError> {
+    let payload = if payload.iter().all(|b| b.is_ascii_whitespace()) { b"{}" } else { payload };
+    let mut unescape = [0u8; UNESCAPE_BUF_SIZE];
+    serde_json_core::from_slice_escaped(payload, &mut unescape)
+        .map(|(args, _)| args)
+        .map_err(|_| CommandError::InvalidArgs)
+}
+
+/// Only the request id, so it can be echoed even when the arguments are invalid.
+#[derive(Deserialize, Default)]
+struct RequestId {
+    id: Option<String<MAX_REQUEST_ID>>,
+}
+
+pub fn request_id(payload: &[u8]) -> Option<String<MAX_REQUEST_ID>> {
+    parse_args::<RequestId>(payload).ok().and_then(|r| r.id)
+}
+
+/// Arguments of commands that take none besides the request id.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct NoArgs {
+    pub id: Option<String<MAX_REQUEST_ID>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct IntervalArgs {
+    pub id: Option<String<MAX_REQUEST_ID>>,
+    pub publish_interval_secs: Option<u32>,
+    pub sample_interval_ms: Option<u32>,
+    /// Also store the new intervals in flash so they survive a reboot.
+    pub save: Option<bool>,
+}
+
+impl IntervalArgs {
+    /// At least one interval must be given, and neither may be 0.
+    pub fn validate(&self) -> Result<(), CommandError> {
+        if self.publish_interval_secs.is_none() && self.sample_interval_ms.is_none() {
+            return Err(CommandError::InvalidArgs);
+        }
+        if self.publish_interval_secs == Some(0) || self.sample_interval_ms == Some(0) {
+            return Err(CommandError::InvalidValue);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct WifiArgs {
+    pub id: Option<String<MAX_REQUEST_ID>>,
+    /// `none`, `min` or `max`.
+    pub power_save: Option<String<8>>,
+    pub tx_power_dbm: Option<u8>,
+    /// Also store the new settings in flash so they survive a reboot.
+    pub save: Option<bool>,
+}
+
+impl WifiArgs {
+    /// At least one setting must be given, and both must be in range.
+    pub fn validate(&self) -> Result<(Option<PowerSave>, Option<u8>), CommandError> {
+        if self.power_save.is_none() && self.tx_power_dbm.is_none() {
+            return Err(CommandError::InvalidArgs);
+        }
+        let power_save = match &self.power_save {
+            Some(name) => Some(PowerSave::from_name(name).ok_or(CommandError::InvalidValue)?),
+            None => None,
+        };
+        if self.tx_power_dbm.is_some_and(|dbm| !wifi::is_valid_tx_power(dbm)) {
+            return Err(CommandError::InvalidValue);
+        }
+        Ok((power_save, self.tx_power_dbm))
+    }
+}
+
+/// Longest identify blink.
+pub const MAX_IDENTIFY_SECS: u32 = 120;
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct IdentifyArgs {
+    pub id: Option<String<MAX_REQUEST_ID>>,
+    /// How long to blink (default 10 s).
+    pub secs: Option<u32>,
+}
+
+impl IdentifyArgs {
+    pub fn duration_secs(&self) -> u32 {
+        self.secs.unwrap_or(10).clamp(1, MAX_IDENTIFY_SECS)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OtaArgs {
+    pub id: Option<String<MAX_REQUEST_ID>>,
+    /// `http://` URL of the image.
+    pub url: String<MAX_URL_LEN>,
+    /// Image size in bytes.
+    pub size: u32,
+    /// SHA-256 of the image, hex.
+    pub sha256: String<64>,
+    /// Ed25519 signature over the SHA-256 digest, hex.
+    pub signature: String<128>,
+}
+
+impl OtaArgs {
+    /// Checks the URL and decodes the manifest.
+    pub fn manifest(&self) -> Result<Manifest, CommandError> {
+        http::parse_url(&self.url).map_err(CommandError::Update)?;
+        Manifest::parse(self.size, &self.sha256, &self.signature).ok_or(CommandError::InvalidValue)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OtaBeginArgs {
+    pub id: Option<String<MAX_REQUEST_ID>>,
+    pub size: u32,
+    pub sha256: String<64>,
+    pub signature: String<128>,
+    /// Image bytes per chunk (default and maximum 1024).
+    pub chunk_size: Option<u32>,
+}
+
+impl OtaBeginArgs {
+    pub fn manifest(&self) -> Result<Manifest, CommandError> {
+        Manifest::parse(self.size, &self.sha256, &self.signature).ok_or(CommandError::InvalidValue)
+    }
+
+    pub fn chunk_size(&self) -> u32 {
+        self.chunk_size.unwrap_or(DEFAULT_CHUNK_SIZE)
+    }
+}
+
+/// Fields `set_config` may change. Omitted fields are left alone; ADC
+/// channels and the calibration are not covered.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigUpdate {
+    pub id: Option<String<MAX_REQUEST_ID>>,
+    pub ssid: Option<String<32>>,
+    pub password: Option<String<64>>,
+    pub mqtt_host: Option<String<64>>,
+    pub mqtt_port: Option<u16>,
+    pub device_id: Option<String<32>>,
+    pub site: Option<String<32>>,
+    pub topic_template: Option<String<64>>,
+    pub publish_interval_secs: Option<u32>,
+    pub sample_interval_ms: Option<u32>,
+    pub deadband: Option<f32>,
+    pub heartbeat_secs: Option<u32>,
+    pub health_interval_secs: Option<u32>,
+    pub ota_confirm_secs: Option<u32>,
+    pub sensors: Option<Vec<String<8>, MAX_SENSORS>>,
+    pub payload_format: Option<String<8>>,
+    pub ha_discovery: Option<bool>,
+    pub ipv6: Option<bool>,
+    pub dhcpv6: Option<bool>,
+    pub ntp_server: Option<String<64>>,
+    pub buffer_capacity: Option<u16>,
+    pub buffer_max_age_secs: Option<u32>,
+    pub sleep_interval_secs: Option<u32>,
+    pub wifi_power_save: Option<String<8>>,
+    pub wifi_tx_power_dbm: Option<u8>,
+    pub wifi_country: Option<String<2>>,
+    pub wifi_listen_interval: Option<u16>,
+    /// The whole object, every field given.
+    pub battery: Option<BatteryConfig>,
+    pub led_board: Option<String<16>>,
+    pub led_brightness: Option<u8>,
+    pub led_temp_cold: Option<f32>,
+    pub led_temp_hot: Option<f32>,
+}
+
+impl ConfigUpdate {
+    /// Applies the given fields to `config`. On error nothing is changed.
+    pub fn apply(&self, config: &mut AppConfig) -> Result<(), CommandError> {
+        let mut updated = config.clone();
+
+        if let Some(ssid) = &self.ssid {
+            updated.ssid = ssid.clone();
+        }
+        if let Some(password) = &self.password {
+            updated.password = password.clone();
+        }
+        if let Some(host) = &self.mqtt_host {
+            if !is_valid_host(host) {
+                return Err(CommandError::InvalidValue);
+            }
+            updated.mqtt_host = host.clone();
+        }
+        if let Some(port) = self.mqtt_port {
+            if port == 0 {
+                return Err(CommandError::InvalidValue);
+            }
+            updated.mqtt_port = port;
+        }
+        if let Some(device_id) = &self.device_id {
+            // Used as a single topic level, e.g. in `devices/<id>/cmd/+`
+            if topic::validate_topic(device_id).is_err() || device_id.contains('/') {
+                return Err(CommandError::InvalidValue);
+            }
+            updated.device_id = device_id.clone();
+        }
+        if let Some(site) = &self.site {
+            if topic::validate_topic(site).is_err() {
+                return Err(CommandError::InvalidValue);
+            }
+            updated.site = site.clone();
+        }
+        if let Some(template) = &self.topic_template {
+            updated.topic_template = template.clone();
+        }
+        if let Some(secs) = self.publish_interval_secs {
+            if secs == 0 {
+                return Err(CommandError::InvalidValue);
+            }
+            updated.publish_interval_secs = secs;
+        }
+        if let Some(ms) = self.sample_interval_ms {
+            if ms == 0 {
+                return Err(CommandError::InvalidValue);
+            }
+            updated.sample_interval_ms = ms;
+        }
+        if let Some(deadband) = self.deadband {
+            if !deadband.is_finite() || deadband < 0.0 {
+                return Err(CommandError::InvalidValue);
+            }
+            updated.deadband = deadband;
+        }
+        if let Some(secs) = self.heartbeat_secs {
+            updated.heartbeat_secs = secs;
+        }
+        if let Some(secs) = self.health_interval_secs {
+            updated.health_interval_secs = secs;
+        }
+        if let Some(secs) = self.ota_confirm_secs {
+            // Leaves too little time to connect after an update
+            if secs < 60 {
+                return Err(CommandError::InvalidValue);
+            }
+            updated.ota_confirm_secs = secs;
+        }
+        if let Some(names) = &self.sensors {
+            updated.sensors.clear();
+            for name in names {
+                let kind = SensorKind::from_name(name).ok_or(CommandError::InvalidValue)?;
+                let _ = updated.sensors.push(kind);
+            }
+        }
+        if let Some(format) = &self.payload_format {
+            updated.payload_format = PayloadFormat::from_name(format).ok_or(CommandError::InvalidValue)?;
+        }
+        if let Some(enabled) = self.ha_discovery {
+            updated.ha_discovery = enabled;
+        }
+        if let Some(enabled) = self.ipv6 {
+            updated.ipv6 = enabled;
+        }
+        if let Some(enabled) = self.dhcpv6 {
+            updated.dhcpv6 = enabled;
+        }
+        if let Some(server) = &self.ntp_server {
+            if !is_valid_host(server) {
+                return Err(CommandError::InvalidValue);
+            }
+            updated.ntp_server = server.clone();
+        }
+        if let Some(capacity) = self.buffer_capacity {
+            // Nothing could be kept while the broker is away
+            if capacity == 0 {
+                return Err(CommandError::InvalidValue);
+            }
+            updated.buffer_capacity = capacity;
+        }
+        if let Some(secs) = self.buffer_max_age_secs {
+            updated.buffer_max_age_secs = secs;
+        }
+        if let Some(secs) = self.sleep_interval_secs {
+            updated.sleep_interval_secs = secs;
+        }
+        if let Some(name) = &self.wifi_power_save {
+            updated.wifi_power_save = PowerSave::from_name(name).ok_or(CommandError::InvalidValue)?;
+        }
+        if let Some(dbm) = self.wifi_tx_power_dbm {
+            if !wifi::is_valid_tx_power(dbm) {
+                return Err(CommandError::InvalidValue);
+            }
+            updated.wifi_tx_power_dbm = dbm;
+        }
+        if let Some(country) = &self.wifi_country {
+            if !wifi::is_valid_country(country) {
+                return Err(CommandError::InvalidValue);
+            }
+            updated.wifi_country = country.clone();
+        }
+        if let Some(beacons) = self.wifi_listen_interval {
+            if !wifi::is_valid_listen_interval(beacons) {
+                return Err(CommandError::InvalidValue);
+            }
+            updated.wifi_listen_interval = beacons;
+        }
+        if let Some(battery) = self.battery {
+            if !battery.is_valid() {
+                return Err(CommandError::InvalidValue);
+            }
+            updated.battery = battery;
+        }
+        if let Some(name) = &self.led_board {
+            updated.led_board = LedBoard::from_name(name).ok_or(CommandError::InvalidValue)?;
+        }
+        if let Some(percent) = self.led_brightness {
+            if !led::is_valid_brightness(percent) {
+                return Err(CommandError::InvalidValue);
+            }
+            updated.led_brightness = percent;
+        }
+        // Checked together, so the range can be moved in one update
+        if self.led_temp_cold.is_some() || self.led_temp_hot.is_some() {
+            let cold = self.led_temp_cold.unwrap_or(updated.led_temp_cold);
+            let hot = self.led_temp_hot.unwrap_or(updated.led_temp_hot);
+            if !led::is_valid_temp_range(cold, hot) {
+                return Err(CommandError::InvalidValue);
+            }
+            updated.led_temp_cold = cold;
+            updated.led_temp_hot = hot;
+        }
+
+        // The template is checked with the final site and device id
+        if topic::check_template(&updated.topic_template, &updated.site, &updated.device_id).is_err() {
+            return Err(CommandError::InvalidValue);
+        }
+
+        *config = updated;
+        Ok(())
+    }
+}
+
+/// An IPv4 or IPv6 literal (the latter optionally in brackets) or a host name
+/// of dot-separated labels: letters, digits and inner hyphens.
+pub fn is_valid_host(host: &str) -> bool {
+    let literal = host.strip_prefix('[').and_then(|h| h.strip_suffix(']')).unwrap_or(host);
+    if literal.parse::<IpAddr>().is_ok() {
+        return true;
+    }
+    !host.is_empty()
+        && host.len() <= 253
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        })
+}
+
+fn push_str<const N: usize>(s: &str, out: &mut String<N>) -> Result<(), EncodeError> {
+    out.push_str(s).map_err(|_| EncodeError::Overflow)
+}
+
+/// Writes `config` as a JSON object, leaving out the Wi-Fi password.
+pub fn write_config_json<const N: usize>(config: &AppConfig, out: &mut String<N>) -> Result<(), EncodeError> {
+    push_str("{\"ssid\":", out)?;
+    push_json_str(&config.ssid, out)?;
+    push_str(",\"mqtt_host\":", out)?;
+    push_json_str(&config.mqtt_host, out)?;
+    write!(out, ",\"mqtt_port\":{},\"device_id\":", config.mqtt_port)?;
+    push_json_str(&config.device_id, out)?;
+    push_str(",\"site\":", out)?;
+    push_json_str(&config.site, out)?;
+    push_str(",\"topic_template\":", out)?;
+    push_json_str(&config.topic_template, out)?;
+    write!(
+        out,
+        ",\"publish_interval_secs\":{},\"sample_interval_ms\":{},\"deadband\":{},\"heartbeat_secs\":{},\"health_interval_secs\":{}",
+        config.publish_interval_secs, config.sample_interval_ms, config.deadband, config.heartbeat_secs, config.health_interval_secs
+    )?;
+
+    push_str(",\"sensors\":[", out)?;
+    for (i, kind) in config.sensors.iter().enumerate() {
+        if i > 0 {
+            push_str(",", out)?;
+        }
+        push_json_str(kind.name(), out)?;
+    }
+    push_str("],\"adc_channels\":[", out)?;
+    for (i, channel) in config.adc_channels.iter().enumerate() {
+        if i > 0 {
+            push_str(",", out)?;
+        }
+        push_str("{\"name\":", out)?;
+        push_json_str(&channel.name, out)?;
+        write!(
+            out,
+            ",\"pin\":{},\"attenuation\":\"{}\",\"oversample\":{},\"scale\":[",
+            channel.pin, channel.attenuation.name(), channel.oversample
+        )?;
+        for (j, c) in channel.scale.iter().enumerate() {
+            write!(out, "{}{}", if j > 0 { "," } else { "" }, c)?;
+        }
+        push_str("],\"unit\":", out)?;
+        push_json_str(&channel.unit, out)?;
+        push_str("}", out)?;
+    }
+    let cal = &config.calibration;
+    write!(
+        out,
+        "],\"calibration\":{{\"offset\":{},\"slope\":{},\"cpu\":{},\"radio\":{}}}",
+        cal.offset, cal.slope, cal.cpu_coeff, cal.radio_coeff
+    )?;
+    write!(
+        out,
+        ",\"payload_format\":\"{}\",\"ha_discovery\":{},\"ota_confirm_secs\":{},\"ipv6\":{},\"dhcpv6\":{},\"ntp_server\":",
+        config.payload_format.name(), config.ha_discovery, config.ota_confirm_secs, config.ipv6, config.dhcpv6
+    )?;
+    push_json_str(&config.ntp_server, out)?;
+    write!(
+        out,
+        ",\"buffer_capacity\":{},\"buffer_max_age_secs\":{},\"sleep_interval_secs\":{}",
+        config.buffer_capacity, config.buffer_max_age_secs, config.sleep_interval_secs
+    )?;
+    write!(
+        out,
+        ",\"wifi_power_save\":\"{}\",\"wifi_tx_power_dbm\":{},\"wifi_country\":",
+        config.wifi_power_save.name(), config.wifi_tx_power_dbm
+    )?;
+    push_json_str(&config.wifi_country, out)?;
+    let battery = &config.battery;
+    write!(
+        out,
+        ",\"wifi_listen_interval\":{},\"battery\":{{\"pin\":{},\"divider\":{},\"low_mv\":{},\"cutoff_mv\":{},\"low_sleep_secs\":{}}}",
+        config.wifi_listen_interval, battery.pin, battery.divider, battery.low_mv, battery.cutoff_mv, battery.low_sleep_secs
+    )?;
+    write!(
+        out,
+        ",\"led_board\":\"{}\",\"led_brightness\":{},\"led_temp_cold\":{},\"led_temp_hot\":{}}}",
+        config.led_board.name(), config.led_brightness, config.led_temp_cold, config.led_temp_hot
+    )?;
+    Ok(())
+}
+
+/// Encodes a response into `out` (which is cleared first):
+/// `{"id":..,"ok":true,"result":<result>}` or `{"id":..,"ok":false,"error":".."}`.
+/// `result` is a JSON value; empty leaves it out, as does a missing `id`.
+pub fn write_response<const N: usize>(
+    id: Option<&str>,
+    result: Result<&str, CommandError>,
+    out: &mut String<N>,
+) -> Result<(), EncodeError> {
+    out.clear();
+    push_str("{", out)?;
+    if let Some(id) = id {
+        push_str("\"id\":", out)?;
+        push_json_str(id, out)?;
+        push_str(",", out)?;
+    }
+    match result {
+        Ok("") => push_str("\"ok\":true", out)?,
+        Ok(result) => {
+            push_str("\"ok\":true,\"result\":", out)?;
+            push_str(result, out)?;
+        }
+        Err(e) => write!(out, "\"ok\":false,\"error\":\"{}\"", e.name())?,
+    }
+    push_str("}", out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calibration::Calibration;
+    use crate::sensor::analog::{AdcChannelConfig, Attenuation};
+    use crate::wifi::PowerSave;
+
+    fn config() -> AppConfig {
+        AppConfig {
+            ssid: String::try_from("lab").unwrap(),
+            password: String::try_from("hunter2 \"secret\"").unwrap(),
+            mqtt_host: String::try_from("192.168.0.107").unwrap(),
+            mqtt_port: 1883,
+            device_id: String::try_from("esp32").unwrap(),
+            site: String::try_from("lab").unwrap(),
+            topic_template: String::try_from("sensors/{channel}").unwrap(),
+            publish_interval_secs: 2,
+            sample_interval_ms: 500,
+            deadband: 0.0,
+            heartbeat_secs: 300,
+            health_interval_secs: 60,
+            sensors: Vec::from_slice(&[SensorKind::Internal]).unwrap(),
+            adc_channels: Vec::from_slice(&[AdcChannelConfig {
+                name: String::try_from("soil").unwrap(),
+                pin: 2,
+                attenuation: Attenuation::Db11,
+                oversample: 4,
+                scale: Vec::from_slice(&[0.0, 50.0]).unwrap(),
+                unit: String::try_from("%").unwrap(),
+            }])
+            .unwrap(),
+            calibration: Calibration::IDENTITY,
+            payload_format: PayloadFormat::Value,
+            ha_discovery: true,
+            ota_confirm_secs: 300,
+            ipv6: false,
+            dhcpv6: false,
+            ntp_server: String::try_from("pool.ntp.org").unwrap(),
+            buffer_capacity: 2000,
+            buffer_max_age_secs: 86400,
+            sleep_interval_secs: 0,
+            wifi_power_save: PowerSave::None,
+            wifi_tx_power_dbm: 20,
+            wifi_country: String::try_from("DE").unwrap(),
+            wifi_listen_interval: 3,
+            battery: BatteryConfig { pin: 3, divider: 0.0, low_mv: 3400, cutoff_mv: 3200, low_sleep_secs: 3600 },
+            led_board: LedBoard::Gpio,
+            led_brightness: 20,
+            led_temp_cold: 15.0,
+            led_temp_hot: 30.0,
+        }
+    }
+
+    /// Applies a `set_config` payload to `config`.
+    fn set_config(config: &mut AppConfig, payload: &str) -> Result<(), CommandError> {
+        parse_args::<ConfigUpdate>(payload.as_bytes())?.apply(config)
+    }
+
+    #[test]
+    fn parses_command_topics() {
+        assert_eq!(command_name("devices/esp32/cmd/reboot", "esp32"), Some("reboot"));
+        assert_eq!(command_name("devices/esp32/cmd/", "esp32"), None);
+        assert_eq!(command_name("devices/esp32/cmd/a/b", "esp32"), None);
+        assert_eq!(command_name("devices/other/cmd/reboot", "esp32"), None);
+        assert_eq!(command_name("devices/esp32x/cmd/reboot", "esp32"), None);
+        assert_eq!(subscription::<64>("esp32").unwrap(), "devices/esp32/cmd/+");
+        assert_eq!(response_topic::<64>("esp32", "ota").unwrap(), "devices/esp32/resp/ota");
+        assert_eq!(Command::from_name("set_config"), Some(Command::SetConfig));
+        assert_eq!(Command::from_name("SET_CONFIG"), None);
+    }
+
+    #[test]
+    fn parses_arguments() {
+        let args: IdentifyArgs = parse_args(b"  \n").unwrap();
+        assert_eq!(args.duration_secs(), 10);
+        let args: IdentifyArgs = parse_args(br#"{"id":"a\"b","secs":1000}"#).unwrap();
+        assert_eq!(args.id.as_deref(), Some("a\"b"));
+        assert_eq!(args.duration_secs(), MAX_IDENTIFY_SECS);
+        assert!(matches!(parse_args::<NoArgs>(br#"{"force":true}"#), Err(CommandError::InvalidArgs)));
+        assert!(matches!(parse_args::<IdentifyArgs>(br#"{"secs":"ten"}"#), Err(CommandError::InvalidArgs)));
+        assert!(matches!(parse_args::<NoArgs>(b"[]"), Err(CommandError::InvalidArgs)));
+    }
+
+    #[test]
+    fn request_id_survives_invalid_arguments() {
+        assert_eq!(request_id(br#"{"id":"42","secs":"ten"}"#).as_deref(), Some("42"));
+        assert_eq!(request_id(b"{}"), None);
+        assert_eq!(request_id(b"not json"), None);
+    }
+
+    #[test]
+    fn validates_interval_and_wifi_arguments() {
+        let interval = |payload: &str| parse_args::<IntervalArgs>(payload.as_bytes()).unwrap().validate();
+        assert_eq!(interval(r#"{"publish_interval_secs":10}"#), Ok(()));
+        assert_eq!(interval(r#"{"save":true}"#), Err(CommandError::InvalidArgs));
+        assert_eq!(interval(r#"{"sample_interval_ms":0}"#), Err(CommandError::InvalidValue));
+
+        let wifi = |payload: &str| parse_args::<WifiArgs>(payload.as_bytes()).unwrap().validate();
+        assert_eq!(wifi(r#"{"power_save":"max"}"#), Ok((Some(PowerSave::Max), None)));
+        assert_eq!(wifi(r#"{"tx_power_dbm":8}"#), Ok((None, Some(8))));
+        assert_eq!(wifi(r#"{"save":true}"#), Err(CommandError::InvalidArgs));
+        assert_eq!(wifi(r#"{"power_save":"off"}"#), Err(CommandError::InvalidValue));
+        assert_eq!(wifi(r#"{"tx_power_dbm":21}"#), Err(CommandError::InvalidValue));
+    }
+
+    #[test]
+    fn applies_the_given_fields() {
+        let mut config = config();
+        let payload = r#"{"id":"1","mqtt_host":"[2001:db8::10]","mqtt_port":8883,"sensors":["internal","sht3x"],
+            "payload_format":"json","ntp_server":"time.example.org","buffer_capacity":1,
+            "wifi_power_save":"max","led_temp_hot":40.0}"#;
+        assert_eq!(set_config(&mut config, payload), Ok(()));
+
+        let mut expected = self::config();
+        expected.mqtt_host = String::try_from("[2001:db8::10]").unwrap();
+        expected.mqtt_port = 8883;
+        expected.sensors = Vec::from_slice(&[SensorKind::Internal, SensorKind::Sht3x]).unwrap();
+        expected.payload_format = PayloadFormat::Json;
+        expected.ntp_server = String::try_from("time.example.org").unwrap();
+        expected.buffer_capacity = 1;
+        expected.wifi_power_save = PowerSave::Max;
+        expected.led_temp_hot = 40.0;
+        assert_eq!(config, expected);
+
+        assert_eq!(set_config(&mut config, "{}"), Ok(()));
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn rejects_invalid_values_without_changing_anything() {
+        for payload in [
+            r#"{"mqtt_host":""}"#,
+            r#"{"mqtt_host":"broker .lan"}"#,
+            r#"{"mqtt_port":0}"#,
+            r#"{"device_id":"a/b"}"#,
+            r#"{"device_id":"+"}"#,
+            r##"{"site":"#"}"##,
+            r#"{"topic_template":"sensors/{unknown}"}"#,
+            r#"{"publish_interval_secs":0}"#,
+            r#"{"deadband":-1.0}"#,
+            r#"{"ota_confirm_secs":59}"#,
+            r#"{"sensors":["internal","dht22"]}"#,
+            r#"{"payload_format":"xml"}"#,
+            r#"{"ntp_server":""}"#,
+            r#"{"ntp_server":"pool.ntp.org/"}"#,
+            r#"{"ntp_server":"-pool.ntp.org"}"#,
+            r#"{"ntp_server":"pool..ntp.org"}"#,
+            r#"{"buffer_capacity":0}"#,
+            r#"{"wifi_tx_power_dbm":1}"#,
+            r#"{"wifi_country":"de"}"#,
+            r#"{"wifi_listen_interval":0}"#,
+            r#"{"battery":{"pin":3,"divider":2.0,"low_mv":3200,"cutoff_mv":3400,"low_sleep_secs":60}}"#,
+            r#"{"led_board":"rgb"}"#,
+            r#"{"led_brightness":0}"#,
+            r#"{"led_temp_cold":30.0}"#,
+        ] {
+            // Valid fields before the invalid one must not stick either
+            let payload = payload.replacen('{', r#"{"ssid":"changed","#, 1);
+            let mut config = config();
+            assert_eq!(set_config(&mut config, &payload), Err(CommandError::InvalidValue), "{}", payload);
+            assert_eq!(config, self::config(), "{}", payload);
+        }
+    }
+
+    #[test]
+    fn checks_the_template_against_the_final_site() {
+        let mut config = config();
+        config.topic_template = String::try_from("{site}/{site}/{site}/{site}/{channel}").unwrap();
+        let site = "s".repeat(32);
+        // Four 32-byte sites make the topic too long
+        let payload = format!(r#"{{"site":"{}"}}"#, site);
+        assert_eq!(set_config(&mut config, &payload), Err(CommandError::InvalidValue));
+        let payload = format!(r#"{{"site":"{}","topic_template":"{{site}}/{{channel}}"}}"#, site);
+        assert_eq!(set_config(&mut config, &payload), Ok(()));
+    }
+
+    #[test]
+    fn moves_the_led_range_in_one_update() {
+        let mut config = config();
+        assert_eq!(set_config(&mut config, r#"{"led_temp_cold":35.0,"led_temp_hot":45.0}"#), Ok(()));
+        assert_eq!((config.led_temp_cold, config.led_temp_hot), (35.0, 45.0));
+    }
+
+    #[test]
+    fn accepts_addresses_and_host_names() {
+        for host in ["pool.ntp.org", "ntp1", "time-a.example.com", "10.0.0.1", "2001:db8::1", "[2001:db8::1]"] {
+            assert!(is_valid_host(host), "{}", host);
+        }
+        for host in ["", ".", "pool.ntp.org.", "a_b.lan", "-a.lan", "a-.lan", "ntp:123", "[10.0.0.1", "pool ntp"] {
+            assert!(!is_valid_host(host), "{}", host);
+        }
+        let long_label = "a".repeat(64);
+        assert!(!is_valid_host(&long_label));
+        assert!(is_valid_host(&long_label[1..]));
+    }
+
+    #[test]
+    fn config_json_leaves_out_the_password() {
+        let mut config = config();
+        config.ssid = String::try_from("Caf\u{e9} \"Wi-Fi\"").unwrap();
+        let mut out = String::<1024>::new();
+        write_config_json(&config, &mut out).unwrap();
+        assert!(!out.contains("hunter2"));
+        assert!(!out.contains("password"));
+
+        let json: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(json["ssid"], "Caf\u{e9} \"Wi-Fi\"");
+        assert_eq!(json["mqtt_port"], 1883);
+        assert_eq!(json["sensors"], serde_json::json!(["internal"]));
+        assert_eq!(json["adc_channels"][0]["attenuation"], "11db");
+        assert_eq!(json["adc_channels"][0]["scale"], serde_json::json!([0, 50]));
+        assert_eq!(json["ntp_server"], "pool.ntp.org");
+        assert_eq!(json["buffer_capacity"], 2000);
+        assert_eq!(json["battery"]["low_mv"], 3400);
+        assert_eq!(json["led_temp_hot"], 30.0);
+    }
+
+    #[test]
+    fn config_json_can_be_applied_back() {
+        let mut out = String::<1024>::new();
+        write_config_json(&config(), &mut out).unwrap();
+        // Everything but the fields `set_config` does not cover
+        let mut json: serde_json::Value = serde_json::from_str(&out).unwrap();
+        let fields = json.as_object_mut().unwrap();
+        fields.remove("adc_channels");
+        fields.remove("calibration");
+        let payload = serde_json::to_string(&json).unwrap();
+
+        let mut config = config();
+        config.ssid.clear();
+        config.sensors.clear();
+        config.buffer_capacity = 1;
+        assert_eq!(set_config(&mut config, &payload), Ok(()));
+        assert_eq!(config, self::config());
+    }
+
+    #[test]
+    fn config_json_reports_overflow() {
+        let mut out = String::<64>::new();
+        assert!(write_config_json(&config(), &mut out).is_err());
+    }
+
+    #[test]
+    fn writes_responses() {
+        let mut out = String::<128>::new();
+        write_response(Some("7"), Ok(""), &mut out).unwrap();
+        assert_eq!(out, r#"{"id":"7","ok":true}"#);
+        write_response(None, Ok(r#"{"a":1}"#), &mut out).unwrap();
+        assert_eq!(out, r#"{"ok":true,"result":{"a":1}}"#);
+        write_response(Some("a\"b"), Err(CommandError::InvalidValue), &mut out).unwrap();
+        assert_eq!(out, r#"{"id":"a\"b","ok":false,"error":"invalid_value"}"#);
+        write_response(None, Err(CommandError::Update(OtaError::Busy)), &mut out).unwrap();
+        assert_eq!(out.as_str(), format!(r#"{{"ok":false,"error":"{}"}}"#, OtaError::Busy.name()));
+
+        let mut small = String::<8>::new();
+        assert!(write_response(None, Ok(r#"{"a":1}"#), &mut small).is_err());
+    }
+}
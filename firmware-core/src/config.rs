@@ -0,0 +1,81 @@
+use heapless::{String, Vec};
+use serde::{Deserialize, Serialize};
+
+use crate::battery::BatteryConfig;
+use crate::calibration::Calibration;
+use crate::led::LedBoard;
+use crate::sensor::analog::{AdcChannelConfig, MAX_ADC_CHANNELS};
+use crate::sensor::{SensorKind, MAX_SENSORS};
+use crate::telemetry::PayloadFormat;
+use crate::wifi::PowerSave;
+
+// --- Settings ---
+// Everything the device is configured with. The defaults come from
+// `config.json` at build time and the record is kept in flash, so both live in
+// the firmware (`src/config.rs`); `command` edits it here.
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AppConfig {
+    pub ssid: String<32>,
+    pub password: String<64>,
+    /// Broker address: IPv4/IPv6 literal (optionally bracketed) or hostname.
+    pub mqtt_host: String<64>,
+    pub mqtt_port: u16,
+    pub device_id: String<32>,
+    /// Site name, available as `{site}` in the topic template.
+    pub site: String<32>,
+    /// Topic for readings; supports `{site}`, `{device_id}` and `{channel}`.
+    pub topic_template: String<64>,
+    /// Seconds between published readings (the aggregation window).
+    pub publish_interval_secs: u32,
+    /// Milliseconds between sensor reads within a window.
+    pub sample_interval_ms: u32,
+    /// Only publish when the window mean moved at least this much (0 = always).
+    pub deadband: f32,
+    /// With a deadband, publish at least this often anyway.
+    pub heartbeat_secs: u32,
+    /// Seconds between health reports on `devices/<id>/status` (0 = off).
+    pub health_interval_secs: u32,
+    /// Sensors to read; drivers must be enabled via cargo features.
+    pub sensors: Vec<SensorKind, MAX_SENSORS>,
+    /// Analog inputs; each is published as its own channel.
+    pub adc_channels: Vec<AdcChannelConfig, MAX_ADC_CHANNELS>,
+    /// Correction from die temperature to ambient (set via the `cal` console command).
+    pub calibration: Calibration,
+    /// Encoding used for published readings.
+    pub payload_format: PayloadFormat,
+    /// Announce every channel to Home Assistant via MQTT discovery.
+    pub ha_discovery: bool,
+    /// An updated image that has not connected to the broker this long after
+    /// its first boot is rolled back.
+    pub ota_confirm_secs: u32,
+    /// Enable IPv6 (SLAAC) alongside DHCPv4.
+    pub ipv6: bool,
+    /// Ask a DHCPv6 server for DNS servers when the router does not advertise them.
+    pub dhcpv6: bool,
+    /// SNTP server (hostname or address) used to timestamp readings.
+    pub ntp_server: String<64>,
+    /// Maximum number of readings kept in flash while the broker is unreachable.
+    pub buffer_capacity: u16,
+    /// Buffered readings older than this are discarded instead of published.
+    pub buffer_max_age_secs: u32,
+    /// Deep sleep between cycles of sample, connect and publish (0 = stay awake).
+    pub sleep_interval_secs: u32,
+    /// Modem sleep between beacons.
+    pub wifi_power_save: PowerSave,
+    /// Maximum TX power in dBm (2..=20).
+    pub wifi_tx_power_dbm: u8,
+    /// Regulatory domain, e.g. `DE` (`01` = world-safe channels).
+    pub wifi_country: String<2>,
+    /// Beacons between wake-ups with `wifi_power_save` `max`.
+    pub wifi_listen_interval: u16,
+    /// Supply voltage measurement and low-battery thresholds.
+    pub battery: BatteryConfig,
+    /// Status LED on GPIO8: plain (`gpio`, `gpio_inverted`) or `ws2812`.
+    pub led_board: LedBoard,
+    /// WS2812 brightness in percent.
+    pub led_brightness: u8,
+    /// Temperatures shown blue and red by the WS2812 while running.
+    pub led_temp_cold: f32,
+    pub led_temp_hot: f32,
+}
@@ -0,0 +1,371 @@
+// --- Status Indicator ---
+// What the device is doing, shown on the status LED so it can be read on site
+// without a laptop. Each state has a blink pattern, defined as data below:
+// the steps of one period, repeated while the state lasts. Errors blink
+// their code (a number of long blinks, then a pause) and are shown at least
+// `ERROR_REPEATS` times before the indicator moves on.
+
+/// System states, as set by the connection and publish code.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum State {
+    /// Safe mode: waiting for settings over BLE or the console.
+    Provisioning,
+    /// Associating with the access point.
+    WifiConnecting,
+    /// Associated, waiting for an address.
+    Dhcp,
+    /// Resolving and connecting to the MQTT broker.
+    BrokerConnecting,
+    /// Connected and publishing.
+    Running,
+    /// A firmware update is being received.
+    Ota,
+    Error(ErrorCode),
+}
+
+/// Blink codes. They start at 2, so a code is never mistaken for the single
+/// flash of `Running`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorCode {
+    /// Wi-Fi association failed (wrong password, access point out of range).
+    Wifi,
+    /// No DHCP lease.
+    Dhcp,
+    /// The broker name did not resolve.
+    Dns,
+    /// The broker refused or dropped the TCP or MQTT connection.
+    Broker,
+    /// A sensor read failed.
+    Sensor,
+}
+
+impl ErrorCode {
+    /// Number of blinks.
+    pub fn blinks(&self) -> u8 {
+        match self {
+            Self::Wifi => 2,
+            Self::Dhcp => 3,
+            Self::Dns => 4,
+            Self::Broker => 5,
+            Self::Sensor => 6,
+        }
+    }
+}
+
+/// One step of a pattern: the LED level and how long it is held.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Step {
+    pub on: bool,
+    pub ms: u32,
+}
+
+const fn on(ms: u32) -> Step {
+    Step { on: true, ms }
+}
+
+const fn off(ms: u32) -> Step {
+    Step { on: false, ms }
+}
+
+/// Blink of an error code, the gap between blinks and the pause after them.
+pub const CODE_ON_MS: u32 = 300;
+pub const CODE_OFF_MS: u32 = 300;
+pub const CODE_PAUSE_MS: u32 = 1500;
+
+/// An error is shown this many times before the indicator moves on.
+pub const ERROR_REPEATS: u8 = 2;
+
+/// Strobe of the `identify` command.
+pub const IDENTIFY: Pattern = Pattern::Steps(&[on(50), off(50)]);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pattern {
+    /// One period, repeated.
+    Steps(&'static [Step]),
+    /// `n` blinks, then a pause.
+    Code(u8),
+}
+
+// One period of each state
+const PROVISIONING: &[Step] = &[on(100), off(150), on(100), off(1650)]; // double flash
+const WIFI_CONNECTING: &[Step] = &[on(100), off(100)]; // fast blink
+const DHCP: &[Step] = &[on(250), off(250)];
+const BROKER_CONNECTING: &[Step] = &[on(1000), off(1000)]; // slow blink
+const RUNNING: &[Step] = &[on(50), off(2950)]; // heartbeat
+const OTA: &[Step] = &[on(900), off(100)]; // mostly on
+
+/// How each state looks.
+pub fn pattern(state: State) -> Pattern {
+    match state {
+        State::Provisioning => Pattern::Steps(PROVISIONING),
+        State::WifiConnecting => Pattern::Steps(WIFI_CONNECTING),
+        State::Dhcp => Pattern::Steps(DHCP),
+        State::BrokerConnecting => Pattern::Steps(BROKER_CONNECTING),
+        State::Running => Pattern::Steps(RUNNING),
+        State::Ota => Pattern::Steps(OTA),
+        State::Error(code) => Pattern::Code(code.blinks()),
+    }
+}
+
+impl Pattern {
+    /// Steps in one period.
+    pub fn step_count(&self) -> usize {
+        match *self {
+            Self::Steps(steps) => steps.len(),
+            Self::Code(n) => 2 * n.max(1) as usize,
+        }
+    }
+
+    /// Step `index`, wrapping around at the end of the period.
+    pub fn step(&self, index: usize) -> Step {
+        let index = index % self.step_count();
+        match *self {
+            Self::Steps(steps) => steps[index],
+            Self::Code(_) if index.is_multiple_of(2) => on(CODE_ON_MS),
+            Self::Code(_) if index + 1 == self.step_count() => off(CODE_PAUSE_MS),
+            Self::Code(_) => off(CODE_OFF_MS),
+        }
+    }
+
+    pub fn period_ms(&self) -> u32 {
+        (0..self.step_count()).map(|i| self.step(i).ms).sum()
+    }
+
+    /// Whether the LED is on `ms` after the period started (wraps around).
+    pub fn is_on_at(&self, ms: u32) -> bool {
+        let mut ms = ms % self.period_ms().max(1);
+        for i in 0..self.step_count() {
+            let step = self.step(i);
+            if ms < step.ms {
+                return step.on;
+            }
+            ms -= step.ms;
+        }
+        false
+    }
+}
+
+/// Steps through the pattern of the current state. The caller shows
+/// `step()`, waits for its duration and calls `advance()`; state changes
+/// come in through `set_state()`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Indicator {
+    state: State,
+    /// Shown once the current error has been shown often enough.
+    next: Option<State>,
+    step: usize,
+    /// Completed periods of the current state.
+    repeats: u8,
+}
+
+impl Indicator {
+    pub const fn new(state: State) -> Self {
+        Self { state, next: None, step: 0, repeats: 0 }
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Takes a new system state. An error interrupts the current state, which
+    /// comes back after the error unless a newer one arrived; an error that
+    /// is raised again keeps showing. Returns whether the pattern changed,
+    /// so the caller starts the new one right away.
+    pub fn set_state(&mut self, state: State) -> bool {
+        match (self.state, state) {
+            (current, new) if current == new => {
+                self.repeats = 0;
+                false
+            }
+            (State::Error(_), State::Error(_)) => {
+                self.switch(state);
+                true
+            }
+            (State::Error(_), _) if self.repeats < ERROR_REPEATS => {
+                self.next = Some(state);
+                false
+            }
+            (current, State::Error(_)) => {
+                let next = self.next.take().unwrap_or(current);
+                self.switch(state);
+                self.next = Some(next);
+                true
+            }
+            _ => {
+                self.switch(state);
+                self.next = None;
+                true
+            }
+        }
+    }
+
+    fn switch(&mut self, state: State) {
+        self.state = state;
+        self.step = 0;
+        self.repeats = 0;
+    }
+
+    /// The state whose pattern is shown: a firmware update in progress wins
+    /// over everything but provisioning.
+    pub fn shown(&self, ota_busy: bool) -> State {
+        match self.state {
+            State::Provisioning => State::Provisioning,
+            _ if ota_busy => State::Ota,
+            state => state,
+        }
+    }
+
+    /// The step to show now.
+    pub fn step(&self, ota_busy: bool) -> Step {
+        pattern(self.shown(ota_busy)).step(self.step)
+    }
+
+    /// Moves on to the next step once the current one has been shown.
+    pub fn advance(&mut self, ota_busy: bool) {
+        self.step += 1;
+        if self.step < pattern(self.shown(ota_busy)).step_count() {
+            return;
+        }
+        self.step = 0;
+        self.repeats = self.repeats.saturating_add(1);
+        if matches!(self.state, State::Error(_))
+            && self.repeats >= ERROR_REPEATS
+            && let Some(next) = self.next.take()
+        {
+            self.switch(next);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Advances through `periods` whole periods of what is shown.
+    fn run_periods(indicator: &mut Indicator, periods: u8, ota_busy: bool) {
+        for _ in 0..periods {
+            let steps = pattern(indicator.shown(ota_busy)).step_count();
+            for _ in 0..steps {
+                indicator.advance(ota_busy);
+            }
+        }
+    }
+
+    #[test]
+    fn error_shows_for_error_repeats_periods() {
+        let mut indicator = Indicator::new(State::Running);
+        assert!(indicator.set_state(State::Error(ErrorCode::Dns)));
+        // Back to normal while the error is still showing: queued
+        assert!(!indicator.set_state(State::Running));
+
+        run_periods(&mut indicator, ERROR_REPEATS - 1, false);
+        let steps = pattern(indicator.state()).step_count();
+        for _ in 0..steps - 1 {
+            indicator.advance(false);
+        }
+        assert_eq!(indicator.state(), State::Error(ErrorCode::Dns));
+        indicator.advance(false);
+        assert_eq!(indicator.state(), State::Running);
+        assert_eq!(indicator.step(false), RUNNING[0]);
+    }
+
+    #[test]
+    fn interrupted_state_comes_back() {
+        let mut indicator = Indicator::new(State::BrokerConnecting);
+        indicator.set_state(State::Error(ErrorCode::Broker));
+        run_periods(&mut indicator, ERROR_REPEATS, false);
+        assert_eq!(indicator.state(), State::BrokerConnecting);
+    }
+
+    #[test]
+    fn newer_state_replaces_the_interrupted_one() {
+        let mut indicator = Indicator::new(State::WifiConnecting);
+        indicator.set_state(State::Error(ErrorCode::Wifi));
+        indicator.set_state(State::WifiConnecting);
+        indicator.set_state(State::Dhcp);
+        run_periods(&mut indicator, ERROR_REPEATS, false);
+        assert_eq!(indicator.state(), State::Dhcp);
+    }
+
+    #[test]
+    fn repeated_error_keeps_showing() {
+        let mut indicator = Indicator::new(State::Running);
+        indicator.set_state(State::Error(ErrorCode::Sensor));
+        run_periods(&mut indicator, ERROR_REPEATS - 1, false);
+        assert!(!indicator.set_state(State::Error(ErrorCode::Sensor)));
+        run_periods(&mut indicator, ERROR_REPEATS - 1, false);
+        assert_eq!(indicator.state(), State::Error(ErrorCode::Sensor));
+        run_periods(&mut indicator, 1, false);
+        assert_eq!(indicator.state(), State::Running);
+    }
+
+    #[test]
+    fn another_error_shows_right_away() {
+        let mut indicator = Indicator::new(State::Running);
+        indicator.set_state(State::Error(ErrorCode::Dns));
+        indicator.advance(false);
+        assert!(indicator.set_state(State::Error(ErrorCode::Broker)));
+        assert_eq!(indicator.step(false), on(CODE_ON_MS));
+        run_periods(&mut indicator, ERROR_REPEATS, false);
+        assert_eq!(indicator.state(), State::Running);
+    }
+
+    #[test]
+    fn other_states_switch_right_away() {
+        let mut indicator = Indicator::new(State::WifiConnecting);
+        indicator.advance(false);
+        assert!(indicator.set_state(State::Dhcp));
+        assert_eq!(indicator.step(false), DHCP[0]);
+        assert!(!indicator.set_state(State::Dhcp));
+    }
+
+    #[test]
+    fn update_wins_over_everything_but_provisioning() {
+        assert_eq!(Indicator::new(State::Provisioning).shown(true), State::Provisioning);
+        assert_eq!(Indicator::new(State::Running).shown(true), State::Ota);
+        assert_eq!(Indicator::new(State::Error(ErrorCode::Broker)).shown(true), State::Ota);
+        assert_eq!(Indicator::new(State::Running).shown(false), State::Running);
+        assert_eq!(Indicator::new(State::Provisioning).step(true), PROVISIONING[0]);
+        assert_eq!(Indicator::new(State::Running).step(true), OTA[0]);
+    }
+
+    #[test]
+    fn code_steps() {
+        let code = Pattern::Code(3);
+        assert_eq!(code.step_count(), 6);
+        let steps: Vec<Step> = (0..6).map(|i| code.step(i)).collect();
+        assert_eq!(
+            steps,
+            [on(CODE_ON_MS), off(CODE_OFF_MS), on(CODE_ON_MS), off(CODE_OFF_MS), on(CODE_ON_MS), off(CODE_PAUSE_MS)]
+        );
+        // Wraps around
+        assert_eq!(code.step(6), on(CODE_ON_MS));
+        assert_eq!(code.step(11), off(CODE_PAUSE_MS));
+        // A code of 0 still blinks once
+        assert_eq!(Pattern::Code(0).step_count(), 2);
+    }
+
+    #[test]
+    fn period_timing() {
+        assert_eq!(Pattern::Code(3).period_ms(), 3 * CODE_ON_MS + 2 * CODE_OFF_MS + CODE_PAUSE_MS);
+        assert_eq!(pattern(State::Running).period_ms(), 3000);
+        assert_eq!(IDENTIFY.period_ms(), 100);
+
+        let code = Pattern::Code(2);
+        assert!(code.is_on_at(0));
+        assert!(code.is_on_at(CODE_ON_MS - 1));
+        assert!(!code.is_on_at(CODE_ON_MS));
+        assert!(code.is_on_at(CODE_ON_MS + CODE_OFF_MS));
+        assert!(!code.is_on_at(2 * CODE_ON_MS + CODE_OFF_MS));
+        assert!(code.is_on_at(code.period_ms()));
+    }
+
+    #[test]
+    fn codes_are_distinct_and_not_a_heartbeat() {
+        let codes = [ErrorCode::Wifi, ErrorCode::Dhcp, ErrorCode::Dns, ErrorCode::Broker, ErrorCode::Sensor];
+        for (i, a) in codes.iter().enumerate() {
+            assert!(a.blinks() >= 2);
+            assert!(codes[i + 1..].iter().all(|b| b.blinks() != a.blinks()));
+        }
+    }
+}
@@ -0,0 +1,456 @@
+use core::net::Ipv6Addr;
+use heapless::Vec;
+
+// --- IPv6 Neighbour Discovery and DHCPv6 Messages ---
+// The packets the firmware's IPv6 autoconfiguration (`src/ipv6.rs`) sends and
+// parses: Router Solicitations and Advertisements (RFC 4861) with the prefix
+// and RDNSS options, and stateless DHCPv6 Information-Request / Reply
+// (RFC 8415). Everything here works on byte slices; sockets are up to the
+// firmware.
+
+pub const ICMPV6_ROUTER_SOLICIT: u8 = 133;
+pub const ICMPV6_ROUTER_ADVERT: u8 = 134;
+
+const NDISC_OPT_PREFIX_INFO: u8 = 3;
+const NDISC_OPT_RDNSS: u8 = 25;
+
+pub const DHCPV6_CLIENT_PORT: u16 = 546;
+pub const DHCPV6_SERVER_PORT: u16 = 547;
+const DHCPV6_REPLY: u8 = 7;
+const DHCPV6_INFORMATION_REQUEST: u8 = 11;
+const DHCPV6_OPT_CLIENTID: u16 = 1;
+const DHCPV6_OPT_ORO: u16 = 6;
+const DHCPV6_OPT_ELAPSED_TIME: u16 = 8;
+const DHCPV6_OPT_DNS_SERVERS: u16 = 23;
+
+pub const ALL_ROUTERS: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 2);
+pub const ALL_DHCP_AGENTS: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 1, 2);
+
+/// Length of a Router Solicitation packet, IPv6 header included.
+pub const ROUTER_SOLICIT_LEN: usize = 48;
+
+/// Length of the DHCPv6 Information-Request.
+pub const INFORMATION_REQUEST_LEN: usize = 30;
+
+/// The parts of a Router Advertisement we care about.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RouterAdvert {
+    pub router: Ipv6Addr,
+    pub router_lifetime_secs: u16,
+    /// "Managed" flag: addresses come from stateful DHCPv6.
+    pub managed: bool,
+    /// "Other" flag: other configuration (e.g. DNS) is available via DHCPv6.
+    pub other: bool,
+    /// First on-link /64 prefix with the autonomous (SLAAC) flag set.
+    pub prefix: Option<Ipv6Addr>,
+    /// Valid lifetime of `prefix`; `u32::MAX` is infinite.
+    pub prefix_valid_secs: u32,
+    /// The first RDNSS servers; any beyond three are ignored.
+    pub dns_servers: Vec<Ipv6Addr, 3>,
+}
+
+/// Builds the modified EUI-64 interface identifier from a MAC address.
+pub fn eui64_interface_id(mac: [u8; 6]) -> [u8; 8] {
+    [mac[0] ^ 0x02, mac[1], mac[2], 0xff, 0xfe, mac[3], mac[4], mac[5]]
+}
+
+/// Combines a /64 prefix with the EUI-64 interface identifier.
+pub fn slaac_address(prefix: &Ipv6Addr, mac: [u8; 6]) -> Ipv6Addr {
+    let mut octets = prefix.octets();
+    octets[8..].copy_from_slice(&eui64_interface_id(mac));
+    Ipv6Addr::from(octets)
+}
+
+/// The link-local (fe80::/64) address for a MAC address.
+pub fn link_local_address(mac: [u8; 6]) -> Ipv6Addr {
+    slaac_address(&Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 0), mac)
+}
+
+/// Computes the ICMPv6 checksum over the IPv6 pseudo-header and `message`.
+pub fn icmpv6_checksum(src: &Ipv6Addr, dst: &Ipv6Addr, message: &[u8]) -> u16 {
+    let mut sum: u32 = 0;
+    let mut add = |bytes: &[u8]| {
+        for chunk in bytes.chunks(2) {
+            let word = if chunk.len() == 2 { u16::from_be_bytes([chunk[0], chunk[1]]) } else { u16::from(chunk[0]) << 8 };
+            sum += u32::from(word);
+        }
+    };
+    add(&src.octets());
+    add(&dst.octets());
+    add(&(message.len() as u32).to_be_bytes());
+    add(&[0, 0, 0, 58]); // Next Header: ICMPv6
+    add(message);
+
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Builds a complete IPv6 packet carrying a Router Solicitation.
+/// Returns the packet length.
+pub fn build_router_solicit(src: &Ipv6Addr, out: &mut [u8; ROUTER_SOLICIT_LEN]) -> usize {
+    // IPv6 Header (40 bytes)
+    out[0] = 0x60; // Version 6
+    out[4..6].copy_from_slice(&8u16.to_be_bytes()); // Payload length
+    out[6] = 58; // Next Header: ICMPv6
+    out[7] = 255; // Hop Limit (required for ND)
+    out[8..24].copy_from_slice(&src.octets());
+    out[24..40].copy_from_slice(&ALL_ROUTERS.octets());
+
+    // ICMPv6 Router Solicitation (8 bytes): Type, Code, Checksum, Reserved
+    out[40] = ICMPV6_ROUTER_SOLICIT;
+    let checksum = icmpv6_checksum(src, &ALL_ROUTERS, &out[40..48]);
+    out[42..44].copy_from_slice(&checksum.to_be_bytes());
+    ROUTER_SOLICIT_LEN
+}
+
+/// Parses a raw IPv6 packet and returns the Router Advertisement it carries, if any.
+pub fn parse_router_advert(packet: &[u8]) -> Option<RouterAdvert> {
+    // IPv6 header: next header must be ICMPv6 (no extension headers expected for ND)
+    if packet.len() < 40 + 16 || packet[0] >> 4 != 6 || packet[6] != 58 {
+        return None;
+    }
+    let mut router = [0u8; 16];
+    router.copy_from_slice(&packet[8..24]);
+
+    let icmp = &packet[40..];
+    if icmp[0] != ICMPV6_ROUTER_ADVERT || icmp[1] != 0 {
+        return None;
+    }
+
+    let mut ra = RouterAdvert {
+        router: Ipv6Addr::from(router),
+        router_lifetime_secs: u16::from_be_bytes([icmp[6], icmp[7]]),
+        managed: icmp[5] & 0x80 != 0,
+        other: icmp[5] & 0x40 != 0,
+        prefix: None,
+        prefix_valid_secs: 0,
+        dns_servers: Vec::new(),
+    };
+
+    // Options follow the 16-byte RA header, each is `len * 8` bytes long
+    let mut idx = 16;
+    while idx + 2 <= icmp.len() {
+        let kind = icmp[idx];
+        let len = icmp[idx + 1] as usize * 8;
+        if len == 0 || idx + len > icmp.len() {
+            break;
+        }
+        let opt = &icmp[idx..idx + len];
+
+        match kind {
+            NDISC_OPT_PREFIX_INFO if len == 32 && ra.prefix.is_none() => {
+                let prefix_len = opt[2];
+                let autonomous = opt[3] & 0x40 != 0;
+                let valid_lifetime = u32::from_be_bytes([opt[4], opt[5], opt[6], opt[7]]);
+                // SLAAC only works with /64 prefixes
+                if autonomous && prefix_len == 64 && valid_lifetime > 0 {
+                    let mut prefix = [0u8; 16];
+                    prefix[..8].copy_from_slice(&opt[16..24]);
+                    ra.prefix = Some(Ipv6Addr::from(prefix));
+                    ra.prefix_valid_secs = valid_lifetime;
+                }
+            }
+            NDISC_OPT_RDNSS if len >= 24 => {
+                for server in opt[8..].as_chunks::<16>().0 {
+                    let _ = ra.dns_servers.push(Ipv6Addr::from(*server));
+                }
+            }
+            _ => {}
+        }
+        idx += len;
+    }
+
+    Some(ra)
+}
+
+/// Builds a DHCPv6 Information-Request asking for DNS servers.
+/// Returns the message length.
+pub fn build_information_request(transaction_id: [u8; 3], mac: [u8; 6], out: &mut [u8; INFORMATION_REQUEST_LEN]) -> usize {
+    let mut idx = 0;
+    out[idx] = DHCPV6_INFORMATION_REQUEST; idx += 1;
+    out[idx..idx + 3].copy_from_slice(&transaction_id); idx += 3;
+
+    // Client Identifier: DUID-LL (type 3), hardware type 1 (Ethernet), MAC
+    out[idx..idx + 2].copy_from_slice(&DHCPV6_OPT_CLIENTID.to_be_bytes()); idx += 2;
+    out[idx..idx + 2].copy_from_slice(&10u16.to_be_bytes()); idx += 2;
+    out[idx..idx + 2].copy_from_slice(&3u16.to_be_bytes()); idx += 2;
+    out[idx..idx + 2].copy_from_slice(&1u16.to_be_bytes()); idx += 2;
+    out[idx..idx + 6].copy_from_slice(&mac); idx += 6;
+
+    // Option Request: DNS Recursive Name Servers
+    out[idx..idx + 2].copy_from_slice(&DHCPV6_OPT_ORO.to_be_bytes()); idx += 2;
+    out[idx..idx + 2].copy_from_slice(&2u16.to_be_bytes()); idx += 2;
+    out[idx..idx + 2].copy_from_slice(&DHCPV6_OPT_DNS_SERVERS.to_be_bytes()); idx += 2;
+
+    // Elapsed Time: 0
+    out[idx..idx + 2].copy_from_slice(&DHCPV6_OPT_ELAPSED_TIME.to_be_bytes()); idx += 2;
+    out[idx..idx + 2].copy_from_slice(&2u16.to_be_bytes()); idx += 2;
+    out[idx..idx + 2].copy_from_slice(&0u16.to_be_bytes()); idx += 2;
+
+    idx
+}
+
+/// Extracts DNS servers from a DHCPv6 Reply matching `transaction_id`.
+pub fn parse_dhcpv6_dns(reply: &[u8], transaction_id: [u8; 3]) -> Option<Vec<Ipv6Addr, 3>> {
+    if reply.len() < 4 || reply[0] != DHCPV6_REPLY || reply[1..4] != transaction_id {
+        return None;
+    }
+
+    let mut servers = Vec::new();
+    let mut idx = 4;
+    while idx + 4 <= reply.len() {
+        let code = u16::from_be_bytes([reply[idx], reply[idx + 1]]);
+        let len = u16::from_be_bytes([reply[idx + 2], reply[idx + 3]]) as usize;
+        idx += 4;
+        if idx + len > reply.len() {
+            break;
+        }
+        if code == DHCPV6_OPT_DNS_SERVERS {
+            for server in reply[idx..idx + len].as_chunks::<16>().0 {
+                let _ = servers.push(Ipv6Addr::from(*server));
+            }
+        }
+        idx += len;
+    }
+    Some(servers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec as StdVec;
+
+    const MAC: [u8; 6] = [0x34, 0x85, 0x18, 0x01, 0x02, 0x03];
+
+    fn addr(s: &str) -> Ipv6Addr {
+        s.parse().unwrap()
+    }
+
+    /// IPv6 header plus RA header from fe80::1, followed by `options`.
+    fn router_advert(flags: u8, lifetime: u16, options: &[u8]) -> StdVec<u8> {
+        let mut packet = vec![0u8; 56];
+        packet[0] = 0x60;
+        packet[4..6].copy_from_slice(&((16 + options.len()) as u16).to_be_bytes());
+        packet[6] = 58;
+        packet[7] = 255;
+        packet[8..24].copy_from_slice(&addr("fe80::1").octets());
+        packet[24..40].copy_from_slice(&addr("ff02::1").octets());
+        packet[40] = ICMPV6_ROUTER_ADVERT;
+        packet[44] = 64; // Cur Hop Limit
+        packet[45] = flags;
+        packet[46..48].copy_from_slice(&lifetime.to_be_bytes());
+        packet.extend_from_slice(options);
+        packet
+    }
+
+    fn prefix_option(prefix: &str, len: u8, flags: u8, valid: u32) -> StdVec<u8> {
+        let mut opt = vec![NDISC_OPT_PREFIX_INFO, 4, len, flags];
+        opt.extend_from_slice(&valid.to_be_bytes());
+        opt.extend_from_slice(&valid.to_be_bytes()); // preferred
+        opt.extend_from_slice(&[0; 4]);
+        opt.extend_from_slice(&addr(prefix).octets());
+        opt
+    }
+
+    fn rdnss_option(servers: &[&str]) -> StdVec<u8> {
+        let mut opt = vec![NDISC_OPT_RDNSS, (1 + 2 * servers.len()) as u8, 0, 0];
+        opt.extend_from_slice(&600u32.to_be_bytes());
+        for server in servers {
+            opt.extend_from_slice(&addr(server).octets());
+        }
+        opt
+    }
+
+    #[test]
+    fn eui64_flips_the_universal_local_bit() {
+        assert_eq!(eui64_interface_id(MAC), [0x36, 0x85, 0x18, 0xff, 0xfe, 0x01, 0x02, 0x03]);
+        // A locally administered MAC gets the bit cleared
+        assert_eq!(eui64_interface_id([0x02, 0, 0, 0, 0, 1])[0], 0x00);
+        assert_eq!(link_local_address(MAC), addr("fe80::3685:18ff:fe01:203"));
+        assert_eq!(slaac_address(&addr("2001:db8:1:2::"), MAC), addr("2001:db8:1:2:3685:18ff:fe01:203"));
+    }
+
+    #[test]
+    fn checksum_matches_rfc_1071() {
+        // The RFC 1071 example words sum to 0xddf2; the pseudo-header of an
+        // 8-byte ICMPv6 message between unspecified addresses adds 8 + 58
+        let message = [0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7];
+        assert_eq!(icmpv6_checksum(&Ipv6Addr::UNSPECIFIED, &Ipv6Addr::UNSPECIFIED, &message), !(0xddf2u16 + 8 + 58));
+        // Odd lengths are padded with a zero byte
+        assert_eq!(icmpv6_checksum(&Ipv6Addr::UNSPECIFIED, &Ipv6Addr::UNSPECIFIED, &[0x01]), !(0x0100u16 + 1 + 58));
+    }
+
+    #[test]
+    fn router_solicit_carries_a_valid_checksum() {
+        let src = link_local_address(MAC);
+        let mut out = [0u8; ROUTER_SOLICIT_LEN];
+        assert_eq!(build_router_solicit(&src, &mut out), ROUTER_SOLICIT_LEN);
+        assert_eq!(out[40], ICMPV6_ROUTER_SOLICIT);
+        assert_eq!(&out[24..40], &ALL_ROUTERS.octets());
+        // Summing a message that includes its checksum gives zero
+        assert_eq!(icmpv6_checksum(&src, &ALL_ROUTERS, &out[40..]), 0);
+    }
+
+    #[test]
+    fn parses_a_router_advert() {
+        let mut options = prefix_option("2001:db8:1:2::", 64, 0xC0, 86400);
+        options.extend(rdnss_option(&["2001:db8::53"]));
+        let ra = parse_router_advert(&router_advert(0x40, 1800, &options)).unwrap();
+        assert_eq!(ra.router, addr("fe80::1"));
+        assert_eq!(ra.router_lifetime_secs, 1800);
+        assert!(!ra.managed);
+        assert!(ra.other);
+        assert_eq!(ra.prefix, Some(addr("2001:db8:1:2::")));
+        assert_eq!(ra.prefix_valid_secs, 86400);
+        assert_eq!(ra.dns_servers.as_slice(), &[addr("2001:db8::53")]);
+    }
+
+    #[test]
+    fn prefix_bits_past_64_are_ignored() {
+        let options = prefix_option("2001:db8:1:2:ffff::", 64, 0xC0, 86400);
+        let ra = parse_router_advert(&router_advert(0, 1800, &options)).unwrap();
+        assert_eq!(ra.prefix, Some(addr("2001:db8:1:2::")));
+    }
+
+    #[test]
+    fn only_autonomous_64_prefixes_are_used() {
+        for options in [
+            prefix_option("2001:db8:1::", 48, 0xC0, 86400),
+            prefix_option("2001:db8:1:2::", 80, 0xC0, 86400),
+            prefix_option("2001:db8:1:2::", 64, 0x80, 86400), // not autonomous
+            prefix_option("2001:db8:1:2::", 64, 0xC0, 0),     // expired
+        ] {
+            let ra = parse_router_advert(&router_advert(0, 1800, &options)).unwrap();
+            assert_eq!(ra.prefix, None);
+        }
+        // A later usable prefix is still taken
+        let mut options = prefix_option("2001:db8:1::", 48, 0xC0, 86400);
+        options.extend(prefix_option("2001:db8:1:2::", 64, 0xC0, 3600));
+        let ra = parse_router_advert(&router_advert(0, 1800, &options)).unwrap();
+        assert_eq!(ra.prefix, Some(addr("2001:db8:1:2::")));
+        assert_eq!(ra.prefix_valid_secs, 3600);
+    }
+
+    #[test]
+    fn keeps_the_first_three_rdnss_servers() {
+        let options = rdnss_option(&["2001:db8::1", "2001:db8::2", "2001:db8::3", "2001:db8::4"]);
+        let ra = parse_router_advert(&router_advert(0, 1800, &options)).unwrap();
+        assert_eq!(ra.dns_servers.as_slice(), &[addr("2001:db8::1"), addr("2001:db8::2"), addr("2001:db8::3")]);
+    }
+
+    #[test]
+    fn stops_at_zero_length_and_truncated_options() {
+        // A zero length would otherwise never advance
+        let mut options = vec![NDISC_OPT_RDNSS, 0, 0, 0, 0, 0, 0, 0];
+        options.extend(prefix_option("2001:db8:1:2::", 64, 0xC0, 86400));
+        let ra = parse_router_advert(&router_advert(0, 1800, &options)).unwrap();
+        assert_eq!(ra.prefix, None);
+
+        // The prefix option claims 32 bytes but the packet ends early
+        let mut options = prefix_option("2001:db8:1:2::", 64, 0xC0, 86400);
+        options.truncate(20);
+        let ra = parse_router_advert(&router_advert(0, 1800, &options)).unwrap();
+        assert_eq!(ra.prefix, None);
+
+        // A lone option type byte
+        assert!(parse_router_advert(&router_advert(0, 1800, &[NDISC_OPT_PREFIX_INFO])).is_some());
+        // Options of the wrong size are skipped, not misread
+        let options = [NDISC_OPT_PREFIX_INFO, 1, 64, 0xC0, 0, 0, 0, 1];
+        assert_eq!(parse_router_advert(&router_advert(0, 1800, &options)).unwrap().prefix, None);
+    }
+
+    #[test]
+    fn rejects_other_packets() {
+        let packet = router_advert(0, 1800, &[]);
+        assert!(parse_router_advert(&packet).is_some());
+        assert!(parse_router_advert(&packet[..55]).is_none());
+        assert!(parse_router_advert(&[]).is_none());
+
+        let mut not_v6 = packet.clone();
+        not_v6[0] = 0x45;
+        assert!(parse_router_advert(&not_v6).is_none());
+        let mut not_icmp = packet.clone();
+        not_icmp[6] = 17;
+        assert!(parse_router_advert(&not_icmp).is_none());
+        let mut solicit = packet.clone();
+        solicit[40] = ICMPV6_ROUTER_SOLICIT;
+        assert!(parse_router_advert(&solicit).is_none());
+        let mut bad_code = packet;
+        bad_code[41] = 1;
+        assert!(parse_router_advert(&bad_code).is_none());
+    }
+
+    #[test]
+    fn builds_the_information_request() {
+        let mut out = [0u8; INFORMATION_REQUEST_LEN];
+        assert_eq!(build_information_request([0xAB, 0xCD, 0xEF], MAC, &mut out), INFORMATION_REQUEST_LEN);
+        assert_eq!(out[..4], [DHCPV6_INFORMATION_REQUEST, 0xAB, 0xCD, 0xEF]);
+        // Client ID: option 1, 10 bytes, DUID-LL, Ethernet, MAC
+        assert_eq!(out[4..18], [0, 1, 0, 10, 0, 3, 0, 1, 0x34, 0x85, 0x18, 0x01, 0x02, 0x03]);
+        // Option Request for DNS servers, then Elapsed Time 0
+        assert_eq!(out[18..], [0, 6, 0, 2, 0, 23, 0, 8, 0, 2, 0, 0]);
+    }
+
+    fn dhcpv6_reply(transaction_id: [u8; 3], options: &[u8]) -> StdVec<u8> {
+        let mut reply = vec![DHCPV6_REPLY];
+        reply.extend_from_slice(&transaction_id);
+        reply.extend_from_slice(options);
+        reply
+    }
+
+    fn dns_option(servers: &[&str]) -> StdVec<u8> {
+        let mut opt = DHCPV6_OPT_DNS_SERVERS.to_be_bytes().to_vec();
+        opt.extend_from_slice(&((16 * servers.len()) as u16).to_be_bytes());
+        for server in servers {
+            opt.extend_from_slice(&addr(server).octets());
+        }
+        opt
+    }
+
+    #[test]
+    fn parses_dns_servers_from_the_reply() {
+        // A server identifier first, which is skipped
+        let mut options = vec![0, 2, 0, 4, 1, 2, 3, 4];
+        options.extend(dns_option(&["2001:db8::53", "2001:db8::54"]));
+        let servers = parse_dhcpv6_dns(&dhcpv6_reply([1, 2, 3], &options), [1, 2, 3]).unwrap();
+        assert_eq!(servers.as_slice(), &[addr("2001:db8::53"), addr("2001:db8::54")]);
+
+        let options = dns_option(&["2001:db8::1", "2001:db8::2", "2001:db8::3", "2001:db8::4"]);
+        let servers = parse_dhcpv6_dns(&dhcpv6_reply([1, 2, 3], &options), [1, 2, 3]).unwrap();
+        assert_eq!(servers.len(), 3);
+    }
+
+    #[test]
+    fn ignores_replies_to_other_requests() {
+        let options = dns_option(&["2001:db8::53"]);
+        assert_eq!(parse_dhcpv6_dns(&dhcpv6_reply([1, 2, 4], &options), [1, 2, 3]), None);
+        let mut advertise = dhcpv6_reply([1, 2, 3], &options);
+        advertise[0] = 2;
+        assert_eq!(parse_dhcpv6_dns(&advertise, [1, 2, 3]), None);
+        assert_eq!(parse_dhcpv6_dns(&[DHCPV6_REPLY, 1, 2], [1, 2, 3]), None);
+    }
+
+    #[test]
+    fn survives_zero_length_and_truncated_dhcpv6_options() {
+        // Zero-length options still advance past their header
+        let mut options = vec![0, 99, 0, 0, 0, 99, 0, 0];
+        options.extend(dns_option(&["2001:db8::53"]));
+        let servers = parse_dhcpv6_dns(&dhcpv6_reply([1, 2, 3], &options), [1, 2, 3]).unwrap();
+        assert_eq!(servers.as_slice(), &[addr("2001:db8::53")]);
+
+        // The DNS option claims more than arrived
+        let mut options = dns_option(&["2001:db8::53"]);
+        options.truncate(12);
+        assert!(parse_dhcpv6_dns(&dhcpv6_reply([1, 2, 3], &options), [1, 2, 3]).unwrap().is_empty());
+        // A partial option header
+        assert!(parse_dhcpv6_dns(&dhcpv6_reply([1, 2, 3], &[0, 23, 0]), [1, 2, 3]).unwrap().is_empty());
+        // Lengths that are not a multiple of 16 drop the remainder
+        let mut options = dns_option(&["2001:db8::53"]);
+        options[3] = 20;
+        options.extend_from_slice(&[0; 4]);
+        let servers = parse_dhcpv6_dns(&dhcpv6_reply([1, 2, 3], &options), [1, 2, 3]).unwrap();
+        assert_eq!(servers.as_slice(), &[addr("2001:db8::53")]);
+    }
+}
@@ -0,0 +1,181 @@
+use serde::{Deserialize, Serialize};
+
+use crate::indicator::State;
+
+// --- Status LED ---
+// GPIO8 drives either a plain LED or an addressable RGB LED (WS2812), as
+// selected by `led_board`. The blink patterns are the same on both; the RGB
+// LED also shows a colour per state, and while running a gradient from the
+// latest temperature, blue at `led_temp_cold` through green to red at
+// `led_temp_hot`. The driver is in the firmware (`src/led.rs`).
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum LedBoard {
+    /// Plain LED, lit when the pin is high.
+    Gpio,
+    /// Plain LED between 3.3 V and the pin, lit when the pin is low.
+    GpioInverted,
+    /// Addressable RGB LED, e.g. on the ESP32-C3-DevKitM-1.
+    Ws2812,
+}
+
+impl LedBoard {
+    /// Parses the names used in `config.json`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "gpio" => Some(Self::Gpio),
+            "gpio_inverted" => Some(Self::GpioInverted),
+            "ws2812" => Some(Self::Ws2812),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Gpio => "gpio",
+            Self::GpioInverted => "gpio_inverted",
+            Self::Ws2812 => "ws2812",
+        }
+    }
+}
+
+/// Brightness in percent; the LEDs are blinding at full power.
+pub fn is_valid_brightness(percent: u8) -> bool {
+    (1..=100).contains(&percent)
+}
+
+/// The gradient needs a range to spread over.
+pub fn is_valid_temp_range(cold: f32, hot: f32) -> bool {
+    cold.is_finite() && hot.is_finite() && cold < hot
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const OFF: Self = Self::new(0, 0, 0);
+    pub const WHITE: Self = Self::new(255, 255, 255);
+    pub const RED: Self = Self::new(255, 0, 0);
+    pub const GREEN: Self = Self::new(0, 255, 0);
+    pub const BLUE: Self = Self::new(0, 0, 255);
+
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    /// Scaled to `percent` of full brightness.
+    pub fn dimmed(self, percent: u8) -> Self {
+        let scale = |c: u8| (c as u16 * percent.min(100) as u16 / 100) as u8;
+        Self::new(scale(self.r), scale(self.g), scale(self.b))
+    }
+}
+
+/// Blue at `cold`, green halfway, red at `hot`; clamped outside the range.
+pub fn gradient(celsius: f32, cold: f32, hot: f32) -> Rgb {
+    let t = ((celsius - cold) / (hot - cold)).clamp(0.0, 1.0);
+    if t.is_nan() {
+        return Rgb::GREEN;
+    }
+    let ramp = |x: f32| (x * 255.0) as u8;
+    if t < 0.5 {
+        Rgb::new(0, ramp(2.0 * t), ramp(1.0 - 2.0 * t))
+    } else {
+        Rgb::new(ramp(2.0 * t - 1.0), ramp(2.0 - 2.0 * t), 0)
+    }
+}
+
+/// Colour of the RGB LED while a state's pattern is on. `Running` follows
+/// the temperature, once there is one.
+pub fn colour(state: State, temperature: Option<f32>, cold: f32, hot: f32) -> Rgb {
+    match state {
+        State::Provisioning => Rgb::BLUE,
+        State::WifiConnecting => Rgb::new(255, 120, 0), // amber
+        State::Dhcp => Rgb::new(0, 200, 255),           // cyan
+        State::BrokerConnecting => Rgb::new(160, 0, 255), // purple
+        State::Running => temperature.map_or(Rgb::GREEN, |t| gradient(t, cold, hot)),
+        State::Ota => Rgb::WHITE,
+        State::Error(_) => Rgb::RED,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indicator::ErrorCode;
+
+    #[test]
+    fn gradient_ends_and_middle() {
+        assert_eq!(gradient(10.0, 10.0, 30.0), Rgb::BLUE);
+        assert_eq!(gradient(20.0, 10.0, 30.0), Rgb::GREEN);
+        assert_eq!(gradient(30.0, 10.0, 30.0), Rgb::RED);
+    }
+
+    #[test]
+    fn gradient_in_between() {
+        assert_eq!(gradient(15.0, 10.0, 30.0), Rgb::new(0, 127, 127));
+        assert_eq!(gradient(25.0, 10.0, 30.0), Rgb::new(127, 127, 0));
+    }
+
+    #[test]
+    fn gradient_clamps_outside_the_range() {
+        assert_eq!(gradient(-40.0, 10.0, 30.0), Rgb::BLUE);
+        assert_eq!(gradient(85.0, 10.0, 30.0), Rgb::RED);
+        assert_eq!(gradient(f32::INFINITY, 10.0, 30.0), Rgb::RED);
+        assert_eq!(gradient(f32::NEG_INFINITY, 10.0, 30.0), Rgb::BLUE);
+    }
+
+    #[test]
+    fn gradient_of_nan_is_green() {
+        assert_eq!(gradient(f32::NAN, 10.0, 30.0), Rgb::GREEN);
+    }
+
+    #[test]
+    fn colour_per_state() {
+        assert_eq!(colour(State::Provisioning, Some(30.0), 10.0, 30.0), Rgb::BLUE);
+        assert_eq!(colour(State::Ota, None, 10.0, 30.0), Rgb::WHITE);
+        assert_eq!(colour(State::Error(ErrorCode::Wifi), Some(10.0), 10.0, 30.0), Rgb::RED);
+        let connecting = [State::WifiConnecting, State::Dhcp, State::BrokerConnecting].map(|s| colour(s, None, 10.0, 30.0));
+        assert!(connecting.iter().all(|c| ![Rgb::OFF, Rgb::RED, Rgb::GREEN, Rgb::BLUE, Rgb::WHITE].contains(c)));
+        assert!(connecting[0] != connecting[1] && connecting[1] != connecting[2] && connecting[0] != connecting[2]);
+    }
+
+    #[test]
+    fn running_follows_the_temperature() {
+        assert_eq!(colour(State::Running, None, 10.0, 30.0), Rgb::GREEN);
+        assert_eq!(colour(State::Running, Some(10.0), 10.0, 30.0), Rgb::BLUE);
+        assert_eq!(colour(State::Running, Some(30.0), 10.0, 30.0), Rgb::RED);
+    }
+
+    #[test]
+    fn dims() {
+        assert_eq!(Rgb::WHITE.dimmed(100), Rgb::WHITE);
+        assert_eq!(Rgb::WHITE.dimmed(150), Rgb::WHITE);
+        assert_eq!(Rgb::new(255, 100, 1).dimmed(10), Rgb::new(25, 10, 0));
+        assert_eq!(Rgb::WHITE.dimmed(0), Rgb::OFF);
+    }
+
+    #[test]
+    fn validates_settings() {
+        assert!(!is_valid_brightness(0));
+        assert!(is_valid_brightness(1));
+        assert!(is_valid_brightness(100));
+        assert!(!is_valid_brightness(101));
+        assert!(is_valid_temp_range(10.0, 30.0));
+        assert!(!is_valid_temp_range(30.0, 30.0));
+        assert!(!is_valid_temp_range(30.0, 10.0));
+        assert!(!is_valid_temp_range(f32::NAN, 30.0));
+        assert!(!is_valid_temp_range(10.0, f32::INFINITY));
+    }
+
+    #[test]
+    fn board_names_round_trip() {
+        for board in [LedBoard::Gpio, LedBoard::GpioInverted, LedBoard::Ws2812] {
+            assert_eq!(LedBoard::from_name(board.name()), Some(board));
+        }
+        assert_eq!(LedBoard::from_name("rgb"), None);
+    }
+}
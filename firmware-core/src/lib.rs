@@ -0,0 +1,16 @@
+#![cfg_attr(not(test), no_std)]
+
+pub mod aggregate;
+pub mod battery;
+pub mod calibration;
+pub mod command;
+pub mod config;
+pub mod indicator;
+pub mod ipv6;
+pub mod led;
+pub mod ota;
+pub mod sensor;
+pub mod sntp;
+pub mod telemetry;
+pub mod topic;
+pub mod wifi;
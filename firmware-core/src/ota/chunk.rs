@@ -0,0 +1,244 @@
+use core::fmt::Write;
+use heapless::String;
+
+use super::image::{Manifest, OtaError};
+use crate::telemetry::EncodeError;
+
+// --- OTA over MQTT ---
+// For sites where only the MQTT port is open. The sender announces the image
+// with the `ota_begin` command, then publishes it in numbered chunks on
+// `devices/<id>/ota/chunk`: a 4-byte big-endian chunk number followed by
+// `chunk_size` bytes of the image (the last chunk may be shorter). Each chunk
+// is acknowledged on `devices/<id>/ota/ack` with the number of the chunk the
+// device expects next, so after a reconnect the sender repeats `ota_begin`
+// for the same image and carries on from there.
+
+/// Bytes before the image data in a chunk.
+pub const CHUNK_HEADER_LEN: usize = 4;
+
+/// Largest chunk accepted (image bytes per chunk).
+pub const MAX_CHUNK_SIZE: u32 = 1024;
+
+/// Used when `ota_begin` does not ask for a chunk size.
+pub const DEFAULT_CHUNK_SIZE: u32 = 1024;
+
+/// Largest chunk packet: topic, chunk number and data.
+pub const MAX_CHUNK_PACKET: usize = 2 + 128 + CHUNK_HEADER_LEN + MAX_CHUNK_SIZE as usize;
+
+/// `devices/<id>/ota/chunk`
+pub fn chunk_topic<const N: usize>(device_id: &str) -> Result<String<N>, EncodeError> {
+    let mut topic = String::new();
+    write!(topic, "devices/{}/ota/chunk", device_id)?;
+    Ok(topic)
+}
+
+/// `devices/<id>/ota/ack`
+pub fn ack_topic<const N: usize>(device_id: &str) -> Result<String<N>, EncodeError> {
+    let mut topic = String::new();
+    write!(topic, "devices/{}/ota/ack", device_id)?;
+    Ok(topic)
+}
+
+/// Splits a chunk into its number and data.
+pub fn parse_chunk(payload: &[u8]) -> Option<(u32, &[u8])> {
+    if payload.len() < CHUNK_HEADER_LEN {
+        return None;
+    }
+    let (header, data) = payload.split_at(CHUNK_HEADER_LEN);
+    Some((u32::from_be_bytes([header[0], header[1], header[2], header[3]]), data))
+}
+
+/// What to do with an incoming chunk.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChunkStatus {
+    /// The expected chunk: write it.
+    Accept,
+    /// Already written (e.g. resent after a lost ack); just ack again.
+    Duplicate,
+    /// A later chunk (an earlier one was lost); ack so the sender goes back.
+    OutOfOrder,
+}
+
+/// Progress of a chunked transfer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transfer {
+    pub manifest: Manifest,
+    pub chunk_size: u32,
+    /// Number of the next chunk expected.
+    pub next: u32,
+}
+
+impl Transfer {
+    pub fn new(manifest: Manifest, chunk_size: u32) -> Result<Self, OtaError> {
+        if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE {
+            return Err(OtaError::SizeMismatch);
+        }
+        Ok(Self { manifest, chunk_size, next: 0 })
+    }
+
+    pub fn chunk_count(&self) -> u32 {
+        self.manifest.size.div_ceil(self.chunk_size)
+    }
+
+    /// Whether `manifest` describes the image being received, so a repeated
+    /// `ota_begin` resumes instead of starting over.
+    pub fn is_same_image(&self, manifest: &Manifest, chunk_size: u32) -> bool {
+        self.manifest == *manifest && self.chunk_size == chunk_size
+    }
+
+    /// Data length of chunk `index`.
+    fn chunk_len(&self, index: u32) -> u32 {
+        let start = index.saturating_mul(self.chunk_size);
+        self.manifest.size.saturating_sub(start).min(self.chunk_size)
+    }
+
+    pub fn check(&self, index: u32, data: &[u8]) -> Result<ChunkStatus, OtaError> {
+        if index < self.next {
+            return Ok(ChunkStatus::Duplicate);
+        }
+        if index > self.next {
+            return Ok(ChunkStatus::OutOfOrder);
+        }
+        if self.is_complete() || data.len() != self.chunk_len(index) as usize {
+            return Err(OtaError::SizeMismatch);
+        }
+        Ok(ChunkStatus::Accept)
+    }
+
+    /// Records that the expected chunk was written.
+    pub fn advance(&mut self) {
+        self.next += 1;
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.next >= self.chunk_count()
+    }
+}
+
+/// Encodes an acknowledgement into `out` (which is cleared first):
+/// `{"next":12}`, `{"next":42,"state":"installed"}` or `{"next":12,"error":".."}`.
+pub fn write_ack<const N: usize>(next: u32, outcome: Result<Option<&str>, OtaError>, out: &mut String<N>) -> Result<(), EncodeError> {
+    out.clear();
+    write!(out, "{{\"next\":{}", next)?;
+    match outcome {
+        Ok(None) => {}
+        Ok(Some(state)) => write!(out, ",\"state\":\"{}\"", state)?,
+        Err(e) => write!(out, ",\"error\":\"{}\"", e.name())?,
+    }
+    out.push('}').map_err(|_| EncodeError::Overflow)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2500 bytes in chunks of 1024: two full chunks and one of 452.
+    fn transfer() -> Transfer {
+        let manifest = Manifest { size: 2500, sha256: [0x11; 32], signature: [0x22; 64] };
+        Transfer::new(manifest, 1024).unwrap()
+    }
+
+    #[test]
+    fn splits_chunk_number_and_data() {
+        assert_eq!(parse_chunk(&[0, 0, 1, 2, 0xE9, 0x03]), Some((258, &[0xE9, 0x03][..])));
+        assert_eq!(parse_chunk(&[0xFF, 0xFF, 0xFF, 0xFF]), Some((u32::MAX, &[][..])));
+        assert_eq!(parse_chunk(&[0, 0, 1]), None);
+        assert_eq!(parse_chunk(&[]), None);
+    }
+
+    #[test]
+    fn rejects_bad_chunk_sizes() {
+        let manifest = transfer().manifest;
+        assert_eq!(Transfer::new(manifest, 0), Err(OtaError::SizeMismatch));
+        assert_eq!(Transfer::new(manifest, MAX_CHUNK_SIZE + 1), Err(OtaError::SizeMismatch));
+        assert!(Transfer::new(manifest, MAX_CHUNK_SIZE).is_ok());
+    }
+
+    #[test]
+    fn receives_in_order() {
+        let mut transfer = transfer();
+        assert_eq!(transfer.chunk_count(), 3);
+        for (index, len) in [(0, 1024), (1, 1024), (2, 452)] {
+            assert!(!transfer.is_complete());
+            assert_eq!(transfer.check(index, &vec![0; len]), Ok(ChunkStatus::Accept));
+            transfer.advance();
+        }
+        assert!(transfer.is_complete());
+    }
+
+    #[test]
+    fn acks_a_duplicate_again() {
+        let mut transfer = transfer();
+        transfer.advance();
+        assert_eq!(transfer.check(0, &[0; 1024]), Ok(ChunkStatus::Duplicate));
+        // Even if it is damaged: it is not written again
+        assert_eq!(transfer.check(0, &[0; 3]), Ok(ChunkStatus::Duplicate));
+        assert_eq!(transfer.next, 1);
+    }
+
+    #[test]
+    fn sends_the_sender_back_after_a_gap() {
+        let transfer = transfer();
+        assert_eq!(transfer.check(1, &[0; 1024]), Ok(ChunkStatus::OutOfOrder));
+        assert_eq!(transfer.check(7, &[0; 1024]), Ok(ChunkStatus::OutOfOrder));
+    }
+
+    #[test]
+    fn only_the_last_chunk_may_be_short() {
+        let mut transfer = transfer();
+        assert_eq!(transfer.check(0, &[0; 452]), Err(OtaError::SizeMismatch));
+        assert_eq!(transfer.check(0, &[0; 1025]), Err(OtaError::SizeMismatch));
+        transfer.advance();
+        transfer.advance();
+        assert_eq!(transfer.check(2, &[0; 1024]), Err(OtaError::SizeMismatch));
+        assert_eq!(transfer.check(2, &[0; 451]), Err(OtaError::SizeMismatch));
+        assert_eq!(transfer.check(2, &[0; 452]), Ok(ChunkStatus::Accept));
+    }
+
+    #[test]
+    fn image_of_whole_chunks() {
+        let manifest = Manifest { size: 2048, ..transfer().manifest };
+        let mut transfer = Transfer::new(manifest, 1024).unwrap();
+        assert_eq!(transfer.chunk_count(), 2);
+        transfer.advance();
+        assert_eq!(transfer.check(1, &[0; 1024]), Ok(ChunkStatus::Accept));
+    }
+
+    #[test]
+    fn completed_transfer_takes_no_more_data() {
+        let mut transfer = transfer();
+        for _ in 0..3 {
+            transfer.advance();
+        }
+        assert_eq!(transfer.check(2, &[0; 452]), Ok(ChunkStatus::Duplicate));
+        assert_eq!(transfer.check(3, &[]), Err(OtaError::SizeMismatch));
+        assert_eq!(transfer.check(3, &[0; 1024]), Err(OtaError::SizeMismatch));
+    }
+
+    #[test]
+    fn resumes_only_the_same_image() {
+        let transfer = transfer();
+        assert!(transfer.is_same_image(&transfer.manifest, 1024));
+        assert!(!transfer.is_same_image(&transfer.manifest, 512));
+        let other = Manifest { sha256: [0x33; 32], ..transfer.manifest };
+        assert!(!transfer.is_same_image(&other, 1024));
+    }
+
+    #[test]
+    fn writes_acks() {
+        let mut out = String::<64>::new();
+        write_ack(12, Ok(None), &mut out).unwrap();
+        assert_eq!(out, "{\"next\":12}");
+        write_ack(3, Ok(Some("installed")), &mut out).unwrap();
+        assert_eq!(out, "{\"next\":3,\"state\":\"installed\"}");
+        write_ack(1, Err(OtaError::SizeMismatch), &mut out).unwrap();
+        assert_eq!(out, "{\"next\":1,\"error\":\"size_mismatch\"}");
+        assert_eq!(write_ack(1, Ok(None), &mut String::<8>::new()), Err(EncodeError::Overflow));
+    }
+
+    #[test]
+    fn topics() {
+        assert_eq!(chunk_topic::<64>("esp32").unwrap(), "devices/esp32/ota/chunk");
+        assert_eq!(ack_topic::<64>("esp32").unwrap(), "devices/esp32/ota/ack");
+    }
+}
@@ -0,0 +1,229 @@
+use core::fmt::Write;
+use heapless::String;
+
+use super::image::OtaError;
+
+// --- Minimal HTTP Client Helpers ---
+// Just enough HTTP to fetch an image: the request is HTTP/1.0, so servers
+// answer with a plain body (no chunked encoding) and close the connection
+// when done. TLS is not available; the image is protected by its signature.
+
+/// Longest URL accepted for a download.
+pub const MAX_URL_LEN: usize = 192;
+
+/// Parts of an `http://host[:port]/path` URL.
+#[derive(Debug, PartialEq)]
+pub struct Url<'a> {
+    /// Host name or address; IPv6 literals keep their brackets.
+    pub host: &'a str,
+    pub port: u16,
+    /// Path including the query, always starting with `/`.
+    pub path: &'a str,
+}
+
+pub fn parse_url(url: &str) -> Result<Url<'_>, OtaError> {
+    let rest = url.strip_prefix("http://").ok_or(OtaError::UnsupportedUrl)?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    if authority.contains('@') || path.bytes().any(|b| b.is_ascii_whitespace() || b.is_ascii_control()) {
+        return Err(OtaError::UnsupportedUrl);
+    }
+
+    // `[v6]:port` or `host:port`; a bare IPv6 literal would be ambiguous
+    let (host, port) = if authority.starts_with('[') {
+        let end = authority.find(']').ok_or(OtaError::UnsupportedUrl)?;
+        (&authority[..=end], &authority[end + 1..])
+    } else {
+        match authority.rfind(':') {
+            Some(i) => (&authority[..i], &authority[i..]),
+            None => (authority, ""),
+        }
+    };
+    let port = match port {
+        "" => 80,
+        port => port
+            .strip_prefix(':')
+            .and_then(|p| p.parse::<u16>().ok())
+            .filter(|&p| p != 0)
+            .ok_or(OtaError::UnsupportedUrl)?,
+    };
+    if host.is_empty() || (!host.starts_with('[') && host.contains(':')) {
+        return Err(OtaError::UnsupportedUrl);
+    }
+    Ok(Url { host, port, path })
+}
+
+/// Writes the GET request for `url` into `out` (which is cleared first).
+pub fn write_request<const N: usize>(url: &Url, out: &mut String<N>) -> Result<(), OtaError> {
+    out.clear();
+    let written = match url.port {
+        80 => write!(out, "GET {} HTTP/1.0\r\nHost: {}", url.path, url.host),
+        port => write!(out, "GET {} HTTP/1.0\r\nHost: {}:{}", url.path, url.host, port),
+    };
+    written.map_err(|_| OtaError::UnsupportedUrl)?;
+    out.push_str("\r\nUser-Agent: esp-blinky-rust\r\nConnection: close\r\n\r\n").map_err(|_| OtaError::UnsupportedUrl)
+}
+
+/// Status line and the headers we care about.
+#[derive(Debug, PartialEq)]
+pub struct ResponseHead {
+    pub status: u16,
+    pub content_length: Option<u32>,
+    /// Bytes up to and including the blank line; the body follows.
+    pub header_len: usize,
+}
+
+/// Parses the response head at the start of `buf`.
+/// Returns `Ok(None)` while the blank line that ends it has not arrived.
+pub fn parse_response_head(buf: &[u8]) -> Result<Option<ResponseHead>, OtaError> {
+    let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") else {
+        return Ok(None);
+    };
+    let head = core::str::from_utf8(&buf[..end]).map_err(|_| OtaError::BadResponse)?;
+    let mut lines = head.split("\r\n");
+
+    // `HTTP/1.1 200 OK`
+    let status_line = lines.next().unwrap_or("");
+    let mut parts = status_line.splitn(3, ' ');
+    if !parts.next().is_some_and(|v| v.starts_with("HTTP/1.")) {
+        return Err(OtaError::BadResponse);
+    }
+    let status = parts.next().and_then(|s| s.parse::<u16>().ok()).ok_or(OtaError::BadResponse)?;
+
+    let mut content_length = None;
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            return Err(OtaError::BadResponse);
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = Some(value.parse::<u32>().map_err(|_| OtaError::BadResponse)?);
+        } else if name.eq_ignore_ascii_case("transfer-encoding") && !value.eq_ignore_ascii_case("identity") {
+            // Not expected in answer to HTTP/1.0, and we cannot decode it
+            return Err(OtaError::BadResponse);
+        }
+    }
+    Ok(Some(ResponseHead { status, content_length, header_len: end + 4 }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_host_port_and_path() {
+        let url = parse_url("http://updates.local/fw/blinky.bin?v=2").unwrap();
+        assert_eq!(url, Url { host: "updates.local", port: 80, path: "/fw/blinky.bin?v=2" });
+        let url = parse_url("http://192.168.1.10:8080/fw.bin").unwrap();
+        assert_eq!(url, Url { host: "192.168.1.10", port: 8080, path: "/fw.bin" });
+    }
+
+    #[test]
+    fn missing_path_is_the_root() {
+        assert_eq!(parse_url("http://updates.local").unwrap().path, "/");
+        assert_eq!(parse_url("http://updates.local:81").unwrap(), Url { host: "updates.local", port: 81, path: "/" });
+    }
+
+    #[test]
+    fn ipv6_hosts_keep_their_brackets() {
+        let url = parse_url("http://[fd00::1]/fw.bin").unwrap();
+        assert_eq!(url, Url { host: "[fd00::1]", port: 80, path: "/fw.bin" });
+        let url = parse_url("http://[fd00::1]:8080").unwrap();
+        assert_eq!(url, Url { host: "[fd00::1]", port: 8080, path: "/" });
+        // Unbracketed, the port cannot be told apart from the address
+        assert_eq!(parse_url("http://fd00::1/fw.bin"), Err(OtaError::UnsupportedUrl));
+        assert_eq!(parse_url("http://[fd00::1/fw.bin"), Err(OtaError::UnsupportedUrl));
+        assert_eq!(parse_url("http://[fd00::1]8080/"), Err(OtaError::UnsupportedUrl));
+    }
+
+    #[test]
+    fn rejects_other_schemes_and_bad_authorities() {
+        for url in [
+            "https://updates.local/fw.bin",
+            "ftp://updates.local/fw.bin",
+            "updates.local/fw.bin",
+            "http:///fw.bin",
+            "http://user:pw@updates.local/fw.bin",
+            "http://updates.local:0/fw.bin",
+            "http://updates.local:65536/fw.bin",
+            "http://updates.local:/fw.bin",
+            "http://updates.local:http/fw.bin",
+            "http://updates.local/fw bin",
+            "http://updates.local/fw.bin\r\nX-Injected: 1",
+        ] {
+            assert_eq!(parse_url(url), Err(OtaError::UnsupportedUrl), "{url}");
+        }
+    }
+
+    #[test]
+    fn writes_the_request() {
+        let mut out = String::<256>::new();
+        write_request(&parse_url("http://updates.local/fw.bin").unwrap(), &mut out).unwrap();
+        assert_eq!(
+            out.as_str(),
+            "GET /fw.bin HTTP/1.0\r\nHost: updates.local\r\nUser-Agent: esp-blinky-rust\r\nConnection: close\r\n\r\n"
+        );
+        write_request(&parse_url("http://[fd00::1]:8080/a").unwrap(), &mut out).unwrap();
+        assert!(out.starts_with("GET /a HTTP/1.0\r\nHost: [fd00::1]:8080\r\n"));
+
+        let mut short = String::<32>::new();
+        assert_eq!(write_request(&parse_url("http://updates.local/fw.bin").unwrap(), &mut short), Err(OtaError::UnsupportedUrl));
+    }
+
+    #[test]
+    fn partial_head_needs_more_data() {
+        assert_eq!(parse_response_head(b""), Ok(None));
+        assert_eq!(parse_response_head(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n"), Ok(None));
+        assert_eq!(parse_response_head(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r"), Ok(None));
+    }
+
+    #[test]
+    fn parses_status_length_and_body_offset() {
+        let response = b"HTTP/1.1 200 OK\r\nServer: test\r\nContent-Length: 1234\r\n\r\n\xE9\x03";
+        let head = parse_response_head(response).unwrap().unwrap();
+        assert_eq!(head, ResponseHead { status: 200, content_length: Some(1234), header_len: response.len() - 2 });
+        assert_eq!(&response[head.header_len..], b"\xE9\x03");
+    }
+
+    #[test]
+    fn reports_non_2xx_status() {
+        let head = parse_response_head(b"HTTP/1.0 404 Not Found\r\nContent-Length: 9\r\n\r\n").unwrap().unwrap();
+        assert_eq!(head.status, 404);
+        let head = parse_response_head(b"HTTP/1.1 301\r\nLocation: /x\r\n\r\n").unwrap().unwrap();
+        assert_eq!(head.status, 301);
+    }
+
+    #[test]
+    fn content_length_is_optional_but_must_fit() {
+        let head = parse_response_head(b"HTTP/1.0 200 OK\r\n\r\n").unwrap().unwrap();
+        assert_eq!(head.content_length, None);
+        assert_eq!(parse_response_head(b"HTTP/1.0 200 OK\r\nContent-Length: 4294967296\r\n\r\n"), Err(OtaError::BadResponse));
+        assert_eq!(parse_response_head(b"HTTP/1.0 200 OK\r\nContent-Length: -1\r\n\r\n"), Err(OtaError::BadResponse));
+        assert_eq!(parse_response_head(b"HTTP/1.0 200 OK\r\nContent-Length:\r\n\r\n"), Err(OtaError::BadResponse));
+    }
+
+    #[test]
+    fn header_names_ignore_case() {
+        let head = parse_response_head(b"HTTP/1.1 200 OK\r\ncontent-LENGTH:  42 \r\n\r\n").unwrap().unwrap();
+        assert_eq!(head.content_length, Some(42));
+        assert_eq!(parse_response_head(b"HTTP/1.1 200 OK\r\nTRANSFER-ENCODING: Chunked\r\n\r\n"), Err(OtaError::BadResponse));
+        let head = parse_response_head(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: IDENTITY\r\n\r\n").unwrap().unwrap();
+        assert_eq!(head.content_length, None);
+    }
+
+    #[test]
+    fn rejects_malformed_heads() {
+        for head in [
+            &b"HTTP/2 200\r\n\r\n"[..],
+            b"ICY 200 OK\r\n\r\n",
+            b"HTTP/1.1 OK\r\n\r\n",
+            b"HTTP/1.1\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nno colon here\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nX: \xFF\r\n\r\n",
+        ] {
+            assert_eq!(parse_response_head(head), Err(OtaError::BadResponse));
+        }
+    }
+}
@@ -0,0 +1,220 @@
+// --- OTA Image Manifest ---
+// An update is described by its size, the SHA-256 of the image and an
+// Ed25519 signature over that digest, made with the key whose public half is
+// built into the firmware. Signing the digest rather than the image lets the
+// device check the signature after streaming the image into flash.
+
+pub const DIGEST_LEN: usize = 32;
+pub const SIGNATURE_LEN: usize = 64;
+pub const PUBLIC_KEY_LEN: usize = 32;
+
+/// First byte of every ESP-IDF application image.
+pub const IMAGE_MAGIC: u8 = 0xE9;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OtaError {
+    /// The firmware was built without `ota_public_key`.
+    Disabled,
+    /// Another update is in progress.
+    Busy,
+    /// A chunk arrived without an `ota_begin` for it.
+    NoTransfer,
+    /// The URL is malformed or not `http://`.
+    UnsupportedUrl,
+    /// The host name could not be resolved.
+    Dns,
+    /// The TCP connection to the server failed.
+    Connect,
+    /// The connection dropped or timed out mid-transfer.
+    Network,
+    /// The server answered with something other than `200 OK`.
+    Http(u16),
+    /// The response could not be parsed.
+    BadResponse,
+    /// The image does not fit the OTA slot.
+    TooLarge,
+    /// More or fewer bytes arrived than announced.
+    SizeMismatch,
+    /// The data does not start like an ESP-IDF application image.
+    NotAnImage,
+    /// The partition table has no OTA slots (or no OTA data partition).
+    NoSlot,
+    /// Writing or reading the slot failed.
+    Flash,
+    /// The SHA-256 of the written image differs from the manifest.
+    DigestMismatch,
+    /// The signature does not match the digest and the built-in key.
+    BadSignature,
+}
+
+impl OtaError {
+    /// Error code sent in command responses.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Disabled => "ota_disabled",
+            Self::Busy => "ota_busy",
+            Self::NoTransfer => "no_transfer",
+            Self::UnsupportedUrl => "unsupported_url",
+            Self::Dns => "dns",
+            Self::Connect => "connect",
+            Self::Network => "network",
+            Self::Http(_) => "http_status",
+            Self::BadResponse => "bad_response",
+            Self::TooLarge => "too_large",
+            Self::SizeMismatch => "size_mismatch",
+            Self::NotAnImage => "not_an_image",
+            Self::NoSlot => "no_ota_slot",
+            Self::Flash => "flash",
+            Self::DigestMismatch => "digest_mismatch",
+            Self::BadSignature => "bad_signature",
+        }
+    }
+}
+
+/// Decodes exactly `N` bytes from hex (either case).
+pub fn parse_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    let hex = hex.as_bytes();
+    if hex.len() != 2 * N {
+        return None;
+    }
+    let mut out = [0u8; N];
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = (nibble(hex[2 * i])? << 4) | nibble(hex[2 * i + 1])?;
+    }
+    Some(out)
+}
+
+fn nibble(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// The public key built into the firmware, or `None` if OTA is disabled.
+pub fn public_key(hex: &str) -> Option<[u8; PUBLIC_KEY_LEN]> {
+    parse_hex(hex)
+}
+
+/// What the device must receive for an update to be accepted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Manifest {
+    pub size: u32,
+    pub sha256: [u8; DIGEST_LEN],
+    pub signature: [u8; SIGNATURE_LEN],
+}
+
+impl Manifest {
+    /// Builds a manifest from the hex strings sent by the operator.
+    pub fn parse(size: u32, sha256: &str, signature: &str) -> Option<Self> {
+        if size == 0 {
+            return None;
+        }
+        Some(Self { size, sha256: parse_hex(sha256)?, signature: parse_hex(signature)? })
+    }
+}
+
+/// Checks the start of the image before anything is written to flash.
+/// An empty slice passes: nothing is written for it, so the writer checks
+/// again with the next data it gets (an HTTP response may end its head
+/// exactly at a packet boundary).
+pub fn check_header(first: &[u8]) -> Result<(), OtaError> {
+    match first.first() {
+        Some(&IMAGE_MAGIC) | None => Ok(()),
+        Some(_) => Err(OtaError::NotAnImage),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA256: &str = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+
+    fn signature_hex() -> heapless::String<128> {
+        let mut hex = heapless::String::new();
+        for _ in 0..SIGNATURE_LEN {
+            hex.push_str("a5").unwrap();
+        }
+        hex
+    }
+
+    #[test]
+    fn decodes_hex_in_either_case() {
+        assert_eq!(parse_hex::<4>("deadBEEF"), Some([0xDE, 0xAD, 0xBE, 0xEF]));
+        assert_eq!(parse_hex::<2>("0aF0"), Some([0x0A, 0xF0]));
+        assert_eq!(parse_hex::<0>(""), Some([]));
+    }
+
+    #[test]
+    fn hex_must_have_exactly_two_digits_per_byte() {
+        assert_eq!(parse_hex::<4>("deadbee"), None);
+        assert_eq!(parse_hex::<4>("deadbeef0"), None);
+        assert_eq!(parse_hex::<4>("deadbe"), None);
+        assert_eq!(parse_hex::<4>(""), None);
+    }
+
+    #[test]
+    fn hex_rejects_other_characters() {
+        assert_eq!(parse_hex::<4>("deadbeeg"), None);
+        assert_eq!(parse_hex::<4>("0xdeadbe"), None);
+        assert_eq!(parse_hex::<4>("dead beef"), None);
+        assert_eq!(parse_hex::<2>("+1-2"), None);
+        // Two bytes of UTF-8, so the length alone would match
+        assert_eq!(parse_hex::<1>("é"), None);
+    }
+
+    #[test]
+    fn public_key_is_optional() {
+        assert_eq!(public_key(""), None);
+        assert_eq!(public_key(&SHA256.to_uppercase()), parse_hex(SHA256));
+    }
+
+    #[test]
+    fn parses_the_manifest() {
+        let manifest = Manifest::parse(1024, SHA256, &signature_hex()).unwrap();
+        assert_eq!(manifest.size, 1024);
+        assert_eq!(manifest.sha256[..4], [0x9F, 0x86, 0xD0, 0x81]);
+        assert_eq!(manifest.signature, [0xA5; SIGNATURE_LEN]);
+    }
+
+    #[test]
+    fn manifest_rejects_empty_image_and_bad_hex() {
+        assert_eq!(Manifest::parse(0, SHA256, &signature_hex()), None);
+        assert_eq!(Manifest::parse(1024, &SHA256[2..], &signature_hex()), None);
+        assert_eq!(Manifest::parse(1024, SHA256, &signature_hex()[2..]), None);
+        assert_eq!(Manifest::parse(1024, &SHA256.replace('a', "z"), &signature_hex()), None);
+        // Swapped fields have the wrong lengths
+        assert_eq!(Manifest::parse(1024, &signature_hex(), SHA256), None);
+    }
+
+    #[test]
+    fn header_needs_the_image_magic() {
+        assert_eq!(check_header(&[IMAGE_MAGIC, 0x03, 0x02]), Ok(()));
+        assert_eq!(check_header(&[IMAGE_MAGIC]), Ok(()));
+        assert_eq!(check_header(&[0x7F, b'E', b'L', b'F']), Err(OtaError::NotAnImage));
+        assert_eq!(check_header(&[0x00]), Err(OtaError::NotAnImage));
+    }
+
+    #[test]
+    fn empty_first_data_defers_the_header_check() {
+        assert_eq!(check_header(&[]), Ok(()));
+    }
+
+    #[test]
+    fn error_names_are_distinct() {
+        let errors = [
+            OtaError::Disabled, OtaError::Busy, OtaError::NoTransfer, OtaError::UnsupportedUrl,
+            OtaError::Dns, OtaError::Connect, OtaError::Network, OtaError::Http(404),
+            OtaError::BadResponse, OtaError::TooLarge, OtaError::SizeMismatch, OtaError::NotAnImage,
+            OtaError::NoSlot, OtaError::Flash, OtaError::DigestMismatch, OtaError::BadSignature,
+        ];
+        for (i, a) in errors.iter().enumerate() {
+            for b in &errors[i + 1..] {
+                assert_ne!(a.name(), b.name());
+            }
+        }
+    }
+}
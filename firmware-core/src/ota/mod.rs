@@ -0,0 +1,8 @@
+// --- Over-the-Air Updates ---
+// The image manifest, the bookkeeping of transfers over MQTT and the HTTP
+// parsing for downloads. Writing the image to flash is up to the firmware
+// (`src/ota/`).
+
+pub mod chunk;
+pub mod http;
+pub mod image;
@@ -0,0 +1,176 @@
+use heapless::{String, Vec};
+use serde::{Serialize, Deserialize};
+
+// --- Analog Input Channels ---
+// Configuration and scaling for the ADC channels. The voltage is measured in
+// volts and mapped to engineering units with a polynomial, e.g. a 4–20 mA
+// loop across a 120 Ω shunt (0.48–2.4 V) to 0–10 bar is `[-2.5, 5.2083]`.
+// Reading the inputs is up to the firmware (`src/sensor/adc.rs`).
+
+/// ADC1 has five usable inputs (GPIO0..=GPIO4); ADC2 is shared with Wi-Fi.
+pub const MAX_ADC_CHANNELS: usize = 5;
+
+/// Up to a cubic polynomial.
+pub const MAX_SCALE_COEFFS: usize = 4;
+
+/// Longest channel name.
+pub const MAX_CHANNEL_NAME: usize = 16;
+
+/// Longest unit string, e.g. `bar` or `m³/h`.
+pub const MAX_UNIT: usize = 8;
+
+/// Input attenuation, which sets the measurable voltage range.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Attenuation {
+    /// About 0–0.75 V.
+    Db0,
+    /// About 0–1.05 V.
+    Db2_5,
+    /// About 0–1.3 V.
+    Db6,
+    /// About 0–2.5 V.
+    Db11,
+}
+
+impl Attenuation {
+    /// Parses the names used in `config.json` (`0db`, `2.5db`, `6db`, `11db`).
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "0db" => Some(Self::Db0),
+            "2.5db" => Some(Self::Db2_5),
+            "6db" => Some(Self::Db6),
+            "11db" => Some(Self::Db11),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Db0 => "0db",
+            Self::Db2_5 => "2.5db",
+            Self::Db6 => "6db",
+            Self::Db11 => "11db",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AdcChannelConfig {
+    /// Measurement name, also used as `{channel}` in the topic template.
+    pub name: String<MAX_CHANNEL_NAME>,
+    /// GPIO number (0..=4).
+    pub pin: u8,
+    pub attenuation: Attenuation,
+    /// Number of conversions averaged per reading (1 = none).
+    pub oversample: u8,
+    /// Polynomial coefficients `c0, c1, ..` applied to the input voltage;
+    /// empty publishes the voltage itself.
+    pub scale: Vec<f32, MAX_SCALE_COEFFS>,
+    /// Unit of the scaled value, announced to Home Assistant (empty = none).
+    pub unit: String<MAX_UNIT>,
+}
+
+/// Why a channel configuration was rejected.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AdcConfigError {
+    /// Only GPIO0..=GPIO4 are connected to ADC1.
+    InvalidPin,
+    /// The name is empty or would break the topic (`/`, `+`, `#`, spaces)
+    /// or the payload (`,`, quotes).
+    InvalidName,
+    /// A coefficient is NaN or infinite.
+    InvalidScale,
+}
+
+impl AdcChannelConfig {
+    /// Converts an input voltage (V) to engineering units.
+    pub fn scale(&self, volts: f32) -> f32 {
+        if self.scale.is_empty() {
+            return volts;
+        }
+        // Horner's method: c0 + v * (c1 + v * (c2 + ..))
+        self.scale.iter().rev().fold(0.0, |acc, c| acc * volts + c)
+    }
+
+    pub fn validate(&self) -> Result<(), AdcConfigError> {
+        if self.pin as usize >= MAX_ADC_CHANNELS {
+            return Err(AdcConfigError::InvalidPin);
+        }
+        if self.name.is_empty() || self.name.chars().any(|c| matches!(c, '/' | '+' | '#' | ' ' | ',' | '"' | '\'' | '\0')) {
+            return Err(AdcConfigError::InvalidName);
+        }
+        if self.scale.iter().any(|c| !c.is_finite()) {
+            return Err(AdcConfigError::InvalidScale);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel(scale: &[f32]) -> AdcChannelConfig {
+        AdcChannelConfig {
+            name: String::try_from("pressure").unwrap(),
+            pin: 2,
+            attenuation: Attenuation::Db11,
+            oversample: 1,
+            scale: Vec::from_slice(scale).unwrap(),
+            unit: String::try_from("bar").unwrap(),
+        }
+    }
+
+    #[test]
+    fn empty_scale_is_the_voltage() {
+        assert_eq!(channel(&[]).scale(1.25), 1.25);
+    }
+
+    #[test]
+    fn scales_a_current_loop() {
+        let loop_4_20 = channel(&[-2.5, 5.2083]);
+        assert!(loop_4_20.scale(0.48).abs() < 0.001);
+        assert!((loop_4_20.scale(2.4) - 10.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn evaluates_every_coefficient() {
+        // 1 + 2v + 3v² + 4v³
+        let cubic = channel(&[1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(cubic.scale(0.0), 1.0);
+        assert_eq!(cubic.scale(2.0), 1.0 + 4.0 + 12.0 + 32.0);
+        assert_eq!(cubic.scale(-1.0), 1.0 - 2.0 + 3.0 - 4.0);
+        assert_eq!(channel(&[7.0]).scale(3.0), 7.0);
+    }
+
+    #[test]
+    fn accepts_a_valid_channel() {
+        assert_eq!(channel(&[-2.5, 5.2083]).validate(), Ok(()));
+        let mut last_pin = channel(&[]);
+        last_pin.pin = MAX_ADC_CHANNELS as u8 - 1;
+        assert_eq!(last_pin.validate(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_pins_outside_adc1() {
+        let mut config = channel(&[]);
+        config.pin = MAX_ADC_CHANNELS as u8;
+        assert_eq!(config.validate(), Err(AdcConfigError::InvalidPin));
+    }
+
+    #[test]
+    fn rejects_names_that_break_topics_or_payloads() {
+        for name in ["", "a/b", "a+", "#", "tank level", "a,b", "\"q\"", "it's"] {
+            let mut config = channel(&[]);
+            config.name = String::try_from(name).unwrap();
+            assert_eq!(config.validate(), Err(AdcConfigError::InvalidName), "{name:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_non_finite_coefficients() {
+        for bad in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            assert_eq!(channel(&[0.0, bad]).validate(), Err(AdcConfigError::InvalidScale));
+        }
+    }
+}
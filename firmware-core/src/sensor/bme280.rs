@@ -0,0 +1,337 @@
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::i2c::I2c;
+
+use super::{push, Quantity, Readings, Sensor, SensorError, SensorKind};
+
+// --- Bosch BME280 ---
+// Forced mode with 1x oversampling and the filter off: one conversion per
+// read, after which the chip goes back to sleep. Compensation uses the
+// floating-point formulas from the datasheet (section 8.1).
+
+/// Address with SDO low (0x77 with it high).
+pub const DEFAULT_ADDRESS: u8 = 0x76;
+
+const CHIP_ID: u8 = 0x60;
+
+const REG_CALIB_00: u8 = 0x88;
+const REG_CHIP_ID: u8 = 0xD0;
+const REG_CALIB_26: u8 = 0xE1;
+const REG_CTRL_HUM: u8 = 0xF2;
+const REG_STATUS: u8 = 0xF3;
+const REG_CTRL_MEAS: u8 = 0xF4;
+const REG_CONFIG: u8 = 0xF5;
+const REG_DATA: u8 = 0xF7;
+
+const STATUS_MEASURING: u8 = 0x08;
+/// osrs_t = x1, osrs_p = x1, mode = forced.
+const CTRL_MEAS_FORCED: u8 = (0b001 << 5) | (0b001 << 2) | 0b01;
+/// Typical conversion time with everything at 1x oversampling is under 10 ms.
+const MEASURE_TIME_MS: u32 = 10;
+
+/// Factory trimming parameters.
+#[derive(Clone, Copy, Debug, Default)]
+struct Trim {
+    t1: u16,
+    t2: i16,
+    t3: i16,
+    p1: u16,
+    p2: i16,
+    p3: i16,
+    p4: i16,
+    p5: i16,
+    p6: i16,
+    p7: i16,
+    p8: i16,
+    p9: i16,
+    h1: u8,
+    h2: i16,
+    h3: u8,
+    h4: i16,
+    h5: i16,
+    h6: i8,
+}
+
+impl Trim {
+    /// Parses registers 0x88..=0xA1 and 0xE1..=0xE7.
+    fn parse(a: &[u8; 26], b: &[u8; 7]) -> Self {
+        let u = |i: usize| u16::from_le_bytes([a[i], a[i + 1]]);
+        let s = |i: usize| i16::from_le_bytes([a[i], a[i + 1]]);
+        Self {
+            t1: u(0),
+            t2: s(2),
+            t3: s(4),
+            p1: u(6),
+            p2: s(8),
+            p3: s(10),
+            p4: s(12),
+            p5: s(14),
+            p6: s(16),
+            p7: s(18),
+            p8: s(20),
+            p9: s(22),
+            h1: a[25],
+            h2: i16::from_le_bytes([b[0], b[1]]),
+            h3: b[2],
+            h4: ((b[3] as i8 as i16) << 4) | (b[4] & 0x0F) as i16,
+            h5: ((b[5] as i8 as i16) << 4) | (b[4] >> 4) as i16,
+            h6: b[6] as i8,
+        }
+    }
+
+    /// Returns (temperature °C, pressure hPa, humidity %) from raw ADC values.
+    fn compensate(&self, adc_t: i32, adc_p: i32, adc_h: i32) -> (f32, f32, f32) {
+        let (adc_t, adc_p, adc_h) = (adc_t as f64, adc_p as f64, adc_h as f64);
+
+        let var1 = (adc_t / 16384.0 - self.t1 as f64 / 1024.0) * self.t2 as f64;
+        let var2 = adc_t / 131072.0 - self.t1 as f64 / 8192.0;
+        let var2 = var2 * var2 * self.t3 as f64;
+        let t_fine = var1 + var2;
+        let temperature = t_fine / 5120.0;
+
+        let mut var1 = t_fine / 2.0 - 64000.0;
+        let mut var2 = var1 * var1 * self.p6 as f64 / 32768.0;
+        var2 += var1 * self.p5 as f64 * 2.0;
+        var2 = var2 / 4.0 + self.p4 as f64 * 65536.0;
+        var1 = (self.p3 as f64 * var1 * var1 / 524288.0 + self.p2 as f64 * var1) / 524288.0;
+        var1 = (1.0 + var1 / 32768.0) * self.p1 as f64;
+        let pressure = if var1 == 0.0 {
+            0.0 // Avoid division by zero with an unprogrammed chip
+        } else {
+            let p = 1048576.0 - adc_p;
+            let p = (p - var2 / 4096.0) * 6250.0 / var1;
+            let var1 = self.p9 as f64 * p * p / 2147483648.0;
+            let var2 = p * self.p8 as f64 / 32768.0;
+            p + (var1 + var2 + self.p7 as f64) / 16.0
+        };
+
+        let h = t_fine - 76800.0;
+        let h = (adc_h - (self.h4 as f64 * 64.0 + self.h5 as f64 / 16384.0 * h))
+            * (self.h2 as f64 / 65536.0
+                * (1.0 + self.h6 as f64 / 67108864.0 * h * (1.0 + self.h3 as f64 / 67108864.0 * h)));
+        let humidity = h * (1.0 - self.h1 as f64 * h / 524288.0);
+
+        (temperature as f32, (pressure / 100.0) as f32, humidity.clamp(0.0, 100.0) as f32)
+    }
+}
+
+pub struct Bme280<I, D> {
+    i2c: I,
+    delay: D,
+    address: u8,
+    /// Read on first use, and again after a bus error in case the chip was power-cycled.
+    trim: Option<Trim>,
+}
+
+impl<I: I2c, D: DelayNs> Bme280<I, D> {
+    pub fn new(i2c: I, delay: D, address: u8) -> Self {
+        Self { i2c, delay, address, trim: None }
+    }
+
+    async fn read_regs(&mut self, reg: u8, buf: &mut [u8]) -> Result<(), SensorError> {
+        self.i2c.write_read(self.address, &[reg], buf).await.map_err(|_| SensorError::Bus)
+    }
+
+    async fn write_reg(&mut self, reg: u8, value: u8) -> Result<(), SensorError> {
+        self.i2c.write(self.address, &[reg, value]).await.map_err(|_| SensorError::Bus)
+    }
+
+    /// Checks the chip ID, loads the trimming parameters and sets up oversampling.
+    pub async fn init(&mut self) -> Result<(), SensorError> {
+        let mut id = [0u8; 1];
+        self.read_regs(REG_CHIP_ID, &mut id).await?;
+        if id[0] != CHIP_ID {
+            return Err(SensorError::NotFound);
+        }
+
+        let mut a = [0u8; 26];
+        let mut b = [0u8; 7];
+        self.read_regs(REG_CALIB_00, &mut a).await?;
+        self.read_regs(REG_CALIB_26, &mut b).await?;
+
+        // ctrl_hum only takes effect after a write to ctrl_meas, which `measure` does
+        self.write_reg(REG_CTRL_HUM, 0b001).await?;
+        self.write_reg(REG_CONFIG, 0).await?;
+        self.trim = Some(Trim::parse(&a, &b));
+        Ok(())
+    }
+
+    /// Returns temperature (°C), pressure (hPa) and relative humidity (%).
+    pub async fn measure(&mut self) -> Result<(f32, f32, f32), SensorError> {
+        let trim = match self.trim {
+            Some(trim) => trim,
+            None => {
+                self.init().await?;
+                self.trim.ok_or(SensorError::NotFound)?
+            }
+        };
+
+        self.write_reg(REG_CTRL_MEAS, CTRL_MEAS_FORCED).await?;
+        let mut status = [STATUS_MEASURING];
+        for _ in 0..5 {
+            self.delay.delay_ms(MEASURE_TIME_MS).await;
+            self.read_regs(REG_STATUS, &mut status).await?;
+            if status[0] & STATUS_MEASURING == 0 {
+                break;
+            }
+        }
+        if status[0] & STATUS_MEASURING != 0 {
+            return Err(SensorError::NotReady);
+        }
+
+        let mut d = [0u8; 8];
+        self.read_regs(REG_DATA, &mut d).await?;
+        let adc_p = ((d[0] as i32) << 12) | ((d[1] as i32) << 4) | (d[2] as i32 >> 4);
+        let adc_t = ((d[3] as i32) << 12) | ((d[4] as i32) << 4) | (d[5] as i32 >> 4);
+        let adc_h = ((d[6] as i32) << 8) | d[7] as i32;
+
+        // 0x80000 / 0x8000 mean "skipped", which happens right after a reset
+        if adc_t == 0x80000 || adc_p == 0x80000 || adc_h == 0x8000 {
+            return Err(SensorError::NotReady);
+        }
+        Ok(trim.compensate(adc_t, adc_p, adc_h))
+    }
+}
+
+impl<I: I2c, D: DelayNs> Sensor for Bme280<I, D> {
+    fn kind(&self) -> SensorKind {
+        SensorKind::Bme280
+    }
+
+    async fn read(&mut self, out: &mut Readings) -> Result<(), SensorError> {
+        let result = self.measure().await;
+        if result == Err(SensorError::Bus) {
+            self.trim = None;
+        }
+        let (temperature, pressure, humidity) = result?;
+        push(out, Quantity::Temperature, temperature)?;
+        push(out, Quantity::Humidity, humidity)?;
+        push(out, Quantity::Pressure, pressure)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embassy_futures::block_on;
+    use embedded_hal::i2c::ErrorKind;
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+    use embedded_hal_mock::eh1::i2c::{Mock, Transaction};
+
+    /// Registers 0x88..=0xA1 holding the worked example from the Bosch
+    /// BMP280 datasheet (section 3.12), whose temperature and pressure
+    /// compensation the BME280 shares, and H1 = 75.
+    fn calib_00() -> [u8; 26] {
+        let words: [i32; 12] = [27504, 26435, -1000, 36477, -10685, 3024, 2855, 140, -7, 15500, -14600, 6000];
+        let mut a = [0u8; 26];
+        for (i, word) in words.iter().enumerate() {
+            a[2 * i..2 * i + 2].copy_from_slice(&(*word as u16).to_le_bytes());
+        }
+        a[25] = 75;
+        a
+    }
+
+    /// Registers 0xE1..=0xE7: H2 = 362, H3 = 0, H4 = 313, H5 = 50, H6 = 30.
+    /// H4 and H5 share 0xE5, one nibble each.
+    const CALIB_26: [u8; 7] = [0x6A, 0x01, 0x00, 0x13, 0x29, 0x03, 0x1E];
+
+    /// Data registers for adc_P = 415148, adc_T = 519888, adc_H = 30000.
+    const DATA: [u8; 8] = [0x65, 0x5A, 0xC0, 0x7E, 0xED, 0x00, 0x75, 0x30];
+
+    fn init() -> Vec<Transaction> {
+        vec![
+            Transaction::write_read(DEFAULT_ADDRESS, vec![REG_CHIP_ID], vec![CHIP_ID]),
+            Transaction::write_read(DEFAULT_ADDRESS, vec![REG_CALIB_00], calib_00().to_vec()),
+            Transaction::write_read(DEFAULT_ADDRESS, vec![REG_CALIB_26], CALIB_26.to_vec()),
+            Transaction::write(DEFAULT_ADDRESS, vec![REG_CTRL_HUM, 0b001]),
+            Transaction::write(DEFAULT_ADDRESS, vec![REG_CONFIG, 0]),
+        ]
+    }
+
+    fn measurement(data: [u8; 8]) -> Vec<Transaction> {
+        vec![
+            Transaction::write(DEFAULT_ADDRESS, vec![REG_CTRL_MEAS, CTRL_MEAS_FORCED]),
+            Transaction::write_read(DEFAULT_ADDRESS, vec![REG_STATUS], vec![0x00]),
+            Transaction::write_read(DEFAULT_ADDRESS, vec![REG_DATA], data.to_vec()),
+        ]
+    }
+
+    #[test]
+    fn parses_the_trimming_registers() {
+        let trim = Trim::parse(&calib_00(), &CALIB_26);
+        assert_eq!((trim.t1, trim.t2, trim.t3), (27504, 26435, -1000));
+        assert_eq!((trim.p1, trim.p2, trim.p3), (36477, -10685, 3024));
+        assert_eq!((trim.p4, trim.p5, trim.p6), (2855, 140, -7));
+        assert_eq!((trim.p7, trim.p8, trim.p9), (15500, -14600, 6000));
+        assert_eq!((trim.h1, trim.h2, trim.h3), (75, 362, 0));
+        assert_eq!((trim.h4, trim.h5, trim.h6), (313, 50, 30));
+    }
+
+    #[test]
+    fn parses_negative_humidity_trims() {
+        // H4 = -300 (0xED4), H5 = -20 (0xFEC), H6 = -10
+        let trim = Trim::parse(&calib_00(), &[0x6A, 0x01, 0x00, 0xED, 0xC4, 0xFE, 0xF6]);
+        assert_eq!((trim.h4, trim.h5, trim.h6), (-300, -20, -10));
+    }
+
+    #[test]
+    fn compensates_the_datasheet_example() {
+        let trim = Trim::parse(&calib_00(), &CALIB_26);
+        let (temperature, pressure, humidity) = trim.compensate(519888, 415148, 30000);
+        // Datasheet: 25.08 °C and 100653.27 Pa
+        assert!((temperature - 25.08).abs() < 0.005);
+        assert!((pressure - 1006.5327).abs() < 0.001);
+        // The datasheet's integer compensation gives 54.997 %
+        assert!((humidity - 55.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn reads_over_i2c() {
+        let mut i2c = Mock::new(&[init(), measurement(DATA)].concat());
+        let mut sensor = Bme280::new(i2c.clone(), NoopDelay::new(), DEFAULT_ADDRESS);
+
+        let mut out = Readings::new();
+        block_on(sensor.read(&mut out)).unwrap();
+        let quantities: Vec<_> = out.iter().map(|m| m.quantity).collect();
+        assert_eq!(quantities, [Quantity::Temperature, Quantity::Humidity, Quantity::Pressure]);
+        assert!((out[0].value - 25.08).abs() < 0.005);
+        i2c.done();
+    }
+
+    #[test]
+    fn wrong_chip_id() {
+        let mut i2c = Mock::new(&[Transaction::write_read(DEFAULT_ADDRESS, vec![REG_CHIP_ID], vec![0x58])]);
+        let mut sensor = Bme280::new(i2c.clone(), NoopDelay::new(), DEFAULT_ADDRESS);
+
+        assert_eq!(block_on(sensor.measure()), Err(SensorError::NotFound));
+        i2c.done();
+    }
+
+    #[test]
+    fn skipped_measurement_is_not_ready() {
+        let skipped = [0x80, 0x00, 0x00, 0x80, 0x00, 0x00, 0x80, 0x00];
+        let mut i2c = Mock::new(&[init(), measurement(skipped)].concat());
+        let mut sensor = Bme280::new(i2c.clone(), NoopDelay::new(), DEFAULT_ADDRESS);
+
+        assert_eq!(block_on(sensor.measure()), Err(SensorError::NotReady));
+        i2c.done();
+    }
+
+    #[test]
+    fn reloads_the_trim_after_a_bus_error() {
+        let mut i2c = Mock::new(
+            &[
+                init(),
+                vec![Transaction::write(DEFAULT_ADDRESS, vec![REG_CTRL_MEAS, CTRL_MEAS_FORCED]).with_error(ErrorKind::Other)],
+                init(),
+                measurement(DATA),
+            ]
+            .concat(),
+        );
+        let mut sensor = Bme280::new(i2c.clone(), NoopDelay::new(), DEFAULT_ADDRESS);
+
+        let mut out = Readings::new();
+        assert_eq!(block_on(sensor.read(&mut out)), Err(SensorError::Bus));
+        block_on(sensor.read(&mut out)).unwrap();
+        i2c.done();
+    }
+}
@@ -0,0 +1,205 @@
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::{InputPin, OutputPin};
+use embedded_hal_async::delay::DelayNs as AsyncDelayNs;
+
+use super::onewire::{crc8, OneWire};
+use super::{push, Quantity, Readings, Sensor, SensorError, SensorKind};
+
+// --- Maxim DS18B20 ---
+// A single probe on the bus (addressed with Skip ROM). A 12-bit conversion
+// takes up to 750 ms, so reads are pipelined: each read collects the result
+// of the previous conversion and starts the next one. The first read after
+// power-up therefore reports `NotReady`. 85 °C is also the value the probe
+// powers up with, so it is only trusted once a conversion has been read.
+
+const CMD_SKIP_ROM: u8 = 0xCC;
+const CMD_CONVERT_T: u8 = 0x44;
+const CMD_READ_SCRATCHPAD: u8 = 0xBE;
+
+/// Scratchpad temperature after power-on, before any conversion (85 °C).
+const POWER_ON_RAW: i16 = 0x0550;
+
+pub struct Ds18b20<P, D> {
+    bus: OneWire<P, D>,
+    /// A conversion was started by the previous read.
+    converting: bool,
+    /// A conversion has been read since the probe was found, so its
+    /// scratchpad no longer holds the power-on value.
+    converted: bool,
+}
+
+impl<P: InputPin + OutputPin, D: DelayNs + AsyncDelayNs> Ds18b20<P, D> {
+    pub fn new(pin: P, delay: D) -> Self {
+        Self { bus: OneWire::new(pin, delay), converting: false, converted: false }
+    }
+
+    async fn command(&mut self, command: u8) -> Result<(), SensorError> {
+        if !self.bus.reset().await? {
+            return Err(SensorError::NotFound);
+        }
+        self.bus.write_byte(CMD_SKIP_ROM).await?;
+        self.bus.write_byte(command).await
+    }
+
+    async fn read_scratchpad(&mut self) -> Result<[u8; 9], SensorError> {
+        self.command(CMD_READ_SCRATCHPAD).await?;
+        let mut scratchpad = [0u8; 9];
+        for byte in scratchpad.iter_mut() {
+            *byte = self.bus.read_byte().await?;
+        }
+        // A device that stopped answering after the presence pulse reads as all ones
+        if scratchpad.iter().all(|&b| b == 0xFF) {
+            return Err(SensorError::NotFound);
+        }
+        Ok(scratchpad)
+    }
+}
+
+/// Temperature (°C) from a scratchpad. `converted` is whether a conversion
+/// was read before; until then 85 °C is taken for the power-on value.
+fn temperature(scratchpad: &[u8; 9], converted: bool) -> Result<f32, SensorError> {
+    if crc8(&scratchpad[..8]) != scratchpad[8] {
+        return Err(SensorError::Crc);
+    }
+    let raw = i16::from_le_bytes([scratchpad[0], scratchpad[1]]);
+    if raw == POWER_ON_RAW && !converted {
+        return Err(SensorError::NotReady);
+    }
+    Ok(raw as f32 / 16.0)
+}
+
+impl<P: InputPin + OutputPin, D: DelayNs + AsyncDelayNs> Sensor for Ds18b20<P, D> {
+    fn kind(&self) -> SensorKind {
+        SensorKind::Ds18b20
+    }
+
+    async fn read(&mut self, out: &mut Readings) -> Result<(), SensorError> {
+        let result = if self.converting {
+            match self.read_scratchpad().await {
+                Ok(scratchpad) => {
+                    let result = temperature(&scratchpad, self.converted);
+                    if result != Err(SensorError::Crc) {
+                        self.converted = true;
+                    }
+                    result
+                }
+                // Perhaps unplugged: when it answers again it starts from power-on
+                Err(SensorError::NotFound) => {
+                    self.converted = false;
+                    Err(SensorError::NotFound)
+                }
+                Err(e) => Err(e),
+            }
+        } else {
+            Err(SensorError::NotReady)
+        };
+
+        self.converting = false;
+        self.command(CMD_CONVERT_T).await?;
+        self.converting = true;
+
+        push(out, Quantity::Temperature, result?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sensor::onewire::tests::{read, reset, write};
+    use embassy_futures::block_on;
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+    use embedded_hal_mock::eh1::digital::{Mock, State, Transaction};
+
+    /// 25.0625 °C, the datasheet example.
+    const SCRATCHPAD_25: [u8; 9] = [0x91, 0x01, 0x4B, 0x46, 0x7F, 0xFF, 0x0F, 0x10, 0x25];
+    /// The power-on contents, 85 °C.
+    const SCRATCHPAD_85: [u8; 9] = [0x50, 0x05, 0x4B, 0x46, 0x7F, 0xFF, 0x0C, 0x10, 0x1C];
+
+    fn convert() -> Vec<Transaction> {
+        [reset(true), write(CMD_SKIP_ROM), write(CMD_CONVERT_T)].concat()
+    }
+
+    fn scratchpad(bytes: [u8; 9]) -> Vec<Transaction> {
+        let mut transactions = [reset(true), write(CMD_SKIP_ROM), write(CMD_READ_SCRATCHPAD)].concat();
+        for byte in bytes {
+            transactions.extend(read(byte));
+        }
+        transactions
+    }
+
+    /// A probe whose pin sees `reads`, after the release in `new`.
+    fn probe(reads: &[Vec<Transaction>]) -> (Ds18b20<Mock, NoopDelay>, Mock) {
+        let pin = Mock::new(&[&[vec![Transaction::set(State::High)]], reads].concat().concat());
+        (Ds18b20::new(pin.clone(), NoopDelay::new()), pin)
+    }
+
+    #[test]
+    fn decodes_the_scratchpad() {
+        assert_eq!(temperature(&SCRATCHPAD_25, false), Ok(25.0625));
+        let below_zero = [0x5E, 0xFF, 0x4B, 0x46, 0x7F, 0xFF, 0x02, 0x10, 0xB6];
+        assert_eq!(temperature(&below_zero, false), Ok(-10.125));
+    }
+
+    #[test]
+    fn rejects_a_bad_crc() {
+        let mut corrupted = SCRATCHPAD_25;
+        corrupted[0] ^= 0x01;
+        assert_eq!(temperature(&corrupted, true), Err(SensorError::Crc));
+    }
+
+    #[test]
+    fn first_read_only_starts_a_conversion() {
+        let (mut sensor, mut pin) = probe(&[convert()]);
+        let mut out = Readings::new();
+
+        assert_eq!(block_on(sensor.read(&mut out)), Err(SensorError::NotReady));
+        assert!(out.is_empty());
+        pin.done();
+    }
+
+    #[test]
+    fn reads_the_previous_conversion() {
+        let (mut sensor, mut pin) = probe(&[convert(), scratchpad(SCRATCHPAD_25), convert()]);
+        let mut out = Readings::new();
+
+        let _ = block_on(sensor.read(&mut out));
+        block_on(sensor.read(&mut out)).unwrap();
+        assert_eq!(out[0].quantity, Quantity::Temperature);
+        assert_eq!(out[0].value, 25.0625);
+        pin.done();
+    }
+
+    #[test]
+    fn trusts_85_after_the_first_conversion() {
+        let (mut sensor, mut pin) =
+            probe(&[convert(), scratchpad(SCRATCHPAD_85), convert(), scratchpad(SCRATCHPAD_85), convert()]);
+        let mut out = Readings::new();
+
+        let _ = block_on(sensor.read(&mut out));
+        assert_eq!(block_on(sensor.read(&mut out)), Err(SensorError::NotReady));
+        block_on(sensor.read(&mut out)).unwrap();
+        assert_eq!(out[0].value, 85.0);
+        pin.done();
+    }
+
+    #[test]
+    fn missing_probe() {
+        let (mut sensor, mut pin) = probe(&[reset(false)]);
+        let mut out = Readings::new();
+
+        assert_eq!(block_on(sensor.read(&mut out)), Err(SensorError::NotFound));
+        pin.done();
+    }
+
+    #[test]
+    fn scratchpad_crc_error() {
+        let mut corrupted = SCRATCHPAD_25;
+        corrupted[8] ^= 0x80;
+        let (mut sensor, mut pin) = probe(&[convert(), scratchpad(corrupted), convert()]);
+        let mut out = Readings::new();
+
+        let _ = block_on(sensor.read(&mut out));
+        assert_eq!(block_on(sensor.read(&mut out)), Err(SensorError::Crc));
+        pin.done();
+    }
+}
@@ -0,0 +1,123 @@
+use heapless::Vec;
+use serde::{Serialize, Deserialize};
+pub use telemetry_schema::Quantity;
+
+pub mod analog;
+pub mod bme280;
+pub mod ds18b20;
+pub mod onewire;
+pub mod sht3x;
+
+// --- Sensors ---
+// Every sensor yields typed measurements through the `Sensor` trait. External
+// drivers are generic over the embedded-hal (async) I2C, GPIO and delay
+// traits; the tests below each driver run them against `embedded-hal-mock`.
+// The firmware only wires a driver up when its cargo feature is enabled, and
+// `AppConfig::sensors` selects which of the compiled-in sensors are used.
+
+/// Maximum number of sensors configured at once.
+pub const MAX_SENSORS: usize = 4;
+
+/// Maximum number of measurements (channels) across all sensors.
+pub const MAX_CHANNELS: usize = 12;
+
+/// One value read from a sensor.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Measurement {
+    pub quantity: Quantity,
+    pub value: f32,
+}
+
+pub type Readings = Vec<Measurement, MAX_CHANNELS>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SensorError {
+    /// The bus transaction failed (no ACK, arbitration lost, ...).
+    Bus,
+    /// The data arrived but its checksum did not match.
+    Crc,
+    /// Nothing answered, or the device is not the expected chip.
+    NotFound,
+    /// The device has no valid measurement yet.
+    NotReady,
+    /// More measurements than fit into `Readings`.
+    Overflow,
+}
+
+/// Supported sensor types, as named in `config.json`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum SensorKind {
+    /// The ESP32-C3 die temperature sensor (always available).
+    Internal,
+    /// Sensirion SHT3x temperature/humidity sensor on I2C.
+    Sht3x,
+    /// Bosch BME280 temperature/humidity/pressure sensor on I2C.
+    Bme280,
+    /// Maxim DS18B20 temperature probe on 1-Wire.
+    Ds18b20,
+    /// ADC inputs, configured in `AppConfig::adc_channels`. Each channel is
+    /// reported as `Quantity::Other(index)`.
+    Adc,
+}
+
+impl SensorKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "internal" => Some(Self::Internal),
+            "sht3x" => Some(Self::Sht3x),
+            "bme280" => Some(Self::Bme280),
+            "ds18b20" => Some(Self::Ds18b20),
+            "adc" => Some(Self::Adc),
+            _ => None,
+        }
+    }
+
+    /// Name used in `config.json` and as the `sensor` tag.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Internal => "internal",
+            Self::Sht3x => "sht3x",
+            Self::Bme280 => "bme280",
+            Self::Ds18b20 => "ds18b20",
+            Self::Adc => "adc",
+        }
+    }
+
+    /// Whether the driver was compiled into this firmware. The firmware's
+    /// sensor features enable the features of the same name here.
+    pub fn is_available(&self) -> bool {
+        match self {
+            Self::Internal => true,
+            Self::Sht3x => cfg!(feature = "sht3x"),
+            Self::Bme280 => cfg!(feature = "bme280"),
+            Self::Ds18b20 => cfg!(feature = "ds18b20"),
+            Self::Adc => true,
+        }
+    }
+
+    /// Quantities the sensor reports. ADC channels depend on the configuration,
+    /// so `Adc` reports none here.
+    pub fn quantities(&self) -> &'static [Quantity] {
+        match self {
+            Self::Internal | Self::Ds18b20 => &[Quantity::Temperature],
+            Self::Sht3x => &[Quantity::Temperature, Quantity::Humidity],
+            Self::Bme280 => &[Quantity::Temperature, Quantity::Humidity, Quantity::Pressure],
+            Self::Adc => &[],
+        }
+    }
+}
+
+// Sensors are only used through static dispatch (see `AnySensor`), so the
+// missing `Send` bound on the returned futures does not matter.
+#[allow(async_fn_in_trait)]
+pub trait Sensor {
+    fn kind(&self) -> SensorKind;
+
+    /// Takes one reading of every channel and appends it to `out`.
+    async fn read(&mut self, out: &mut Readings) -> Result<(), SensorError>;
+}
+
+/// Appends a measurement, failing if `out` is full.
+pub fn push(out: &mut Readings, quantity: Quantity, value: f32) -> Result<(), SensorError> {
+    out.push(Measurement { quantity, value }).map_err(|_| SensorError::Overflow)
+}
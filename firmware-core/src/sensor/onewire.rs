@@ -0,0 +1,186 @@
+use embassy_futures::yield_now;
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::{InputPin, OutputPin};
+use embedded_hal_async::delay::DelayNs as AsyncDelayNs;
+
+use super::SensorError;
+
+// --- Bit-banged 1-Wire Master ---
+// Standard-speed timings from Maxim AN126. The pin must be open-drain with a
+// pull-up (4.7 kΩ external recommended). Each time slot and the reset pulse
+// run in a critical section with busy-wait delays, so an interrupt cannot
+// stretch them. The gaps between slots have no upper limit, so the bus waits
+// out the reset recovery asynchronously and yields to other tasks after every
+// byte: no more than about half a millisecond is spent blocking at a time.
+
+/// `delay` must implement both the blocking delay (slot timing) and the async
+/// one (reset recovery), as `embassy_time::Delay` does.
+pub struct OneWire<P, D> {
+    pin: P,
+    delay: D,
+}
+
+impl<P: InputPin + OutputPin, D: DelayNs + AsyncDelayNs> OneWire<P, D> {
+    pub fn new(mut pin: P, delay: D) -> Self {
+        let _ = pin.set_high(); // Release the bus
+        Self { pin, delay }
+    }
+
+    /// Sends a reset pulse; returns `true` if a device answered with a presence pulse.
+    pub async fn reset(&mut self) -> Result<bool, SensorError> {
+        let present = critical_section::with(|_| -> Result<bool, SensorError> {
+            self.pin.set_low().map_err(|_| SensorError::Bus)?;
+            DelayNs::delay_us(&mut self.delay, 480);
+            self.pin.set_high().map_err(|_| SensorError::Bus)?;
+            DelayNs::delay_us(&mut self.delay, 70);
+            self.pin.is_low().map_err(|_| SensorError::Bus)
+        })?;
+        AsyncDelayNs::delay_us(&mut self.delay, 410).await;
+        Ok(present)
+    }
+
+    fn write_bit(&mut self, bit: bool) -> Result<(), SensorError> {
+        let (low_us, high_us) = if bit { (6, 64) } else { (60, 10) };
+        critical_section::with(|_| {
+            self.pin.set_low().map_err(|_| SensorError::Bus)?;
+            DelayNs::delay_us(&mut self.delay, low_us);
+            self.pin.set_high().map_err(|_| SensorError::Bus)?;
+            DelayNs::delay_us(&mut self.delay, high_us);
+            Ok(())
+        })
+    }
+
+    fn read_bit(&mut self) -> Result<bool, SensorError> {
+        critical_section::with(|_| {
+            self.pin.set_low().map_err(|_| SensorError::Bus)?;
+            DelayNs::delay_us(&mut self.delay, 6);
+            self.pin.set_high().map_err(|_| SensorError::Bus)?;
+            DelayNs::delay_us(&mut self.delay, 9);
+            let bit = self.pin.is_high().map_err(|_| SensorError::Bus)?;
+            DelayNs::delay_us(&mut self.delay, 55);
+            Ok(bit)
+        })
+    }
+
+    /// Writes a byte, least significant bit first.
+    pub async fn write_byte(&mut self, byte: u8) -> Result<(), SensorError> {
+        for i in 0..8 {
+            self.write_bit(byte & (1 << i) != 0)?;
+        }
+        yield_now().await;
+        Ok(())
+    }
+
+    /// Reads a byte, least significant bit first.
+    pub async fn read_byte(&mut self) -> Result<u8, SensorError> {
+        let mut byte = 0;
+        for i in 0..8 {
+            if self.read_bit()? {
+                byte |= 1 << i;
+            }
+        }
+        yield_now().await;
+        Ok(byte)
+    }
+}
+
+/// Dallas/Maxim CRC-8 (polynomial x^8 + x^5 + x^4 + 1, reflected).
+pub fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in data {
+        let mut byte = byte;
+        for _ in 0..8 {
+            let mix = (crc ^ byte) & 0x01;
+            crc >>= 1;
+            if mix != 0 {
+                crc ^= 0x8C;
+            }
+            byte >>= 1;
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use embassy_futures::block_on;
+    use embedded_hal_mock::eh1::delay::{CheckedDelay, NoopDelay, Transaction as Delay};
+    use embedded_hal_mock::eh1::digital::{Mock, State, Transaction};
+
+    /// Pin activity for a reset pulse.
+    pub(crate) fn reset(present: bool) -> Vec<Transaction> {
+        let answer = if present { State::Low } else { State::High };
+        vec![Transaction::set(State::Low), Transaction::set(State::High), Transaction::get(answer)]
+    }
+
+    /// Pin activity for writing `byte`.
+    pub(crate) fn write(byte: u8) -> Vec<Transaction> {
+        let _ = byte; // Write slots look the same on the pin, only their timing differs
+        (0..8).flat_map(|_| [Transaction::set(State::Low), Transaction::set(State::High)]).collect()
+    }
+
+    /// Pin activity for reading `byte` from a device.
+    pub(crate) fn read(byte: u8) -> Vec<Transaction> {
+        (0..8)
+            .flat_map(|i| {
+                let bit = if byte & (1 << i) != 0 { State::High } else { State::Low };
+                [Transaction::set(State::Low), Transaction::set(State::High), Transaction::get(bit)]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn crc_of_the_power_on_scratchpad() {
+        assert_eq!(crc8(&[0x50, 0x05, 0x4B, 0x46, 0x7F, 0xFF, 0x0C, 0x10]), 0x1C);
+    }
+
+    #[test]
+    fn reset_waits_out_the_recovery_asynchronously() {
+        let mut pin = Mock::new(&[vec![Transaction::set(State::High)], reset(true)].concat());
+        let mut delay = CheckedDelay::new(&[
+            Delay::blocking_delay_us(480),
+            Delay::blocking_delay_us(70),
+            Delay::async_delay_us(410),
+        ]);
+        let mut bus = OneWire::new(pin.clone(), delay.clone());
+
+        assert_eq!(block_on(bus.reset()), Ok(true));
+        pin.done();
+        delay.done();
+    }
+
+    #[test]
+    fn reset_without_presence_pulse() {
+        let mut pin = Mock::new(&[vec![Transaction::set(State::High)], reset(false)].concat());
+        let mut bus = OneWire::new(pin.clone(), NoopDelay::new());
+
+        assert_eq!(block_on(bus.reset()), Ok(false));
+        pin.done();
+    }
+
+    #[test]
+    fn write_slots_are_timed_by_bit() {
+        let mut pin = Mock::new(&[vec![Transaction::set(State::High)], write(0b01)].concat());
+        // LSB first: a 1 slot, then seven 0 slots
+        let mut slots = vec![Delay::blocking_delay_us(6), Delay::blocking_delay_us(64)];
+        for _ in 1..8 {
+            slots.extend([Delay::blocking_delay_us(60), Delay::blocking_delay_us(10)]);
+        }
+        let mut delay = CheckedDelay::new(&slots);
+        let mut bus = OneWire::new(pin.clone(), delay.clone());
+
+        block_on(bus.write_byte(0b01)).unwrap();
+        pin.done();
+        delay.done();
+    }
+
+    #[test]
+    fn reads_lsb_first() {
+        let mut pin = Mock::new(&[vec![Transaction::set(State::High)], read(0xA5)].concat());
+        let mut bus = OneWire::new(pin.clone(), NoopDelay::new());
+
+        assert_eq!(block_on(bus.read_byte()), Ok(0xA5));
+        pin.done();
+    }
+}
@@ -0,0 +1,131 @@
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::i2c::I2c;
+
+use super::{push, Quantity, Readings, Sensor, SensorError, SensorKind};
+
+// --- Sensirion SHT3x ---
+// Single-shot measurement, high repeatability, no clock stretching.
+
+/// Address with the ADDR pin low (0x45 with it high).
+pub const DEFAULT_ADDRESS: u8 = 0x44;
+
+const CMD_MEASURE_HIGH: [u8; 2] = [0x24, 0x00];
+/// Maximum measurement duration for high repeatability is 15.5 ms.
+const MEASURE_TIME_MS: u32 = 16;
+
+pub struct Sht3x<I, D> {
+    i2c: I,
+    delay: D,
+    address: u8,
+}
+
+impl<I: I2c, D: DelayNs> Sht3x<I, D> {
+    pub fn new(i2c: I, delay: D, address: u8) -> Self {
+        Self { i2c, delay, address }
+    }
+
+    /// Returns temperature (°C) and relative humidity (%).
+    pub async fn measure(&mut self) -> Result<(f32, f32), SensorError> {
+        self.i2c.write(self.address, &CMD_MEASURE_HIGH).await.map_err(|_| SensorError::Bus)?;
+        self.delay.delay_ms(MEASURE_TIME_MS).await;
+
+        let mut buf = [0u8; 6];
+        self.i2c.read(self.address, &mut buf).await.map_err(|_| SensorError::Bus)?;
+        let raw_t = checked_word(&buf[0..3])?;
+        let raw_rh = checked_word(&buf[3..6])?;
+
+        let temperature = -45.0 + 175.0 * raw_t as f32 / 65535.0;
+        let humidity = (100.0 * raw_rh as f32 / 65535.0).clamp(0.0, 100.0);
+        Ok((temperature, humidity))
+    }
+}
+
+impl<I: I2c, D: DelayNs> Sensor for Sht3x<I, D> {
+    fn kind(&self) -> SensorKind {
+        SensorKind::Sht3x
+    }
+
+    async fn read(&mut self, out: &mut Readings) -> Result<(), SensorError> {
+        let (temperature, humidity) = self.measure().await?;
+        push(out, Quantity::Temperature, temperature)?;
+        push(out, Quantity::Humidity, humidity)
+    }
+}
+
+/// Big-endian word followed by its CRC.
+fn checked_word(bytes: &[u8]) -> Result<u16, SensorError> {
+    if crc8(&bytes[..2]) != bytes[2] {
+        return Err(SensorError::Crc);
+    }
+    Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+/// CRC-8 as used by Sensirion: polynomial 0x31, init 0xFF.
+pub fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0xFFu8;
+    for &byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x31 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embassy_futures::block_on;
+    use embedded_hal::i2c::ErrorKind;
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+    use embedded_hal_mock::eh1::i2c::{Mock, Transaction};
+
+    fn measurement(response: [u8; 6]) -> Vec<Transaction> {
+        vec![
+            Transaction::write(DEFAULT_ADDRESS, CMD_MEASURE_HIGH.to_vec()),
+            Transaction::read(DEFAULT_ADDRESS, response.to_vec()),
+        ]
+    }
+
+    #[test]
+    fn crc_matches_the_datasheet() {
+        assert_eq!(crc8(&[0xBE, 0xEF]), 0x92);
+    }
+
+    #[test]
+    fn reads_temperature_and_humidity() {
+        let mut i2c = Mock::new(&measurement([0x66, 0x66, 0x93, 0x80, 0x00, 0xA2]));
+        let mut sensor = Sht3x::new(i2c.clone(), NoopDelay::new(), DEFAULT_ADDRESS);
+
+        let mut out = Readings::new();
+        block_on(sensor.read(&mut out)).unwrap();
+        assert_eq!(out[0].quantity, Quantity::Temperature);
+        assert!((out[0].value - 25.0).abs() < 0.01);
+        assert_eq!(out[1].quantity, Quantity::Humidity);
+        assert!((out[1].value - 50.0).abs() < 0.01);
+        i2c.done();
+    }
+
+    #[test]
+    fn rejects_a_bad_crc() {
+        // Humidity CRC off by one
+        let mut i2c = Mock::new(&measurement([0x66, 0x66, 0x93, 0x80, 0x00, 0xA3]));
+        let mut sensor = Sht3x::new(i2c.clone(), NoopDelay::new(), DEFAULT_ADDRESS);
+
+        let mut out = Readings::new();
+        assert_eq!(block_on(sensor.read(&mut out)), Err(SensorError::Crc));
+        assert!(out.is_empty());
+        i2c.done();
+    }
+
+    #[test]
+    fn bus_error_ends_the_measurement() {
+        let mut i2c = Mock::new(&[
+            Transaction::write(DEFAULT_ADDRESS, CMD_MEASURE_HIGH.to_vec()).with_error(ErrorKind::Other),
+        ]);
+        let mut sensor = Sht3x::new(i2c.clone(), NoopDelay::new(), DEFAULT_ADDRESS);
+
+        assert_eq!(block_on(sensor.measure()), Err(SensorError::Bus));
+        i2c.done();
+    }
+}
@@ -0,0 +1,154 @@
+// --- SNTP Packets ---
+// Requests and replies of SNTPv4 (RFC 4330). The request's transmit timestamp
+// is a nonce the server echoes back as the originate timestamp, which ties a
+// reply to our request. Keeping the clock is up to the firmware (`src/sntp.rs`).
+
+pub const SNTP_PACKET_LEN: usize = 48;
+
+/// Seconds between the NTP epoch (1900) and the Unix epoch (1970).
+const NTP_UNIX_OFFSET_SECS: u64 = 2_208_988_800;
+
+/// Length of an NTP era: the 32-bit seconds counter wraps in February 2036.
+const NTP_ERA_SECS: u64 = 1 << 32;
+
+/// Why a reply was not used.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SntpError {
+    /// Shorter than an NTP header.
+    Short,
+    /// Not a server or broadcast packet.
+    NotServer,
+    /// The originate timestamp is not the one we sent: a stale or forged reply.
+    WrongOriginate,
+    /// A "kiss-o'-death" (stratum 0) or an unsynchronised server (LI = 3).
+    Unsynchronised,
+    /// The server sent no transmit timestamp.
+    NoTime,
+}
+
+/// Builds an SNTP client request (LI = 0, VN = 4, Mode = 3) carrying `nonce`
+/// as its transmit timestamp. The nonce must not be all zeros.
+pub fn build_request(out: &mut [u8; SNTP_PACKET_LEN], nonce: [u8; 8]) {
+    out.fill(0);
+    out[0] = 0x23;
+    out[40..48].copy_from_slice(&nonce);
+}
+
+/// Extracts the server's transmit timestamp as Unix milliseconds from the
+/// reply to a request sent with `nonce`.
+pub fn parse_response(packet: &[u8], nonce: [u8; 8]) -> Result<u64, SntpError> {
+    if packet.len() < SNTP_PACKET_LEN {
+        return Err(SntpError::Short);
+    }
+    let leap = packet[0] >> 6;
+    let mode = packet[0] & 0x07;
+    let stratum = packet[1];
+    if !(mode == 4 || mode == 5) {
+        return Err(SntpError::NotServer);
+    }
+    if packet[24..32] != nonce {
+        return Err(SntpError::WrongOriginate);
+    }
+    if leap == 3 || stratum == 0 {
+        return Err(SntpError::Unsynchronised);
+    }
+
+    let secs = u32::from_be_bytes([packet[40], packet[41], packet[42], packet[43]]) as u64;
+    let frac = u32::from_be_bytes([packet[44], packet[45], packet[46], packet[47]]) as u64;
+    if secs == 0 && frac == 0 {
+        return Err(SntpError::NoTime);
+    }
+    Ok(unix_secs(secs) * 1000 + ((frac * 1000) >> 32))
+}
+
+/// Converts NTP seconds to Unix seconds. Values before the Unix epoch cannot
+/// be a current time, so they are taken to be in era 1 (from 2036 on).
+fn unix_secs(ntp_secs: u64) -> u64 {
+    if ntp_secs >= NTP_UNIX_OFFSET_SECS {
+        ntp_secs - NTP_UNIX_OFFSET_SECS
+    } else {
+        ntp_secs + NTP_ERA_SECS - NTP_UNIX_OFFSET_SECS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NONCE: [u8; 8] = [0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xF0];
+
+    /// A stratum 2 server reply to `NONCE` with the given transmit time.
+    fn reply(secs: u32, frac: u32) -> [u8; SNTP_PACKET_LEN] {
+        let mut packet = [0u8; SNTP_PACKET_LEN];
+        packet[0] = 0x24; // LI 0, VN 4, Mode 4
+        packet[1] = 2;
+        packet[24..32].copy_from_slice(&NONCE);
+        packet[40..44].copy_from_slice(&secs.to_be_bytes());
+        packet[44..48].copy_from_slice(&frac.to_be_bytes());
+        packet
+    }
+
+    #[test]
+    fn builds_the_request() {
+        let mut out = [0xFF; SNTP_PACKET_LEN];
+        build_request(&mut out, NONCE);
+        assert_eq!(out[0], 0x23);
+        assert!(out[1..40].iter().all(|&b| b == 0));
+        assert_eq!(out[40..], NONCE);
+    }
+
+    #[test]
+    fn parses_a_known_reply() {
+        // 2026-01-01T00:00:00.5Z: NTP 3976214400 = Unix 1767225600
+        assert_eq!(parse_response(&reply(3_976_214_400, 1 << 31), NONCE), Ok(1_767_225_600_500));
+        assert_eq!(parse_response(&reply(NTP_UNIX_OFFSET_SECS as u32, 0), NONCE), Ok(0));
+        // Trailing bytes (extension fields, MAC) are ignored
+        let mut long = [0u8; 68];
+        long[..SNTP_PACKET_LEN].copy_from_slice(&reply(3_976_214_400, 0));
+        assert_eq!(parse_response(&long, NONCE), Ok(1_767_225_600_000));
+    }
+
+    #[test]
+    fn handles_the_2036_era_rollover() {
+        // The last second of era 0 and the first of era 1
+        assert_eq!(parse_response(&reply(u32::MAX, 0), NONCE), Ok(2_085_978_495_000));
+        assert_eq!(parse_response(&reply(0, 1 << 31), NONCE), Ok(2_085_978_496_500));
+        assert_eq!(parse_response(&reply(60, 0), NONCE), Ok(2_085_978_556_000));
+    }
+
+    #[test]
+    fn rejects_kiss_of_death_and_unsynchronised_servers() {
+        let mut kiss = reply(3_976_214_400, 0);
+        kiss[1] = 0;
+        kiss[12..16].copy_from_slice(b"RATE");
+        assert_eq!(parse_response(&kiss, NONCE), Err(SntpError::Unsynchronised));
+        let mut alarm = reply(3_976_214_400, 0);
+        alarm[0] |= 0xC0;
+        assert_eq!(parse_response(&alarm, NONCE), Err(SntpError::Unsynchronised));
+    }
+
+    #[test]
+    fn rejects_short_packets() {
+        assert_eq!(parse_response(&reply(3_976_214_400, 0)[..47], NONCE), Err(SntpError::Short));
+        assert_eq!(parse_response(&[], NONCE), Err(SntpError::Short));
+    }
+
+    #[test]
+    fn rejects_replies_to_other_requests() {
+        assert_eq!(parse_response(&reply(3_976_214_400, 0), [0; 8]), Err(SntpError::WrongOriginate));
+        let mut forged = reply(3_976_214_400, 0);
+        forged[31] ^= 1;
+        assert_eq!(parse_response(&forged, NONCE), Err(SntpError::WrongOriginate));
+    }
+
+    #[test]
+    fn rejects_client_packets_and_missing_time() {
+        let mut client = reply(3_976_214_400, 0);
+        client[0] = 0x23;
+        assert_eq!(parse_response(&client, NONCE), Err(SntpError::NotServer));
+        let mut broadcast = reply(3_976_214_400, 0);
+        broadcast[0] = 0x25;
+        assert!(parse_response(&broadcast, NONCE).is_ok());
+        assert_eq!(parse_response(&reply(0, 0), NONCE), Err(SntpError::NoTime));
+    }
+}
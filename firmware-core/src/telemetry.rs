@@ -0,0 +1,424 @@
+use core::fmt::Write;
+use heapless::String;
+use serde::{Serialize, Deserialize};
+use telemetry_schema::{Field, Quantity, TelemetryFrame};
+
+// --- Telemetry Payload Encoders ---
+// A reading is described as a `Point` (measurement, tags, fields, timestamp)
+// and encoded in the format selected in `AppConfig`. Nothing here touches
+// hardware, so the encoders can be exercised on the host.
+
+/// Wire format for published readings.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum PayloadFormat {
+    /// Bare value, e.g. `23.4` (Telegraf `data_format = "value"`). Tags,
+    /// extra fields and the timestamp are dropped.
+    Value,
+    /// JSON object: `{"measurement":..,"tags":{..},"fields":{..},"ts":..}`.
+    Json,
+    /// Influx line protocol with a nanosecond timestamp.
+    Influx,
+    /// Versioned postcard frame (see `telemetry-schema`), for low-bandwidth links.
+    Binary,
+}
+
+impl PayloadFormat {
+    /// Parses the names used in `config.json`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "value" => Some(Self::Value),
+            "json" => Some(Self::Json),
+            "influx" => Some(Self::Influx),
+            "binary" => Some(Self::Binary),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Value => "value",
+            Self::Json => "json",
+            Self::Influx => "influx",
+            Self::Binary => "binary",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FieldValue<'a> {
+    Float(f32),
+    Int(i64),
+    UInt(u64),
+    Bool(bool),
+    Str(&'a str),
+}
+
+/// A single measurement with its tags and fields.
+pub struct Point<'a> {
+    pub measurement: &'a str,
+    pub tags: &'a [(&'a str, &'a str)],
+    pub fields: &'a [(&'a str, FieldValue<'a>)],
+    /// Unix time in milliseconds, if known.
+    pub timestamp_ms: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EncodeError {
+    /// The output buffer is too small.
+    Overflow,
+    /// No field could be encoded (e.g. every value was NaN).
+    NoFields,
+    /// `PayloadFormat::Binary` is not a text format; use `encode_binary`.
+    NotText,
+}
+
+impl From<core::fmt::Error> for EncodeError {
+    fn from(_: core::fmt::Error) -> Self {
+        EncodeError::Overflow
+    }
+}
+
+/// Encodes `point` into `out` (which is cleared first) using one of the text formats.
+/// Use `encode_binary` for `PayloadFormat::Binary`.
+pub fn encode<const N: usize>(format: PayloadFormat, point: &Point, out: &mut String<N>) -> Result<(), EncodeError> {
+    out.clear();
+    match format {
+        PayloadFormat::Value => encode_value(point, out),
+        PayloadFormat::Binary => Err(EncodeError::NotText),
+        PayloadFormat::Json => encode_json(point, out),
+        PayloadFormat::Influx => encode_influx(point, out),
+    }
+}
+
+/// NaN and infinities cannot be represented in JSON numbers or Influx floats.
+fn is_encodable(value: &FieldValue) -> bool {
+    match value {
+        FieldValue::Float(v) => v.is_finite(),
+        _ => true,
+    }
+}
+
+fn encode_value<const N: usize>(point: &Point, out: &mut String<N>) -> Result<(), EncodeError> {
+    let (_, value) = point.fields.iter().find(|(_, v)| is_encodable(v)).ok_or(EncodeError::NoFields)?;
+    match value {
+        FieldValue::Float(v) => write!(out, "{:.1}", v)?,
+        FieldValue::Int(v) => write!(out, "{}", v)?,
+        FieldValue::UInt(v) => write!(out, "{}", v)?,
+        FieldValue::Bool(v) => write!(out, "{}", v)?,
+        FieldValue::Str(v) => out.push_str(v).map_err(|_| EncodeError::Overflow)?,
+    }
+    Ok(())
+}
+
+// --- JSON ---
+
+pub fn push_json_str<const N: usize>(s: &str, out: &mut String<N>) -> Result<(), EncodeError> {
+    out.push('"').map_err(|_| EncodeError::Overflow)?;
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\"").map_err(|_| EncodeError::Overflow)?,
+            '\\' => out.push_str("\\\\").map_err(|_| EncodeError::Overflow)?,
+            '\n' => out.push_str("\\n").map_err(|_| EncodeError::Overflow)?,
+            '\r' => out.push_str("\\r").map_err(|_| EncodeError::Overflow)?,
+            '\t' => out.push_str("\\t").map_err(|_| EncodeError::Overflow)?,
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32)?,
+            c => out.push(c).map_err(|_| EncodeError::Overflow)?,
+        }
+    }
+    out.push('"').map_err(|_| EncodeError::Overflow)
+}
+
+fn encode_json<const N: usize>(point: &Point, out: &mut String<N>) -> Result<(), EncodeError> {
+    out.push_str("{\"measurement\":").map_err(|_| EncodeError::Overflow)?;
+    push_json_str(point.measurement, out)?;
+
+    out.push_str(",\"tags\":{").map_err(|_| EncodeError::Overflow)?;
+    for (i, (key, value)) in point.tags.iter().enumerate() {
+        if i > 0 {
+            out.push(',').map_err(|_| EncodeError::Overflow)?;
+        }
+        push_json_str(key, out)?;
+        out.push(':').map_err(|_| EncodeError::Overflow)?;
+        push_json_str(value, out)?;
+    }
+
+    out.push_str("},\"fields\":{").map_err(|_| EncodeError::Overflow)?;
+    let mut written = 0;
+    for (key, value) in point.fields.iter() {
+        if written > 0 {
+            out.push(',').map_err(|_| EncodeError::Overflow)?;
+        }
+        push_json_str(key, out)?;
+        out.push(':').map_err(|_| EncodeError::Overflow)?;
+        match value {
+            FieldValue::Float(v) if v.is_finite() => write!(out, "{}", v)?,
+            FieldValue::Float(_) => out.push_str("null").map_err(|_| EncodeError::Overflow)?,
+            FieldValue::Int(v) => write!(out, "{}", v)?,
+            FieldValue::UInt(v) => write!(out, "{}", v)?,
+            FieldValue::Bool(v) => write!(out, "{}", v)?,
+            FieldValue::Str(v) => push_json_str(v, out)?,
+        }
+        written += 1;
+    }
+    if written == 0 {
+        return Err(EncodeError::NoFields);
+    }
+    out.push('}').map_err(|_| EncodeError::Overflow)?;
+
+    if let Some(ts) = point.timestamp_ms {
+        write!(out, ",\"ts\":{}", ts)?;
+    }
+    out.push('}').map_err(|_| EncodeError::Overflow)
+}
+
+// --- Influx Line Protocol ---
+// measurement,tag=v,tag=v field=v,field=v timestamp_ns
+
+/// Prefixes every character in `chars` with a backslash.
+fn push_escaped<const N: usize>(s: &str, chars: &[char], out: &mut String<N>) -> Result<(), EncodeError> {
+    for c in s.chars() {
+        if chars.contains(&c) {
+            out.push('\\').map_err(|_| EncodeError::Overflow)?;
+        }
+        out.push(c).map_err(|_| EncodeError::Overflow)?;
+    }
+    Ok(())
+}
+
+const MEASUREMENT_ESCAPES: &[char] = &[',', ' '];
+const KEY_ESCAPES: &[char] = &[',', '=', ' '];
+const STRING_ESCAPES: &[char] = &['"', '\\'];
+
+fn encode_influx<const N: usize>(point: &Point, out: &mut String<N>) -> Result<(), EncodeError> {
+    push_escaped(point.measurement, MEASUREMENT_ESCAPES, out)?;
+
+    for (key, value) in point.tags.iter() {
+        // Empty tag values are not allowed in line protocol
+        if value.is_empty() {
+            continue;
+        }
+        out.push(',').map_err(|_| EncodeError::Overflow)?;
+        push_escaped(key, KEY_ESCAPES, out)?;
+        out.push('=').map_err(|_| EncodeError::Overflow)?;
+        push_escaped(value, KEY_ESCAPES, out)?;
+    }
+
+    let mut written = 0;
+    for (key, value) in point.fields.iter().filter(|(_, v)| is_encodable(v)) {
+        out.push(if written == 0 { ' ' } else { ',' }).map_err(|_| EncodeError::Overflow)?;
+        push_escaped(key, KEY_ESCAPES, out)?;
+        out.push('=').map_err(|_| EncodeError::Overflow)?;
+        match value {
+            FieldValue::Float(v) => write!(out, "{}", v)?,
+            FieldValue::Int(v) => write!(out, "{}i", v)?,
+            FieldValue::UInt(v) => write!(out, "{}u", v)?,
+            FieldValue::Bool(v) => write!(out, "{}", v)?,
+            FieldValue::Str(v) => {
+                out.push('"').map_err(|_| EncodeError::Overflow)?;
+                push_escaped(v, STRING_ESCAPES, out)?;
+                out.push('"').map_err(|_| EncodeError::Overflow)?;
+            }
+        }
+        written += 1;
+    }
+    if written == 0 {
+        return Err(EncodeError::NoFields);
+    }
+
+    if let Some(ts) = point.timestamp_ms {
+        write!(out, " {}000000", ts)?;
+    }
+    Ok(())
+}
+
+// --- Binary (postcard) ---
+
+/// Maps a field to a binary quantity. The generic `value` field takes the
+/// quantity of the point itself; unknown names cannot be represented.
+fn quantity_for(value_quantity: Quantity, key: &str) -> Option<Quantity> {
+    match key {
+        "value" => Some(value_quantity),
+        "temperature" => Some(Quantity::Temperature),
+        "humidity" => Some(Quantity::Humidity),
+        "pressure" => Some(Quantity::Pressure),
+        "voltage" => Some(Quantity::Voltage),
+        "rssi" => Some(Quantity::Rssi),
+        _ => None,
+    }
+}
+
+/// Encodes `point` as a versioned binary frame into `buf` and returns the used part.
+/// The `value` field is sent as `quantity`. Tags are not included (the topic
+/// identifies the device); string and bool fields and unknown field names are skipped.
+pub fn encode_binary<'b>(point: &Point, quantity: Quantity, seq: u32, buf: &'b mut [u8]) -> Result<&'b [u8], EncodeError> {
+    let mut frame = TelemetryFrame { seq, timestamp_ms: point.timestamp_ms, fields: heapless::Vec::new() };
+
+    for (key, value) in point.fields.iter() {
+        let value = match value {
+            FieldValue::Float(v) => *v,
+            FieldValue::Int(v) => *v as f32,
+            FieldValue::UInt(v) => *v as f32,
+            FieldValue::Bool(_) | FieldValue::Str(_) => continue,
+        };
+        if let Some(field_quantity) = quantity_for(quantity, key) {
+            frame.fields.push(Field { quantity: field_quantity, value }).map_err(|_| EncodeError::Overflow)?;
+        }
+    }
+    if frame.fields.is_empty() {
+        return Err(EncodeError::NoFields);
+    }
+
+    telemetry_schema::encode(&frame, buf).map(|b| &*b).map_err(|_| EncodeError::Overflow)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TAGS: &[(&str, &str)] = &[("device", "esp32"), ("site", "lab")];
+
+    fn point<'a>(fields: &'a [(&'a str, FieldValue<'a>)]) -> Point<'a> {
+        Point { measurement: "temperature", tags: TAGS, fields, timestamp_ms: Some(1_767_225_600_000) }
+    }
+
+    fn encoded(format: PayloadFormat, point: &Point) -> String<256> {
+        let mut out = String::new();
+        encode(format, point, &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn influx_line() {
+        let fields = [("value", FieldValue::Float(23.4)), ("n", FieldValue::UInt(4)), ("rssi", FieldValue::Int(-61))];
+        assert_eq!(
+            encoded(PayloadFormat::Influx, &point(&fields)),
+            "temperature,device=esp32,site=lab value=23.4,n=4u,rssi=-61i 1767225600000000000"
+        );
+    }
+
+    #[test]
+    fn influx_escapes_measurement_tags_and_keys() {
+        let tags = [("room name", "lab, east"), ("k=v", "a=b")];
+        let fields = [("my field", FieldValue::Float(1.0))];
+        let point = Point { measurement: "air temp,c", tags: &tags, fields: &fields, timestamp_ms: None };
+        assert_eq!(
+            encoded(PayloadFormat::Influx, &point),
+            r"air\ temp\,c,room\ name=lab\,\ east,k\=v=a\=b my\ field=1"
+        );
+    }
+
+    #[test]
+    fn influx_escapes_string_fields() {
+        let fields = [("msg", FieldValue::Str(r#"say "hi" C:\x"#))];
+        let point = Point { measurement: "log", tags: &[], fields: &fields, timestamp_ms: None };
+        assert_eq!(encoded(PayloadFormat::Influx, &point), r#"log msg="say \"hi\" C:\\x""#);
+    }
+
+    #[test]
+    fn influx_skips_empty_tags() {
+        let tags = [("device", "esp32"), ("site", "")];
+        let fields = [("value", FieldValue::Float(1.5))];
+        let point = Point { measurement: "t", tags: &tags, fields: &fields, timestamp_ms: None };
+        assert_eq!(encoded(PayloadFormat::Influx, &point), "t,device=esp32 value=1.5");
+    }
+
+    #[test]
+    fn json_escapes_strings() {
+        let mut out = String::<64>::new();
+        push_json_str("a\"b\\c\nd\u{1}", &mut out).unwrap();
+        assert_eq!(out, r#""a\"b\\c\nd\u0001""#);
+
+        let tags = [("site", "lab \"east\", 1=2")];
+        let fields = [("value", FieldValue::Float(1.5))];
+        let point = Point { measurement: "t", tags: &tags, fields: &fields, timestamp_ms: None };
+        assert_eq!(
+            encoded(PayloadFormat::Json, &point),
+            r#"{"measurement":"t","tags":{"site":"lab \"east\", 1=2"},"fields":{"value":1.5}}"#
+        );
+    }
+
+    #[test]
+    fn json_object() {
+        let fields = [("value", FieldValue::Float(23.4)), ("ok", FieldValue::Bool(true))];
+        assert_eq!(
+            encoded(PayloadFormat::Json, &point(&fields)),
+            r#"{"measurement":"temperature","tags":{"device":"esp32","site":"lab"},"fields":{"value":23.4,"ok":true},"ts":1767225600000}"#
+        );
+    }
+
+    #[test]
+    fn non_finite_values() {
+        for v in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            let fields = [("value", FieldValue::Float(v)), ("n", FieldValue::UInt(3))];
+            let point = point(&fields);
+            // Dropped from line protocol, null in JSON, skipped for the bare value
+            assert_eq!(
+                encoded(PayloadFormat::Influx, &point),
+                "temperature,device=esp32,site=lab n=3u 1767225600000000000"
+            );
+            assert!(encoded(PayloadFormat::Json, &point).contains(r#""fields":{"value":null,"n":3}"#));
+            assert_eq!(encoded(PayloadFormat::Value, &point), "3");
+
+            let only = [("value", FieldValue::Float(v))];
+            let mut out = String::<64>::new();
+            assert_eq!(encode(PayloadFormat::Influx, &self::point(&only), &mut out), Err(EncodeError::NoFields));
+            assert_eq!(encode(PayloadFormat::Value, &self::point(&only), &mut out), Err(EncodeError::NoFields));
+        }
+    }
+
+    #[test]
+    fn floats_keep_f32_precision() {
+        // Printed as f32, not widened to f64 (which would give 23.399999618530273)
+        for (v, text) in [(23.4, "23.4"), (0.1, "0.1"), (-40.0, "-40"), (1013.25, "1013.25"), (1e-7, "0.0000001")] {
+            let fields = [("value", FieldValue::Float(v))];
+            let point = Point { measurement: "t", tags: &[], fields: &fields, timestamp_ms: None };
+            assert_eq!(encoded(PayloadFormat::Influx, &point), format!("t value={}", text).as_str());
+            assert!(encoded(PayloadFormat::Json, &point).ends_with(&format!(r#""value":{}}}}}"#, text)));
+        }
+    }
+
+    #[test]
+    fn value_rounds_to_one_decimal() {
+        // 23.45 is stored as 23.4500008 and rounds up; 1013.25 is exact and
+        // rounds to even
+        for (v, text) in [(23.44, "23.4"), (23.46, "23.5"), (23.45, "23.5"), (-0.04, "-0.0"), (25.0, "25.0"), (1013.25, "1013.2")] {
+            let fields = [("value", FieldValue::Float(v))];
+            assert_eq!(encoded(PayloadFormat::Value, &point(&fields)), text, "{}", v);
+        }
+    }
+
+    #[test]
+    fn binary_is_not_text() {
+        let fields = [("value", FieldValue::Float(23.4))];
+        let mut out = String::<64>::new();
+        assert_eq!(encode(PayloadFormat::Binary, &point(&fields), &mut out), Err(EncodeError::NotText));
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let fields = [
+            ("value", FieldValue::Float(48.5)),
+            ("n", FieldValue::UInt(4)),
+            ("rssi", FieldValue::Int(-61)),
+            ("sensor", FieldValue::Str("sht3x")),
+        ];
+        let mut buf = [0u8; telemetry_schema::MAX_FRAME_LEN];
+        let bytes = encode_binary(&point(&fields), Quantity::Humidity, 7, &mut buf).unwrap();
+        let frame = telemetry_schema::decode(bytes).unwrap();
+        assert_eq!(frame.seq, 7);
+        assert_eq!(frame.timestamp_ms, Some(1_767_225_600_000));
+        // `value` takes the point's quantity; `n` and strings have no quantity
+        assert_eq!(
+            frame.fields.as_slice(),
+            &[Field { quantity: Quantity::Humidity, value: 48.5 }, Field { quantity: Quantity::Rssi, value: -61.0 }]
+        );
+    }
+
+    #[test]
+    fn overflow() {
+        let fields = [("value", FieldValue::Float(23.4))];
+        let mut out = String::<16>::new();
+        assert_eq!(encode(PayloadFormat::Influx, &point(&fields), &mut out), Err(EncodeError::Overflow));
+        assert_eq!(encode(PayloadFormat::Json, &point(&fields), &mut out), Err(EncodeError::Overflow));
+    }
+}
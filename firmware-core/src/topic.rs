@@ -0,0 +1,187 @@
+use heapless::String;
+
+use crate::sensor::analog::MAX_CHANNEL_NAME;
+
+// --- MQTT Topic Templates ---
+// Topics are configured as templates such as `{site}/{device_id}/temperature`
+// and expanded once the values are known.
+
+/// Longest topic `mqtt_publish` sends: its 128-byte header buffer also holds
+/// the fixed header (up to 5 bytes) and the topic length (2 bytes).
+pub const MAX_TOPIC_LEN: usize = 128 - 7;
+
+/// Used when the configured template is invalid. Every channel gets its own
+/// topic, which `value` payloads need since they carry no channel name.
+pub const FALLBACK_TEMPLATE: &str = "sensors/{channel}";
+
+/// Stands in for `{channel}` when checking a template: ADC channel names can
+/// be `MAX_CHANNEL_NAME` bytes, longer than any quantity name.
+const LONGEST_CHANNEL: &str = "xxxxxxxxxxxxxxxx";
+const _: () = assert!(LONGEST_CHANNEL.len() == MAX_CHANNEL_NAME);
+
+/// Why a topic (or template) was rejected.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TopicError {
+    Empty,
+    TooLong,
+    /// `+` and `#` are only allowed in subscriptions.
+    Wildcard,
+    NullChar,
+    /// Topics starting with `$` are reserved for the broker.
+    Reserved,
+    UnknownPlaceholder,
+    UnterminatedPlaceholder,
+}
+
+/// Values substituted into a topic template.
+pub struct TopicVars<'a> {
+    pub site: &'a str,
+    pub device_id: &'a str,
+    /// Measurement channel, e.g. `temperature`.
+    pub channel: &'a str,
+}
+
+/// Checks a topic against the MQTT rules for publishing.
+pub fn validate_topic(topic: &str) -> Result<(), TopicError> {
+    if topic.is_empty() {
+        return Err(TopicError::Empty);
+    }
+    if topic.len() > MAX_TOPIC_LEN {
+        return Err(TopicError::TooLong);
+    }
+    if topic.starts_with('$') {
+        return Err(TopicError::Reserved);
+    }
+    for c in topic.chars() {
+        match c {
+            '+' | '#' => return Err(TopicError::Wildcard),
+            '\0' => return Err(TopicError::NullChar),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Expands `{site}`, `{device_id}` and `{channel}` in `template` and validates the result.
+pub fn expand<const N: usize>(template: &str, vars: &TopicVars) -> Result<String<N>, TopicError> {
+    let mut out = String::<N>::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]).map_err(|_| TopicError::TooLong)?;
+        let after = &rest[start + 1..];
+        let end = after.find('}').ok_or(TopicError::UnterminatedPlaceholder)?;
+
+        let value = match &after[..end] {
+            "site" => vars.site,
+            "device_id" => vars.device_id,
+            "channel" => vars.channel,
+            _ => return Err(TopicError::UnknownPlaceholder),
+        };
+        out.push_str(value).map_err(|_| TopicError::TooLong)?;
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest).map_err(|_| TopicError::TooLong)?;
+
+    validate_topic(&out)?;
+    Ok(out)
+}
+
+/// Checks that `template` expands to a publishable topic for every channel.
+pub fn check_template(template: &str, site: &str, device_id: &str) -> Result<(), TopicError> {
+    let vars = TopicVars { site, device_id, channel: LONGEST_CHANNEL };
+    expand::<MAX_TOPIC_LEN>(template, &vars).map(|_| ())
+}
+
+/// Subscription filter matching every topic `template` can expand to: each
+/// level holding a placeholder becomes `+`, e.g. `{site}/{device_id}/{channel}`
+/// gives `+/+/+` and `sensors/{channel}` gives `sensors/+`.
+pub fn subscription<const N: usize>(template: &str) -> Result<String<N>, TopicError> {
+    let mut out = String::<N>::new();
+    for (i, level) in template.split('/').enumerate() {
+        if i > 0 {
+            out.push('/').map_err(|_| TopicError::TooLong)?;
+        }
+        let level = if level.contains('{') { "+" } else { level };
+        out.push_str(level).map_err(|_| TopicError::TooLong)?;
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VARS: TopicVars = TopicVars { site: "lab", device_id: "esp32", channel: "temperature" };
+
+    #[test]
+    fn expands_placeholders() {
+        assert_eq!(expand::<64>("{site}/{device_id}/{channel}", &VARS).unwrap(), "lab/esp32/temperature");
+        assert_eq!(expand::<64>("sensors/{channel}", &VARS).unwrap(), "sensors/temperature");
+        assert_eq!(expand::<64>("{device_id}-{channel}", &VARS).unwrap(), "esp32-temperature");
+        assert_eq!(expand::<64>("{channel}{channel}", &VARS).unwrap(), "temperaturetemperature");
+        assert_eq!(expand::<64>("sensors/temp", &VARS).unwrap(), "sensors/temp");
+    }
+
+    #[test]
+    fn rejects_bad_placeholders() {
+        assert_eq!(expand::<64>("sensors/{room}", &VARS), Err(TopicError::UnknownPlaceholder));
+        assert_eq!(expand::<64>("sensors/{Channel}", &VARS), Err(TopicError::UnknownPlaceholder));
+        assert_eq!(expand::<64>("sensors/{}", &VARS), Err(TopicError::UnknownPlaceholder));
+        assert_eq!(expand::<64>("sensors/{channel", &VARS), Err(TopicError::UnterminatedPlaceholder));
+    }
+
+    #[test]
+    fn expanded_topic_must_be_publishable() {
+        assert_eq!(expand::<64>("", &VARS), Err(TopicError::Empty));
+        assert_eq!(expand::<64>("sensors/+/{channel}", &VARS), Err(TopicError::Wildcard));
+        assert_eq!(expand::<64>("sensors/#", &VARS), Err(TopicError::Wildcard));
+        assert_eq!(expand::<64>("$SYS/{channel}", &VARS), Err(TopicError::Reserved));
+        assert_eq!(expand::<64>("sensors/\0", &VARS), Err(TopicError::NullChar));
+        // Values are checked too, not just the template
+        let vars = TopicVars { site: "a+b", ..VARS };
+        assert_eq!(expand::<64>("{site}/{channel}", &vars), Err(TopicError::Wildcard));
+    }
+
+    #[test]
+    fn overlong_topics_are_rejected() {
+        let long = "x".repeat(MAX_TOPIC_LEN);
+        assert_eq!(validate_topic(&long), Ok(()));
+        assert_eq!(validate_topic(&(long.clone() + "x")), Err(TopicError::TooLong));
+        // Overflowing the buffer while expanding
+        assert_eq!(expand::<16>("sensors/{channel}", &VARS), Err(TopicError::TooLong));
+        assert_eq!(expand::<8>("sensors/temp", &VARS), Err(TopicError::TooLong));
+        // Fits the buffer but not a publish packet
+        let template = "x".repeat(MAX_TOPIC_LEN - 8) + "/{channel}";
+        assert_eq!(expand::<256>(&template, &VARS), Err(TopicError::TooLong));
+    }
+
+    #[test]
+    fn template_is_checked_with_the_longest_channel() {
+        assert_eq!(check_template("{site}/{device_id}/{channel}", "lab", "esp32"), Ok(()));
+        // "temperature" would fit, a 16-byte ADC channel name does not
+        let template = "x".repeat(MAX_TOPIC_LEN - 1 - MAX_CHANNEL_NAME) + "/{channel}";
+        assert_eq!(check_template(&template, "lab", "esp32"), Ok(()));
+        let template = "x".repeat(MAX_TOPIC_LEN - MAX_CHANNEL_NAME) + "/{channel}";
+        assert_eq!(check_template(&template, "lab", "esp32"), Err(TopicError::TooLong));
+        assert_eq!(check_template("{site}/{nope}", "lab", "esp32"), Err(TopicError::UnknownPlaceholder));
+    }
+
+    #[test]
+    fn fallback_gives_each_channel_its_own_topic() {
+        assert_eq!(check_template(FALLBACK_TEMPLATE, "lab", "esp32"), Ok(()));
+        let humidity = TopicVars { channel: "humidity", ..VARS };
+        assert_ne!(expand::<64>(FALLBACK_TEMPLATE, &VARS).unwrap(), expand::<64>(FALLBACK_TEMPLATE, &humidity).unwrap());
+    }
+
+    #[test]
+    fn subscription_replaces_placeholder_levels() {
+        assert_eq!(subscription::<64>("{site}/{device_id}/{channel}").unwrap(), "+/+/+");
+        assert_eq!(subscription::<64>("sensors/{channel}").unwrap(), "sensors/+");
+        assert_eq!(subscription::<64>("sensors/temp").unwrap(), "sensors/temp");
+        // A level mixing text and a placeholder still becomes `+`
+        assert_eq!(subscription::<64>("site-{site}/{device_id}-x/data").unwrap(), "+/+/data");
+        assert_eq!(subscription::<64>("a//{channel}").unwrap(), "a//+");
+        assert_eq!(subscription::<8>("sensors/{channel}"), Err(TopicError::TooLong));
+    }
+}
@@ -0,0 +1,101 @@
+use serde::{Deserialize, Serialize};
+
+// --- Wi-Fi Radio Settings ---
+// Modem power save, TX power, country code and listen interval trade power
+// against responsiveness. The country code is set when the driver starts and
+// the listen interval on association, so both need a restart; power save and
+// TX power can also be changed at runtime with `set_wifi`.
+
+/// Modem sleep between beacons while associated.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum PowerSave {
+    /// Radio always on: lowest latency, highest current.
+    None,
+    /// Wake for every DTIM beacon.
+    Min,
+    /// Wake every `wifi_listen_interval` beacons; commands may wait that long.
+    Max,
+}
+
+impl PowerSave {
+    /// Parses the names used in `config.json`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(Self::None),
+            "min" => Some(Self::Min),
+            "max" => Some(Self::Max),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Min => "min",
+            Self::Max => "max",
+        }
+    }
+}
+
+/// TX power range the driver accepts, in dBm.
+pub const MIN_TX_POWER_DBM: u8 = 2;
+pub const MAX_TX_POWER_DBM: u8 = 20;
+
+/// Longest listen interval, in beacons (about 10 s at the usual 102.4 ms).
+/// Access points buffer frames for sleeping stations only so long.
+pub const MAX_LISTEN_INTERVAL: u16 = 100;
+
+pub fn is_valid_tx_power(dbm: u8) -> bool {
+    (MIN_TX_POWER_DBM..=MAX_TX_POWER_DBM).contains(&dbm)
+}
+
+pub fn is_valid_listen_interval(beacons: u16) -> bool {
+    (1..=MAX_LISTEN_INTERVAL).contains(&beacons)
+}
+
+/// Two upper-case letters (ISO 3166-1 alpha-2), or `01` for the world-safe
+/// channel set.
+pub fn is_valid_country(code: &str) -> bool {
+    code == "01" || (code.len() == 2 && code.bytes().all(|b| b.is_ascii_uppercase()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn power_save_names_round_trip() {
+        for mode in [PowerSave::None, PowerSave::Min, PowerSave::Max] {
+            assert_eq!(PowerSave::from_name(mode.name()), Some(mode));
+        }
+        assert_eq!(PowerSave::from_name("Max"), None);
+        assert_eq!(PowerSave::from_name(""), None);
+    }
+
+    #[test]
+    fn tx_power_range() {
+        assert!(!is_valid_tx_power(1));
+        assert!(is_valid_tx_power(MIN_TX_POWER_DBM));
+        assert!(is_valid_tx_power(MAX_TX_POWER_DBM));
+        assert!(!is_valid_tx_power(21));
+    }
+
+    #[test]
+    fn listen_interval_range() {
+        assert!(!is_valid_listen_interval(0));
+        assert!(is_valid_listen_interval(1));
+        assert!(is_valid_listen_interval(MAX_LISTEN_INTERVAL));
+        assert!(!is_valid_listen_interval(MAX_LISTEN_INTERVAL + 1));
+    }
+
+    #[test]
+    fn country_codes() {
+        assert!(is_valid_country("DE"));
+        assert!(is_valid_country("01"));
+        assert!(!is_valid_country("de"));
+        assert!(!is_valid_country("D"));
+        assert!(!is_valid_country("DEU"));
+        assert!(!is_valid_country("02"));
+        assert!(!is_valid_country(""));
+    }
+}
@@ -14,7 +14,7 @@ MAX_MESSAGES = 3
 def on_connect(client, userdata, flags, rc):
     if rc == 0:
         print(f"✅ Connected to MQTT Broker at {userdata['host']}!")
-        topic = "sensors/temp"
+        topic = "sensors/temperature"
         client.subscribe(topic)
         print(f"📡 Subscribed to '{topic}'. Waiting for {MAX_MESSAGES} messages...")
     else:
@@ -0,0 +1,102 @@
+use core::ptr::addr_of_mut;
+use core::sync::atomic::{AtomicU32, Ordering};
+use rtt_target::rprintln;
+
+// The levels and their thresholds are hardware-independent (see `firmware-core/`)
+pub use firmware_core::battery::{BatteryConfig, Level, HYSTERESIS_MV, OVERSAMPLE};
+
+// --- State ---
+// The level, and the last one reported to the broker, are kept in RTC memory:
+// a flat battery has to be recognised right after waking, and the final
+// alert is sent only once.
+
+/// Upper half of the RTC word, so garbage is not mistaken for a level.
+const STATE_MAGIC: u32 = 0xBA77_0000;
+
+/// Stands for "nothing reported yet" in the RTC word.
+const NOT_REPORTED: u32 = 0xFF;
+
+#[esp_hal::ram(unstable(rtc_fast, persistent))]
+static mut STATE: u32 = 0;
+
+fn level_code(level: Level) -> u32 {
+    match level {
+        Level::Ok => 0,
+        Level::Low => 1,
+        Level::Critical => 2,
+    }
+}
+
+fn level_from_code(code: u32) -> Option<Level> {
+    match code {
+        0 => Some(Level::Ok),
+        1 => Some(Level::Low),
+        2 => Some(Level::Critical),
+        _ => None,
+    }
+}
+
+/// `(level, reported)`. Callers hold a critical section, since both the
+/// sampler and the main task update it.
+fn load_state() -> (Level, Option<Level>) {
+    let value = unsafe { addr_of_mut!(STATE).read_volatile() };
+    if value & 0xFFFF_0000 != STATE_MAGIC {
+        return (Level::Ok, None);
+    }
+    let level = level_from_code((value >> 8) & 0xFF).unwrap_or(Level::Ok);
+    (level, level_from_code(value & 0xFF))
+}
+
+fn store_state(level: Level, reported: Option<Level>) {
+    let reported = reported.map(level_code).unwrap_or(NOT_REPORTED);
+    unsafe { addr_of_mut!(STATE).write_volatile(STATE_MAGIC | (level_code(level) << 8) | reported) };
+}
+
+/// Latest supply voltage (0 = not measured yet).
+static MILLIVOLTS: AtomicU32 = AtomicU32::new(0);
+
+/// Records a reading and updates the level.
+pub fn record(config: &BatteryConfig, mv: u32) {
+    MILLIVOLTS.store(mv, Ordering::Relaxed);
+    let (previous, level) = critical_section::with(|_| {
+        let (previous, reported) = load_state();
+        let level = config.level(mv, previous);
+        store_state(level, reported);
+        (previous, level)
+    });
+    if level != previous {
+        rprintln!("Battery: {} -> {} at {}mV", previous.name(), level.name(), mv);
+    }
+}
+
+pub fn millivolts() -> Option<u32> {
+    match MILLIVOLTS.load(Ordering::Relaxed) {
+        0 => None,
+        mv => Some(mv),
+    }
+}
+
+pub fn level() -> Level {
+    critical_section::with(|_| load_state().0)
+}
+
+/// The level, if the broker has not been told about it yet.
+pub fn unreported() -> Option<Level> {
+    critical_section::with(|_| {
+        let (level, reported) = load_state();
+        (reported != Some(level)).then_some(level)
+    })
+}
+
+/// Whether the final alert went out: the device should only sleep now.
+pub fn is_shut_down() -> bool {
+    critical_section::with(|_| load_state() == (Level::Critical, Some(Level::Critical)))
+}
+
+/// Call once `level` reached the broker.
+pub fn mark_reported(level: Level) {
+    critical_section::with(|_| {
+        let (current, _) = load_state();
+        store_state(current, Some(level));
+    });
+}
@@ -1,17 +1,67 @@
 #![no_std]
 #![no_main]
 
-use esp_blinky_rust::{setup, Duration, Timer};
-use esp_blinky_rust::config::ConfigStore;
-use esp_blinky_rust::mqtt::{mqtt_connect, mqtt_publish};
+use esp_blinky_rust::{setup, BleStack, Duration, Timer};
+use esp_blinky_rust::aggregate::{Deadband, Window};
+use esp_blinky_rust::battery::{self, BatteryConfig};
+use esp_blinky_rust::boot;
+use esp_blinky_rust::buffer::{Sample, TelemetryBuffer};
+use esp_blinky_rust::calibration::{self, LoadMeter, THERMAL_TIME_CONSTANT_MS};
+use esp_blinky_rust::command::{
+    self, Command, CommandError, ConfigUpdate, IdentifyArgs, IntervalArgs, NoArgs, OtaArgs, OtaBeginArgs, WifiArgs,
+    MAX_COMMAND_PACKET, MAX_REQUEST_ID,
+};
+use esp_blinky_rust::config::{self, AppConfig, ConfigStore};
+use esp_blinky_rust::crash::{self, CrashRecord};
+use esp_blinky_rust::discovery::{self, DeviceInfo, Entity};
+use esp_blinky_rust::flash::Flash;
+use esp_blinky_rust::indicator::{self, ErrorCode, Indicator, State};
+use esp_blinky_rust::sensor::{AnySensor, Quantity, Readings, Sensor, SensorKind, MAX_CHANNELS, MAX_SENSORS};
+use esp_blinky_rust::sensor::adc::{AdcSensor, AnalogInputs};
+use esp_blinky_rust::sensor::analog::AdcChannelConfig;
+use esp_blinky_rust::sensor::internal::InternalTemp;
+use esp_blinky_rust::sleep;
+use esp_blinky_rust::mqtt::{
+    mqtt_connect, mqtt_disconnect, mqtt_ping, mqtt_publish, mqtt_publish_retained, mqtt_read_packet, mqtt_subscribe, LastWill,
+    Packet, KEEP_ALIVE_SECS,
+};
+use esp_blinky_rust::ipv6;
+use esp_blinky_rust::led::{self, Rgb, StatusLed};
+use esp_blinky_rust::net::{self, dhcp_config, format_net_report, resolve_host, wait_for_dhcp, DHCP_TIMEOUT};
+use esp_blinky_rust::ota::{self, ChunkReceiver, Manifest, OtaError, Transfer, PUBLIC_KEY_LEN};
+use esp_blinky_rust::ota::chunk::MAX_CHUNK_PACKET;
+use esp_blinky_rust::ota::http::MAX_URL_LEN;
+use esp_blinky_rust::provision;
+use esp_blinky_rust::sntp::{self, SNTP_RESYNC_INTERVAL, SNTP_RETRY_INTERVAL};
+use esp_blinky_rust::status::{self, net_status};
+use esp_blinky_rust::telemetry::{self, FieldValue, PayloadFormat, Point};
+use esp_blinky_rust::topic::{self, TopicError, TopicVars};
+use esp_blinky_rust::watchdog::{self, Task, Watchdogs};
+use esp_blinky_rust::wifi::RadioSettings;
 use rtt_target::rprintln;
 use embassy_executor::Spawner;
-use esp_radio::wifi::{ClientConfig, ModeConfig, WifiDevice};
-use embassy_net::{Runner, Config as NetConfig, StackResources, Ipv4Address};
+use esp_radio::wifi::{ClientConfig, ModeConfig, ScanConfig, WifiController, WifiDevice};
+use embassy_net::{Runner, Config as NetConfig, Stack, StackResources, StaticConfigV6};
 use embassy_net::tcp::TcpSocket;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_sync::mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_futures::select::{select, select4, Either, Either4};
+use embassy_time::{with_timeout, Instant, Ticker};
+use esp_hal::tsens::TemperatureSensor;
+#[cfg(any(feature = "sht3x", feature = "bme280"))]
+use esp_hal::i2c::master::I2c;
+#[cfg(any(feature = "sht3x", feature = "bme280"))]
+use esp_blinky_rust::sensor::I2cBus;
+use esp_hal::gpio::Input;
+use esp_hal::usb_serial_jtag::UsbSerialJtag;
+use esp_hal::Async;
+use embedded_io_async::{Read, Write as _};
 use static_cell::StaticCell;
 use heapless::String;
-use core::str::FromStr;
+use core::cell::Cell;
+use telemetry_schema::MAX_FRAME_LEN;
 use alloc::string::ToString;
 
 extern crate alloc;
@@ -19,17 +69,1198 @@ extern crate alloc;
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
     rprintln!("PANIC: {:?}", info);
-    loop {}
+    // Keep the details for the next boot and start over; nobody may be around to power-cycle
+    crash::record(info, FIRMWARE_VERSION);
+    esp_hal::system::software_reset()
 }
 
 esp_bootloader_esp_idf::esp_app_desc!();
 
+const FIRMWARE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Watchdog deadlines (see `watchdog`). The main loop waits at most a
+/// keep-alive interval for the broker and 30 s for DHCP between check-ins.
+const NET_DEADLINE: Duration = Duration::from_secs(10);
+const MQTT_DEADLINE: Duration = Duration::from_secs(120);
+const CONSOLE_DEADLINE: Duration = Duration::from_secs(30);
+
+/// The console checks in at least this often while no input arrives.
+const CONSOLE_IDLE_CHECK_IN: Duration = Duration::from_secs(10);
+
+/// Largest packet read from the broker: a command or an OTA chunk.
+const MAX_PACKET: usize = if MAX_COMMAND_PACKET > MAX_CHUNK_PACKET { MAX_COMMAND_PACKET } else { MAX_CHUNK_PACKET };
+
 /// Background task to drive the network stack.
 /// This task runs the background network operations (DHCP, TCP/IP state machine, etc.).
 /// It must be spawned for the stack to function.
 #[embassy_executor::task]
 async fn net_task(mut runner: Runner<'static, WifiDevice<'static>>) {
-    runner.run().await
+    // The runner never returns; checking in next to it shows it is still being polled
+    watchdog::register(Task::Net, NET_DEADLINE);
+    watchdog::keep_alive(Task::Net, runner.run()).await
+}
+
+/// Feeds the watchdogs while every registered task keeps checking in.
+/// Also puts the device to sleep at the end of a duty cycle, as it owns the RTC.
+#[embassy_executor::task]
+async fn watchdog_task(mut watchdogs: Watchdogs) {
+    loop {
+        if let Some(task) = watchdog::starved(Instant::now()) {
+            watchdogs.starve(task);
+            core::future::pending::<()>().await;
+        }
+        watchdogs.feed();
+        if let Either::Second(interval) = select(Timer::after(watchdog::FEED_INTERVAL), sleep::requested()).await {
+            watchdogs.sleep_deep(interval);
+        }
+    }
+}
+
+type SharedBuffer = Mutex<CriticalSectionRawMutex, TelemetryBuffer>;
+
+/// Live samples handed from the sampler to the publish loop.
+static SAMPLES: Channel<CriticalSectionRawMutex, Sample, 16> = Channel::new();
+
+/// Sampling and publishing rates for the sampler task.
+#[derive(Clone, Copy)]
+struct SamplerSettings {
+    sample_interval: Duration,
+    publish_interval: Duration,
+    /// Minimum change of a channel's window mean worth publishing (0 = always publish).
+    deadband: f32,
+    /// Publish at least this often even if the value did not move.
+    heartbeat: Duration,
+}
+
+impl SamplerSettings {
+    fn new(publish_interval_secs: u32, sample_interval_ms: u32, deadband: f32, heartbeat_secs: u32) -> Self {
+        let publish_interval = Duration::from_secs(publish_interval_secs.max(1) as u64);
+        Self {
+            // Never sample slower than we publish
+            sample_interval: Duration::from_millis(sample_interval_ms.max(100) as u64).min(publish_interval),
+            publish_interval,
+            deadband,
+            heartbeat: Duration::from_secs(heartbeat_secs as u64),
+        }
+    }
+
+    /// How long the sampler may go without checking in: a couple of
+    /// intervals plus slow sensors and waiting for the flash buffer.
+    fn watchdog_deadline(&self) -> Duration {
+        self.sample_interval * 2 + Duration::from_secs(60)
+    }
+}
+
+/// New settings for the running sampler (from the `set_interval` command).
+static SAMPLER_SETTINGS: Signal<CriticalSectionRawMutex, SamplerSettings> = Signal::new();
+
+/// A sample tick this late means another task was holding the CPU.
+const BUSY_LATENESS: Duration = Duration::from_micros(500);
+
+/// Aggregation state of one sensor channel (e.g. the SHT3x humidity).
+struct ChannelState {
+    sensor: SensorKind,
+    quantity: Quantity,
+    window: Window,
+    deadband: Deadband,
+}
+
+/// Background task that reads every sensor each `sample_interval`, connected or not.
+/// Every `publish_interval` each channel's readings are summarised into one
+/// sample, which is dropped if its deadband says nothing changed. While MQTT
+/// is up, samples go straight to the publish loop; otherwise (or if the
+/// publisher falls behind) they are queued in flash.
+#[embassy_executor::task]
+async fn sampler_task(mut sensors: heapless::Vec<AnySensor, MAX_SENSORS>, buffer: &'static SharedBuffer, mut settings: SamplerSettings) {
+    let mut channels: heapless::Vec<ChannelState, MAX_CHANNELS> = heapless::Vec::new();
+    let mut readings = Readings::new();
+    // Consecutive failed reads per sensor, to log only the start and end of an outage
+    let mut failures = [0u32; MAX_SENSORS];
+    let mut load_meter = LoadMeter::new(THERMAL_TIME_CONSTANT_MS);
+    let mut ticker = Ticker::every(settings.sample_interval);
+    let mut next_tick = Instant::now() + settings.sample_interval;
+    let mut next_publish = Instant::now() + settings.publish_interval;
+    watchdog::register(Task::Sampler, settings.watchdog_deadline());
+
+    loop {
+        watchdog::check_in(Task::Sampler);
+        for (sensor, failures) in sensors.iter_mut().zip(failures.iter_mut()) {
+            let kind = sensor.kind();
+            readings.clear();
+            match sensor.read(&mut readings).await {
+                Ok(()) if *failures > 0 => {
+                    rprintln!("Sensor {}: recovered after {} failed reads", kind.name(), failures);
+                    *failures = 0;
+                }
+                Ok(()) => {}
+                Err(e) => {
+                    if *failures == 0 {
+                        rprintln!("Sensor {}: read failed: {:?}", kind.name(), e);
+                    }
+                    *failures += 1;
+                    indicate(State::Error(ErrorCode::Sensor));
+                }
+            }
+
+            for measurement in readings.iter() {
+                let index = match channels.iter().position(|c| c.sensor == kind && c.quantity == measurement.quantity) {
+                    Some(index) => index,
+                    None => {
+                        let channel = ChannelState {
+                            sensor: kind,
+                            quantity: measurement.quantity,
+                            window: Window::new(),
+                            deadband: Deadband::new(settings.deadband, settings.heartbeat.as_millis()),
+                        };
+                        if channels.push(channel).is_err() {
+                            continue; // More channels than we can track
+                        }
+                        channels.len() - 1
+                    }
+                };
+                channels[index].window.push(measurement.value);
+            }
+        }
+
+        if Instant::now() >= next_publish {
+            next_publish += settings.publish_interval;
+            let now_ms = Instant::now().as_millis();
+            // The first temperature channel colours the LED
+            let mut coloured = false;
+
+            for channel in channels.iter_mut() {
+                let Some(summary) = channel.window.take() else {
+                    continue;
+                };
+                if channel.quantity == Quantity::Temperature && !coloured {
+                    LED_TEMPERATURE.signal(summary.mean);
+                    coloured = true;
+                }
+                rprintln!(
+                    "Status: Running | {} {}: {:.2} (min {:.2} max {:.2} sd {:.3}, n={})",
+                    channel.sensor.name(), channel.quantity.name(), summary.mean, summary.min, summary.max, summary.stddev, summary.count
+                );
+
+                if channel.deadband.check(summary.mean, now_ms) {
+                    let sample = Sample::now(channel.sensor, channel.quantity, &summary);
+                    if !status::mqtt_connected() || SAMPLES.try_send(sample).is_err() {
+                        if let Err(e) = buffer.lock().await.push(&sample).await {
+                            rprintln!("Buffer push failed: {:?}", e);
+                        }
+                    }
+                }
+            }
+        }
+
+        ticker.next().await;
+        let busy = Instant::now().saturating_duration_since(next_tick) > BUSY_LATENESS;
+        next_tick += settings.sample_interval;
+        load_meter.record(settings.sample_interval.as_millis(), busy, status::wifi_connected());
+        calibration::set_load(load_meter.load());
+
+        // Changed intervals start a fresh schedule; the current windows carry over
+        if let Some(new) = SAMPLER_SETTINGS.try_take() {
+            settings = new;
+            ticker = Ticker::every(settings.sample_interval);
+            next_tick = Instant::now() + settings.sample_interval;
+            next_publish = Instant::now() + settings.publish_interval;
+            watchdog::register(Task::Sampler, settings.watchdog_deadline());
+        }
+    }
+}
+
+/// Reads every sensor once for a duty cycle and queues the readings in RTC
+/// memory. Readings pushed out of a full queue go to the flash buffer.
+async fn sample_cycle(sensors: &mut [AnySensor], buffer: &SharedBuffer) {
+    let mut readings = Readings::new();
+    let mut coloured = false;
+    for sensor in sensors.iter_mut() {
+        let kind = sensor.kind();
+        readings.clear();
+        if let Err(e) = sensor.read(&mut readings).await {
+            rprintln!("Sensor {}: read failed: {:?}", kind.name(), e);
+            indicate(State::Error(ErrorCode::Sensor));
+            continue;
+        }
+        for measurement in readings.iter() {
+            let mut window = Window::new();
+            window.push(measurement.value);
+            let Some(summary) = window.take() else {
+                continue;
+            };
+            rprintln!("Status: Cycle | {} {}: {:.2}", kind.name(), measurement.quantity.name(), summary.mean);
+            if measurement.quantity == Quantity::Temperature && !coloured {
+                LED_TEMPERATURE.signal(summary.mean);
+                coloured = true;
+            }
+            let sample = Sample::now(kind, measurement.quantity, &summary);
+            if let Some(oldest) = sleep::push(&sample)
+                && let Err(e) = buffer.lock().await.push(&oldest).await
+            {
+                rprintln!("Buffer push failed: {:?}", e);
+            }
+        }
+    }
+}
+
+/// System state shown by the status LED, see `indicator::pattern`.
+static LED_STATE: Signal<CriticalSectionRawMutex, State> = Signal::new();
+
+/// Strobe for a while so the device can be found (`identify` command).
+static IDENTIFY: Signal<CriticalSectionRawMutex, Duration> = Signal::new();
+
+/// Latest temperature, for the colour of a WS2812 while running.
+static LED_TEMPERATURE: Signal<CriticalSectionRawMutex, f32> = Signal::new();
+
+fn indicate(state: State) {
+    LED_STATE.signal(state);
+}
+
+/// Plays the pattern of the current system state. A state change starts its
+/// pattern at once, except that an error code is finished first.
+#[embassy_executor::task]
+async fn led_task(mut led: StatusLed, initial: State, temp_cold: f32, temp_hot: f32) {
+    let mut indicator = Indicator::new(initial);
+    let mut temperature = None;
+    loop {
+        let ota_busy = ota::is_busy();
+        let shown = indicator.shown(ota_busy);
+        let step = indicator.step(ota_busy);
+        led.show(if step.on { led::colour(shown, temperature, temp_cold, temp_hot) } else { Rgb::OFF });
+        let step_end = Instant::now() + Duration::from_millis(step.ms as u64);
+        loop {
+            match select4(Timer::at(step_end), LED_STATE.wait(), IDENTIFY.wait(), LED_TEMPERATURE.wait()).await {
+                Either4::First(()) => {
+                    indicator.advance(ota::is_busy());
+                    break;
+                }
+                Either4::Second(state) => {
+                    if indicator.set_state(state) {
+                        break;
+                    }
+                }
+                Either4::Third(duration) => {
+                    let end = Instant::now() + duration;
+                    let mut index = 0;
+                    while Instant::now() < end {
+                        let step = indicator::IDENTIFY.step(index);
+                        led.show(if step.on { Rgb::WHITE } else { Rgb::OFF });
+                        Timer::after(Duration::from_millis(step.ms as u64)).await;
+                        index += 1;
+                    }
+                    break;
+                }
+                // Shown from the next step on
+                Either4::Fourth(celsius) => temperature = Some(celsius),
+            }
+        }
+    }
+}
+
+/// Creates drivers for the sensors listed in the config, plus the ADC if any
+/// analog channels are configured. Sensors whose driver is not compiled in are
+/// skipped; if nothing is left, the internal sensor is used.
+fn build_sensors(
+    kinds: &[SensorKind],
+    adc_channels: &[AdcChannelConfig],
+    temp_sensor: TemperatureSensor<'static>,
+    analog: AnalogInputs,
+    battery: &BatteryConfig,
+    #[cfg(any(feature = "sht3x", feature = "bme280"))] i2c: I2c<'static, Async>,
+    #[cfg(feature = "ds18b20")] onewire_pin: esp_hal::gpio::Flex<'static>,
+) -> heapless::Vec<AnySensor, MAX_SENSORS> {
+    #[cfg(any(feature = "sht3x", feature = "bme280"))]
+    let i2c_bus: &'static I2cBus = {
+        static I2C_BUS: StaticCell<I2cBus> = StaticCell::new();
+        I2C_BUS.init(Mutex::new(i2c))
+    };
+    #[cfg(any(feature = "sht3x", feature = "bme280"))]
+    use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice;
+
+    let mut temp_sensor = Some(temp_sensor);
+    #[cfg(feature = "ds18b20")]
+    let mut onewire_pin = Some(onewire_pin);
+
+    let mut sensors = heapless::Vec::new();
+    for kind in kinds {
+        #[allow(unreachable_patterns)]
+        let sensor = match kind {
+            SensorKind::Internal => temp_sensor.take().map(|t| AnySensor::Internal(InternalTemp::new(t))),
+            // Enabled by `adc_channels`, see below
+            SensorKind::Adc => None,
+            #[cfg(feature = "sht3x")]
+            SensorKind::Sht3x => {
+                use esp_blinky_rust::sensor::sht3x::{Sht3x, DEFAULT_ADDRESS};
+                Some(AnySensor::Sht3x(Sht3x::new(I2cDevice::new(i2c_bus), embassy_time::Delay, DEFAULT_ADDRESS)))
+            }
+            #[cfg(feature = "bme280")]
+            SensorKind::Bme280 => {
+                use esp_blinky_rust::sensor::bme280::{Bme280, DEFAULT_ADDRESS};
+                Some(AnySensor::Bme280(Bme280::new(I2cDevice::new(i2c_bus), embassy_time::Delay, DEFAULT_ADDRESS)))
+            }
+            #[cfg(feature = "ds18b20")]
+            SensorKind::Ds18b20 => {
+                use esp_blinky_rust::sensor::ds18b20::Ds18b20;
+                onewire_pin.take().map(|pin| AnySensor::Ds18b20(Ds18b20::new(pin, embassy_time::Delay)))
+            }
+            _ => {
+                rprintln!("Sensor {} is not compiled in (enable the '{}' feature)", kind.name(), kind.name());
+                None
+            }
+        };
+        // A sensor listed twice only gets one driver
+        if let Some(sensor) = sensor {
+            rprintln!("Sensor {} enabled", kind.name());
+            if sensors.push(sensor).is_err() {
+                break;
+            }
+        }
+    }
+
+    if !adc_channels.is_empty() || battery.is_enabled() {
+        let adc = AdcSensor::new(analog, adc_channels, battery);
+        if !adc.is_empty() {
+            let _ = sensors.push(AnySensor::Adc(adc));
+        }
+    }
+
+    // The battery input alone publishes nothing
+    let publishes = |sensor: &AnySensor| !matches!(sensor, AnySensor::Adc(adc) if !adc.has_channels());
+    if !sensors.iter().any(publishes)
+        && let Some(t) = temp_sensor.take()
+    {
+        rprintln!("No usable sensor configured, using the internal one");
+        let _ = sensors.push(AnySensor::Internal(InternalTemp::new(t)));
+    }
+    sensors
+}
+
+/// Everything needed to turn a sample into a payload on the right topic.
+struct Publisher<'a> {
+    /// Topic template, expanded per sample with the channel name.
+    topic_template: &'a str,
+    format: PayloadFormat,
+    device_id: &'a str,
+    site: &'a str,
+    /// Names of the ADC channels, indexed by `Quantity::Other`.
+    adc_channels: &'a [AdcChannelConfig],
+    /// Sequence number for binary frames.
+    seq: Cell<u32>,
+}
+
+impl Publisher<'_> {
+    /// Measurement name and `{channel}`: the quantity, or the configured name of an ADC channel.
+    fn channel_name(&self, sensor: SensorKind, quantity: Quantity) -> &str {
+        match quantity {
+            Quantity::Other(index) if sensor == SensorKind::Adc => {
+                self.adc_channels.get(index as usize).map(|c| c.name.as_str()).unwrap_or("other")
+            }
+            quantity => quantity.name(),
+        }
+    }
+
+    /// Topic the readings of `channel` are published to.
+    fn topic(&self, channel: &str) -> Result<String<128>, TopicError> {
+        let vars = TopicVars { site: self.site, device_id: self.device_id, channel };
+        topic::expand(self.topic_template, &vars)
+    }
+}
+
+/// Publishes a single reading in the configured payload format.
+/// `rssi` is only attached to live readings; for buffered ones it would
+/// describe the link at publish time, not at measurement time.
+async fn publish_sample(socket: &mut TcpSocket<'_>, publisher: &Publisher<'_>, sample: &Sample, rssi: Option<i32>) -> Result<(), ()> {
+    let channel = publisher.channel_name(sample.sensor, sample.quantity);
+    let topic = match publisher.topic(channel) {
+        Ok(topic) => topic,
+        Err(e) => {
+            rprintln!("Invalid topic for {}: {:?}", channel, e);
+            return Ok(());
+        }
+    };
+
+    let tags = [
+        ("device", publisher.device_id),
+        ("site", publisher.site),
+        ("sensor", sample.sensor.name()),
+        ("fw", FIRMWARE_VERSION),
+    ];
+    let mut fields: heapless::Vec<(&str, FieldValue), 6> = heapless::Vec::new();
+    let _ = fields.push(("value", FieldValue::Float(sample.value)));
+    let _ = fields.push(("min", FieldValue::Float(sample.min)));
+    let _ = fields.push(("max", FieldValue::Float(sample.max)));
+    let _ = fields.push(("stddev", FieldValue::Float(sample.stddev)));
+    let _ = fields.push(("n", FieldValue::UInt(sample.count as u64)));
+    if let Some(rssi) = rssi {
+        let _ = fields.push(("rssi", FieldValue::Int(rssi as i64)));
+    }
+    let point = Point { measurement: channel, tags: &tags, fields: &fields, timestamp_ms: sample.unix_ms() };
+
+    if publisher.format == PayloadFormat::Binary {
+        let mut frame = [0u8; MAX_FRAME_LEN];
+        let seq = publisher.seq.get();
+        let bytes = match telemetry::encode_binary(&point, sample.quantity, seq, &mut frame) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                rprintln!("Encoding failed: {:?}", e);
+                return Ok(());
+            }
+        };
+        mqtt_publish(socket, topic.as_str(), bytes).await?;
+        publisher.seq.set(seq.wrapping_add(1));
+        // Carried across deep sleep
+        sleep::set_sequence(seq.wrapping_add(1));
+        rprintln!("Published: {} -> frame #{} ({} bytes)", topic, seq, bytes.len());
+        return Ok(());
+    }
+
+    let mut payload = String::<256>::new();
+    if let Err(e) = telemetry::encode(publisher.format, &point, &mut payload) {
+        rprintln!("Encoding failed: {:?}", e);
+        return Ok(()); // Nothing sensible to send; don't tear down the connection
+    }
+    mqtt_publish(socket, topic.as_str(), payload.as_bytes()).await?;
+    rprintln!("Published: {} -> {}", topic, payload);
+    Ok(())
+}
+
+/// Announces every channel to Home Assistant. The configs are retained, so
+/// Home Assistant picks them up even if it starts after the device.
+async fn publish_discovery(
+    socket: &mut TcpSocket<'_>,
+    publisher: &Publisher<'_>,
+    device: &DeviceInfo<'_>,
+    channels: &[(SensorKind, Quantity)],
+    availability_topic: &str,
+) -> Result<(), ()> {
+    for &(sensor, quantity) in channels {
+        let channel = publisher.channel_name(sensor, quantity);
+        let Ok(state_topic) = publisher.topic(channel) else {
+            continue;
+        };
+        let unit = match quantity {
+            Quantity::Other(index) if sensor == SensorKind::Adc => {
+                publisher.adc_channels.get(index as usize).map(|c| c.unit.as_str()).unwrap_or("")
+            }
+            _ => "",
+        };
+        let entity = Entity { sensor: sensor.name(), channel, quantity, unit, state_topic: state_topic.as_str() };
+
+        let mut payload = String::<1024>::new();
+        let topic = match discovery::config_topic::<128>(device.device_id, &entity) {
+            Ok(topic) => topic,
+            Err(e) => {
+                rprintln!("Discovery topic for {} failed: {:?}", channel, e);
+                continue;
+            }
+        };
+        if let Err(e) = discovery::config_payload(device, &entity, publisher.format, availability_topic, &mut payload) {
+            rprintln!("Discovery config for {} failed: {:?}", channel, e);
+            continue;
+        }
+        mqtt_publish_retained(socket, topic.as_str(), payload.as_bytes()).await?;
+    }
+    rprintln!("Announced {} channels to Home Assistant", channels.len());
+    Ok(())
+}
+
+/// What the OTA commands need besides the MQTT connection.
+struct OtaContext {
+    flash: Flash,
+    /// `None` if the firmware was built without `ota_public_key`.
+    public_key: Option<[u8; PUBLIC_KEY_LEN]>,
+    /// Image being received over MQTT; kept across reconnects.
+    transfer: Option<ChunkReceiver>,
+}
+
+/// Starts receiving the image announced by `ota_begin`, or resumes if it is
+/// the one already being received.
+fn begin_transfer(ota_context: &mut OtaContext, args: &OtaBeginArgs) -> Result<Transfer, CommandError> {
+    let manifest = args.manifest()?;
+    if ota_context.public_key.is_none() {
+        return Err(CommandError::Update(OtaError::Disabled));
+    }
+    let chunk_size = args.chunk_size();
+    if let Some(receiver) = &ota_context.transfer
+        && receiver.transfer().is_same_image(&manifest, chunk_size)
+    {
+        rprintln!("OTA: resuming at chunk {}", receiver.transfer().next);
+        return Ok(*receiver.transfer());
+    }
+
+    // A different image replaces the one being received
+    ota_context.transfer = None;
+    // `SlotWriter` refuses to start while another update holds the slot
+    let receiver = ChunkReceiver::begin(ota_context.flash, &manifest, chunk_size).map_err(CommandError::Update)?;
+    let transfer = *receiver.transfer();
+    ota_context.transfer = Some(receiver);
+    Ok(transfer)
+}
+
+/// Restarts after giving queued packets a moment to leave.
+async fn restart(socket: &mut TcpSocket<'_>) -> ! {
+    rprintln!("Rebooting...");
+    let _ = socket.flush().await;
+    Timer::after(Duration::from_millis(500)).await;
+    boot::planned_restart()
+}
+
+/// Ends a duty cycle: leaves the broker cleanly, so the will is not
+/// published, and sleeps for `interval`.
+async fn end_cycle(socket: &mut TcpSocket<'_>, interval: Duration) -> ! {
+    let _ = mqtt_disconnect(socket).await;
+    let _ = socket.flush().await;
+    socket.close();
+    sleep::enter(interval).await
+}
+
+/// The stored config when a command asks to `save`. Loaded before anything is
+/// applied, so a config that cannot be read fails the command unchanged.
+async fn stored_config(config_store: &mut ConfigStore, save: Option<bool>) -> Result<Option<AppConfig>, CommandError> {
+    match save {
+        Some(true) => provision::load_config(config_store).await.map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// `set_interval`: applies the intervals right away and, with `save`, stores them.
+async fn set_interval<const N: usize>(
+    payload: &[u8],
+    config_store: &mut ConfigStore,
+    settings: &mut SamplerSettings,
+    result: &mut String<N>,
+) -> Result<(), CommandError> {
+    use core::fmt::Write;
+    let args = command::parse_args::<IntervalArgs>(payload)?;
+    args.validate()?;
+    let stored = stored_config(config_store, args.save).await?;
+
+    let publish_secs = args.publish_interval_secs.unwrap_or(settings.publish_interval.as_secs() as u32);
+    let sample_ms = args.sample_interval_ms.unwrap_or(settings.sample_interval.as_millis() as u32);
+    *settings = SamplerSettings::new(publish_secs, sample_ms, settings.deadband, settings.heartbeat.as_secs() as u32);
+    SAMPLER_SETTINGS.signal(*settings);
+
+    let mut saved = false;
+    if let Some(mut config) = stored {
+        config.publish_interval_secs = publish_secs;
+        config.sample_interval_ms = sample_ms;
+        saved = config_store.save(&config).await.is_ok();
+    }
+    let _ = write!(
+        result,
+        "{{\"publish_interval_secs\":{},\"sample_interval_ms\":{},\"saved\":{}}}",
+        settings.publish_interval.as_secs(), settings.sample_interval.as_millis(), saved
+    );
+    Ok(())
+}
+
+/// `set_wifi`: applies power save and TX power right away and, with `save`, stores them.
+async fn set_wifi<const N: usize>(
+    payload: &[u8],
+    config_store: &mut ConfigStore,
+    wifi: &mut WifiController<'static>,
+    radio: &mut RadioSettings,
+    result: &mut String<N>,
+) -> Result<(), CommandError> {
+    use core::fmt::Write;
+    let args = command::parse_args::<WifiArgs>(payload)?;
+    let (power_save, tx_power_dbm) = args.validate()?;
+    let stored = stored_config(config_store, args.save).await?;
+
+    let updated = RadioSettings {
+        power_save: power_save.unwrap_or(radio.power_save),
+        tx_power_dbm: tx_power_dbm.unwrap_or(radio.tx_power_dbm),
+    };
+    if updated.apply(wifi).is_err() {
+        // Put back what was running before
+        let _ = radio.apply(wifi);
+        return Err(CommandError::InvalidValue);
+    }
+    *radio = updated;
+
+    let mut saved = false;
+    if let Some(mut config) = stored {
+        config.wifi_power_save = radio.power_save;
+        config.wifi_tx_power_dbm = radio.tx_power_dbm;
+        saved = config_store.save(&config).await.is_ok();
+    }
+    let _ = write!(
+        result,
+        "{{\"power_save\":\"{}\",\"tx_power_dbm\":{},\"saved\":{}}}",
+        radio.power_save.name(), radio.tx_power_dbm, saved
+    );
+    Ok(())
+}
+
+/// `get_config`: the stored config, i.e. what applies after the next reboot.
+async fn get_config<const N: usize>(
+    payload: &[u8],
+    config_store: &mut ConfigStore,
+    result: &mut String<N>,
+) -> Result<(), CommandError> {
+    command::parse_args::<NoArgs>(payload)?;
+    let config = provision::load_config(config_store).await?;
+    command::write_config_json(&config, result).map_err(|_| CommandError::InvalidValue)
+}
+
+/// `set_config`: validates and stores the given fields.
+async fn set_config<const N: usize>(
+    payload: &[u8],
+    config_store: &mut ConfigStore,
+    result: &mut String<N>,
+) -> Result<(), CommandError> {
+    use core::fmt::Write;
+    let update = command::parse_args::<ConfigUpdate>(payload)?;
+    provision::save_update(config_store, &update).await?;
+    let _ = write!(result, "{{\"restart_required\":true}}");
+    Ok(())
+}
+
+/// `ota`: hands the download to `ota_download_task`, which reports the outcome
+/// once it is done.
+fn start_download<const N: usize>(
+    payload: &[u8],
+    id: Option<&String<MAX_REQUEST_ID>>,
+    ota_context: &OtaContext,
+    result: &mut String<N>,
+) -> Result<(), CommandError> {
+    use core::fmt::Write;
+    let args = command::parse_args::<OtaArgs>(payload)?;
+    let manifest = args.manifest()?;
+    let key = ota_context.public_key.ok_or(CommandError::Update(OtaError::Disabled))?;
+    if ota::is_busy() || OTA_REQUEST.signaled() {
+        return Err(CommandError::Update(OtaError::Busy));
+    }
+    OTA_REQUEST.signal(HttpUpdate { id: id.cloned(), url: args.url, manifest, key });
+    let _ = write!(result, "{{\"state\":\"downloading\"}}");
+    Ok(())
+}
+
+/// Carries out a remote command and publishes the response.
+/// Errors mean the response could not be sent.
+async fn handle_command(
+    socket: &mut TcpSocket<'_>,
+    device_id: &str,
+    name: &str,
+    payload: &[u8],
+    config_store: &mut ConfigStore,
+    settings: &mut SamplerSettings,
+    wifi: &mut WifiController<'static>,
+    radio: &mut RadioSettings,
+    ota_context: &mut OtaContext,
+) -> Result<(), ()> {
+    use core::fmt::Write;
+    // Not the payload: `set_config` may carry the Wi-Fi password
+    rprintln!("Command '{}' ({} bytes)", name, payload.len());
+    let id = command::request_id(payload);
+    let mut result = String::<1536>::new();
+    let mut reboot = false;
+
+    let outcome = match Command::from_name(name) {
+        None => Err(CommandError::UnknownCommand),
+        Some(Command::Reboot) => command::parse_args::<NoArgs>(payload).map(|_| reboot = true),
+        Some(Command::SetInterval) => set_interval(payload, config_store, settings, &mut result).await,
+        Some(Command::SetWifi) => set_wifi(payload, config_store, wifi, radio, &mut result).await,
+        Some(Command::Identify) => command::parse_args::<IdentifyArgs>(payload).map(|args| {
+            IDENTIFY.signal(Duration::from_secs(args.duration_secs() as u64));
+        }),
+        Some(Command::GetConfig) => get_config(payload, config_store, &mut result).await,
+        Some(Command::SetConfig) => set_config(payload, config_store, &mut result).await,
+        Some(Command::Ota) => start_download(payload, id.as_ref(), ota_context, &mut result),
+        Some(Command::OtaBegin) => command::parse_args::<OtaBeginArgs>(payload)
+            .and_then(|args| begin_transfer(ota_context, &args))
+            .map(|transfer| {
+                let _ = write!(
+                    result,
+                    "{{\"state\":\"receiving\",\"next\":{},\"chunk_size\":{}}}",
+                    transfer.next, transfer.chunk_size
+                );
+            }),
+    };
+
+    let Ok(topic) = command::response_topic::<128>(device_id, name) else {
+        rprintln!("Response topic for '{}' is too long", name);
+        return Ok(());
+    };
+    let mut response = String::<1600>::new();
+    if command::write_response(id.as_deref(), outcome.map(|()| result.as_str()), &mut response).is_err() {
+        rprintln!("Response for '{}' does not fit", name);
+        return Ok(());
+    }
+    mqtt_publish(socket, topic.as_str(), response.as_bytes()).await?;
+    rprintln!("Response: {} -> {}", topic, response);
+
+    if reboot {
+        restart(socket).await;
+    }
+    Ok(())
+}
+
+/// An `ota` command, handed from the MQTT loop to `ota_download_task`.
+struct HttpUpdate {
+    id: Option<String<MAX_REQUEST_ID>>,
+    url: String<MAX_URL_LEN>,
+    manifest: Manifest,
+    key: [u8; PUBLIC_KEY_LEN],
+}
+
+/// The update requested with `ota`, for `ota_download_task`.
+static OTA_REQUEST: Signal<CriticalSectionRawMutex, HttpUpdate> = Signal::new();
+
+/// How that update went, for the MQTT loop to report.
+static OTA_RESULT: Signal<CriticalSectionRawMutex, (Option<String<MAX_REQUEST_ID>>, Result<(), OtaError>)> = Signal::new();
+
+/// Downloads and installs the images requested with `ota`. A download can
+/// take minutes; running it here keeps the MQTT loop pinging the broker and
+/// answering commands in the meantime.
+#[embassy_executor::task]
+async fn ota_download_task(stack: Stack<'static>, flash: Flash) {
+    loop {
+        let update = OTA_REQUEST.wait().await;
+        // The download times out on its own if the server goes quiet
+        let installed = ota::install(stack, flash, &update.url, &update.manifest, &update.key).await;
+        if let Err(e) = installed {
+            rprintln!("OTA failed: {:?}", e);
+        }
+        OTA_RESULT.signal((update.id, installed));
+    }
+}
+
+/// Answers an `ota` command with the outcome of the download, and restarts
+/// into the new image once it is installed. Errors mean the response could
+/// not be sent.
+async fn report_update(
+    socket: &mut TcpSocket<'_>,
+    device_id: &str,
+    id: Option<String<MAX_REQUEST_ID>>,
+    installed: Result<(), OtaError>,
+) -> Result<(), ()> {
+    let reboot = installed.is_ok();
+    let outcome = installed.map(|()| "{\"state\":\"installed\"}").map_err(CommandError::Update);
+    let mut response = String::<256>::new();
+    if let Ok(topic) = command::response_topic::<128>(device_id, "ota")
+        && command::write_response(id.as_deref(), outcome, &mut response).is_ok()
+    {
+        let sent = mqtt_publish(socket, topic.as_str(), response.as_bytes()).await;
+        // The new image boots even if the response is lost
+        if !reboot {
+            sent?;
+        }
+        rprintln!("Response: {} -> {}", topic, response);
+    }
+    if reboot {
+        restart(socket).await;
+    }
+    Ok(())
+}
+
+/// Writes one chunk of an MQTT transfer and acknowledges it on `ack_topic`.
+/// Errors mean the acknowledgement could not be sent.
+async fn handle_chunk(socket: &mut TcpSocket<'_>, ota_context: &mut OtaContext, ack_topic: &str, payload: &[u8]) -> Result<(), ()> {
+    let received = match (ota_context.transfer.as_mut(), ota::chunk::parse_chunk(payload)) {
+        (None, _) => Err(OtaError::NoTransfer),
+        (Some(_), None) => Err(OtaError::SizeMismatch),
+        (Some(receiver), Some((index, data))) => receiver.receive(index, data),
+    };
+    let next = ota_context.transfer.as_ref().map(|r| r.transfer().next).unwrap_or(0);
+
+    let mut installed = false;
+    let outcome = match received {
+        Ok(false) => Ok(None),
+        Ok(true) => match ota_context.transfer.take().zip(ota_context.public_key) {
+            Some((receiver, key)) => receiver.finish(&key).map(|()| {
+                installed = true;
+                Some("installed")
+            }),
+            None => Err(OtaError::NoTransfer),
+        },
+        Err(e) => {
+            // The sender has to start over with `ota_begin`
+            if ota_context.transfer.take().is_some() {
+                rprintln!("OTA: transfer aborted: {:?}", e);
+            }
+            Err(e)
+        }
+    };
+    if let Err(e) = outcome {
+        rprintln!("OTA chunk rejected: {:?}", e);
+    }
+
+    let mut ack = String::<64>::new();
+    if ota::chunk::write_ack(next, outcome, &mut ack).is_ok() {
+        // The new image boots even if the ack is lost
+        let sent = mqtt_publish(socket, ack_topic, ack.as_bytes()).await;
+        if !installed {
+            sent?;
+        }
+    }
+    if installed {
+        restart(socket).await;
+    }
+    Ok(())
+}
+
+/// Publishes a health report on `devices/<id>/status` as Influx line
+/// protocol, so Telegraf can store it next to the readings.
+async fn publish_health(
+    socket: &mut TcpSocket<'_>,
+    publisher: &Publisher<'_>,
+    wifi: &WifiController<'static>,
+    radio: &RadioSettings,
+    topic: &str,
+    reset_reason: &str,
+    starved_task: Option<Task>,
+) -> Result<(), ()> {
+    let counters = status::counters();
+    let latency = status::latency();
+    let tags = [
+        ("device", publisher.device_id),
+        ("site", publisher.site),
+        ("fw", FIRMWARE_VERSION),
+        ("reset_reason", reset_reason),
+    ];
+    let mut fields: heapless::Vec<(&str, FieldValue), 16> = heapless::Vec::new();
+    let _ = fields.push(("uptime_s", FieldValue::UInt(Instant::now().as_secs())));
+    let _ = fields.push(("heap_free", FieldValue::UInt(esp_alloc::HEAP.free() as u64)));
+    let _ = fields.push(("heap_used", FieldValue::UInt(esp_alloc::HEAP.used() as u64)));
+    if let Ok(rssi) = wifi.rssi() {
+        let _ = fields.push(("rssi", FieldValue::Int(rssi as i64)));
+    }
+    if let Some(channel) = status::wifi_channel() {
+        let _ = fields.push(("channel", FieldValue::UInt(channel as u64)));
+    }
+    let _ = fields.push(("wifi_reconnects", FieldValue::UInt(counters.wifi_reconnects as u64)));
+    let _ = fields.push(("mqtt_reconnects", FieldValue::UInt(counters.mqtt_reconnects as u64)));
+    let _ = fields.push(("publish_failures", FieldValue::UInt(counters.publish_failures as u64)));
+    let _ = fields.push(("power_save", FieldValue::Str(radio.power_save.name())));
+    let _ = fields.push(("tx_power_dbm", FieldValue::UInt(radio.tx_power_dbm as u64)));
+    if latency.wifi_connect_ms > 0 {
+        let _ = fields.push(("wifi_connect_ms", FieldValue::UInt(latency.wifi_connect_ms as u64)));
+    }
+    if latency.reconnect_ms > 0 {
+        let _ = fields.push(("reconnect_ms", FieldValue::UInt(latency.reconnect_ms as u64)));
+    }
+    if let Some(mv) = battery::millivolts() {
+        let _ = fields.push(("battery_mv", FieldValue::UInt(mv as u64)));
+        let _ = fields.push(("battery", FieldValue::Str(battery::level().name())));
+    }
+    if let Some(task) = starved_task {
+        let _ = fields.push(("starved_task", FieldValue::Str(task.name())));
+    }
+    let point = Point { measurement: "device_health", tags: &tags, fields: &fields, timestamp_ms: sntp::unix_time_ms() };
+
+    let mut payload = String::<512>::new();
+    if let Err(e) = telemetry::encode(PayloadFormat::Influx, &point, &mut payload) {
+        rprintln!("Health encoding failed: {:?}", e);
+        return Ok(());
+    }
+    mqtt_publish(socket, topic, payload.as_bytes()).await?;
+    rprintln!("Health: {}", payload);
+    Ok(())
+}
+
+/// Publishes the crash record from before the last reset on `devices/<id>/crash`.
+async fn publish_crash(socket: &mut TcpSocket<'_>, device_id: &str, record: &CrashRecord) -> Result<(), ()> {
+    use core::fmt::Write;
+    let mut topic = String::<64>::new();
+    let mut payload = String::<768>::new();
+    if write!(topic, "devices/{}/crash", device_id).is_err() || crash::write_json(record, &mut payload).is_err() {
+        rprintln!("Crash record does not fit");
+        return Ok(());
+    }
+    mqtt_publish(socket, topic.as_str(), payload.as_bytes()).await?;
+    rprintln!("Crash report: {}", payload);
+    Ok(())
+}
+
+/// Publishes a change of the battery level, retained on `devices/<id>/battery`.
+async fn report_battery(socket: &mut TcpSocket<'_>, device_id: &str) -> Result<(), ()> {
+    use core::fmt::Write;
+    let Some(level) = battery::unreported() else {
+        return Ok(());
+    };
+    let mut topic = String::<64>::new();
+    let mut payload = String::<48>::new();
+    if write!(topic, "devices/{}/battery", device_id).is_err()
+        || write!(payload, "{{\"level\":\"{}\",\"mv\":{}}}", level.name(), battery::millivolts().unwrap_or(0)).is_err()
+    {
+        return Ok(());
+    }
+    mqtt_publish_retained(socket, topic.as_str(), payload.as_bytes()).await?;
+    battery::mark_reported(level);
+    rprintln!("Battery report: {}", payload);
+    Ok(())
+}
+
+/// Publishes queued samples oldest-first. The buffer is only locked to take
+/// a sample out, so the sampler can keep queueing while the publish is in
+/// flight; a sample that fails to go out is queued again for next time.
+async fn drain_buffer(socket: &mut TcpSocket<'_>, publisher: &Publisher<'_>, buffer: &SharedBuffer) -> Result<(), ()> {
+    let queued = buffer.lock().await.len();
+    if queued == 0 {
+        return Ok(());
+    }
+
+    rprintln!("Buffer: publishing {} queued samples...", queued);
+    loop {
+        let taken = buffer.lock().await.take().await;
+        let sample = match taken {
+            Ok(Some(sample)) => sample,
+            Ok(None) => return Ok(()),
+            Err(e) => {
+                rprintln!("Buffer read failed: {:?}", e);
+                return Ok(());
+            }
+        };
+        watchdog::check_in(Task::Mqtt);
+        if publish_sample(socket, publisher, &sample, None).await.is_err() {
+            // Goes to the back; its timestamp still places it correctly
+            if let Err(e) = buffer.lock().await.push(&sample).await {
+                rprintln!("Buffer: could not requeue a sample: {:?}", e);
+            }
+            return Err(());
+        }
+    }
+}
+
+/// Publishes the readings kept in RTC memory oldest-first, removing each one
+/// once it has been sent.
+async fn drain_sleep_queue(socket: &mut TcpSocket<'_>, publisher: &Publisher<'_>) -> Result<(), ()> {
+    if sleep::len() == 0 {
+        return Ok(());
+    }
+    rprintln!("Publishing {} readings from RTC memory...", sleep::len());
+    while let Some(sample) = sleep::peek() {
+        watchdog::check_in(Task::Mqtt);
+        publish_sample(socket, publisher, &sample, None).await?;
+        sleep::pop();
+    }
+    Ok(())
+}
+
+/// Background task that follows the DHCPv4 lease after the first one.
+#[embassy_executor::task]
+async fn dhcp_watch_task(stack: Stack<'static>) {
+    net::watch_dhcp(stack).await
+}
+
+/// Background task that follows IPv6 Router Advertisements after boot.
+#[embassy_executor::task]
+async fn ipv6_watch_task(stack: Stack<'static>, config: Option<StaticConfigV6>) {
+    ipv6::watch_routers(stack, config).await
+}
+
+/// Background task that keeps the wall clock in sync via SNTP.
+#[embassy_executor::task]
+async fn sntp_task(stack: Stack<'static>, server: String<64>) {
+    loop {
+        match sntp::sync(stack, server.as_str()).await {
+            Ok(_) => Timer::after(SNTP_RESYNC_INTERVAL).await,
+            Err(_) => Timer::after(SNTP_RETRY_INTERVAL).await,
+        }
+    }
+}
+
+/// Handles one console line and writes the reply into `reply`.
+async fn handle_console_line(line: &str, config_store: &mut ConfigStore, reply: &mut String<128>) {
+    use core::fmt::Write;
+    let (command, args) = line.split_once(' ').unwrap_or((line, ""));
+    match command {
+        "cal" => {
+            let mut cal = calibration::current();
+            let args = args.trim();
+            if args.is_empty() {
+                let _ = write!(
+                    reply,
+                    "offset={} slope={} cpu={} radio={}",
+                    cal.offset, cal.slope, cal.cpu_coeff, cal.radio_coeff
+                );
+                return;
+            }
+            if args == "reset" {
+                cal = calibration::Calibration::IDENTITY;
+            } else if let Err(e) = cal.update(args) {
+                let _ = write!(reply, "error: {:?}", e);
+                return;
+            }
+
+            let Ok(mut config) = provision::load_config(config_store).await else {
+                let _ = write!(reply, "error: {}", CommandError::Storage.name());
+                return;
+            };
+            calibration::set(cal);
+            config.calibration = cal;
+            match config_store.save(&config).await {
+                Ok(()) => {
+                    let _ = write!(reply, "ok offset={} slope={} cpu={} radio={}", cal.offset, cal.slope, cal.cpu_coeff, cal.radio_coeff);
+                }
+                Err(e) => {
+                    let _ = write!(reply, "applied, but not saved: {:?}", e);
+                }
+            }
+        }
+        // A `set_config` object, for fixing settings without the broker
+        "config" => {
+            let result = match command::parse_args::<ConfigUpdate>(args.trim().as_bytes()) {
+                Ok(update) => provision::save_update(config_store, &update).await,
+                Err(e) => Err(e),
+            };
+            let _ = match result {
+                Ok(()) => write!(reply, "ok (reboot to apply)"),
+                Err(e) => write!(reply, "error: {}", e.name()),
+            };
+        }
+        "reboot" => {
+            rprintln!("Rebooting...");
+            boot::planned_restart();
+        }
+        "" => {}
+        _ => {
+            let _ = write!(reply, "unknown command '{}' (try: cal [reset | offset=.. slope=.. cpu=.. radio=..], config {{..}}, reboot)", command);
+        }
+    }
+}
+
+/// Line-based command console on the USB Serial/JTAG port.
+#[embassy_executor::task]
+async fn console_task(serial: UsbSerialJtag<'static, Async>, flash: Flash) {
+    let (mut rx, mut tx) = serial.split();
+    let mut config_store = ConfigStore::new(flash);
+    // Room for a `config` line with a few settings
+    let mut line = String::<256>::new();
+    let mut buf = [0u8; 32];
+    watchdog::register(Task::Console, CONSOLE_DEADLINE);
+
+    loop {
+        let read = with_timeout(CONSOLE_IDLE_CHECK_IN, rx.read(&mut buf)).await;
+        watchdog::check_in(Task::Console);
+        let n = match read {
+            Ok(Ok(n)) => n,
+            _ => continue,
+        };
+        for &byte in &buf[..n] {
+            match byte {
+                b'\r' | b'\n' => {
+                    let mut reply = String::<128>::new();
+                    handle_console_line(line.trim(), &mut config_store, &mut reply).await;
+                    line.clear();
+                    if !reply.is_empty() {
+                        let _ = tx.write_all(reply.as_bytes()).await;
+                        let _ = tx.write_all(b"\r\n").await;
+                    }
+                }
+                // Overlong lines are truncated
+                byte if byte.is_ascii() && !byte.is_ascii_control() => {
+                    let _ = line.push(byte as char);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Connects to the configured access point, retrying until it succeeds.
+async fn connect_wifi(wifi: &mut WifiController<'static>, ssid: &str) {
+    rprintln!("Connecting to Wi-Fi...");
+    indicate(State::WifiConnecting);
+    let started = Instant::now();
+    loop {
+        // Use connect_async() to await the connection process
+        match wifi.connect_async().await {
+            Ok(_) => {
+                let elapsed = started.elapsed().as_millis() as u32;
+                rprintln!("Wi-Fi Connected in {}ms!", elapsed);
+                status::set_wifi_connected(true);
+                status::record_latency(|l| l.wifi_connect_ms = elapsed);
+                // The association does not report the channel, so look the AP up
+                let channel = match wifi.scan_with_config_async(ScanConfig::default().with_ssid(ssid)).await {
+                    Ok(aps) => aps.iter().max_by_key(|ap| ap.signal_strength).map(|ap| ap.channel).unwrap_or(0),
+                    Err(_) => 0,
+                };
+                status::set_wifi_channel(channel);
+                break;
+            }
+            Err(e) => {
+                rprintln!("Wi-Fi Connect Failed: {:?}. Retrying in 3s...", e);
+                indicate(State::Error(ErrorCode::Wifi));
+                Timer::after(Duration::from_millis(3000)).await;
+                watchdog::check_in(Task::Mqtt);
+            }
+        }
+    }
+}
+
+/// Serves firmware updates over BLE, for devices that cannot reach the network.
+#[embassy_executor::task]
+async fn ble_dfu_task(
+    stack: &'static BleStack<'static>,
+    flash: Flash,
+    public_key: [u8; PUBLIC_KEY_LEN],
+    name: String<32>,
+) {
+    ota::ble::run(stack, flash, public_key, name.as_str()).await;
+    rprintln!("BLE DFU stopped");
+}
+
+/// Puts a duty cycle that takes too long (e.g. no Wi-Fi) to sleep. Waits
+/// for a firmware update in progress.
+#[embassy_executor::task]
+async fn awake_limit_task(interval: Duration) {
+    Timer::after(sleep::AWAKE_LIMIT).await;
+    while ota::is_busy() {
+        Timer::after(Duration::from_secs(1)).await;
+    }
+    rprintln!("Duty cycle: not done within {}s", sleep::AWAKE_LIMIT.as_secs());
+    sleep::request(interval);
+}
+
+/// Provisioning over BLE, for devices in safe mode.
+#[embassy_executor::task]
+async fn provision_task(stack: &'static BleStack<'static>, flash: Flash, name: String<32>, button: Input<'static>) {
+    provision::run(stack, flash, name.as_str(), button).await;
+    rprintln!("BLE provisioning stopped");
+}
+
+/// Forgets earlier failed boots once this one has run long enough.
+#[embassy_executor::task]
+async fn boot_health_task() {
+    Timer::after(boot::HEALTHY_UPTIME).await;
+    boot::mark_healthy();
+}
+
+/// Runs only the serial console and BLE provisioning, until a fix arrives
+/// (which restarts the device) or `boot::SAFE_MODE_RETRY` passes.
+async fn safe_mode(
+    spawner: Spawner,
+    serial: UsbSerialJtag<'static, Async>,
+    flash: Flash,
+    ble_stack: BleStack<'static>,
+    button: Input<'static>,
+    led: StatusLed,
+    config: &AppConfig,
+    boot_count: u32,
+) -> ! {
+    rprintln!("SAFE MODE: {} boots in a row ended early. MQTT and sensors are off.", boot_count);
+    spawner.spawn(console_task(serial, flash)).unwrap();
+    spawner.spawn(led_task(led, State::Provisioning, config.led_temp_cold, config.led_temp_hot)).unwrap();
+    static BLE_STACK: StaticCell<BleStack<'static>> = StaticCell::new();
+    let ble_stack = BLE_STACK.init(ble_stack);
+    rprintln!("BLE provisioning: press BOOT to advertise as '{}'", config.device_id);
+    spawner.spawn(provision_task(ble_stack, flash, config.device_id.clone(), button)).unwrap();
+
+    Timer::after(boot::SAFE_MODE_RETRY).await;
+    rprintln!("SAFE MODE: no fix within {}s, trying a normal boot", boot::SAFE_MODE_RETRY.as_secs());
+    boot::planned_restart()
+}
+
+/// Rolls back to the previous image unless the running one is confirmed in time.
+#[embassy_executor::task]
+async fn ota_rollback_task(flash: Flash, deadline: Duration) {
+    Timer::after(deadline).await;
+    if !ota::is_confirmed() {
+        rprintln!("OTA: image not confirmed within {}s", deadline.as_secs());
+        ota::roll_back(flash);
+    }
 }
 
 // --- Main Application ---
@@ -39,91 +1270,305 @@ async fn main(spawner: Spawner) -> ! {
     rtt_target::rtt_init_print!();
     
     rprintln!("Initializing...");
+    // Counted before anything that might fail
+    let boot_count = boot::count_boot();
     let mut app = setup(spawner).await;
+    spawner.spawn(watchdog_task(app.watchdogs)).unwrap();
 
     // 1. Load Configuration
-    // We load Wi-Fi credentials and MQTT settings from Flash memory.
-    let mut config_store = ConfigStore::new(app.flash);
-    let config = config_store.load().await.unwrap_or_default();
+    // Wi-Fi credentials and MQTT settings were loaded from flash by `setup`.
+    // The config store, telemetry buffer, console, OTA and BLE each get a
+    // handle on the shared flash (see `flash`).
+    let flash = app.flash;
+    let mut config_store = ConfigStore::new(flash);
+    let config = app.config;
 
-    rprintln!("Booting... SSID='{}'", config.ssid);
+    rprintln!("Booting... SSID='{}' firmware {}", config.ssid, FIRMWARE_VERSION);
+
+    // A freshly updated image has to reach the broker before the deadline
+    if ota::needs_confirmation(flash) {
+        rprintln!("OTA: new image, confirming once MQTT is up (rollback in {}s)", config.ota_confirm_secs);
+        spawner.spawn(ota_rollback_task(flash, Duration::from_secs(config.ota_confirm_secs as u64))).unwrap();
+    }
+    // Crash-looping: leave out everything but the console and BLE provisioning
+    if boot::is_boot_loop(boot_count) {
+        safe_mode(spawner, app.serial, flash, app.ble_stack, app.button, app.led, &config, boot_count).await;
+    }
+    spawner.spawn(boot_health_task()).unwrap();
+
+    // Clock and readings kept across deep sleep
+    sleep::wake();
+
+    let ota_public_key = ota::image::public_key(config::OTA_PUBLIC_KEY);
+    match ota_public_key {
+        // Works without Wi-Fi, e.g. to fix a device with wrong credentials
+        Some(key) => {
+            static BLE_STACK: StaticCell<BleStack<'static>> = StaticCell::new();
+            let ble_stack = BLE_STACK.init(app.ble_stack);
+            rprintln!("OTA over BLE: advertising as '{}'", config.device_id);
+            spawner.spawn(ble_dfu_task(ble_stack, flash, key, config.device_id.clone())).unwrap();
+        }
+        None => rprintln!("OTA updates disabled: no ota_public_key in config.json"),
+    }
+
+    // Calibration can be changed at runtime from the serial console
+    if config.calibration.is_valid() {
+        calibration::set(config.calibration);
+    }
+    spawner.spawn(console_task(app.serial, flash)).unwrap();
+    spawner.spawn(led_task(app.led, State::WifiConnecting, config.led_temp_cold, config.led_temp_hot)).unwrap();
+
+    // Start sampling right away; readings are buffered until MQTT is up.
+    static BUFFER: StaticCell<SharedBuffer> = StaticCell::new();
+    let buffer = BUFFER.init(Mutex::new(
+        TelemetryBuffer::new(flash, config.buffer_capacity, config.buffer_max_age_secs).await,
+    ));
+    // Kept here as well, so `set_interval` can change one of the intervals
+    let mut settings =
+        SamplerSettings::new(config.publish_interval_secs, config.sample_interval_ms, config.deadband, config.heartbeat_secs);
+    let mut sensors = build_sensors(
+        &config.sensors,
+        &config.adc_channels,
+        app.temp_sensor,
+        app.analog,
+        &config.battery,
+        #[cfg(any(feature = "sht3x", feature = "bme280"))]
+        app.i2c,
+        #[cfg(feature = "ds18b20")]
+        app.onewire_pin,
+    );
+    // Remember what will be published, for Home Assistant discovery
+    let mut channels: heapless::Vec<(SensorKind, Quantity), MAX_CHANNELS> = heapless::Vec::new();
+    for sensor in sensors.iter() {
+        let kind = sensor.kind();
+        if let AnySensor::Adc(adc) = sensor {
+            for index in adc.channel_indices() {
+                let _ = channels.push((kind, Quantity::Other(index)));
+            }
+        }
+        for &quantity in kind.quantities() {
+            let _ = channels.push((kind, quantity));
+        }
+    }
+
+    // A flat battery is not spent on Wi-Fi: after the final alert the device
+    // only wakes to measure it
+    let low_battery_sleep = Duration::from_secs(config.battery.low_sleep_secs as u64);
+    let mut battery_level = None;
+    if let Some(AnySensor::Adc(adc)) = sensors.iter_mut().find(|sensor| matches!(sensor, AnySensor::Adc(_)))
+        && let Some(mv) = adc.read_battery().await
+    {
+        rprintln!("Battery: {}mV ({})", mv, battery::level().name());
+        if battery::is_shut_down() {
+            rprintln!("Battery: still below {}mV after the final alert", config.battery.cutoff_mv);
+            sleep::enter(low_battery_sleep).await;
+        }
+        battery_level = Some(battery::level());
+    }
+    // Without a reading the level in RTC memory may be left over from an earlier configuration
+    let battery_monitored = battery_level.is_some();
+
+    // On batteries each boot is one cycle of sample, connect, publish and sleep
+    let sleep_secs = match battery_level {
+        Some(level) => config.battery.sleep_secs(config.sleep_interval_secs, level),
+        None => config.sleep_interval_secs,
+    };
+    let duty_cycle = (config.sleep_interval_secs > 0).then(|| Duration::from_secs(sleep_secs as u64));
+    if let Some(interval) = duty_cycle {
+        rprintln!("Duty cycle: sleeping {}s between cycles", interval.as_secs());
+        spawner.spawn(awake_limit_task(interval)).unwrap();
+    }
+    match duty_cycle {
+        Some(_) => sample_cycle(&mut sensors, buffer).await,
+        None => spawner.spawn(sampler_task(sensors, buffer, settings)).unwrap(),
+    }
+
+    // Check the topic template once; an invalid template falls back to a fixed one
+    let topic_template = match topic::check_template(config.topic_template.as_str(), config.site.as_str(), config.device_id.as_str()) {
+        Ok(()) => config.topic_template.as_str(),
+        Err(e) => {
+            rprintln!("Invalid topic template '{}': {:?}. Using {}", config.topic_template, e, topic::FALLBACK_TEMPLATE);
+            topic::FALLBACK_TEMPLATE
+        }
+    };
+    rprintln!("Publishing to '{}' every {}s as {:?}", topic_template, settings.publish_interval.as_secs(), config.payload_format);
+    if let Ok(filter) = topic::subscription::<64>(topic_template) {
+        rprintln!("Subscribe to '{}' to receive all readings (e.g. in telegraf.conf)", filter);
+    }
+    let publisher = Publisher {
+        topic_template,
+        adc_channels: &config.adc_channels,
+        format: config.payload_format,
+        device_id: config.device_id.as_str(),
+        site: config.site.as_str(),
+        seq: Cell::new(if duty_cycle.is_some() { sleep::sequence() } else { 0 }),
+    };
+
+    // The broker marks us offline when the connection drops without a DISCONNECT
+    let mut availability_topic = String::<64>::new();
+    {
+        use core::fmt::Write;
+        let _ = write!(availability_topic, "devices/{}/availability", config.device_id);
+    }
+    let will = LastWill { topic: availability_topic.as_str(), payload: b"offline", retain: true };
+    let device = DeviceInfo {
+        device_id: config.device_id.as_str(),
+        model: "ESP32-C3",
+        manufacturer: "Espressif",
+        firmware: FIRMWARE_VERSION,
+        mac: esp_hal::efuse::Efuse::mac_address(),
+        site: config.site.as_str(),
+    };
+    let command_filter = command::subscription::<64>(config.device_id.as_str()).unwrap_or_default();
+    let ota_chunk_topic = ota::chunk::chunk_topic::<64>(config.device_id.as_str()).unwrap_or_default();
+    let ota_ack_topic = ota::chunk::ack_topic::<64>(config.device_id.as_str()).unwrap_or_default();
+    let mut health_topic = String::<64>::new();
+    let mut reset_reason = String::<24>::new();
+    {
+        use core::fmt::Write;
+        let _ = write!(health_topic, "devices/{}/status", config.device_id);
+        let _ = match esp_hal::system::reset_reason() {
+            Some(reason) => write!(reset_reason, "{:?}", reason),
+            None => write!(reset_reason, "Unknown"),
+        };
+    }
+    rprintln!("Reset reason: {}", reset_reason);
+    let starved_task = watchdog::take_starved_record();
+    if let Some(task) = starved_task {
+        rprintln!("Watchdog reset: task '{}' had stalled", task.name());
+    }
+    // Reported (and forgotten) once it reaches the broker
+    let mut last_crash = crash::last();
+    if let Some(record) = &last_crash {
+        rprintln!("Crashed last time: '{}' at {}:{}:{}", record.message(), record.file(), record.line, record.column);
+    }
+    let health_interval = Duration::from_secs(config.health_interval_secs as u64);
+    let ha_discovery = config.ha_discovery && discovery::is_supported(config.payload_format);
+    if config.ha_discovery && !ha_discovery {
+        rprintln!("Home Assistant discovery is not available with {:?} payloads", config.payload_format);
+    }
 
     // 2. Configure Wi-Fi
     // We use the credentials from the config store.
     let client_config = ClientConfig::default();
     let client_config = client_config.with_ssid(config.ssid.to_string());
     let client_config = client_config.with_password(config.password.to_string());
+    // How often a sleeping modem wakes for beacons with power save `max`
+    let client_config = client_config.with_listen_interval(config.wifi_listen_interval);
     
     if let Err(e) = app.wifi.set_config(&ModeConfig::Client(client_config)) {
         rprintln!("Error setting Wi-Fi config: {:?}", e);
     }
 
+    // Power save and TX power can be changed at runtime with `set_wifi`
+    let mut radio = RadioSettings::from_config(&config);
+
     // 3. Connect to Wi-Fi
     // We attempt to connect in a loop until successful.
-    rprintln!("Connecting to Wi-Fi...");
-    loop {
-        // Use connect_async() to await the connection process
-        match app.wifi.connect_async().await {
-            Ok(_) => {
-                rprintln!("Wi-Fi Connected!");
-                break;
-            }
-            Err(e) => {
-                rprintln!("Wi-Fi Connect Failed: {:?}. Retrying in 3s...", e);
-                Timer::after(Duration::from_millis(3000)).await;
-            }
-        }
-    }
+    // From here on the main loop checks in with the watchdog.
+    watchdog::register(Task::Mqtt, MQTT_DEADLINE);
+    // Start of the current outage, for the reconnect latency in health reports
+    let mut offline_since = Instant::now();
+    connect_wifi(&mut app.wifi, config.ssid.as_str()).await;
 
     // 4. Initialize Network Stack
     // We allocate static resources for the embassy-net stack.
-    // Use StackResources<3> for 3 sockets.
-    static STACK_RESOURCES: StaticCell<StackResources<3>> = StaticCell::new();
+    // Use StackResources<7> for 7 sockets (DHCP, DNS, MQTT, SNTP, OTA download, IPv6 RA listener and DHCPv6).
+    static STACK_RESOURCES: StaticCell<StackResources<7>> = StaticCell::new();
 
     // Initialize the stack (embassy_net::new returns stack handle + runner)
     // We pass app.wifi_interface directly (by value), so the Runner takes ownership of it.
     let (stack, runner) = embassy_net::new(
         app.wifi_interface,
-        NetConfig::dhcpv4(Default::default()),
-        STACK_RESOURCES.init(StackResources::<3>::new()),
+        NetConfig::dhcpv4(dhcp_config()),
+        STACK_RESOURCES.init(StackResources::<7>::new()),
         1234, // Random seed (Replace with TRNG for production security)
     );
 
     // Start the background network task
     spawner.spawn(net_task(runner)).unwrap();
 
-    // Wait for DHCP to acquire an IP address
+    // Bring up IPv6 first: a Router Advertisement usually arrives within a second,
+    // and on IPv6-only networks it is the only way we get an address.
+    if config.ipv6 {
+        let v6 = match ipv6::autoconfigure(stack, config.dhcpv6).await {
+            Ok(v6) => {
+                status::set_ipv6(Some(v6.address));
+                Some(v6)
+            }
+            Err(_) => {
+                rprintln!("IPv6 autoconfiguration failed, continuing with IPv4 only.");
+                None
+            }
+        };
+        spawner.spawn(ipv6_watch_task(stack, v6)).unwrap();
+    }
+
+    // Wait for DHCP to acquire an IP address.
+    // A lease that never arrives (e.g. wrong VLAN) should not look like a hang,
+    // so after DHCP_TIMEOUT we drop the association and try again.
+    // With a global IPv6 address we can carry on without IPv4.
     rprintln!("Waiting for IP address...");
     loop {
-        if stack.is_config_up() {
-            break;
+        watchdog::check_in(Task::Mqtt);
+        indicate(State::Dhcp);
+        match wait_for_dhcp(stack, DHCP_TIMEOUT).await {
+            Ok(lease) => {
+                rprintln!("Network Up! IP: {} GW: {:?} DNS: {:?}", lease.address, lease.gateway, lease.dns_servers);
+                break;
+            }
+            Err(_) if net_status().ipv6.is_some() => {
+                rprintln!("No DHCPv4 lease, continuing IPv6-only.");
+                break;
+            }
+            Err(_) => {
+                rprintln!("DHCP timed out after {}s. Re-associating Wi-Fi...", DHCP_TIMEOUT.as_secs());
+                indicate(State::Error(ErrorCode::Dhcp));
+                if let Err(e) = app.wifi.disconnect_async().await {
+                    rprintln!("Wi-Fi Disconnect Failed: {:?}", e);
+                }
+                status::set_wifi_connected(false);
+                status::count(|c| c.wifi_reconnects += 1);
+                connect_wifi(&mut app.wifi, config.ssid.as_str()).await;
+            }
         }
-        Timer::after(Duration::from_millis(500)).await;
     }
-    
-    if let Some(config) = stack.config_v4() {
-        rprintln!("Network Up! IP: {:?}", config.address);
+
+    // Later losses and changes of the lease are only logged and reported
+    spawner.spawn(dhcp_watch_task(stack)).unwrap();
+    // Timestamps for readings come from SNTP
+    spawner.spawn(sntp_task(stack, config.ntp_server.clone())).unwrap();
+    // Downloads requested with the `ota` command
+    if ota_public_key.is_some() {
+        spawner.spawn(ota_download_task(stack, flash)).unwrap();
     }
 
     // 5. MQTT Configuration
     let mut rx_buffer = [0u8; 1024];
     let mut tx_buffer = [0u8; 1024];
-
-    // Parse the MQTT broker IP from the config string
-    let broker_ip: Ipv4Address = match Ipv4Address::from_str(config.mqtt_host.as_str()) {
-        Ok(ip) => ip,
-        Err(_) => {
-            rprintln!("Error: MQTT Host '{}' is not a valid IPv4 address.", config.mqtt_host);
-            Ipv4Address::new(127, 0, 0, 1) // Fallback to localhost
-        }
-    };
-    
-    let broker_endpoint = (broker_ip, config.mqtt_port);
+    // Incoming packets (commands) are read into this
+    let mut packet_buffer = [0u8; MAX_PACKET];
+    let keep_alive = Duration::from_secs(KEEP_ALIVE_SECS as u64);
+    let mut ota_context = OtaContext { flash, public_key: ota_public_key, transfer: None };
 
     // 6. Main Application Loop
     // Connects to MQTT, publishes temperature, and handles reconnections.
     loop {
-        rprintln!("Connecting to MQTT Broker at {:?}:{}...", broker_endpoint.0, broker_endpoint.1);
+        watchdog::check_in(Task::Mqtt);
+        indicate(State::BrokerConnecting);
+        // Resolve the broker on every attempt so DNS changes are picked up
+        let broker_ip = match resolve_host(stack, config.mqtt_host.as_str()).await {
+            Ok(ip) => ip,
+            Err(_) => {
+                rprintln!("Error: cannot resolve MQTT Host '{}'. Retrying in 5s...", config.mqtt_host);
+                indicate(State::Error(ErrorCode::Dns));
+                Timer::after(Duration::from_secs(5)).await;
+                continue;
+            }
+        };
+        let broker_endpoint = (broker_ip, config.mqtt_port);
+
+        rprintln!("Connecting to MQTT Broker at {}:{}...", broker_endpoint.0, broker_endpoint.1);
         
         // Create a TCP socket
         // 'stack' is a Copy handle, so we pass it directly
@@ -133,44 +1578,226 @@ async fn main(spawner: Spawner) -> ! {
         // TCP Connect
         if let Err(e) = socket.connect(broker_endpoint).await {
             rprintln!("TCP Connect failed: {:?}. Retrying in 5s...", e);
+            indicate(State::Error(ErrorCode::Broker));
             Timer::after(Duration::from_secs(5)).await;
             continue;
         }
 
         rprintln!("TCP Connected. Sending MQTT CONNECT...");
         // MQTT Handshake
-        if let Err(_) = mqtt_connect(&mut socket, config.device_id.as_str()).await {
+        if let Err(_) = mqtt_connect(&mut socket, config.device_id.as_str(), Some(&will)).await {
              rprintln!("MQTT CONNECT failed. Closing socket.");
+             indicate(State::Error(ErrorCode::Broker));
              socket.close();
              Timer::after(Duration::from_secs(5)).await;
              continue;
         }
 
-        rprintln!("MQTT Connected! Starting publish loop...");
+        let reconnect_ms = offline_since.elapsed().as_millis() as u32;
+        status::record_latency(|l| l.reconnect_ms = reconnect_ms);
+        rprintln!("MQTT Connected in {}ms! Starting publish loop...", reconnect_ms);
+        indicate(State::Running);
+
+        // Remote commands; without them the device still publishes
+        if let Err(_) = mqtt_subscribe(&mut socket, command_filter.as_str(), 1).await {
+            rprintln!("Subscribing to '{}' failed. Remote commands are unavailable.", command_filter);
+        }
+        if ota_context.public_key.is_some() && mqtt_subscribe(&mut socket, ota_chunk_topic.as_str(), 2).await.is_err() {
+            rprintln!("Subscribing to '{}' failed. OTA over MQTT is unavailable.", ota_chunk_topic);
+        }
+
+        // Replace the retained "offline" left by the will, then (re)announce our channels
+        match mqtt_publish_retained(&mut socket, availability_topic.as_str(), b"online").await {
+            // Reaching the broker is what a new image has to prove
+            Ok(()) if !ota::is_confirmed() => {
+                if let Err(e) = ota::confirm(ota_context.flash) {
+                    rprintln!("OTA: confirming the image failed: {:?}", e);
+                }
+            }
+            Ok(()) => {}
+            Err(_) => rprintln!("Availability update failed."),
+        }
+        if ha_discovery && publish_discovery(&mut socket, &publisher, &device, &channels, availability_topic.as_str()).await.is_err() {
+            rprintln!("Home Assistant discovery failed.");
+        }
+
+        // Report the current addressing so the backend can see how the device is attached
+        {
+            let mut topic = String::<64>::new();
+            let mut payload = String::<256>::new();
+            use core::fmt::Write;
+            if write!(topic, "devices/{}/net", config.device_id).is_ok() && format_net_report(&net_status(), &mut payload).is_ok() {
+                if let Err(_) = mqtt_publish(&mut socket, topic.as_str(), payload.as_bytes()).await {
+                    rprintln!("Network report failed.");
+                }
+            }
+        }
         
-        // Publish Loop
-        loop {
-            // Read Temperature
-            let temp = app.temp_sensor.get_temperature().to_celsius();
-            rprintln!("Status: Running | Temp: {:.1} C", temp);
-             
-            // Format Payload
+        if let Some(record) = &last_crash
+            && publish_crash(&mut socket, config.device_id.as_str(), record).await.is_ok()
+        {
+            crash::clear();
+            last_crash = None;
+        }
+
+        // Catch up on readings taken while we were offline
+        status::set_mqtt_connected(true);
+        if drain_buffer(&mut socket, &publisher, buffer).await.is_ok()
+            && drain_sleep_queue(&mut socket, &publisher).await.is_ok()
+        {
+            let (queued, dropped) = {
+                let buffer = buffer.lock().await;
+                (buffer.len(), buffer.dropped())
+            };
+            let mut topic = String::<64>::new();
             let mut payload = String::<64>::new();
             use core::fmt::Write;
-            if write!(payload, "{:.1}", temp).is_ok() {
-                // Publish
-                if let Err(_) = mqtt_publish(&mut socket, "sensors/temp", payload.as_bytes()).await {
-                    rprintln!("Publish failed. Reconnecting...");
-                    break; // Break inner loop to trigger reconnection
+            if write!(topic, "devices/{}/buffer", config.device_id).is_ok()
+                && write!(payload, "{{\"queued\":{},\"dropped\":{}}}", queued, dropped).is_ok()
+            {
+                let _ = mqtt_publish(&mut socket, topic.as_str(), payload.as_bytes()).await;
+            }
+        }
+
+        // Health report right away, so a reboot shows up with its reset reason
+        let mut next_health = Instant::now();
+
+        // Publish Loop
+        // Waits for a sample, a packet from the broker, the next health report
+        // or the keep-alive deadline.
+        // With a deadband there may be no reading for minutes, so an idle
+        // connection pings; a ping still unanswered at the next deadline means
+        // the broker is gone.
+        let mut last_sent = Instant::now();
+        let mut ping_outstanding = false;
+        // In a duty cycle: when to go back to sleep, once the readings are out
+        let mut sleep_at = duty_cycle.map(|_| Instant::now() + sleep::LISTEN_WINDOW);
+        loop {
+            watchdog::check_in(Task::Mqtt);
+            // The final alert on a flat battery is the last thing this boot sends
+            if battery_monitored && report_battery(&mut socket, config.device_id.as_str()).await.is_err() {
+                rprintln!("Battery report failed. Reconnecting...");
+                status::count(|c| c.publish_failures += 1);
+                break;
+            }
+            if battery_monitored && battery::is_shut_down() {
+                rprintln!("Battery: below {}mV, final alert sent", config.battery.cutoff_mv);
+                end_cycle(&mut socket, low_battery_sleep).await;
+            }
+            // Anything still in flash goes out before newer readings
+            if drain_buffer(&mut socket, &publisher, buffer).await.is_err() {
+                rprintln!("Publish failed. Reconnecting...");
+                status::count(|c| c.publish_failures += 1);
+                break;
+            }
+
+            let ping_at = (last_sent + keep_alive / 2).min(sleep_at.unwrap_or(Instant::MAX));
+            let health_at = if health_interval.as_ticks() > 0 { next_health } else { Instant::MAX };
+            let health_or_update = select(Timer::at(health_at), OTA_RESULT.wait());
+            match select4(SAMPLES.receive(), socket.wait_read_ready(), Timer::at(ping_at), health_or_update).await {
+                Either4::First(sample) => {
+                    let rssi = app.wifi.rssi().ok();
+                    if let Err(_) = publish_sample(&mut socket, &publisher, &sample, rssi).await {
+                        rprintln!("Publish failed. Reconnecting...");
+                        status::count(|c| c.publish_failures += 1);
+                        let _ = buffer.lock().await.push(&sample).await;
+                        break; // Break inner loop to trigger reconnection
+                    }
+                    last_sent = Instant::now();
+
+                }
+                Either4::Second(()) => match mqtt_read_packet(&mut socket, &mut packet_buffer).await {
+                    Ok(Packet::Publish { topic, payload, retain: false }) if topic == ota_chunk_topic.as_str() => {
+                        if handle_chunk(&mut socket, &mut ota_context, ota_ack_topic.as_str(), payload).await.is_err() {
+                            rprintln!("OTA ack failed. Reconnecting...");
+                            break;
+                        }
+                        last_sent = Instant::now();
+                        sleep_at = sleep_at.map(|_| last_sent + sleep::LISTEN_WINDOW);
+                    }
+                    Ok(Packet::Publish { topic, payload, retain }) => {
+                        match command::command_name(topic, config.device_id.as_str()) {
+                            // A retained command would run again on every connect
+                            Some(_) if retain => rprintln!("Ignoring retained command on '{}'", topic),
+                            Some(name) => {
+                                let device_id = config.device_id.as_str();
+                                let handled = handle_command(
+                                    &mut socket,
+                                    device_id,
+                                    name,
+                                    payload,
+                                    &mut config_store,
+                                    &mut settings,
+                                    &mut app.wifi,
+                                    &mut radio,
+                                    &mut ota_context,
+                                )
+                                .await;
+                                if handled.is_err() {
+                                    rprintln!("Command response failed. Reconnecting...");
+                                    break;
+                                }
+                                last_sent = Instant::now();
+                                sleep_at = sleep_at.map(|_| last_sent + sleep::LISTEN_WINDOW);
+                            }
+                            None => rprintln!("Unexpected message on '{}'", topic),
+                        }
+                    }
+                    Ok(Packet::PingResp) => ping_outstanding = false,
+                    Ok(Packet::TooLarge) => rprintln!("Dropped a packet larger than {} bytes", MAX_PACKET),
+                    Ok(_) => {}
+                    Err(_) => {
+                        rprintln!("MQTT connection lost. Reconnecting...");
+                        break;
+                    }
+                },
+                Either4::Third(()) => {
+                    if let (Some(interval), Some(at)) = (duty_cycle, sleep_at)
+                        && Instant::now() >= at
+                    {
+                        if !ota::is_busy() {
+                            end_cycle(&mut socket, interval).await;
+                        }
+                        sleep_at = Some(Instant::now() + sleep::LISTEN_WINDOW);
+                        continue;
+                    }
+                    if ping_outstanding {
+                        rprintln!("No PINGRESP from broker. Reconnecting...");
+                        break;
+                    }
+                    if let Err(_) = mqtt_ping(&mut socket).await {
+                        rprintln!("Ping failed. Reconnecting...");
+                        break;
+                    }
+                    ping_outstanding = true;
+                    last_sent = Instant::now();
+                }
+                Either4::Fourth(Either::Second((id, installed))) => {
+                    if report_update(&mut socket, config.device_id.as_str(), id, installed).await.is_err() {
+                        rprintln!("OTA response failed. Reconnecting...");
+                        break;
+                    }
+                    last_sent = Instant::now();
+                }
+                Either4::Fourth(Either::First(())) => {
+                    next_health = Instant::now() + health_interval;
+                    if let Err(_) = publish_health(&mut socket, &publisher, &app.wifi, &radio, health_topic.as_str(), reset_reason.as_str(), starved_task).await {
+                        rprintln!("Health report failed. Reconnecting...");
+                        status::count(|c| c.publish_failures += 1);
+                        break;
+                    }
+                    last_sent = Instant::now();
                 }
-                rprintln!("Published: sensors/temp -> {}", payload);
             }
+        }
+        status::set_mqtt_connected(false);
+        status::count(|c| c.mqtt_reconnects += 1);
+        offline_since = Instant::now();
+        indicate(State::Error(ErrorCode::Broker));
 
-            // Blink LED
-            app.led.toggle();
-            
-            // Sleep before next publish
-            Timer::after(Duration::from_secs(2)).await;
+        // Keep readings that were already handed over to the publisher
+        while let Ok(sample) = SAMPLES.try_receive() {
+            let _ = buffer.lock().await.push(&sample).await;
         }
         
         // Cleanup before retrying
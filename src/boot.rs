@@ -0,0 +1,63 @@
+use core::ptr::addr_of_mut;
+use embassy_time::Duration;
+
+// --- Boot Loop Detection ---
+// Every boot bumps a counter in RTC memory, and running for a while without
+// resetting clears it. So the counter says how many boots in a row ended
+// early (in a panic, a watchdog reset, a brownout, ...). Past a limit the
+// device comes up in safe mode, with only the serial console and BLE
+// provisioning, so a bad configuration can be fixed in the field. A power
+// cycle starts counting from zero.
+
+/// Boots in a row that end within `HEALTHY_UPTIME` before safe mode.
+pub const SAFE_MODE_BOOTS: u32 = 5;
+
+/// Running this long counts as a good boot.
+pub const HEALTHY_UPTIME: Duration = Duration::from_secs(5 * 60);
+
+/// Safe mode gives up waiting for a fix after this long and tries a normal
+/// boot again, in case the cause (e.g. a broker outage) went away.
+pub const SAFE_MODE_RETRY: Duration = Duration::from_secs(30 * 60);
+
+/// Upper half of the RTC word, so garbage is not mistaken for a count.
+const COUNT_MAGIC: u32 = 0xB007_0000;
+
+#[esp_hal::ram(unstable(rtc_fast, persistent))]
+static mut BOOT_COUNT: u32 = 0;
+
+fn stored_count() -> u32 {
+    let value = unsafe { addr_of_mut!(BOOT_COUNT).read_volatile() };
+    if value & 0xFFFF_0000 == COUNT_MAGIC { value & 0xFFFF } else { 0 }
+}
+
+fn store_count(count: u32) {
+    // Only touched from the main task
+    unsafe { addr_of_mut!(BOOT_COUNT).write_volatile(COUNT_MAGIC | count.min(0xFFFF)) };
+}
+
+/// Counts this boot. Returns how many boots in a row (this one included)
+/// have not reached `HEALTHY_UPTIME` yet.
+pub fn count_boot() -> u32 {
+    let count = stored_count() + 1;
+    store_count(count);
+    count
+}
+
+/// Whether `count` boots in a row call for safe mode.
+pub fn is_boot_loop(count: u32) -> bool {
+    count >= SAFE_MODE_BOOTS
+}
+
+/// Forgets earlier boots once this one has run for `HEALTHY_UPTIME`.
+pub fn mark_healthy() {
+    store_count(0);
+}
+
+/// Resets on purpose (a reboot command, new settings, ...). The current boot
+/// does not count as failed. Leaving safe mode this way allows one more early
+/// reset before the device is back in safe mode.
+pub fn planned_restart() -> ! {
+    let count = stored_count().saturating_sub(1).min(SAFE_MODE_BOOTS - 2);
+    store_count(count);
+    esp_hal::system::software_reset()
+}
@@ -0,0 +1,254 @@
+use serde::{Serialize, Deserialize};
+use sequential_storage::queue::{QueueConfig, QueueStorage};
+use sequential_storage::cache::NoCache;
+use embassy_embedded_hal::adapter::BlockingAsync;
+use embassy_time::Instant;
+use rtt_target::rprintln;
+
+use crate::aggregate::Summary;
+use crate::flash::Flash;
+use crate::sensor::{Quantity, SensorKind};
+use crate::sntp;
+
+// --- Store-and-Forward Telemetry Buffer ---
+// Readings taken while the broker is unreachable are queued in a dedicated
+// flash region and published oldest-first after reconnecting.
+
+// Dedicated region near the end of the 4 MB flash, well clear of the
+// NVS/config pages and the application partitions.
+const BUFFER_ADDR_START: u32 = 0x3E0000;
+const BUFFER_SECTOR_SIZE: u32 = 4096;
+const BUFFER_PAGES: u32 = 16;
+const BUFFER_ADDR_END: u32 = BUFFER_ADDR_START + BUFFER_PAGES * BUFFER_SECTOR_SIZE;
+
+// Version byte and postcard-encoded `Sample`, plus headroom
+pub const SAMPLE_BUF_SIZE: usize = 48;
+
+/// Layout of a queued record: this byte, then the postcard-encoded `Sample`.
+/// Records written before versioning start with the `SensorKind` index,
+/// which postcard encodes below 0x80, so versions count up from 0x80. When
+/// `Sample` changes, bump this and keep decoding the old layout in `decode`.
+const SAMPLE_VERSION: u8 = 0x80 | 1;
+
+/// Why a queued record could not be read back.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RecordError {
+    /// Written by newer firmware.
+    UnknownVersion(u8),
+    Malformed,
+}
+
+fn encode(sample: &Sample, buf: &mut [u8; SAMPLE_BUF_SIZE]) -> usize {
+    buf[0] = SAMPLE_VERSION;
+    1 + postcard::to_slice(sample, &mut buf[1..]).expect("Sample serialization failed").len()
+}
+
+fn decode(record: &[u8]) -> Result<Sample, RecordError> {
+    match record.first() {
+        Some(&SAMPLE_VERSION) => postcard::from_bytes(&record[1..]).map_err(|_| RecordError::Malformed),
+        // Unversioned records have the same layout as version 0x81
+        Some(&first) if first < 0x80 => postcard::from_bytes(record).map_err(|_| RecordError::Malformed),
+        Some(&version) => Err(RecordError::UnknownVersion(version)),
+        None => Err(RecordError::Malformed),
+    }
+}
+
+type BufferError = sequential_storage::Error<esp_storage::FlashStorageError>;
+
+/// One published data point: the summary of one channel over a sampling
+/// window, timestamped at the end of the window.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Sample {
+    pub sensor: SensorKind,
+    pub quantity: Quantity,
+    /// Unix time in milliseconds if `epoch` is set, otherwise uptime in
+    /// milliseconds (the clock was not synchronised yet).
+    pub timestamp_ms: u64,
+    pub epoch: bool,
+    /// Mean over the window.
+    pub value: f32,
+    pub min: f32,
+    pub max: f32,
+    pub stddev: f32,
+    /// Number of readings in the window.
+    pub count: u16,
+}
+
+impl Sample {
+    /// Creates a sample stamped with Unix time if the clock is set, uptime otherwise.
+    pub fn now(sensor: SensorKind, quantity: Quantity, summary: &Summary) -> Self {
+        let uptime_ms = Instant::now().as_millis();
+        let (timestamp_ms, epoch) = match sntp::to_unix_ms(uptime_ms) {
+            Some(unix_ms) => (unix_ms, true),
+            None => (uptime_ms, false),
+        };
+        Self {
+            sensor,
+            quantity,
+            timestamp_ms,
+            epoch,
+            value: summary.mean,
+            min: summary.min,
+            max: summary.max,
+            stddev: summary.stddev,
+            count: summary.count,
+        }
+    }
+
+    /// Unix timestamp (ms), converting uptime stamps if the clock has been set since.
+    /// Only valid for samples taken during the current boot.
+    pub fn unix_ms(&self) -> Option<u64> {
+        if self.epoch { Some(self.timestamp_ms) } else { sntp::to_unix_ms(self.timestamp_ms) }
+    }
+
+    /// Age in milliseconds relative to the current time.
+    fn age_ms(&self) -> Option<u64> {
+        if self.epoch {
+            sntp::unix_time_ms().map(|now| now.saturating_sub(self.timestamp_ms))
+        } else {
+            Instant::now().as_millis().checked_sub(self.timestamp_ms)
+        }
+    }
+}
+
+pub struct TelemetryBuffer {
+    storage: QueueStorage<BlockingAsync<Flash>, NoCache>,
+    capacity: u16,
+    max_age_ms: u64,
+    len: u16,
+    /// Entries queued before this boot; their uptime stamps are meaningless now.
+    boot_backlog: u16,
+    dropped: u32,
+}
+
+impl TelemetryBuffer {
+    /// Opens the buffer, keeping at most `capacity` samples no older than `max_age_secs`.
+    pub async fn new(flash: Flash, capacity: u16, max_age_secs: u32) -> Self {
+        let flash = BlockingAsync::new(flash);
+        let config = QueueConfig::new(BUFFER_ADDR_START..BUFFER_ADDR_END);
+
+        let mut buffer = Self {
+            storage: QueueStorage::new(flash, config, NoCache::new()),
+            capacity,
+            max_age_ms: max_age_secs as u64 * 1000,
+            len: 0,
+            boot_backlog: 0,
+            dropped: 0,
+        };
+
+        // Samples survive a reboot, so count what is already queued
+        match buffer.count().await {
+            Ok(len) => {
+                buffer.len = len;
+                buffer.boot_backlog = len;
+            }
+            Err(e) => {
+                rprintln!("Buffer: unreadable ({:?}), erasing", e);
+                let _ = buffer.storage.erase_all().await;
+            }
+        }
+        rprintln!("Buffer: {} samples queued", buffer.len);
+        buffer
+    }
+
+    async fn count(&mut self) -> Result<u16, BufferError> {
+        let mut buf = [0u8; SAMPLE_BUF_SIZE];
+        let mut len = 0;
+        let mut iter = self.storage.iter().await?;
+        while iter.next(&mut buf).await?.is_some() {
+            len += 1;
+        }
+        Ok(len)
+    }
+
+    /// Number of samples waiting to be published.
+    pub fn len(&self) -> u16 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of samples discarded because the buffer was full.
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    /// Appends a sample, discarding the oldest one if the buffer is full.
+    pub async fn push(&mut self, sample: &Sample) -> Result<(), BufferError> {
+        let mut ser_buf = [0u8; SAMPLE_BUF_SIZE];
+        let len = encode(sample, &mut ser_buf);
+        let bytes = &ser_buf[..len];
+
+        if self.len >= self.capacity {
+            self.pop().await?;
+            self.dropped += 1;
+        }
+
+        // If the flash region fills up before `capacity` is reached, the oldest
+        // entries are overwritten; count those as dropped too.
+        match self.storage.push(bytes, false).await {
+            Ok(()) => {}
+            Err(sequential_storage::Error::FullStorage) => {
+                self.pop().await?;
+                self.dropped += 1;
+                self.storage.push(bytes, false).await?;
+            }
+            Err(e) => return Err(e),
+        }
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Returns the oldest sample that is still within the maximum age, without removing it.
+    /// Stale samples, and uptime-stamped ones from before the last reboot, are discarded.
+    /// Epoch-stamped samples are kept until the clock is set and their age can be checked.
+    pub async fn peek(&mut self) -> Result<Option<Sample>, BufferError> {
+        let mut buf = [0u8; SAMPLE_BUF_SIZE];
+        loop {
+            let Some(bytes) = self.storage.peek(&mut buf).await? else {
+                self.len = 0;
+                self.boot_backlog = 0;
+                return Ok(None);
+            };
+
+            let keep = match decode(bytes) {
+                Ok(sample) if !sample.epoch && self.boot_backlog > 0 => None,
+                Ok(sample) => match sample.age_ms() {
+                    Some(age) if age > self.max_age_ms => None,
+                    _ => Some(sample),
+                },
+                Err(e) => {
+                    rprintln!("Buffer: dropping unreadable record: {:?}", e);
+                    self.dropped += 1;
+                    None
+                }
+            };
+
+            match keep {
+                Some(sample) => return Ok(Some(sample)),
+                None => self.pop().await?,
+            }
+        }
+    }
+
+    /// Removes and returns the oldest sample still within the maximum age.
+    pub async fn take(&mut self) -> Result<Option<Sample>, BufferError> {
+        let sample = self.peek().await?;
+        if sample.is_some() {
+            self.pop().await?;
+        }
+        Ok(sample)
+    }
+
+    /// Removes the oldest sample (call after it has been published).
+    pub async fn pop(&mut self) -> Result<(), BufferError> {
+        let mut buf = [0u8; SAMPLE_BUF_SIZE];
+        if self.storage.pop(&mut buf).await?.is_some() {
+            self.len = self.len.saturating_sub(1);
+            self.boot_backlog = self.boot_backlog.saturating_sub(1);
+        }
+        Ok(())
+    }
+}
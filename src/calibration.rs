@@ -0,0 +1,31 @@
+use core::cell::Cell;
+use critical_section::Mutex;
+
+// The correction and the load estimate are hardware-independent (see `firmware-core/`)
+pub use firmware_core::calibration::{Calibration, CalibrationError, Load, LoadMeter, THERMAL_TIME_CONSTANT_MS};
+
+// --- Calibration State ---
+// What the sampler, the internal sensor and the console share.
+
+/// The calibration in effect, shared between the sampler and the console.
+static CALIBRATION: Mutex<Cell<Calibration>> = Mutex::new(Cell::new(Calibration::IDENTITY));
+
+pub fn current() -> Calibration {
+    critical_section::with(|cs| CALIBRATION.borrow(cs).get())
+}
+
+/// Replaces the calibration used for subsequent readings.
+pub fn set(calibration: Calibration) {
+    critical_section::with(|cs| CALIBRATION.borrow(cs).set(calibration));
+}
+
+/// Latest load estimate, updated by the sampler and used by the internal sensor.
+static LOAD: Mutex<Cell<Load>> = Mutex::new(Cell::new(Load { cpu: 0.0, radio: 0.0 }));
+
+pub fn load() -> Load {
+    critical_section::with(|cs| LOAD.borrow(cs).get())
+}
+
+pub fn set_load(load: Load) {
+    critical_section::with(|cs| LOAD.borrow(cs).set(load));
+}
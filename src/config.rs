@@ -1,32 +1,81 @@
-use serde::{Serialize, Deserialize};
-use heapless::String;
+use serde::Deserialize;
+use heapless::{String, Vec};
 use sequential_storage::map::{MapConfig, MapStorage};
 use sequential_storage::cache::NoCache;
-use esp_storage::FlashStorage;
-use esp_hal::peripherals::FLASH;
 use embassy_embedded_hal::adapter::BlockingAsync;
+use rtt_target::rprintln;
+
+use crate::battery::BatteryConfig;
+use crate::calibration::Calibration;
+use crate::flash::Flash;
+use crate::led::LedBoard;
+use crate::topic;
+use crate::sensor::{SensorKind, MAX_SENSORS};
+use crate::sensor::analog::{AdcChannelConfig, Attenuation, MAX_ADC_CHANNELS};
+use crate::telemetry::PayloadFormat;
+use crate::wifi::PowerSave;
 
 // Include generated secrets
 include!(concat!(env!("OUT_DIR"), "/secrets.rs"));
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
-pub struct AppConfig {
-    pub ssid: String<32>,
-    pub password: String<64>,
-    pub mqtt_host: String<64>,
-    pub mqtt_port: u16,
-    pub device_id: String<32>,
-}
+// The settings themselves are hardware-independent (see `firmware-core/`)
+pub use firmware_core::config::AppConfig;
 
-impl Default for AppConfig {
-    fn default() -> Self {
-        Self {
-            ssid: String::try_from(DEFAULT_SSID).unwrap_or(String::try_from("Guest").unwrap()),
-            password: String::try_from(DEFAULT_PASSWORD).unwrap_or(String::new()),
-            mqtt_host: String::try_from(DEFAULT_MQTT_HOST).unwrap_or(String::try_from("127.0.0.1").unwrap()),
-            mqtt_port: DEFAULT_MQTT_PORT,
-            device_id: String::try_from(DEFAULT_DEVICE_ID).unwrap_or(String::try_from("esp32").unwrap()),
-        }
+/// The settings baked in from `config.json`, used until others are saved.
+pub fn defaults() -> AppConfig {
+    AppConfig {
+        ssid: String::try_from(DEFAULT_SSID).unwrap_or(String::try_from("Guest").unwrap()),
+        password: String::try_from(DEFAULT_PASSWORD).unwrap_or(String::new()),
+        mqtt_host: String::try_from(DEFAULT_MQTT_HOST).unwrap_or(String::try_from("127.0.0.1").unwrap()),
+        mqtt_port: DEFAULT_MQTT_PORT,
+        device_id: String::try_from(DEFAULT_DEVICE_ID).unwrap_or(String::try_from("esp32").unwrap()),
+        site: String::try_from(DEFAULT_SITE).unwrap_or(String::try_from("default").unwrap()),
+        topic_template: String::try_from(DEFAULT_TOPIC_TEMPLATE).unwrap_or(String::try_from(topic::FALLBACK_TEMPLATE).unwrap()),
+        publish_interval_secs: DEFAULT_PUBLISH_INTERVAL_SECS,
+        sample_interval_ms: DEFAULT_SAMPLE_INTERVAL_MS,
+        deadband: DEFAULT_DEADBAND,
+        heartbeat_secs: DEFAULT_HEARTBEAT_SECS,
+        health_interval_secs: DEFAULT_HEALTH_INTERVAL_SECS,
+        sensors: DEFAULT_SENSORS.iter().filter_map(|name| SensorKind::from_name(name)).take(MAX_SENSORS).collect(),
+        adc_channels: DEFAULT_ADC_CHANNELS
+            .iter()
+            .filter_map(|&(name, pin, attenuation, oversample, scale, unit)| {
+                Some(AdcChannelConfig {
+                    name: String::try_from(name).ok()?,
+                    pin,
+                    attenuation: Attenuation::from_name(attenuation)?,
+                    oversample,
+                    scale: Vec::from_slice(scale).ok()?,
+                    unit: String::try_from(unit).ok()?,
+                })
+            })
+            .take(MAX_ADC_CHANNELS)
+            .collect(),
+        calibration: Calibration::IDENTITY,
+        payload_format: PayloadFormat::from_name(DEFAULT_PAYLOAD_FORMAT).unwrap_or(PayloadFormat::Value),
+        ha_discovery: DEFAULT_HA_DISCOVERY,
+        ota_confirm_secs: DEFAULT_OTA_CONFIRM_SECS,
+        ipv6: DEFAULT_IPV6,
+        dhcpv6: DEFAULT_DHCPV6,
+        ntp_server: String::try_from(DEFAULT_NTP_SERVER).unwrap_or(String::try_from("pool.ntp.org").unwrap()),
+        buffer_capacity: DEFAULT_BUFFER_CAPACITY,
+        buffer_max_age_secs: DEFAULT_BUFFER_MAX_AGE_SECS,
+        sleep_interval_secs: DEFAULT_SLEEP_INTERVAL_SECS,
+        wifi_power_save: PowerSave::from_name(DEFAULT_WIFI_POWER_SAVE).unwrap_or(PowerSave::None),
+        wifi_tx_power_dbm: DEFAULT_WIFI_TX_POWER_DBM,
+        wifi_country: String::try_from(DEFAULT_WIFI_COUNTRY).unwrap_or(String::try_from("01").unwrap()),
+        wifi_listen_interval: DEFAULT_WIFI_LISTEN_INTERVAL,
+        battery: BatteryConfig {
+            pin: DEFAULT_BATTERY_PIN,
+            divider: DEFAULT_BATTERY_DIVIDER,
+            low_mv: DEFAULT_BATTERY_LOW_MV,
+            cutoff_mv: DEFAULT_BATTERY_CUTOFF_MV,
+            low_sleep_secs: DEFAULT_BATTERY_LOW_SLEEP_SECS,
+        },
+        led_board: LedBoard::from_name(DEFAULT_LED_BOARD).unwrap_or(LedBoard::Gpio),
+        led_brightness: DEFAULT_LED_BRIGHTNESS,
+        led_temp_cold: DEFAULT_LED_TEMP_COLD,
+        led_temp_hot: DEFAULT_LED_TEMP_HOT,
     }
 }
 
@@ -35,15 +84,74 @@ const FLASH_SECTOR_SIZE: u32 = 4096;
 const FLASH_PAGES: u32 = 4;
 const FLASH_ADDR_END: u32 = FLASH_ADDR_START + FLASH_PAGES * FLASH_SECTOR_SIZE;
 
-const CONFIG_KEY: u8 = 1;
+// Baseline records: the first five fields of AppConfig, no version
+const LEGACY_CONFIG_KEY: u8 = 1;
+// Versioned records: `CONFIG_VERSION`, then the postcard-encoded AppConfig
+const CONFIG_KEY: u8 = 2;
+
+/// Layout version of the stored AppConfig. postcard is not self-describing,
+/// so any change to the fields of AppConfig (or of the types it contains)
+/// bumps this, keeps the previous layout as `ConfigV<n>` and adds its arm to
+/// `migrate`.
+pub const CONFIG_VERSION: u8 = 1;
+
+// Large enough for an AppConfig with every string field and list at capacity
+const CONFIG_BUF_SIZE: usize = 1024;
 
-pub struct ConfigStore<'a> {
-    storage: MapStorage<u8, BlockingAsync<FlashStorage<'a>>, NoCache>,
+/// The layout written by firmware before config versioning.
+#[derive(Deserialize)]
+struct ConfigV0 {
+    ssid: String<32>,
+    password: String<64>,
+    mqtt_host: String<64>,
+    mqtt_port: u16,
+    device_id: String<32>,
+}
+
+impl From<ConfigV0> for AppConfig {
+    fn from(old: ConfigV0) -> Self {
+        Self {
+            ssid: old.ssid,
+            password: old.password,
+            mqtt_host: old.mqtt_host,
+            mqtt_port: old.mqtt_port,
+            device_id: old.device_id,
+            ..defaults()
+        }
+    }
 }
 
-impl<'a> ConfigStore<'a> {
-    pub fn new(flash_peripheral: FLASH<'a>) -> Self {
-        let flash = FlashStorage::new(flash_peripheral);
+/// Decodes a record of layout `version`.
+fn migrate(version: u8, bytes: &[u8]) -> Result<AppConfig, LoadError> {
+    let config = match version {
+        0 => postcard::from_bytes::<ConfigV0>(bytes).map(AppConfig::from),
+        CONFIG_VERSION => postcard::from_bytes::<AppConfig>(bytes),
+        _ => return Err(LoadError::UnknownVersion(version)),
+    };
+    config.map_err(|_| LoadError::Corrupt(version))
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Storage(sequential_storage::Error<esp_storage::FlashStorageError>),
+    /// Written by newer firmware; saving over it would lose settings.
+    UnknownVersion(u8),
+    /// The record does not decode as its version's layout.
+    Corrupt(u8),
+}
+
+impl From<sequential_storage::Error<esp_storage::FlashStorageError>> for LoadError {
+    fn from(e: sequential_storage::Error<esp_storage::FlashStorageError>) -> Self {
+        Self::Storage(e)
+    }
+}
+
+pub struct ConfigStore {
+    storage: MapStorage<u8, BlockingAsync<Flash>, NoCache>,
+}
+
+impl ConfigStore {
+    pub fn new(flash: Flash) -> Self {
         let flash = BlockingAsync::new(flash);
         let config = MapConfig::new(FLASH_ADDR_START..FLASH_ADDR_END);
         let cache = NoCache::new();
@@ -54,29 +162,60 @@ impl<'a> ConfigStore<'a> {
     }
 
     pub async fn save(&mut self, config: &AppConfig) -> Result<(), sequential_storage::Error<esp_storage::FlashStorageError>> {
-        let mut buf = [0u8; 256]; // Work buffer for storage
-        let mut ser_buf = [0u8; 256]; // Buffer for serialization
+        let mut buf = [0u8; CONFIG_BUF_SIZE]; // Work buffer for storage
+        let mut ser_buf = [0u8; CONFIG_BUF_SIZE + 1]; // Version byte, then the config
         
         // Serialize config to bytes
-        let bytes = postcard::to_slice(config, &mut ser_buf).expect("Config serialization failed");
-        let bytes: &[u8] = bytes;
+        ser_buf[0] = CONFIG_VERSION;
+        let len = postcard::to_slice(config, &mut ser_buf[1..]).expect("Config serialization failed").len();
+        let bytes: &[u8] = &ser_buf[..len + 1];
         
         // store_item(buffer, key, value)
         self.storage.store_item(&mut buf, &CONFIG_KEY, &bytes).await
     }
 
-    pub async fn load(&mut self) -> Result<AppConfig, sequential_storage::Error<esp_storage::FlashStorageError>> {
-        let mut buf = [0u8; 256]; // Work buffer for storage and fetching
+    /// The stored config, migrated to the current layout. Falls back to the
+    /// defaults, logged, when nothing was saved or the record does not decode;
+    /// a record from newer firmware is an error, so callers do not save over it.
+    pub async fn load(&mut self) -> Result<AppConfig, LoadError> {
+        let mut buf = [0u8; CONFIG_BUF_SIZE + 1]; // Work buffer for storage and fetching
         
         // fetch_item(buffer, key)
-        let res = self.storage.fetch_item::<&[u8]>(&mut buf, &CONFIG_KEY).await?;
+        if let Some(bytes) = self.storage.fetch_item::<&[u8]>(&mut buf, &CONFIG_KEY).await? {
+            let Some((&version, bytes)) = bytes.split_first() else {
+                rprintln!("Config: stored record is empty, using defaults");
+                return Ok(defaults());
+            };
+            return match migrate(version, bytes) {
+                Ok(config) => {
+                    if version != CONFIG_VERSION {
+                        rprintln!("Config: migrated from layout v{} to v{}", version, CONFIG_VERSION);
+                    }
+                    Ok(config)
+                }
+                Err(LoadError::Corrupt(version)) => {
+                    rprintln!("Config: stored v{} record does not decode, using defaults", version);
+                    Ok(defaults())
+                }
+                Err(e) => Err(e),
+            };
+        }
 
-        match res {
-            Some(bytes) => {
-                let config = postcard::from_bytes(bytes).unwrap_or(AppConfig::default());
-                Ok(config)
+        match self.storage.fetch_item::<&[u8]>(&mut buf, &LEGACY_CONFIG_KEY).await? {
+            Some(bytes) => match migrate(0, bytes) {
+                Ok(config) => {
+                    rprintln!("Config: migrated from layout v0 to v{}, other settings at defaults", CONFIG_VERSION);
+                    Ok(config)
+                }
+                Err(_) => {
+                    rprintln!("Config: stored v0 record does not decode, using defaults");
+                    Ok(defaults())
+                }
+            },
+            None => {
+                rprintln!("Config: nothing stored, using defaults");
+                Ok(defaults())
             }
-            None => Ok(AppConfig::default()),
         }
     }
 }
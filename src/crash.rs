@@ -0,0 +1,229 @@
+use core::fmt::Write;
+use core::panic::PanicInfo;
+use core::ptr::addr_of_mut;
+use heapless::String;
+
+use crate::telemetry::{push_json_str, EncodeError};
+
+// --- Crash Records ---
+// The panic handler stores where and why it panicked, plus the return
+// addresses found by walking the frame pointers, in RTC fast memory and resets
+// the chip. RTC memory survives the reset (but not a power cycle), so the next
+// boot can publish the record once MQTT is up. Resolve the backtrace against
+// the ELF of the same firmware version, e.g. with `addr2line -e <elf> <addr>`.
+
+/// Bytes kept of the panic message.
+pub const MESSAGE_LEN: usize = 128;
+
+/// Bytes kept of the source path (the end of it, if longer).
+pub const FILE_LEN: usize = 64;
+
+/// Bytes kept of the firmware version.
+pub const VERSION_LEN: usize = 16;
+
+/// Return addresses kept, innermost first.
+pub const MAX_FRAMES: usize = 16;
+
+/// Marks a valid record; anything else in RTC memory is ignored.
+const RECORD_MAGIC: u32 = 0xC8A5_4ED1;
+
+/// A panic, as kept across the reset. Plain arrays only, so any bit pattern
+/// left in RTC memory is a valid (if meaningless) value.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct CrashRecord {
+    magic: u32,
+    pub line: u32,
+    pub column: u32,
+    /// Seconds since boot when it panicked.
+    pub uptime_s: u32,
+    file_len: u32,
+    message_len: u32,
+    version_len: u32,
+    frame_count: u32,
+    file: [u8; FILE_LEN],
+    message: [u8; MESSAGE_LEN],
+    version: [u8; VERSION_LEN],
+    frames: [u32; MAX_FRAMES],
+}
+
+impl CrashRecord {
+    pub const EMPTY: Self = Self {
+        magic: 0,
+        line: 0,
+        column: 0,
+        uptime_s: 0,
+        file_len: 0,
+        message_len: 0,
+        version_len: 0,
+        frame_count: 0,
+        file: [0; FILE_LEN],
+        message: [0; MESSAGE_LEN],
+        version: [0; VERSION_LEN],
+        frames: [0; MAX_FRAMES],
+    };
+
+    /// Builds a record; `message` is written with `write_message`.
+    pub fn new(file: &str, line: u32, column: u32, version: &str, uptime_s: u32) -> Self {
+        let mut record = Self { magic: RECORD_MAGIC, line, column, uptime_s, ..Self::EMPTY };
+        // The end of a long path (e.g. in ~/.cargo/registry) names the file
+        let mut start = file.len().saturating_sub(FILE_LEN);
+        while !file.is_char_boundary(start) {
+            start += 1;
+        }
+        record.file_len = copy_str(&file[start..], &mut record.file) as u32;
+        record.version_len = copy_str(version, &mut record.version) as u32;
+        record
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.magic == RECORD_MAGIC
+    }
+
+    pub fn file(&self) -> &str {
+        stored_str(&self.file, self.file_len)
+    }
+
+    pub fn message(&self) -> &str {
+        stored_str(&self.message, self.message_len)
+    }
+
+    pub fn version(&self) -> &str {
+        stored_str(&self.version, self.version_len)
+    }
+
+    pub fn backtrace(&self) -> &[u32] {
+        &self.frames[..(self.frame_count as usize).min(MAX_FRAMES)]
+    }
+
+    /// Formats the panic message into the record, truncating it if needed.
+    pub fn write_message(&mut self, args: core::fmt::Arguments) {
+        let mut writer = Truncating { buf: &mut self.message, len: 0 };
+        let _ = writer.write_fmt(args);
+        self.message_len = writer.len as u32;
+    }
+
+    pub fn push_frame(&mut self, address: u32) -> bool {
+        let count = self.frame_count as usize;
+        if count >= MAX_FRAMES {
+            return false;
+        }
+        self.frames[count] = address;
+        self.frame_count += 1;
+        true
+    }
+}
+
+/// Copies as much of `s` as fits, up to a character boundary.
+fn copy_str(s: &str, buf: &mut [u8]) -> usize {
+    let mut len = s.len().min(buf.len());
+    while !s.is_char_boundary(len) {
+        len -= 1;
+    }
+    buf[..len].copy_from_slice(&s.as_bytes()[..len]);
+    len
+}
+
+/// Stored text, or as much of it as is valid UTF-8 (RTC memory may hold junk).
+fn stored_str(buf: &[u8], len: u32) -> &str {
+    let buf = &buf[..(len as usize).min(buf.len())];
+    match core::str::from_utf8(buf) {
+        Ok(s) => s,
+        Err(e) => core::str::from_utf8(&buf[..e.valid_up_to()]).unwrap_or(""),
+    }
+}
+
+/// `fmt::Write` into a fixed buffer that silently drops what does not fit.
+struct Truncating<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl Write for Truncating<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.len += copy_str(s, &mut self.buf[self.len..]);
+        Ok(())
+    }
+}
+
+/// Encodes `record` as JSON into `out` (which is cleared first):
+/// `{"fw":"0.1.0","uptime_s":5123,"file":"src/bin/main.rs","line":42,"column":9,"message":"..","backtrace":["0x42001a2c",..]}`
+pub fn write_json<const N: usize>(record: &CrashRecord, out: &mut String<N>) -> Result<(), EncodeError> {
+    out.clear();
+    out.push_str("{\"fw\":").map_err(|_| EncodeError::Overflow)?;
+    push_json_str(record.version(), out)?;
+    write!(out, ",\"uptime_s\":{},\"file\":", record.uptime_s)?;
+    push_json_str(record.file(), out)?;
+    write!(out, ",\"line\":{},\"column\":{},\"message\":", record.line, record.column)?;
+    push_json_str(record.message(), out)?;
+    out.push_str(",\"backtrace\":[").map_err(|_| EncodeError::Overflow)?;
+    for (i, address) in record.backtrace().iter().enumerate() {
+        if i > 0 {
+            out.push(',').map_err(|_| EncodeError::Overflow)?;
+        }
+        write!(out, "\"0x{:08x}\"", address)?;
+    }
+    out.push_str("]}").map_err(|_| EncodeError::Overflow)
+}
+
+// --- RTC Storage ---
+
+#[esp_hal::ram(unstable(rtc_fast, persistent))]
+static mut LAST_CRASH: CrashRecord = CrashRecord::EMPTY;
+
+// SAFETY: only arrays of integers, see `CrashRecord`
+unsafe impl esp_hal::Persistable for CrashRecord {}
+
+/// Stores the panic in RTC memory. Called from the panic handler only.
+pub fn record(info: &PanicInfo, version: &str) {
+    let (file, line, column) = match info.location() {
+        Some(location) => (location.file(), location.line(), location.column()),
+        None => ("", 0, 0),
+    };
+    let uptime_s = embassy_time::Instant::now().as_secs() as u32;
+    let mut record = CrashRecord::new(file, line, column, version, uptime_s);
+    record.write_message(format_args!("{}", info.message()));
+    capture_backtrace(&mut record);
+    // Nothing else runs once we are panicking
+    unsafe { addr_of_mut!(LAST_CRASH).write_volatile(record) };
+}
+
+/// The crash before the last reset, if it has not been reported yet.
+pub fn last() -> Option<CrashRecord> {
+    let record = unsafe { addr_of_mut!(LAST_CRASH).read_volatile() };
+    record.is_valid().then_some(record)
+}
+
+/// Forgets the stored crash once it has been reported.
+pub fn clear() {
+    unsafe { (*addr_of_mut!(LAST_CRASH)).magic = 0 };
+}
+
+/// Internal SRAM, where the stack lives.
+const DRAM: core::ops::Range<u32> = 0x3FC8_0000..0x3FCE_0000;
+/// Internal SRAM as seen by the instruction bus, and flash-mapped code.
+const IRAM: core::ops::Range<u32> = 0x4037_C000..0x403E_0000;
+const IROM: core::ops::Range<u32> = 0x4200_0000..0x4280_0000;
+
+/// Walks the frame pointer chain (built with `-C force-frame-pointers`).
+/// Each frame keeps the return address at `fp - 4` and the caller's frame
+/// pointer at `fp - 8`.
+#[cfg(target_arch = "riscv32")]
+fn capture_backtrace(record: &mut CrashRecord) {
+    let mut fp: u32;
+    unsafe { core::arch::asm!("mv {0}, s0", out(reg) fp) };
+    while DRAM.contains(&fp) && fp % 4 == 0 && DRAM.contains(&(fp - 8)) {
+        let (ra, caller_fp) = unsafe { (*((fp - 4) as *const u32), *((fp - 8) as *const u32)) };
+        if !(IROM.contains(&ra) || IRAM.contains(&ra)) || !record.push_frame(ra) {
+            break;
+        }
+        // Frames get older towards higher addresses; anything else is junk
+        if caller_fp <= fp {
+            break;
+        }
+        fp = caller_fp;
+    }
+}
+
+#[cfg(not(target_arch = "riscv32"))]
+fn capture_backtrace(_record: &mut CrashRecord) {}
@@ -0,0 +1,143 @@
+use core::fmt::Write;
+use heapless::String;
+use telemetry_schema::Quantity;
+
+use crate::telemetry::{push_json_str, EncodeError, PayloadFormat};
+
+// --- Home Assistant MQTT Discovery ---
+// Every published channel is announced with a retained config message on
+// `homeassistant/sensor/<device_id>/<sensor>_<channel>/config`, so Home
+// Assistant creates the entities by itself. Several channels can share one
+// state topic, so the value template only accepts payloads for its own
+// channel.
+
+/// Default discovery prefix of Home Assistant.
+pub const DISCOVERY_PREFIX: &str = "homeassistant";
+
+/// Shown as the device in Home Assistant; shared by all entities.
+pub struct DeviceInfo<'a> {
+    pub device_id: &'a str,
+    pub model: &'a str,
+    pub manufacturer: &'a str,
+    pub firmware: &'a str,
+    pub mac: [u8; 6],
+    /// Suggested area (the configured site).
+    pub site: &'a str,
+}
+
+/// One published channel.
+pub struct Entity<'a> {
+    /// Source sensor, as in the `sensor` tag (e.g. `sht3x`).
+    pub sensor: &'a str,
+    /// Measurement name (e.g. `humidity` or an ADC channel name).
+    pub channel: &'a str,
+    pub quantity: Quantity,
+    /// Unit for quantities without a fixed one (ADC channels); empty = none.
+    pub unit: &'a str,
+    /// Topic the readings of this channel are published to.
+    pub state_topic: &'a str,
+}
+
+/// Whether Home Assistant can parse the format at all (binary frames it cannot).
+pub fn is_supported(format: PayloadFormat) -> bool {
+    !matches!(format, PayloadFormat::Binary)
+}
+
+/// Unit and device class for a quantity, as Home Assistant names them.
+fn unit_and_class(quantity: Quantity, unit: &str) -> (&str, Option<&'static str>) {
+    match quantity {
+        Quantity::Temperature => ("°C", Some("temperature")),
+        Quantity::Humidity => ("%", Some("humidity")),
+        Quantity::Pressure => ("hPa", Some("pressure")),
+        Quantity::Voltage => ("V", Some("voltage")),
+        Quantity::Rssi => ("dBm", Some("signal_strength")),
+        Quantity::Other(_) => (unit, None),
+    }
+}
+
+/// `homeassistant/sensor/<device_id>/<sensor>_<channel>/config`
+pub fn config_topic<const N: usize>(device_id: &str, entity: &Entity) -> Result<String<N>, EncodeError> {
+    let mut topic = String::new();
+    write!(topic, "{}/sensor/{}/{}_{}/config", DISCOVERY_PREFIX, device_id, entity.sensor, entity.channel)?;
+    Ok(topic)
+}
+
+/// Template that extracts the channel's value from a payload in `format`.
+/// Payloads of other channels on the same topic keep the current state.
+/// Bare values carry no channel, so they need a topic per channel.
+fn value_template<const N: usize>(format: PayloadFormat, entity: &Entity, out: &mut String<N>) -> Result<(), EncodeError> {
+    match format {
+        PayloadFormat::Json => write!(
+            out,
+            "{{% if value_json.measurement == '{}' and value_json.tags.sensor == '{}' %}}\
+             {{{{ value_json.fields.value }}}}{{% else %}}{{{{ this.state }}}}{{% endif %}}",
+            entity.channel, entity.sensor
+        )?,
+        // `<measurement>,<tags> value=<mean>,min=.. <timestamp>`
+        PayloadFormat::Influx => write!(
+            out,
+            "{{% if value.startswith('{},') and ',sensor={},' in value %}}\
+             {{{{ value.split(' ')[1].split(',')[0].split('=')[1] }}}}{{% else %}}{{{{ this.state }}}}{{% endif %}}",
+            entity.channel, entity.sensor
+        )?,
+        PayloadFormat::Value | PayloadFormat::Binary => write!(out, "{{{{ value }}}}")?,
+    }
+    Ok(())
+}
+
+/// Encodes the discovery config for `entity` into `out` (which is cleared first).
+pub fn config_payload<const N: usize>(
+    device: &DeviceInfo,
+    entity: &Entity,
+    format: PayloadFormat,
+    availability_topic: &str,
+    out: &mut String<N>,
+) -> Result<(), EncodeError> {
+    out.clear();
+    let (unit, device_class) = unit_and_class(entity.quantity, entity.unit);
+
+    let mut name: String<48> = String::new();
+    write!(name, "{} {}", entity.sensor, entity.channel)?;
+    let mut unique_id: String<96> = String::new();
+    write!(unique_id, "{}_{}_{}", device.device_id, entity.sensor, entity.channel)?;
+    let mut template: String<192> = String::new();
+    value_template(format, entity, &mut template)?;
+
+    out.push_str("{\"name\":").map_err(|_| EncodeError::Overflow)?;
+    push_json_str(&name, out)?;
+    out.push_str(",\"unique_id\":").map_err(|_| EncodeError::Overflow)?;
+    push_json_str(&unique_id, out)?;
+    out.push_str(",\"state_topic\":").map_err(|_| EncodeError::Overflow)?;
+    push_json_str(entity.state_topic, out)?;
+    out.push_str(",\"availability_topic\":").map_err(|_| EncodeError::Overflow)?;
+    push_json_str(availability_topic, out)?;
+    if !unit.is_empty() {
+        out.push_str(",\"unit_of_measurement\":").map_err(|_| EncodeError::Overflow)?;
+        push_json_str(unit, out)?;
+    }
+    if let Some(device_class) = device_class {
+        out.push_str(",\"device_class\":").map_err(|_| EncodeError::Overflow)?;
+        push_json_str(device_class, out)?;
+    }
+    out.push_str(",\"state_class\":\"measurement\",\"value_template\":").map_err(|_| EncodeError::Overflow)?;
+    push_json_str(&template, out)?;
+
+    out.push_str(",\"device\":{\"identifiers\":[").map_err(|_| EncodeError::Overflow)?;
+    push_json_str(device.device_id, out)?;
+    let m = device.mac;
+    write!(
+        out,
+        "],\"connections\":[[\"mac\",\"{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}\"]],\"name\":",
+        m[0], m[1], m[2], m[3], m[4], m[5]
+    )?;
+    push_json_str(device.device_id, out)?;
+    out.push_str(",\"model\":").map_err(|_| EncodeError::Overflow)?;
+    push_json_str(device.model, out)?;
+    out.push_str(",\"manufacturer\":").map_err(|_| EncodeError::Overflow)?;
+    push_json_str(device.manufacturer, out)?;
+    out.push_str(",\"sw_version\":").map_err(|_| EncodeError::Overflow)?;
+    push_json_str(device.firmware, out)?;
+    out.push_str(",\"suggested_area\":").map_err(|_| EncodeError::Overflow)?;
+    push_json_str(device.site, out)?;
+    out.push_str("}}").map_err(|_| EncodeError::Overflow)
+}
@@ -0,0 +1,83 @@
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embedded_storage::nor_flash::{ErrorType, MultiwriteNorFlash, NorFlash, ReadNorFlash};
+use embedded_storage::{ReadStorage, Storage};
+use esp_hal::peripherals::FLASH;
+use esp_storage::{FlashStorage, FlashStorageError};
+use static_cell::StaticCell;
+
+// --- Shared Flash ---
+// The config store, the telemetry buffer, OTA (HTTP, MQTT and BLE) and BLE
+// provisioning all write to the same SPI flash. They share one FlashStorage
+// through `Flash` handles; every read, write and erase locks it for the
+// duration of that one operation.
+
+type SharedStorage = Mutex<CriticalSectionRawMutex, RefCell<FlashStorage<'static>>>;
+
+/// Handle on the flash. Cheap to copy; hand one to every user.
+#[derive(Clone, Copy)]
+pub struct Flash(&'static SharedStorage);
+
+impl Flash {
+    /// Takes the peripheral. Call once, in `setup`.
+    pub fn new(flash: FLASH<'static>) -> Self {
+        static STORAGE: StaticCell<SharedStorage> = StaticCell::new();
+        Self(STORAGE.init(Mutex::new(RefCell::new(FlashStorage::new(flash)))))
+    }
+
+    fn with<R>(&self, f: impl FnOnce(&mut FlashStorage<'static>) -> R) -> R {
+        self.0.lock(|storage| f(&mut storage.borrow_mut()))
+    }
+}
+
+impl ErrorType for Flash {
+    type Error = FlashStorageError;
+}
+
+impl ReadNorFlash for Flash {
+    const READ_SIZE: usize = <FlashStorage<'static> as ReadNorFlash>::READ_SIZE;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.with(|storage| ReadNorFlash::read(storage, offset, bytes))
+    }
+
+    fn capacity(&self) -> usize {
+        self.with(|storage| ReadNorFlash::capacity(storage))
+    }
+}
+
+impl NorFlash for Flash {
+    const WRITE_SIZE: usize = <FlashStorage<'static> as NorFlash>::WRITE_SIZE;
+    const ERASE_SIZE: usize = <FlashStorage<'static> as NorFlash>::ERASE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        self.with(|storage| storage.erase(from, to))
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.with(|storage| NorFlash::write(storage, offset, bytes))
+    }
+}
+
+impl MultiwriteNorFlash for Flash {}
+
+// Byte-granular access, as used by the OTA updater
+impl ReadStorage for Flash {
+    type Error = FlashStorageError;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.with(|storage| ReadStorage::read(storage, offset, bytes))
+    }
+
+    fn capacity(&self) -> usize {
+        self.with(|storage| ReadStorage::capacity(storage))
+    }
+}
+
+impl Storage for Flash {
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.with(|storage| Storage::write(storage, offset, bytes))
+    }
+}
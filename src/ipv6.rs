@@ -0,0 +1,274 @@
+use embassy_net::raw::{PacketMetadata as RawPacketMetadata, RawSocket};
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{ConfigV6, HardwareAddress, IpProtocol, IpVersion, Ipv6Address, Ipv6Cidr, Stack, StaticConfigV6};
+use embassy_time::{with_timeout, Duration, Instant};
+use heapless::Vec;
+use rtt_target::rprintln;
+
+use crate::status;
+
+// Building and parsing the packets is hardware-independent (see `firmware-core/`)
+use firmware_core::ipv6::{
+    build_information_request, build_router_solicit, ALL_DHCP_AGENTS, DHCPV6_CLIENT_PORT, DHCPV6_SERVER_PORT,
+    INFORMATION_REQUEST_LEN, ROUTER_SOLICIT_LEN,
+};
+pub use firmware_core::ipv6::{
+    eui64_interface_id, icmpv6_checksum, link_local_address, parse_dhcpv6_dns, parse_router_advert, slaac_address,
+    RouterAdvert,
+};
+
+// --- IPv6 Autoconfiguration ---
+// smoltcp has no SLAAC or DHCPv6 client, so we do the minimum ourselves:
+// 1. Bring up a link-local address derived from the MAC (EUI-64).
+// 2. Send a Router Solicitation and wait for a Router Advertisement.
+// 3. Build a global address from the advertised prefix (SLAAC), taking DNS
+//    servers from the RDNSS option or, optionally, a stateless DHCPv6 exchange.
+// 4. Keep listening for Router Advertisements: they refresh the prefix and
+//    router lifetimes, a renumbered prefix moves the address, and the global
+//    address and gateway are dropped when their lifetimes run out.
+// Stateful DHCPv6 (address assignment) and Duplicate Address Detection are
+// not supported; EUI-64 addresses only collide when MAC addresses do.
+
+/// How long to wait for a Router Advertisement after soliciting one.
+pub const RA_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Routers advertise at least every 600 s by default (RFC 4861), so a
+/// silence this long means the router is gone.
+pub const RA_SILENCE: Duration = Duration::from_secs(1800);
+
+/// How long to wait for a DHCPv6 Reply.
+pub const DHCPV6_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn station_mac(stack: Stack<'_>) -> Result<[u8; 6], ()> {
+    match stack.hardware_address() {
+        HardwareAddress::Ethernet(mac) => Ok(mac.0),
+        #[allow(unreachable_patterns)]
+        _ => Err(()),
+    }
+}
+
+/// When a lifetime from an advertisement received now runs out; `None` if it
+/// never does.
+fn expiry(secs: u32) -> Option<Instant> {
+    if secs == u32::MAX {
+        return None;
+    }
+    Some(Instant::now() + Duration::from_secs(secs as u64))
+}
+
+/// Solicits a Router Advertisement and waits for one carrying a usable prefix.
+async fn solicit_router(stack: Stack<'_>, link_local: &Ipv6Address) -> Result<RouterAdvert, ()> {
+    let mut rx_meta = [RawPacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0u8; 512];
+    let mut tx_meta = [RawPacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0u8; 64];
+    let socket = RawSocket::new(
+        stack,
+        IpVersion::Ipv6,
+        IpProtocol::Icmpv6,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+
+    let mut rs = [0u8; ROUTER_SOLICIT_LEN];
+    let len = build_router_solicit(link_local, &mut rs);
+    socket.send(&rs[..len]).await;
+
+    let deadline = Instant::now() + RA_TIMEOUT;
+    let mut packet = [0u8; 512];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match with_timeout(remaining, socket.recv(&mut packet)).await {
+            Ok(Ok(n)) => {
+                if let Some(ra) = parse_router_advert(&packet[..n]) {
+                    if ra.prefix.is_some() || ra.managed {
+                        return Ok(ra);
+                    }
+                }
+            }
+            Ok(Err(_)) => {}
+            Err(_) => return Err(()),
+        }
+    }
+}
+
+/// Runs a stateless DHCPv6 exchange to learn DNS servers.
+async fn request_dns_servers(stack: Stack<'_>, mac: [u8; 6]) -> Result<Vec<Ipv6Address, 3>, ()> {
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0u8; 512];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0u8; 64];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    socket.bind(DHCPV6_CLIENT_PORT).map_err(|_| ())?;
+
+    // Any value works as long as it matches the reply
+    let ticks = Instant::now().as_ticks().to_be_bytes();
+    let transaction_id = [ticks[5], ticks[6], ticks[7]];
+
+    let mut request = [0u8; INFORMATION_REQUEST_LEN];
+    let len = build_information_request(transaction_id, mac, &mut request);
+    socket.send_to(&request[..len], (ALL_DHCP_AGENTS, DHCPV6_SERVER_PORT)).await.map_err(|_| ())?;
+
+    let deadline = Instant::now() + DHCPV6_TIMEOUT;
+    let mut reply = [0u8; 512];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match with_timeout(remaining, socket.recv_from(&mut reply)).await {
+            Ok(Ok((n, _))) => {
+                if let Some(servers) = parse_dhcpv6_dns(&reply[..n], transaction_id) {
+                    return Ok(servers);
+                }
+            }
+            Ok(Err(_)) => {}
+            Err(_) => return Err(()),
+        }
+    }
+}
+
+/// Configures IPv6 on `stack`: link-local first, then a SLAAC global address.
+/// When `dhcpv6` is set and the router did not advertise DNS servers, they
+/// are requested with a stateless DHCPv6 Information-Request.
+pub async fn autoconfigure(stack: Stack<'_>, dhcpv6: bool) -> Result<StaticConfigV6, ()> {
+    let mac = station_mac(stack)?;
+    stack.wait_link_up().await;
+
+    // 1. Link-local address so we can talk to the router
+    let link_local = link_local_address(mac);
+    stack.set_config_v6(ConfigV6::Static(StaticConfigV6 {
+        address: Ipv6Cidr::new(link_local, 64),
+        gateway: None,
+        dns_servers: Vec::new(),
+    }));
+    rprintln!("IPv6: link-local {}", link_local);
+
+    // 2. Router Advertisement
+    let ra = match solicit_router(stack, &link_local).await {
+        Ok(ra) => ra,
+        Err(_) => {
+            rprintln!("IPv6: no Router Advertisement within {}s", RA_TIMEOUT.as_secs());
+            return Err(());
+        }
+    };
+    rprintln!("IPv6: RA from {} prefix={:?} M={} O={}", ra.router, ra.prefix, ra.managed, ra.other);
+
+    let Some(prefix) = ra.prefix else {
+        rprintln!("IPv6: router requires stateful DHCPv6, which is not supported");
+        return Err(());
+    };
+
+    // 3. DNS servers: RDNSS first, DHCPv6 as a fallback
+    let mut dns_servers = ra.dns_servers.clone();
+    if dns_servers.is_empty() && dhcpv6 && (ra.other || ra.managed) {
+        match request_dns_servers(stack, mac).await {
+            Ok(servers) => dns_servers = servers,
+            Err(_) => rprintln!("IPv6: DHCPv6 Information-Request got no reply"),
+        }
+    }
+
+    let config = StaticConfigV6 {
+        address: Ipv6Cidr::new(slaac_address(&prefix, mac), 64),
+        gateway: if ra.router_lifetime_secs > 0 { Some(ra.router) } else { None },
+        dns_servers,
+    };
+    stack.set_config_v6(ConfigV6::Static(config.clone()));
+    rprintln!("IPv6: address {} gateway {:?} dns {:?}", config.address, config.gateway, config.dns_servers);
+
+    Ok(config)
+}
+
+/// Follows Router Advertisements for as long as the device runs, starting
+/// from the configuration `autoconfigure` produced (or none, if it failed).
+/// Applies renumbered prefixes and router changes, and falls back to the
+/// link-local address when the prefix or router lifetime expires unrefreshed.
+pub async fn watch_routers(stack: Stack<'_>, mut current: Option<StaticConfigV6>) -> ! {
+    let Ok(mac) = station_mac(stack) else {
+        rprintln!("IPv6: no MAC address, not following Router Advertisements");
+        return core::future::pending().await;
+    };
+    let link_local = link_local_address(mac);
+
+    let mut rx_meta = [RawPacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0u8; 512];
+    let mut tx_meta = [RawPacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0u8; 64];
+    let socket = RawSocket::new(
+        stack,
+        IpVersion::Ipv6,
+        IpProtocol::Icmpv6,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+
+    // Lifetimes of what `autoconfigure` applied are not known here, so the
+    // next advertisement sets them; until then the silence limit applies.
+    let mut prefix_expires = Some(Instant::now() + RA_SILENCE);
+    let mut router_expires = prefix_expires;
+    let mut packet = [0u8; 512];
+    loop {
+        let next_expiry = [prefix_expires, router_expires].into_iter().flatten().min();
+        let wait = next_expiry.map_or(RA_SILENCE, |at| at.saturating_duration_since(Instant::now()));
+
+        match with_timeout(wait, socket.recv(&mut packet)).await {
+            Ok(Ok(n)) => {
+                let Some(ra) = parse_router_advert(&packet[..n]) else { continue };
+                let Some(prefix) = ra.prefix else { continue };
+                prefix_expires = expiry(ra.prefix_valid_secs);
+                router_expires = expiry(ra.router_lifetime_secs as u32);
+
+                // Without RDNSS, keep the servers we have (e.g. from DHCPv6)
+                let dns_servers = if ra.dns_servers.is_empty() {
+                    current.as_ref().map(|c| c.dns_servers.clone()).unwrap_or_default()
+                } else {
+                    ra.dns_servers.clone()
+                };
+                let config = StaticConfigV6 {
+                    address: Ipv6Cidr::new(slaac_address(&prefix, mac), 64),
+                    gateway: if ra.router_lifetime_secs > 0 { Some(ra.router) } else { None },
+                    dns_servers,
+                };
+                if current.as_ref() != Some(&config) {
+                    rprintln!("IPv6: RA from {}: address {} gateway {:?} dns {:?}", ra.router, config.address, config.gateway, config.dns_servers);
+                    stack.set_config_v6(ConfigV6::Static(config.clone()));
+                    status::set_ipv6(Some(config.address));
+                    current = Some(config);
+                }
+            }
+            Ok(Err(_)) => {}
+            Err(_) => {
+                let now = Instant::now();
+                if let Some(config) = current.as_mut()
+                    && config.gateway.is_some()
+                    && router_expires.is_some_and(|at| at <= now)
+                {
+                    rprintln!("IPv6: router lifetime expired, dropping gateway");
+                    config.gateway = None;
+                    stack.set_config_v6(ConfigV6::Static(config.clone()));
+                    router_expires = None;
+                }
+                if current.is_some() && prefix_expires.is_some_and(|at| at <= now) {
+                    rprintln!("IPv6: prefix lifetime expired, back to link-local {}", link_local);
+                    stack.set_config_v6(ConfigV6::Static(StaticConfigV6 {
+                        address: Ipv6Cidr::new(link_local, 64),
+                        gateway: None,
+                        dns_servers: Vec::new(),
+                    }));
+                    status::set_ipv6(None);
+                    current = None;
+                    prefix_expires = None;
+                }
+                // Nothing configured: solicit again every `RA_SILENCE`
+                // (the prefix deadline doubles as the retry timer)
+                if current.is_none() {
+                    let mut rs = [0u8; ROUTER_SOLICIT_LEN];
+                    let len = build_router_solicit(&link_local, &mut rs);
+                    socket.send(&rs[..len]).await;
+                    prefix_expires = Some(Instant::now() + RA_SILENCE);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use esp_hal::gpio::{Level, Output, OutputConfig};
+use esp_hal::peripherals::{GPIO8, RMT};
+use esp_hal::rmt::{Channel, PulseCode, Rmt, Tx, TxChannelConfig, TxChannelCreator};
+use esp_hal::time::Rate;
+use esp_hal::Blocking;
+use rtt_target::rprintln;
+
+// Board selection and colours are hardware-independent (see `firmware-core/`)
+pub use firmware_core::led::{colour, gradient, is_valid_brightness, is_valid_temp_range, LedBoard, Rgb};
+
+// --- Driver ---
+
+/// RMT clock: the APB clock undivided, 12.5 ns per tick.
+const RMT_MHZ: u32 = 80;
+
+const fn ticks(ns: u32) -> u16 {
+    (ns * RMT_MHZ / 1000) as u16
+}
+
+// Bit timings from the WS2812B datasheet
+const T0H: u16 = ticks(400);
+const T0L: u16 = ticks(850);
+const T1H: u16 = ticks(800);
+const T1L: u16 = ticks(450);
+
+/// One WS2812 on an RMT TX channel. Sending a colour takes 30 µs, so it is
+/// done blocking.
+pub struct Ws2812 {
+    /// Taken while a transmission runs; `None` if the channel could not be
+    /// set up.
+    channel: Option<Channel<'static, Blocking, Tx>>,
+}
+
+impl Ws2812 {
+    /// Sets up the RMT before taking the pin, and hands the pin back if that
+    /// fails so the caller can drive a plain LED with it instead.
+    pub fn new(rmt: RMT<'static>, pin: GPIO8<'static>) -> Result<Self, GPIO8<'static>> {
+        let Ok(rmt) = Rmt::new(rmt, Rate::from_mhz(RMT_MHZ)) else {
+            rprintln!("WS2812: RMT init failed");
+            return Err(pin);
+        };
+        let config = TxChannelConfig::default()
+            .with_clk_divider(1)
+            .with_idle_output_level(Level::Low)
+            .with_idle_output(true)
+            .with_carrier_modulation(false);
+        // Channel 0 of an RMT we own is free, so this only fails on a driver
+        // bug; the pin is gone with it and the LED stays dark
+        let channel = rmt
+            .channel0
+            .configure_tx(pin, config)
+            .inspect_err(|e| rprintln!("WS2812: RMT channel setup failed: {:?}", e))
+            .ok();
+        Ok(Self { channel })
+    }
+
+    /// Sends a colour: green, red, blue, most significant bit first.
+    pub fn write(&mut self, colour: Rgb) -> Result<(), ()> {
+        let mut pulses = [PulseCode::end_marker(); 25];
+        let bits = (colour.g as u32) << 16 | (colour.r as u32) << 8 | colour.b as u32;
+        for (i, pulse) in pulses[..24].iter_mut().enumerate() {
+            *pulse = if bits & (1 << (23 - i)) != 0 {
+                PulseCode::new(Level::High, T1H, Level::Low, T1L)
+            } else {
+                PulseCode::new(Level::High, T0H, Level::Low, T0L)
+            };
+        }
+
+        let channel = self.channel.take().ok_or(())?;
+        let transaction = match channel.transmit(&pulses) {
+            Ok(transaction) => transaction,
+            Err(e) => {
+                rprintln!("WS2812: transmit failed: {:?}", e);
+                return Err(());
+            }
+        };
+        match transaction.wait() {
+            Ok(channel) => {
+                self.channel = Some(channel);
+                Ok(())
+            }
+            Err((e, channel)) => {
+                self.channel = Some(channel);
+                rprintln!("WS2812: transmit failed: {:?}", e);
+                Err(())
+            }
+        }
+    }
+}
+
+/// The status LED of the configured board.
+pub enum StatusLed {
+    Gpio { pin: Output<'static>, inverted: bool },
+    Ws2812 { driver: Ws2812, brightness: u8 },
+}
+
+impl StatusLed {
+    /// Falls back to a plain LED if the RMT cannot be set up. The LED starts
+    /// out on, to show the device is powered.
+    pub fn new(board: LedBoard, brightness: u8, rmt: RMT<'static>, pin: GPIO8<'static>) -> Self {
+        let mut led = match board {
+            LedBoard::Ws2812 => match Ws2812::new(rmt, pin) {
+                Ok(driver) => Self::Ws2812 { driver, brightness },
+                Err(pin) => Self::gpio(pin, false),
+            },
+            LedBoard::Gpio => Self::gpio(pin, false),
+            LedBoard::GpioInverted => Self::gpio(pin, true),
+        };
+        led.show(Rgb::WHITE);
+        led
+    }
+
+    fn gpio(pin: GPIO8<'static>, inverted: bool) -> Self {
+        Self::Gpio { pin: Output::new(pin, Level::Low, OutputConfig::default()), inverted }
+    }
+
+    /// Shows `colour`; a plain LED is on for anything but `Rgb::OFF`.
+    pub fn show(&mut self, colour: Rgb) {
+        match self {
+            Self::Gpio { pin, inverted } => pin.set_level(Level::from((colour != Rgb::OFF) != *inverted)),
+            Self::Ws2812 { driver, brightness } => {
+                let _ = driver.write(colour.dimmed(*brightness));
+            }
+        }
+    }
+}
@@ -3,22 +3,52 @@
 
 use esp_hal::clock::CpuClock;
 use esp_hal::timer::timg::TimerGroup;
-use esp_hal::gpio::{Level, Output, OutputConfig};
+use esp_hal::gpio::{Input, InputConfig, Pull};
+#[cfg(feature = "ds18b20")]
+use esp_hal::gpio::{DriveMode, Flex, OutputConfig};
+#[cfg(any(feature = "sht3x", feature = "bme280"))]
+use esp_hal::i2c::master::{I2c, Config as I2cConfig};
 use esp_hal::tsens::{TemperatureSensor, Config as TsensConfig};
 use esp_hal::usb_serial_jtag::UsbSerialJtag;
 use esp_hal::Async;
-use esp_hal::peripherals::FLASH;
+use esp_hal::rtc_cntl::Rtc;
 use esp_radio::ble::controller::BleConnector;
 use bt_hci::controller::ExternalController;
 use trouble_host::prelude::*;
 use rtt_target::rprintln;
-use esp_radio::wifi::{WifiController, Config, ModeConfig, ClientConfig, WifiDevice};
+use sensor::adc::AnalogInputs;
+use esp_radio::wifi::{WifiController, ModeConfig, ClientConfig, WifiDevice};
 use alloc::boxed::Box;
+use config::{AppConfig, ConfigStore};
+use flash::Flash;
+use led::StatusLed;
+use watchdog::Watchdogs;
 
 extern crate alloc;
 
+pub mod battery;
+pub mod boot;
+pub mod buffer;
+pub mod calibration;
 pub mod config;
+pub mod crash;
+pub mod discovery;
+pub mod flash;
+pub mod ipv6;
+pub mod led;
 pub mod mqtt;
+pub mod net;
+pub mod ota;
+pub mod provision;
+pub mod sensor;
+pub mod sleep;
+pub mod sntp;
+pub mod status;
+pub mod watchdog;
+pub mod wifi;
+
+// Hardware-independent modules, built and tested on the host (see `firmware-core/`)
+pub use firmware_core::{aggregate, command, indicator, telemetry, topic};
 
 // Re-exports for main.rs
 pub use esp_radio::wifi::ScanConfig;
@@ -33,13 +63,28 @@ const L2CAP_CHANNELS_MAX: usize = 1;
 pub type BleStack<'a> = Stack<'a, ExternalController<BleConnector<'a>, 1>, DefaultPacketPool>;
 
 pub struct AppState {
-    pub led: Output<'static>,
+    /// Status LED on GPIO8, plain or WS2812 as set by `led_board`.
+    pub led: StatusLed,
     pub wifi: WifiController<'static>,
     pub wifi_interface: WifiDevice<'static>,
     pub ble_stack: BleStack<'static>,
     pub temp_sensor: TemperatureSensor<'static>,
+    /// ADC1 and its input pins (GPIO0..=GPIO4), configured from `AppConfig::adc_channels`.
+    pub analog: AnalogInputs,
+    /// I2C bus for external sensors (SDA = GPIO6, SCL = GPIO7).
+    #[cfg(any(feature = "sht3x", feature = "bme280"))]
+    pub i2c: I2c<'static, Async>,
+    /// 1-Wire data pin (GPIO10), open-drain with pull-up.
+    #[cfg(feature = "ds18b20")]
+    pub onewire_pin: Flex<'static>,
     pub serial: UsbSerialJtag<'static, Async>,
-    pub flash: FLASH<'static>,
+    /// BOOT button (GPIO9), pulled up: low while pressed.
+    pub button: Input<'static>,
+    pub flash: Flash,
+    /// Armed at the end of `setup`; `main` has to start feeding them.
+    pub watchdogs: Watchdogs,
+    /// Stored configuration, loaded early for the radio settings.
+    pub config: AppConfig,
 }
 
 pub async fn setup(_spawner: Spawner) -> AppState {
@@ -59,13 +104,51 @@ pub async fn setup(_spawner: Spawner) -> AppState {
 
     rprintln!("Embassy initialized!");
 
+    // The LED board and radio settings come from the stored config
+    let flash = Flash::new(peripherals.FLASH);
+    let app_config = match ConfigStore::new(flash).load().await {
+        Ok(config) => config,
+        Err(e) => {
+            rprintln!("Config: load failed ({:?}), running on defaults until it is saved", e);
+            config::defaults()
+        }
+    };
+
     // 4. Initialize LED
-    let led = Output::new(peripherals.GPIO8, Level::High, OutputConfig::default());
+    let led = StatusLed::new(app_config.led_board, app_config.led_brightness, peripherals.RMT, peripherals.GPIO8);
 
     // 5. Initialize Sensor and Serial
     let temp_sensor = TemperatureSensor::new(peripherals.TSENS, TsensConfig::default()).expect("Failed to init TSENS");
     
     let serial = UsbSerialJtag::new(peripherals.USB_DEVICE).into_async();
+    let button = Input::new(peripherals.GPIO9, InputConfig::default().with_pull(Pull::Up));
+
+    let analog = AnalogInputs {
+        adc1: peripherals.ADC1,
+        gpio0: peripherals.GPIO0,
+        gpio1: peripherals.GPIO1,
+        gpio2: peripherals.GPIO2,
+        gpio3: peripherals.GPIO3,
+        gpio4: peripherals.GPIO4,
+    };
+
+    // External sensor buses (only with the matching cargo features)
+    #[cfg(any(feature = "sht3x", feature = "bme280"))]
+    let i2c = I2c::new(peripherals.I2C0, I2cConfig::default())
+        .expect("Failed to init I2C")
+        .with_sda(peripherals.GPIO6)
+        .with_scl(peripherals.GPIO7)
+        .into_async();
+
+    #[cfg(feature = "ds18b20")]
+    let onewire_pin = {
+        let mut pin = Flex::new(peripherals.GPIO10);
+        pin.apply_output_config(&OutputConfig::default().with_drive_mode(DriveMode::OpenDrain).with_pull(Pull::Up));
+        pin.set_high();
+        pin.set_input_enable(true);
+        pin.set_output_enable(true);
+        pin
+    };
 
     // 6. Initialize Radio (WiFi & BLE)
     // We leak the radio_init to get a 'static reference, allowing us to return controllers
@@ -75,7 +158,7 @@ pub async fn setup(_spawner: Spawner) -> AppState {
     
     // WiFi Setup
     let (mut wifi_controller, interfaces) =
-        esp_radio::wifi::new(radio_init, peripherals.WIFI, Config::default())
+        esp_radio::wifi::new(radio_init, peripherals.WIFI, wifi::driver_config(&app_config))
             .expect("Failed to initialize Wi-Fi controller");
     
     // Ensure STA mode
@@ -85,6 +168,8 @@ pub async fn setup(_spawner: Spawner) -> AppState {
     // Start WiFi
     rprintln!("Starting WiFi...");
     wifi_controller.start_async().await.unwrap();
+    // Power save was set with the driver; TX power can only be set once it runs
+    let _ = wifi::RadioSettings::from_config(&app_config).apply(&mut wifi_controller);
 
     // BLE Setup
     let transport = BleConnector::new(radio_init, peripherals.BT, Default::default()).unwrap();
@@ -94,7 +179,13 @@ pub async fn setup(_spawner: Spawner) -> AppState {
     static RESOURCES: static_cell::StaticCell<HostResources<DefaultPacketPool, CONNECTIONS_MAX, L2CAP_CHANNELS_MAX>> = static_cell::StaticCell::new();
     let resources = RESOURCES.init(HostResources::new());
     
-    let ble_stack = trouble_host::new(ble_controller, resources);
+    // Random static address derived from the MAC, so hosts see the same device after a reboot
+    let mut address = esp_hal::efuse::Efuse::mac_address();
+    address[5] |= 0xC0;
+    let ble_stack = trouble_host::new(ble_controller, resources).set_random_address(Address::random(address));
+
+    // 7. Watchdogs
+    let watchdogs = Watchdogs::arm(Rtc::new(peripherals.LPWR), timg0.wdt);
 
     rprintln!("Setup complete.");
 
@@ -104,7 +195,15 @@ pub async fn setup(_spawner: Spawner) -> AppState {
         wifi_interface,
         ble_stack,
         temp_sensor,
+        analog,
+        #[cfg(any(feature = "sht3x", feature = "bme280"))]
+        i2c,
+        #[cfg(feature = "ds18b20")]
+        onewire_pin,
         serial,
-        flash: peripherals.FLASH,
+        button,
+        flash,
+        watchdogs,
+        config: app_config,
     }
 }
@@ -2,57 +2,81 @@ use rtt_target::rprintln;
 use embassy_net::tcp::TcpSocket;
 use embedded_io_async::{Read, Write};
 
+use crate::topic::MAX_TOPIC_LEN;
+
 // --- Simple MQTT Helper Functions ---
 
+/// Keep Alive announced in CONNECT. The broker drops the connection if it
+/// hears nothing from us for 1.5x this long, so idle connections send PINGREQ.
+pub const KEEP_ALIVE_SECS: u16 = 60;
+
+/// Message the broker publishes on our behalf when the connection drops
+/// without a DISCONNECT (e.g. power loss or Wi-Fi outage).
+pub struct LastWill<'a> {
+    pub topic: &'a str,
+    pub payload: &'a [u8],
+    pub retain: bool,
+}
+
+/// Appends a length-prefixed field (2-byte big-endian length + bytes).
+fn put_field(packet: &mut [u8], idx: &mut usize, bytes: &[u8]) {
+    packet[*idx..*idx + 2].copy_from_slice(&(bytes.len() as u16).to_be_bytes());
+    *idx += 2;
+    packet[*idx..*idx + bytes.len()].copy_from_slice(bytes);
+    *idx += bytes.len();
+}
+
 /// Helper to send an MQTT CONNECT packet and wait for CONNACK.
 /// This is a minimal implementation to avoid dependency issues with complex MQTT crates.
-pub async fn mqtt_connect<'a>(socket: &mut TcpSocket<'a>, client_id: &str) -> Result<(), ()> {
+pub async fn mqtt_connect<'a>(socket: &mut TcpSocket<'a>, client_id: &str, will: Option<&LastWill<'_>>) -> Result<(), ()> {
     // Fixed Header: Type 1 (CONNECT)
-    // Variable Header: Protocol Name (MQTT), Level (4), Flags (Clean Session), Keep Alive
-    // Payload: Client ID
+    // Variable Header: Protocol Name (MQTT), Level (4), Flags (Clean Session, Will), Keep Alive
+    // Payload: Client ID [, Will Topic, Will Message]
     
     let client_id_bytes = client_id.as_bytes();
     // Header overhead: Len(2) + MQTT(4) + Lvl(1) + Flags(1) + KeepAlive(2) = 10 bytes
     let var_header_len = 10; 
-    let payload_len = 2 + client_id_bytes.len(); // 2 bytes for length prefix + ID bytes
+    let mut payload_len = 2 + client_id_bytes.len(); // 2 bytes for length prefix + ID bytes
+    if let Some(will) = will {
+        payload_len += 2 + will.topic.len() + 2 + will.payload.len();
+    }
     let rem_len = var_header_len + payload_len;
-    
-    if rem_len > 127 {
-        rprintln!("MQTT Error: Connect packet too long for simple helper (max 127 bytes)");
+
+    let mut packet = [0u8; 256];
+    if rem_len > packet.len() - 3 {
+        rprintln!("MQTT Error: Connect packet too long for simple helper");
         return Err(());
     }
 
-    let mut packet = [0u8; 128];
     let mut idx = 0;
 
     // Fixed Header
     packet[idx] = 0x10; idx += 1; // Type 1 (CONNECT) | Reserved (0)
-    packet[idx] = rem_len as u8; idx += 1; // Remaining Length
+    idx += encode_remaining_length(rem_len, &mut packet[idx..]);
 
     // Variable Header
     // Protocol Name "MQTT"
-    packet[idx] = 0x00; idx += 1;
-    packet[idx] = 0x04; idx += 1;
-    packet[idx] = b'M'; idx += 1;
-    packet[idx] = b'Q'; idx += 1;
-    packet[idx] = b'T'; idx += 1;
-    packet[idx] = b'T'; idx += 1;
+    put_field(&mut packet, &mut idx, b"MQTT");
     // Protocol Level 4 (v3.1.1)
     packet[idx] = 0x04; idx += 1;
-    // Connect Flags: Clean Session (0x02)
-    packet[idx] = 0x02; idx += 1;
-    // Keep Alive: 60s (0x003C)
-    packet[idx] = 0x00; idx += 1;
-    packet[idx] = 60; idx += 1;
-
-    // Payload: Client ID (prefixed with length)
-    let len_be = (client_id_bytes.len() as u16).to_be_bytes();
-    packet[idx] = len_be[0]; idx += 1;
-    packet[idx] = len_be[1]; idx += 1;
-    
-    // Copy Client ID bytes
-    packet[idx..idx+client_id_bytes.len()].copy_from_slice(client_id_bytes);
-    idx += client_id_bytes.len();
+    // Connect Flags: Clean Session (0x02), Will Flag (0x04) with QoS 0, Will Retain (0x20)
+    let mut flags = 0x02;
+    if let Some(will) = will {
+        flags |= 0x04;
+        if will.retain {
+            flags |= 0x20;
+        }
+    }
+    packet[idx] = flags; idx += 1;
+    // Keep Alive (seconds)
+    packet[idx..idx + 2].copy_from_slice(&KEEP_ALIVE_SECS.to_be_bytes()); idx += 2;
+
+    // Payload: Client ID, then the will (each prefixed with its length)
+    put_field(&mut packet, &mut idx, client_id_bytes);
+    if let Some(will) = will {
+        put_field(&mut packet, &mut idx, will.topic.as_bytes());
+        put_field(&mut packet, &mut idx, will.payload);
+    }
 
     // Send the CONNECT packet
     socket.write_all(&packet[..idx]).await.map_err(|_| ())?;
@@ -73,27 +97,59 @@ pub async fn mqtt_connect<'a>(socket: &mut TcpSocket<'a>, client_id: &str) -> Re
     }
 }
 
+/// Encodes the MQTT "Remaining Length" field (7 bits per byte, MSB = continuation).
+/// Returns the number of bytes written.
+fn encode_remaining_length(mut len: usize, out: &mut [u8]) -> usize {
+    let mut idx = 0;
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        out[idx] = byte;
+        idx += 1;
+        if len == 0 {
+            return idx;
+        }
+    }
+}
+
+/// Largest Remaining Length we are willing to send (the spec allows 256 MB).
+const MAX_REMAINING_LENGTH: usize = 16 * 1024;
+
 /// Helper to send an MQTT PUBLISH packet.
 /// QoS is set to 0 (At Most Once) for simplicity.
 pub async fn mqtt_publish<'a>(socket: &mut TcpSocket<'a>, topic: &str, payload: &[u8]) -> Result<(), ()> {
-    // Fixed Header: Type 3 (PUBLISH), QoS 0 (0x30)
+    publish(socket, topic, payload, false).await
+}
+
+/// Like `mqtt_publish`, but the broker keeps the message and hands it to
+/// every future subscriber. An empty payload deletes the retained message.
+pub async fn mqtt_publish_retained<'a>(socket: &mut TcpSocket<'a>, topic: &str, payload: &[u8]) -> Result<(), ()> {
+    publish(socket, topic, payload, true).await
+}
+
+async fn publish(socket: &mut TcpSocket<'_>, topic: &str, payload: &[u8], retain: bool) -> Result<(), ()> {
+    // Fixed Header: Type 3 (PUBLISH), QoS 0 (0x30), RETAIN (0x01)
     // Variable Header: Topic Name (Length + String)
     // Payload: Data
 
     let topic_bytes = topic.as_bytes();
     let rem_len = 2 + topic_bytes.len() + payload.len(); // 2 bytes for topic len
 
-    if rem_len > 127 {
+    // Fixed header (max 5 bytes) + topic length (2) must fit the header buffer
+    if rem_len > MAX_REMAINING_LENGTH || topic_bytes.len() > MAX_TOPIC_LEN {
         rprintln!("MQTT Error: Publish packet too long");
         return Err(());
     }
 
-    let mut header = [0u8; 128];
+    let mut header = [0u8; MAX_TOPIC_LEN + 7];
     let mut idx = 0;
 
     // Fixed Header
-    header[idx] = 0x30; idx += 1; // Type 3 (PUBLISH) | QoS 0
-    header[idx] = rem_len as u8; idx += 1;
+    header[idx] = if retain { 0x31 } else { 0x30 }; idx += 1; // Type 3 (PUBLISH) | QoS 0 | RETAIN
+    idx += encode_remaining_length(rem_len, &mut header[idx..]);
 
     // Variable Header: Topic Name
     let tlen_be = (topic_bytes.len() as u16).to_be_bytes();
@@ -111,3 +167,129 @@ pub async fn mqtt_publish<'a>(socket: &mut TcpSocket<'a>, topic: &str, payload:
 
     Ok(())
 }
+
+/// Helper to send an MQTT SUBSCRIBE packet (QoS 0) and wait for its SUBACK.
+/// Call this right after connecting, before anything else is read.
+pub async fn mqtt_subscribe<'a>(socket: &mut TcpSocket<'a>, topic_filter: &str, packet_id: u16) -> Result<(), ()> {
+    // Fixed Header: Type 8 (SUBSCRIBE) with the reserved flags 0b0010
+    // Variable Header: Packet Identifier
+    // Payload: Topic Filter (Length + String), Requested QoS
+
+    let filter_bytes = topic_filter.as_bytes();
+    let rem_len = 2 + 2 + filter_bytes.len() + 1;
+
+    let mut packet = [0u8; 128];
+    if rem_len > 127 || rem_len + 2 > packet.len() {
+        rprintln!("MQTT Error: Subscribe packet too long");
+        return Err(());
+    }
+
+    let mut idx = 0;
+    packet[idx] = 0x82; idx += 1;
+    packet[idx] = rem_len as u8; idx += 1;
+    packet[idx..idx + 2].copy_from_slice(&packet_id.to_be_bytes()); idx += 2;
+    put_field(&mut packet, &mut idx, filter_bytes);
+    packet[idx] = 0x00; idx += 1; // QoS 0
+
+    socket.write_all(&packet[..idx]).await.map_err(|_| ())?;
+
+    // The broker answers before it delivers any retained message for the filter
+    let mut rx = [0u8; 64];
+    loop {
+        match mqtt_read_packet(socket, &mut rx).await? {
+            Packet::SubAck { packet_id: id, return_code } if id == packet_id => {
+                // 0x80 = Failure (e.g. the broker's ACL refuses the filter)
+                if return_code == 0x80 {
+                    rprintln!("MQTT: Subscription to '{}' refused", topic_filter);
+                    return Err(());
+                }
+                return Ok(());
+            }
+            _ => continue,
+        }
+    }
+}
+
+/// Helper to send an MQTT PINGREQ. The broker answers with PINGRESP,
+/// which shows up in `mqtt_read_packet`.
+pub async fn mqtt_ping<'a>(socket: &mut TcpSocket<'a>) -> Result<(), ()> {
+    // Fixed Header: Type 12 (PINGREQ), Remaining Length 0
+    socket.write_all(&[0xC0, 0x00]).await.map_err(|_| ())
+}
+
+/// Helper to send an MQTT DISCONNECT, so the broker closes the session
+/// without publishing the will.
+pub async fn mqtt_disconnect<'a>(socket: &mut TcpSocket<'a>) -> Result<(), ()> {
+    // Fixed Header: Type 14 (DISCONNECT), Remaining Length 0
+    socket.write_all(&[0xE0, 0x00]).await.map_err(|_| ())
+}
+
+/// A packet received from the broker.
+pub enum Packet<'b> {
+    /// An application message on a subscribed topic.
+    Publish { topic: &'b str, payload: &'b [u8], retain: bool },
+    SubAck { packet_id: u16, return_code: u8 },
+    PingResp,
+    /// A packet that did not fit the buffer; its content was discarded.
+    TooLarge,
+    /// Any other packet type (upper nibble of the first byte).
+    Other(u8),
+}
+
+/// Reads one complete packet from the broker into `buf`.
+/// Errors mean the connection is unusable (closed, timed out or malformed data).
+pub async fn mqtt_read_packet<'a, 'b>(socket: &mut TcpSocket<'a>, buf: &'b mut [u8]) -> Result<Packet<'b>, ()> {
+    let mut byte = [0u8; 1];
+    socket.read_exact(&mut byte).await.map_err(|_| ())?;
+    let header = byte[0];
+
+    // Remaining Length: up to 4 bytes, 7 bits each
+    let mut rem_len = 0usize;
+    for shift in 0..4 {
+        socket.read_exact(&mut byte).await.map_err(|_| ())?;
+        rem_len |= ((byte[0] & 0x7F) as usize) << (7 * shift);
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+        if shift == 3 {
+            return Err(());
+        }
+    }
+
+    if rem_len > buf.len() {
+        // Skip it, so the stream stays in sync
+        let mut remaining = rem_len;
+        while remaining > 0 {
+            let n = remaining.min(buf.len());
+            socket.read_exact(&mut buf[..n]).await.map_err(|_| ())?;
+            remaining -= n;
+        }
+        return Ok(Packet::TooLarge);
+    }
+    let body = &mut buf[..rem_len];
+    socket.read_exact(body).await.map_err(|_| ())?;
+    let body = &*body;
+
+    match header >> 4 {
+        // PUBLISH: Topic Name, [Packet Identifier if QoS > 0], Payload
+        3 => {
+            if body.len() < 2 {
+                return Err(());
+            }
+            let topic_len = u16::from_be_bytes([body[0], body[1]]) as usize;
+            let mut idx = 2 + topic_len;
+            if header & 0x06 != 0 {
+                idx += 2; // QoS 1/2 (we only subscribe with QoS 0, but be lenient)
+            }
+            if idx > body.len() {
+                return Err(());
+            }
+            let topic = core::str::from_utf8(&body[2..2 + topic_len]).map_err(|_| ())?;
+            Ok(Packet::Publish { topic, payload: &body[idx..], retain: header & 0x01 != 0 })
+        }
+        // SUBACK: Packet Identifier, Return Code
+        9 if body.len() >= 3 => Ok(Packet::SubAck { packet_id: u16::from_be_bytes([body[0], body[1]]), return_code: body[2] }),
+        13 => Ok(Packet::PingResp),
+        packet_type => Ok(Packet::Other(packet_type)),
+    }
+}
@@ -0,0 +1,148 @@
+use core::fmt::Write;
+use core::str::FromStr;
+use embassy_net::dns::DnsQueryType;
+use embassy_net::{DhcpConfig, IpAddress, Stack, StaticConfigV4};
+use embassy_time::{Duration, Instant, Timer};
+use heapless::String;
+use rtt_target::rprintln;
+
+use crate::status::{self, DhcpState, LeaseInfo, NetStatus};
+
+// --- Network Helpers ---
+
+/// How long to wait for a DHCP lease before forcing a Wi-Fi re-association.
+pub const DHCP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Leases longer than this are capped, so we renew at least once a day.
+pub const DHCP_MAX_LEASE_SECS: u32 = 24 * 60 * 60;
+
+/// DHCP client configuration used for the station interface.
+pub fn dhcp_config() -> DhcpConfig {
+    let mut config = DhcpConfig::default();
+    config.max_lease_duration = Some(Duration::from_secs(DHCP_MAX_LEASE_SECS as u64));
+    config
+}
+
+/// How often `watch_dhcp` compares the stack's configuration with the status.
+const DHCP_WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+fn lease_from(config: StaticConfigV4) -> LeaseInfo {
+    LeaseInfo {
+        address: config.address,
+        gateway: config.gateway,
+        dns_servers: config.dns_servers,
+        lease_cap_secs: DHCP_MAX_LEASE_SECS,
+        acquired_at_secs: Instant::now().as_secs(),
+    }
+}
+
+/// Waits until DHCP hands us an address, or until `timeout` elapses.
+/// State transitions are logged and mirrored into the shared status.
+pub async fn wait_for_dhcp(stack: Stack<'_>, timeout: Duration) -> Result<LeaseInfo, ()> {
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        if stack.is_config_up()
+            && let Some(config) = stack.config_v4()
+        {
+            let lease = lease_from(config);
+            status::set_lease(lease.clone());
+            return Ok(lease);
+        }
+
+        if stack.is_link_up() {
+            status::set_dhcp_state(DhcpState::Discovering);
+        } else {
+            status::set_dhcp_state(DhcpState::LinkDown);
+        }
+
+        if Instant::now() >= deadline {
+            status::set_dhcp_state(DhcpState::TimedOut);
+            return Err(());
+        }
+
+        Timer::after(Duration::from_millis(500)).await;
+    }
+}
+
+/// Follows the DHCPv4 configuration after the first lease, for as long as the
+/// device runs: a lost lease, and a new one with different addressing, are
+/// logged and mirrored into the status (and so into the next net report).
+/// Renewals that keep the addressing do not show up in the stack.
+pub async fn watch_dhcp(stack: Stack<'_>) -> ! {
+    loop {
+        Timer::after(DHCP_WATCH_INTERVAL).await;
+        let lease = status::net_status().lease;
+        match (stack.config_v4(), lease) {
+            (Some(config), Some(lease))
+                if config.address == lease.address
+                    && config.gateway == lease.gateway
+                    && config.dns_servers == lease.dns_servers => {}
+            (Some(config), previous) => {
+                let lease = lease_from(config);
+                match previous {
+                    Some(previous) => rprintln!("DHCP: lease changed from {} to {}", previous.address, lease.address),
+                    None => rprintln!("DHCP: lease acquired: {}", lease.address),
+                }
+                status::set_lease(lease);
+            }
+            (None, Some(lease)) => {
+                rprintln!("DHCP: lease on {} lost", lease.address);
+                status::set_dhcp_state(if stack.is_link_up() { DhcpState::Discovering } else { DhcpState::LinkDown });
+            }
+            (None, None) => {}
+        }
+    }
+}
+
+/// Formats the current addressing (DHCPv4 lease, IPv6 address) as a compact JSON object.
+pub fn format_net_report(status: &NetStatus, out: &mut String<256>) -> Result<(), core::fmt::Error> {
+    out.push('{').map_err(|_| core::fmt::Error)?;
+    if let Some(lease) = &status.lease {
+        write!(out, "\"ip\":\"{}\"", lease.address)?;
+        if let Some(gateway) = lease.gateway {
+            write!(out, ",\"gw\":\"{}\"", gateway)?;
+        }
+        out.push_str(",\"dns\":[").map_err(|_| core::fmt::Error)?;
+        for (i, dns) in lease.dns_servers.iter().enumerate() {
+            if i > 0 {
+                out.push(',').map_err(|_| core::fmt::Error)?;
+            }
+            write!(out, "\"{}\"", dns)?;
+        }
+        write!(out, "],\"lease_cap_s\":{},\"since_s\":{},", lease.lease_cap_secs, lease.acquired_at_secs)?;
+    }
+    if let Some(ipv6) = &status.ipv6 {
+        write!(out, "\"ip6\":\"{}\",", ipv6)?;
+    }
+    write!(out, "\"dhcp_timeouts\":{}}}", status.dhcp_timeouts)
+}
+
+/// Resolves the broker host to an address.
+/// Accepts IPv4 literals, IPv6 literals (bare or in brackets) and hostnames.
+/// Hostnames are looked up as AAAA first when IPv6 is configured, then as A.
+pub async fn resolve_host(stack: Stack<'_>, host: &str) -> Result<IpAddress, ()> {
+    let literal = host.strip_prefix('[').and_then(|h| h.strip_suffix(']')).unwrap_or(host);
+    if let Ok(ip) = IpAddress::from_str(literal) {
+        return Ok(ip);
+    }
+
+    let order = if stack.config_v6().is_some() {
+        [DnsQueryType::Aaaa, DnsQueryType::A]
+    } else {
+        [DnsQueryType::A, DnsQueryType::Aaaa]
+    };
+
+    for query in order {
+        match stack.dns_query(host, query).await {
+            Ok(addrs) => {
+                if let Some(ip) = addrs.first() {
+                    rprintln!("DNS: {} -> {}", host, ip);
+                    return Ok(*ip);
+                }
+            }
+            Err(e) => rprintln!("DNS: {:?} lookup for '{}' failed: {:?}", query, host, e),
+        }
+    }
+    Err(())
+}
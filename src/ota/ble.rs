@@ -0,0 +1,272 @@
+use embassy_futures::join::join;
+use embassy_time::{Duration, Timer};
+use rtt_target::rprintln;
+use trouble_host::prelude::*;
+
+use super::dfu::{self, Control};
+use super::{OtaError, SlotWriter, PUBLIC_KEY_LEN};
+use crate::flash::Flash;
+use crate::BleStack;
+
+// --- BLE DFU Service ---
+// The GATT side of `dfu`. The device advertises the DFU service under its
+// device id whenever OTA is enabled, and keeps a transfer across disconnects
+// so a host can reconnect and resume where it stopped. A connection also
+// confirms a new image, since devices updated this way may never reach the
+// broker.
+
+/// `5e1b0001-6a3c-4f4e-9d2a-8b5c7e0f1a20`, little-endian for advertising.
+const SERVICE_UUID: [u8; 16] = [
+    0x20, 0x1a, 0x0f, 0x7e, 0x5c, 0x8b, 0x2a, 0x9d, 0x4e, 0x4f, 0x3c, 0x6a, 0x01, 0x00, 0x1b, 0x5e,
+];
+
+/// Longest name that fits the scan response next to its header.
+const MAX_ADV_NAME_LEN: usize = 29;
+
+/// Lets the status notification and the link layer settle before the reset.
+const RESET_DELAY: Duration = Duration::from_millis(500);
+
+#[gatt_server]
+pub struct DfuServer {
+    pub dfu: DfuService,
+}
+
+#[gatt_service(uuid = "5e1b0001-6a3c-4f4e-9d2a-8b5c7e0f1a20")]
+pub struct DfuService {
+    #[characteristic(uuid = "5e1b0002-6a3c-4f4e-9d2a-8b5c7e0f1a20", write, value = [0; dfu::CONTROL_LEN])]
+    pub control: [u8; dfu::CONTROL_LEN],
+    #[characteristic(uuid = "5e1b0003-6a3c-4f4e-9d2a-8b5c7e0f1a20", write_without_response, value = [0; dfu::DATA_LEN])]
+    pub data: [u8; dfu::DATA_LEN],
+    #[characteristic(uuid = "5e1b0004-6a3c-4f4e-9d2a-8b5c7e0f1a20", read, notify, value = [0; dfu::STATUS_LEN])]
+    pub status: [u8; dfu::STATUS_LEN],
+}
+
+/// An image arriving over BLE.
+struct Session {
+    writer: SlotWriter,
+    /// Offset reported in the last status notification.
+    acked: u32,
+    /// A misplaced packet was already answered with the expected offset.
+    resync_sent: bool,
+}
+
+struct Dfu {
+    flash: Flash,
+    public_key: [u8; PUBLIC_KEY_LEN],
+    session: Option<Session>,
+}
+
+impl Dfu {
+    fn status(&self, code: u8, att_mtu: u16) -> [u8; dfu::STATUS_LEN] {
+        let offset = self.session.as_ref().map_or(0, |s| s.writer.received());
+        dfu::encode_status(code, offset, att_mtu)
+    }
+
+    fn error(&self, e: OtaError, att_mtu: u16) -> [u8; dfu::STATUS_LEN] {
+        rprintln!("OTA over BLE failed: {}", e.name());
+        self.status(dfu::error_code(e), att_mtu)
+    }
+
+    /// Handles a control write; every command is answered.
+    fn control(&mut self, data: &[u8], att_mtu: u16) -> [u8; dfu::STATUS_LEN] {
+        match dfu::parse_control(data) {
+            Some(Control::Begin(manifest)) => {
+                if let Some(session) = &mut self.session
+                    && session.writer.manifest() == &manifest
+                {
+                    rprintln!("OTA: resuming at byte {}", session.writer.received());
+                    session.acked = session.writer.received();
+                    return self.status(dfu::STATUS_READY, att_mtu);
+                }
+                // A different image replaces the one being received
+                self.session = None;
+                // `SlotWriter` refuses to start while another update holds the slot
+                match SlotWriter::begin(self.flash, &manifest) {
+                    Ok(writer) => {
+                        rprintln!("OTA: receiving over BLE, {} bytes per packet", dfu::max_data(att_mtu));
+                        self.session = Some(Session { writer, acked: 0, resync_sent: false });
+                        self.status(dfu::STATUS_READY, att_mtu)
+                    }
+                    Err(e) => self.error(e, att_mtu),
+                }
+            }
+            Some(Control::Query) if self.session.is_some() => self.status(dfu::STATUS_READY, att_mtu),
+            Some(Control::Query) => self.error(OtaError::NoTransfer, att_mtu),
+            Some(Control::Commit) => {
+                let Some(session) = self.session.take() else {
+                    return self.error(OtaError::NoTransfer, att_mtu);
+                };
+                let size = session.writer.manifest().size;
+                if session.writer.received() != size {
+                    // Keep what arrived; the host can still send the rest
+                    self.session = Some(session);
+                    return self.error(OtaError::SizeMismatch, att_mtu);
+                }
+                match session.writer.finish(&self.public_key) {
+                    Ok(()) => dfu::encode_status(dfu::STATUS_INSTALLED, size, att_mtu),
+                    Err(e) => self.error(e, att_mtu),
+                }
+            }
+            Some(Control::Abort) => {
+                if self.session.take().is_some() {
+                    rprintln!("OTA: BLE transfer aborted");
+                }
+                self.status(dfu::STATUS_READY, att_mtu)
+            }
+            None => self.status(dfu::STATUS_MALFORMED, att_mtu),
+        }
+    }
+
+    /// Handles a data write. Only answered at the end of a window, at the end
+    /// of the image, on errors and when a packet is not the one expected.
+    fn data(&mut self, data: &[u8], att_mtu: u16) -> Option<[u8; dfu::STATUS_LEN]> {
+        let Some(session) = &mut self.session else {
+            return Some(self.error(OtaError::NoTransfer, att_mtu));
+        };
+        let Some((offset, bytes)) = dfu::parse_data(data) else {
+            return Some(self.status(dfu::STATUS_MALFORMED, att_mtu));
+        };
+        if offset != session.writer.received() {
+            // Packets in flight after a gap all miss; answer the first only
+            if session.resync_sent {
+                return None;
+            }
+            session.resync_sent = true;
+            return Some(self.status(dfu::STATUS_READY, att_mtu));
+        }
+        session.resync_sent = false;
+
+        if let Err(e) = session.writer.write(bytes) {
+            self.session = None;
+            return Some(self.error(e, att_mtu));
+        }
+        let received = session.writer.received();
+        let max_data = dfu::max_data(att_mtu);
+        let window_bytes = dfu::window(max_data) as u32 * max_data as u32;
+        if received == session.writer.manifest().size || received - session.acked >= window_bytes {
+            session.acked = received;
+            return Some(self.status(dfu::STATUS_READY, att_mtu));
+        }
+        None
+    }
+}
+
+/// Runs the BLE host and serves the DFU service, one connection at a time.
+/// `name` is advertised so hosts can pick the right device.
+pub async fn run(stack: &'static BleStack<'static>, flash: Flash, public_key: [u8; PUBLIC_KEY_LEN], name: &str) {
+    let Host { mut peripheral, mut runner, .. } = stack.build();
+    let server = match DfuServer::new_with_config(GapConfig::Peripheral(PeripheralConfig {
+        name: "esp-blinky-rust",
+        appearance: &appearance::sensor::GENERIC_SENSOR,
+    })) {
+        Ok(server) => server,
+        Err(e) => {
+            rprintln!("BLE: cannot create the DFU service: {:?}", e);
+            return;
+        }
+    };
+    let mut state = Dfu { flash, public_key, session: None };
+
+    join(
+        async {
+            loop {
+                if let Err(e) = runner.run().await {
+                    rprintln!("BLE host error: {:?}", e);
+                }
+            }
+        },
+        async {
+            loop {
+                match advertise(name, &mut peripheral, &server).await {
+                    Ok(conn) => {
+                        rprintln!("BLE: connected");
+                        // An image that can be reached over BLE can also be replaced over BLE
+                        if !super::is_confirmed() {
+                            let _ = super::confirm(state.flash);
+                        }
+                        serve(&server, &conn, &mut state).await;
+                    }
+                    Err(e) => {
+                        rprintln!("BLE advertising failed: {:?}", e);
+                        Timer::after(Duration::from_secs(5)).await;
+                    }
+                }
+            }
+        },
+    )
+    .await;
+}
+
+async fn advertise<'values, 'server, C: Controller>(
+    name: &str,
+    peripheral: &mut Peripheral<'values, C, DefaultPacketPool>,
+    server: &'server DfuServer<'values>,
+) -> Result<GattConnection<'values, 'server, DefaultPacketPool>, BleHostError<C::Error>> {
+    let mut adv_data = [0u8; 31];
+    let adv_len = AdStructure::encode_slice(
+        &[
+            AdStructure::Flags(LE_GENERAL_DISCOVERABLE | BR_EDR_NOT_SUPPORTED),
+            AdStructure::ServiceUuids128(&[SERVICE_UUID]),
+        ],
+        &mut adv_data[..],
+    )?;
+    let name = name.as_bytes();
+    let name_ad = if name.len() > MAX_ADV_NAME_LEN {
+        AdStructure::ShortenedLocalName(&name[..MAX_ADV_NAME_LEN])
+    } else {
+        AdStructure::CompleteLocalName(name)
+    };
+    let mut scan_data = [0u8; 31];
+    let scan_len = AdStructure::encode_slice(&[name_ad], &mut scan_data[..])?;
+
+    let advertiser = peripheral
+        .advertise(
+            &Default::default(),
+            Advertisement::ConnectableScannableUndirected {
+                adv_data: &adv_data[..adv_len],
+                scan_data: &scan_data[..scan_len],
+            },
+        )
+        .await?;
+    let conn = advertiser.accept().await?.with_attribute_server(server)?;
+    Ok(conn)
+}
+
+/// Handles GATT events until the host disconnects.
+async fn serve(server: &DfuServer<'_>, conn: &GattConnection<'_, '_, DefaultPacketPool>, state: &mut Dfu) {
+    loop {
+        match conn.next().await {
+            GattConnectionEvent::Disconnected { reason } => {
+                rprintln!("BLE: disconnected ({:?})", reason);
+                return;
+            }
+            GattConnectionEvent::Gatt { event } => {
+                let att_mtu = conn.raw().att_mtu();
+                let status = match &event {
+                    GattEvent::Write(write) if write.handle() == server.dfu.control.handle => {
+                        Some(state.control(write.data(), att_mtu))
+                    }
+                    GattEvent::Write(write) if write.handle() == server.dfu.data.handle => state.data(write.data(), att_mtu),
+                    _ => None,
+                };
+                match event.accept() {
+                    Ok(reply) => reply.send().await,
+                    Err(e) => rprintln!("BLE: cannot answer: {:?}", e),
+                }
+
+                let Some(status) = status else {
+                    continue;
+                };
+                if let Err(e) = server.dfu.status.notify(conn, &status).await {
+                    rprintln!("BLE: status notification failed: {:?}", e);
+                }
+                if status[0] == dfu::STATUS_INSTALLED {
+                    rprintln!("Rebooting...");
+                    Timer::after(RESET_DELAY).await;
+                    crate::boot::planned_restart();
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use super::image::{Manifest, OtaError, DIGEST_LEN, SIGNATURE_LEN};
+
+// --- OTA over BLE (DFU Service) ---
+// For devices that cannot reach the network, e.g. with wrong Wi-Fi
+// credentials. The DFU service has three characteristics:
+//   control (write):  `BEGIN`, `QUERY`, `COMMIT` or `ABORT` (see `Control`)
+//   data (write without response):  4-byte little-endian image offset + data
+//   status (read, notify):  `STATUS_LEN` bytes, see `encode_status`
+// After `BEGIN` the host streams data packets of up to `max_data` bytes and
+// waits for a status notification after every `window` packets (one flash
+// sector's worth), so it never runs ahead of the flash writes. A repeated
+// `BEGIN` for the same image resumes at the reported offset. Once all bytes
+// are in, `COMMIT` verifies the image and selects it for the next boot.
+
+/// `BEGIN`: image size (u32 LE), SHA-256, Ed25519 signature over the digest.
+pub const OP_BEGIN: u8 = 0x01;
+/// Report the current offset (e.g. after reconnecting).
+pub const OP_QUERY: u8 = 0x02;
+/// Verify the received image and boot it.
+pub const OP_COMMIT: u8 = 0x03;
+/// Drop the transfer.
+pub const OP_ABORT: u8 = 0x04;
+
+/// Longest control write (`BEGIN`). Needs an ATT MTU of at least 104.
+pub const CONTROL_LEN: usize = 1 + 4 + DIGEST_LEN + SIGNATURE_LEN;
+
+/// Bytes before the image data in a data packet.
+pub const DATA_HEADER_LEN: usize = 4;
+
+/// Longest data write; fits an ATT MTU of 251 (LE Data Length Extension).
+pub const DATA_LEN: usize = 248;
+
+/// Status notification: code, offset (u32 LE), max data (u16 LE), window (u16 LE).
+pub const STATUS_LEN: usize = 9;
+
+/// Bytes the host may send between two status notifications.
+pub const WINDOW_BYTES: u32 = 4096;
+
+pub const STATUS_READY: u8 = 0x00;
+pub const STATUS_INSTALLED: u8 = 0x01;
+/// Error codes have the top bit set; see `error_code`.
+pub const STATUS_ERROR: u8 = 0x80;
+/// The write could not be parsed.
+pub const STATUS_MALFORMED: u8 = 0xFF;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Control {
+    Begin(Manifest),
+    Query,
+    Commit,
+    Abort,
+}
+
+pub fn parse_control(data: &[u8]) -> Option<Control> {
+    match *data.first()? {
+        OP_BEGIN if data.len() == CONTROL_LEN => {
+            let size = u32::from_le_bytes([data[1], data[2], data[3], data[4]]);
+            if size == 0 {
+                return None;
+            }
+            let mut sha256 = [0u8; DIGEST_LEN];
+            sha256.copy_from_slice(&data[5..5 + DIGEST_LEN]);
+            let mut signature = [0u8; SIGNATURE_LEN];
+            signature.copy_from_slice(&data[5 + DIGEST_LEN..]);
+            Some(Control::Begin(Manifest { size, sha256, signature }))
+        }
+        OP_QUERY if data.len() == 1 => Some(Control::Query),
+        OP_COMMIT if data.len() == 1 => Some(Control::Commit),
+        OP_ABORT if data.len() == 1 => Some(Control::Abort),
+        _ => None,
+    }
+}
+
+/// Splits a data packet into the image offset and its data.
+pub fn parse_data(data: &[u8]) -> Option<(u32, &[u8])> {
+    if data.len() <= DATA_HEADER_LEN {
+        return None;
+    }
+    let (header, bytes) = data.split_at(DATA_HEADER_LEN);
+    Some((u32::from_le_bytes([header[0], header[1], header[2], header[3]]), bytes))
+}
+
+/// Image bytes per data packet for the negotiated ATT MTU.
+pub fn max_data(att_mtu: u16) -> u16 {
+    // A write carries MTU - 3 bytes (opcode and handle)
+    let write_len = (att_mtu as usize).saturating_sub(3).min(DATA_LEN);
+    write_len.saturating_sub(DATA_HEADER_LEN) as u16
+}
+
+/// Packets per status notification, so a window is about `WINDOW_BYTES`.
+pub fn window(max_data: u16) -> u16 {
+    if max_data == 0 {
+        return 0;
+    }
+    WINDOW_BYTES.div_ceil(max_data as u32) as u16
+}
+
+/// Error code sent in status notifications.
+pub fn error_code(e: OtaError) -> u8 {
+    STATUS_ERROR
+        | match e {
+            OtaError::Disabled => 0x01,
+            OtaError::Busy => 0x02,
+            OtaError::NoTransfer => 0x03,
+            OtaError::TooLarge => 0x04,
+            OtaError::SizeMismatch => 0x05,
+            OtaError::NotAnImage => 0x06,
+            OtaError::NoSlot => 0x07,
+            OtaError::Flash => 0x08,
+            OtaError::DigestMismatch => 0x09,
+            OtaError::BadSignature => 0x0A,
+            // Only HTTP downloads fail like this
+            OtaError::UnsupportedUrl
+            | OtaError::Dns
+            | OtaError::Connect
+            | OtaError::Network
+            | OtaError::Http(_)
+            | OtaError::BadResponse => 0x7E,
+        }
+}
+
+pub fn encode_status(code: u8, offset: u32, att_mtu: u16) -> [u8; STATUS_LEN] {
+    let max_data = max_data(att_mtu);
+    let mut status = [0u8; STATUS_LEN];
+    status[0] = code;
+    status[1..5].copy_from_slice(&offset.to_le_bytes());
+    status[5..7].copy_from_slice(&max_data.to_le_bytes());
+    status[7..9].copy_from_slice(&window(max_data).to_le_bytes());
+    status
+}
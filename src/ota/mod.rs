@@ -0,0 +1,386 @@
+use core::cell::Cell;
+use core::sync::atomic::{AtomicBool, Ordering};
+use critical_section::Mutex;
+use rtt_target::rprintln;
+use embassy_net::tcp::TcpSocket;
+use embassy_net::Stack;
+use embassy_time::Duration;
+use embedded_io_async::{Read, Write};
+use embedded_storage::{ReadStorage, Storage};
+use esp_bootloader_esp_idf::ota::OtaImageState;
+use esp_bootloader_esp_idf::ota_updater::OtaUpdater;
+use esp_bootloader_esp_idf::partitions::PARTITION_TABLE_MAX_LEN;
+use heapless::String;
+use sha2::{Digest, Sha256};
+use static_cell::ConstStaticCell;
+
+use crate::flash::Flash;
+use crate::net::resolve_host;
+
+pub mod ble;
+pub mod dfu;
+
+// The manifest, chunk bookkeeping and HTTP parsing are hardware-independent (see `firmware-core/`)
+pub use firmware_core::ota::{chunk, http, image};
+
+pub use chunk::{ChunkStatus, Transfer};
+pub use image::{Manifest, OtaError, PUBLIC_KEY_LEN};
+
+// --- Over-the-Air Updates ---
+// A new image is written into the OTA slot we are not running from, read back
+// and checked against the manifest (SHA-256 and Ed25519 signature), and only
+// then selected for the next boot. It boots in the `New` state and has to
+// confirm itself once it is healthy; otherwise the previous image is
+// restored. Needs a partition table with `ota_0`, `ota_1` and `otadata`
+// (see `partitions.csv`).
+
+/// Gives up on a download when the server stays silent this long.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Flash sector; the slot is written one sector at a time.
+const SECTOR_SIZE: usize = 4096;
+
+/// Log download progress every this many bytes.
+const PROGRESS_STEP: u32 = 64 * 1024;
+
+/// Set once the running image has been confirmed (or never needed it).
+static CONFIRMED: AtomicBool = AtomicBool::new(false);
+
+/// Set while a `SlotWriter` exists (or the OTA data is being updated). HTTP,
+/// MQTT and BLE updates all share the flash, so this is what keeps them out
+/// of each other's slot.
+static SLOT_IN_USE: Mutex<Cell<bool>> = Mutex::new(Cell::new(false));
+
+/// Partition table copy and sector buffer, too large for a task's stack.
+/// Whoever sets `SLOT_IN_USE` gets them (see `Lease`).
+struct Buffers {
+    table: [u8; PARTITION_TABLE_MAX_LEN],
+    sector: [u8; SECTOR_SIZE],
+}
+
+static BUFFERS: ConstStaticCell<Buffers> = ConstStaticCell::new(Buffers {
+    table: [0u8; PARTITION_TABLE_MAX_LEN],
+    sector: [0u8; SECTOR_SIZE],
+});
+
+/// The buffers while nobody holds them; `None` before the first use.
+static IDLE_BUFFERS: Mutex<Cell<Option<&'static mut Buffers>>> = Mutex::new(Cell::new(None));
+
+/// Exclusive use of the OTA slot and the buffers. Dropping it releases both.
+struct Lease(Option<&'static mut Buffers>);
+
+impl Lease {
+    fn acquire() -> Result<Self, OtaError> {
+        critical_section::with(|cs| {
+            if SLOT_IN_USE.borrow(cs).replace(true) {
+                return Err(OtaError::Busy);
+            }
+            // While the slot is free the buffers are idle or were never taken
+            let buffers = IDLE_BUFFERS.borrow(cs).take().unwrap_or_else(|| BUFFERS.take());
+            Ok(Self(Some(buffers)))
+        })
+    }
+
+    fn buffers(&mut self) -> &mut Buffers {
+        self.0.as_deref_mut().expect("buffers are held until drop")
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        critical_section::with(|cs| {
+            IDLE_BUFFERS.borrow(cs).set(self.0.take());
+            SLOT_IN_USE.borrow(cs).set(false);
+        });
+    }
+}
+
+/// Whether an update is being written.
+pub fn is_busy() -> bool {
+    critical_section::with(|cs| SLOT_IN_USE.borrow(cs).get())
+}
+
+fn storage_error<E>(_: E) -> OtaError {
+    OtaError::Flash
+}
+
+/// Writes an image into the inactive OTA slot.
+pub struct SlotWriter {
+    flash: Flash,
+    lease: Lease,
+    fill: usize,
+    /// Slot offset `sector` will be written to.
+    offset: u32,
+    manifest: Manifest,
+}
+
+impl SlotWriter {
+    /// Prepares writing an image described by `manifest`. Fails if another
+    /// update is in progress, the partition table has no OTA slots or the
+    /// image does not fit.
+    pub fn begin(flash: Flash, manifest: &Manifest) -> Result<Self, OtaError> {
+        // From here on, dropping `writer` releases the slot
+        let mut writer = Self {
+            flash,
+            lease: Lease::acquire()?,
+            fill: 0,
+            offset: 0,
+            manifest: *manifest,
+        };
+        {
+            let table = &mut writer.lease.buffers().table;
+            let mut updater = OtaUpdater::new(&mut writer.flash, table).map_err(|_| OtaError::NoSlot)?;
+            let (slot, target) = updater.next_partition().map_err(|_| OtaError::NoSlot)?;
+            if manifest.size as usize > slot.capacity() {
+                return Err(OtaError::TooLarge);
+            }
+            rprintln!("OTA: writing {} bytes to {:?}", manifest.size, target);
+        }
+        Ok(writer)
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// Bytes received so far.
+    pub fn received(&self) -> u32 {
+        self.offset + self.fill as u32
+    }
+
+    /// Appends the next part of the image.
+    pub fn write(&mut self, mut data: &[u8]) -> Result<(), OtaError> {
+        if self.received() == 0 {
+            image::check_header(data)?;
+        }
+        if self.received() as usize + data.len() > self.manifest.size as usize {
+            return Err(OtaError::SizeMismatch);
+        }
+        while !data.is_empty() {
+            let n = (SECTOR_SIZE - self.fill).min(data.len());
+            self.lease.buffers().sector[self.fill..self.fill + n].copy_from_slice(&data[..n]);
+            self.fill += n;
+            data = &data[n..];
+            if self.fill == SECTOR_SIZE {
+                self.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), OtaError> {
+        let Buffers { table, sector } = self.lease.buffers();
+        let mut updater = OtaUpdater::new(&mut self.flash, table).map_err(|_| OtaError::NoSlot)?;
+        let (mut slot, _) = updater.next_partition().map_err(|_| OtaError::NoSlot)?;
+        slot.write(self.offset, &sector[..self.fill]).map_err(storage_error)?;
+        self.offset += self.fill as u32;
+        self.fill = 0;
+        Ok(())
+    }
+
+    /// Checks the image as written to flash and selects it for the next boot.
+    pub fn finish(mut self, public_key: &[u8; PUBLIC_KEY_LEN]) -> Result<(), OtaError> {
+        if self.fill > 0 {
+            self.flush()?;
+        }
+        if self.offset != self.manifest.size {
+            return Err(OtaError::SizeMismatch);
+        }
+
+        let Buffers { table, sector } = self.lease.buffers();
+        let mut updater = OtaUpdater::new(&mut self.flash, table).map_err(|_| OtaError::NoSlot)?;
+        {
+            // Hash what is in flash, not what was received
+            let (mut slot, _) = updater.next_partition().map_err(|_| OtaError::NoSlot)?;
+            let mut hasher = Sha256::new();
+            let mut offset = 0;
+            while offset < self.manifest.size {
+                let n = (self.manifest.size - offset).min(SECTOR_SIZE as u32) as usize;
+                slot.read(offset, &mut sector[..n]).map_err(storage_error)?;
+                hasher.update(&sector[..n]);
+                offset += n as u32;
+            }
+            let digest: [u8; image::DIGEST_LEN] = hasher.finalize().into();
+            if digest != self.manifest.sha256 {
+                return Err(OtaError::DigestMismatch);
+            }
+            verify_signature(public_key, &digest, &self.manifest.signature)?;
+        }
+
+        updater.activate_next_partition().map_err(|_| OtaError::NoSlot)?;
+        updater.set_current_ota_state(OtaImageState::New).map_err(storage_error)?;
+        rprintln!("OTA: image verified and activated");
+        Ok(())
+    }
+}
+
+fn verify_signature(
+    public_key: &[u8; PUBLIC_KEY_LEN],
+    digest: &[u8; image::DIGEST_LEN],
+    signature: &[u8; image::SIGNATURE_LEN],
+) -> Result<(), OtaError> {
+    let key = ed25519_compact::PublicKey::new(*public_key);
+    let signature = ed25519_compact::Signature::new(*signature);
+    key.verify(digest, &signature).map_err(|_| OtaError::BadSignature)
+}
+
+/// Downloads the image at `url` (plain HTTP) into `writer`.
+pub async fn download(stack: Stack<'_>, url: &str, writer: &mut SlotWriter) -> Result<(), OtaError> {
+    let url = http::parse_url(url)?;
+    let ip = resolve_host(stack, url.host).await.map_err(|_| OtaError::Dns)?;
+
+    let mut rx_buffer = [0u8; 4096];
+    let mut tx_buffer = [0u8; 512];
+    let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+    socket.set_timeout(Some(HTTP_TIMEOUT));
+    rprintln!("OTA: downloading http://{}:{}{}", url.host, url.port, url.path);
+    socket.connect((ip, url.port)).await.map_err(|_| OtaError::Connect)?;
+
+    let mut request = String::<{ http::MAX_URL_LEN + 128 }>::new();
+    http::write_request(&url, &mut request)?;
+    socket.write_all(request.as_bytes()).await.map_err(|_| OtaError::Network)?;
+
+    // The head must fit the buffer; the rest of it is the start of the body
+    let mut buf = [0u8; 1024];
+    let mut len = 0;
+    let head = loop {
+        if len == buf.len() {
+            return Err(OtaError::BadResponse);
+        }
+        let n = socket.read(&mut buf[len..]).await.map_err(|_| OtaError::Network)?;
+        if n == 0 {
+            return Err(OtaError::Network);
+        }
+        len += n;
+        if let Some(head) = http::parse_response_head(&buf[..len])? {
+            break head;
+        }
+    };
+    if head.status != 200 {
+        return Err(OtaError::Http(head.status));
+    }
+    if head.content_length.is_some_and(|l| l != writer.manifest.size) {
+        return Err(OtaError::SizeMismatch);
+    }
+    writer.write(&buf[head.header_len..len])?;
+
+    let mut next_progress = PROGRESS_STEP;
+    while writer.received() < writer.manifest.size {
+        let n = socket.read(&mut buf).await.map_err(|_| OtaError::Network)?;
+        if n == 0 {
+            break; // `finish` reports the short image
+        }
+        writer.write(&buf[..n])?;
+        if writer.received() >= next_progress {
+            rprintln!("OTA: {}/{} bytes", writer.received(), writer.manifest.size);
+            next_progress += PROGRESS_STEP;
+        }
+    }
+    socket.close();
+    Ok(())
+}
+
+/// Downloads, verifies and activates an update. The caller resets afterwards.
+pub async fn install(
+    stack: Stack<'_>,
+    flash: Flash,
+    url: &str,
+    manifest: &Manifest,
+    public_key: &[u8; PUBLIC_KEY_LEN],
+) -> Result<(), OtaError> {
+    let mut writer = SlotWriter::begin(flash, manifest)?;
+    download(stack, url, &mut writer).await?;
+    writer.finish(public_key)
+}
+
+/// An image arriving in chunks over MQTT (see `chunk`).
+pub struct ChunkReceiver {
+    transfer: Transfer,
+    writer: SlotWriter,
+}
+
+impl ChunkReceiver {
+    pub fn begin(flash: Flash, manifest: &Manifest, chunk_size: u32) -> Result<Self, OtaError> {
+        let transfer = Transfer::new(*manifest, chunk_size)?;
+        let writer = SlotWriter::begin(flash, manifest)?;
+        rprintln!("OTA: expecting {} chunks of {} bytes over MQTT", transfer.chunk_count(), chunk_size);
+        Ok(Self { transfer, writer })
+    }
+
+    pub fn transfer(&self) -> &Transfer {
+        &self.transfer
+    }
+
+    /// Handles chunk `index`. Returns `true` once the last chunk is written.
+    pub fn receive(&mut self, index: u32, data: &[u8]) -> Result<bool, OtaError> {
+        if self.transfer.check(index, data)? == ChunkStatus::Accept {
+            self.writer.write(data)?;
+            self.transfer.advance();
+            if self.writer.received() % PROGRESS_STEP < data.len() as u32 {
+                rprintln!("OTA: {}/{} bytes", self.writer.received(), self.transfer.manifest.size);
+            }
+        }
+        Ok(self.transfer.is_complete())
+    }
+
+    /// Checks the complete image and selects it for the next boot.
+    pub fn finish(self, public_key: &[u8; PUBLIC_KEY_LEN]) -> Result<(), OtaError> {
+        self.writer.finish(public_key)
+    }
+}
+
+// --- Rollback ---
+
+/// Whether the running image was just installed and still has to confirm
+/// itself. Images flashed with a probe (or without OTA data) never do.
+pub fn needs_confirmation(mut flash: Flash) -> bool {
+    // Runs at boot, before anything can start an update
+    let Ok(mut lease) = Lease::acquire() else {
+        return false;
+    };
+    let table = &mut lease.buffers().table;
+    let pending = match OtaUpdater::new(&mut flash, table).and_then(|mut u| u.current_ota_state()) {
+        Ok(state) => matches!(state, OtaImageState::New | OtaImageState::PendingVerify),
+        Err(_) => false,
+    };
+    if !pending {
+        CONFIRMED.store(true, Ordering::Relaxed);
+    }
+    pending
+}
+
+pub fn is_confirmed() -> bool {
+    CONFIRMED.load(Ordering::Relaxed)
+}
+
+/// Marks the running image as good, so the bootloader keeps it.
+/// Fails with `Busy` while an update is being written; try again later.
+pub fn confirm(mut flash: Flash) -> Result<(), OtaError> {
+    let mut lease = Lease::acquire()?;
+    let table = &mut lease.buffers().table;
+    let mut updater = OtaUpdater::new(&mut flash, table).map_err(|_| OtaError::NoSlot)?;
+    updater.set_current_ota_state(OtaImageState::Valid).map_err(storage_error)?;
+    CONFIRMED.store(true, Ordering::Relaxed);
+    rprintln!("OTA: image confirmed");
+    Ok(())
+}
+
+/// Marks the running image as bad and boots the previous one.
+pub fn roll_back(mut flash: Flash) -> ! {
+    match Lease::acquire() {
+        Ok(mut lease) => match OtaUpdater::new(&mut flash, &mut lease.buffers().table) {
+            Ok(mut updater) => {
+                let _ = updater.set_current_ota_state(OtaImageState::Invalid);
+                if let Err(e) = updater.activate_next_partition() {
+                    rprintln!("OTA: cannot select the previous image: {:?}", e);
+                }
+            }
+            Err(e) => rprintln!("OTA: cannot read the OTA data: {:?}", e),
+        },
+        // The image is still unconfirmed, so the bootloader rolls it back
+        // after this reset anyway
+        Err(_) => rprintln!("OTA: update in progress, resetting without marking the image"),
+    }
+    rprintln!("OTA: rolling back...");
+    esp_hal::system::software_reset()
+}
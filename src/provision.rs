@@ -0,0 +1,232 @@
+use embassy_futures::join::join;
+use embassy_futures::select::select;
+use embassy_time::{Duration, Timer};
+use esp_hal::gpio::Input;
+use rtt_target::rprintln;
+use trouble_host::prelude::*;
+
+use crate::command::{self, CommandError, ConfigUpdate};
+use crate::config::{AppConfig, ConfigStore};
+use crate::flash::Flash;
+use crate::{boot, BleStack};
+
+// --- Provisioning ---
+// Changing the stored configuration without the broker: from the serial
+// console, and in safe mode over BLE. Each BLE write to the config
+// characteristic is a `set_config` object (see `command::ConfigUpdate`),
+// applied and saved on its own, so settings that do not fit one write can be
+// sent in several. The outcome is notified on the result characteristic. Once
+// the host disconnects after a successful save the device restarts to try
+// the new settings.
+//
+// The BLE link is neither paired nor encrypted, so anyone in range could
+// rewrite the settings and the Wi-Fi password travels in the clear. The
+// service is therefore only advertised for `PROVISIONING_WINDOW` after the
+// BOOT button is pressed, i.e. by someone at the device.
+
+/// Largest config write: an ATT MTU of 247 minus the write header.
+pub const CONFIG_LEN: usize = 244;
+
+/// Longest result: `ok` or a `CommandError` name.
+pub const RESULT_LEN: usize = 16;
+
+/// `5e1b0101-6a3c-4f4e-9d2a-8b5c7e0f1a20`, little-endian for advertising.
+const SERVICE_UUID: [u8; 16] = [
+    0x20, 0x1a, 0x0f, 0x7e, 0x5c, 0x8b, 0x2a, 0x9d, 0x4e, 0x4f, 0x3c, 0x6a, 0x01, 0x01, 0x1b, 0x5e,
+];
+
+/// Longest name that fits the scan response next to its header.
+const MAX_ADV_NAME_LEN: usize = 29;
+
+/// How long a press of the BOOT button opens BLE provisioning.
+pub const PROVISIONING_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+/// Lets the link layer settle before the restart.
+const RESTART_DELAY: Duration = Duration::from_millis(500);
+
+#[gatt_server]
+pub struct ProvisioningServer {
+    pub provisioning: ProvisioningService,
+}
+
+#[gatt_service(uuid = "5e1b0101-6a3c-4f4e-9d2a-8b5c7e0f1a20")]
+pub struct ProvisioningService {
+    #[characteristic(uuid = "5e1b0102-6a3c-4f4e-9d2a-8b5c7e0f1a20", write, value = [0; CONFIG_LEN])]
+    pub config: [u8; CONFIG_LEN],
+    #[characteristic(uuid = "5e1b0103-6a3c-4f4e-9d2a-8b5c7e0f1a20", read, notify, value = [0; RESULT_LEN])]
+    pub result: [u8; RESULT_LEN],
+}
+
+/// The stored configuration. A config that cannot be read is an error rather
+/// than defaults, so that a change to one setting is never saved over it.
+pub async fn load_config(config_store: &mut ConfigStore) -> Result<AppConfig, CommandError> {
+    config_store.load().await.map_err(|e| {
+        rprintln!("Config load failed: {:?}", e);
+        CommandError::Storage
+    })
+}
+
+/// Applies `update` to the stored configuration. Takes effect after a restart.
+pub async fn save_update(config_store: &mut ConfigStore, update: &ConfigUpdate) -> Result<(), CommandError> {
+    let mut config = load_config(config_store).await?;
+    update.apply(&mut config)?;
+    config_store.save(&config).await.map_err(|e| {
+        rprintln!("Config save failed: {:?}", e);
+        CommandError::Storage
+    })
+}
+
+/// `ok` or the error name, zero-padded.
+fn encode_result(result: Result<(), CommandError>) -> [u8; RESULT_LEN] {
+    let text = match result {
+        Ok(()) => "ok",
+        Err(e) => e.name(),
+    };
+    let mut out = [0u8; RESULT_LEN];
+    let len = text.len().min(RESULT_LEN);
+    out[..len].copy_from_slice(&text.as_bytes()[..len]);
+    out
+}
+
+/// Runs the BLE host and, for `PROVISIONING_WINDOW` after each press of
+/// `button` (low while pressed), serves the provisioning service, one
+/// connection at a time, under `name`.
+pub async fn run(stack: &'static BleStack<'static>, flash: Flash, name: &str, mut button: Input<'static>) {
+    let Host { mut peripheral, mut runner, .. } = stack.build();
+    let server = match ProvisioningServer::new_with_config(GapConfig::Peripheral(PeripheralConfig {
+        name: "esp-blinky-rust",
+        appearance: &appearance::sensor::GENERIC_SENSOR,
+    })) {
+        Ok(server) => server,
+        Err(e) => {
+            rprintln!("BLE: cannot create the provisioning service: {:?}", e);
+            return;
+        }
+    };
+    let mut config_store = ConfigStore::new(flash);
+
+    join(
+        async {
+            loop {
+                if let Err(e) = runner.run().await {
+                    rprintln!("BLE host error: {:?}", e);
+                }
+            }
+        },
+        async {
+            loop {
+                button.wait_for_low().await;
+                rprintln!("BLE: provisioning open for {}s as '{}'", PROVISIONING_WINDOW.as_secs(), name);
+                // Ends advertising, or drops the connection, when the window closes
+                select(accept_updates(name, &mut peripheral, &server, &mut config_store), Timer::after(PROVISIONING_WINDOW)).await;
+                rprintln!("BLE: provisioning closed, press BOOT to open it again");
+                // A button still held does not open the next window
+                button.wait_for_high().await;
+            }
+        },
+    )
+    .await;
+}
+
+/// Advertises and serves one host after another; restarts the device once a
+/// host disconnects after a successful save.
+async fn accept_updates<'values, C: Controller>(
+    name: &str,
+    peripheral: &mut Peripheral<'values, C, DefaultPacketPool>,
+    server: &ProvisioningServer<'values>,
+    config_store: &mut ConfigStore,
+) {
+    loop {
+        match advertise(name, peripheral, server).await {
+            Ok(conn) => {
+                rprintln!("BLE: connected");
+                if serve(server, &conn, config_store).await {
+                    rprintln!("Provisioned over BLE, restarting...");
+                    Timer::after(RESTART_DELAY).await;
+                    boot::planned_restart();
+                }
+            }
+            Err(e) => {
+                rprintln!("BLE advertising failed: {:?}", e);
+                Timer::after(Duration::from_secs(5)).await;
+            }
+        }
+    }
+}
+
+async fn advertise<'values, 'server, C: Controller>(
+    name: &str,
+    peripheral: &mut Peripheral<'values, C, DefaultPacketPool>,
+    server: &'server ProvisioningServer<'values>,
+) -> Result<GattConnection<'values, 'server, DefaultPacketPool>, BleHostError<C::Error>> {
+    let mut adv_data = [0u8; 31];
+    let adv_len = AdStructure::encode_slice(
+        &[
+            AdStructure::Flags(LE_GENERAL_DISCOVERABLE | BR_EDR_NOT_SUPPORTED),
+            AdStructure::ServiceUuids128(&[SERVICE_UUID]),
+        ],
+        &mut adv_data[..],
+    )?;
+    let name = name.as_bytes();
+    let name_ad = if name.len() > MAX_ADV_NAME_LEN {
+        AdStructure::ShortenedLocalName(&name[..MAX_ADV_NAME_LEN])
+    } else {
+        AdStructure::CompleteLocalName(name)
+    };
+    let mut scan_data = [0u8; 31];
+    let scan_len = AdStructure::encode_slice(&[name_ad], &mut scan_data[..])?;
+
+    let advertiser = peripheral
+        .advertise(
+            &Default::default(),
+            Advertisement::ConnectableScannableUndirected {
+                adv_data: &adv_data[..adv_len],
+                scan_data: &scan_data[..scan_len],
+            },
+        )
+        .await?;
+    let conn = advertiser.accept().await?.with_attribute_server(server)?;
+    Ok(conn)
+}
+
+/// Handles GATT events until the host disconnects. Returns whether any
+/// update was saved.
+async fn serve(
+    server: &ProvisioningServer<'_>,
+    conn: &GattConnection<'_, '_, DefaultPacketPool>,
+    config_store: &mut ConfigStore,
+) -> bool {
+    let mut saved = false;
+    loop {
+        match conn.next().await {
+            GattConnectionEvent::Disconnected { reason } => {
+                rprintln!("BLE: disconnected ({:?})", reason);
+                return saved;
+            }
+            GattConnectionEvent::Gatt { event } => {
+                let result = match &event {
+                    GattEvent::Write(write) if write.handle() == server.provisioning.config.handle => {
+                        let result = match command::parse_args::<ConfigUpdate>(write.data()) {
+                            Ok(update) => save_update(config_store, &update).await,
+                            Err(e) => Err(e),
+                        };
+                        saved |= result.is_ok();
+                        Some(encode_result(result))
+                    }
+                    _ => None,
+                };
+                match event.accept() {
+                    Ok(reply) => reply.send().await,
+                    Err(e) => rprintln!("BLE: cannot answer: {:?}", e),
+                }
+
+                if let Some(result) = result
+                    && let Err(e) = server.provisioning.result.notify(conn, &result).await
+                {
+                    rprintln!("BLE: result notification failed: {:?}", e);
+                }
+            }
+            _ => {}
+        }
+    }
+}
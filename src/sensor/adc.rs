@@ -0,0 +1,181 @@
+use esp_hal::analog::adc::{Adc, AdcCalCurve, AdcConfig, AdcPin, Attenuation as HalAttenuation};
+use esp_hal::peripherals::{ADC1, GPIO0, GPIO1, GPIO2, GPIO3, GPIO4};
+use esp_hal::Async;
+use heapless::Vec;
+use rtt_target::rprintln;
+
+use super::analog::{AdcChannelConfig, Attenuation, MAX_ADC_CHANNELS};
+use super::{push, Quantity, Readings, Sensor, SensorError, SensorKind};
+use crate::battery::{self, BatteryConfig};
+
+// --- ADC Inputs ---
+// Readings use the eFuse curve calibration, so conversions come back in mV.
+// The battery voltage, if monitored, is read here as well since it needs ADC1.
+
+type Cal = AdcCalCurve<ADC1<'static>>;
+
+/// ADC1 and the pins it can sample.
+pub struct AnalogInputs {
+    pub adc1: ADC1<'static>,
+    pub gpio0: GPIO0<'static>,
+    pub gpio1: GPIO1<'static>,
+    pub gpio2: GPIO2<'static>,
+    pub gpio3: GPIO3<'static>,
+    pub gpio4: GPIO4<'static>,
+}
+
+/// Each GPIO is its own type, so the enabled pins are kept as an enum.
+enum Pin {
+    Gpio0(AdcPin<GPIO0<'static>, ADC1<'static>, Cal>),
+    Gpio1(AdcPin<GPIO1<'static>, ADC1<'static>, Cal>),
+    Gpio2(AdcPin<GPIO2<'static>, ADC1<'static>, Cal>),
+    Gpio3(AdcPin<GPIO3<'static>, ADC1<'static>, Cal>),
+    Gpio4(AdcPin<GPIO4<'static>, ADC1<'static>, Cal>),
+}
+
+struct Channel {
+    pin: Pin,
+    /// Position in `AppConfig::adc_channels`, reported as `Quantity::Other(index)`.
+    index: u8,
+    config: AdcChannelConfig,
+}
+
+pub struct AdcSensor {
+    adc: Adc<'static, ADC1<'static>, Async>,
+    channels: Vec<Channel, MAX_ADC_CHANNELS>,
+    battery: Option<(Pin, BatteryConfig)>,
+}
+
+/// Pins not enabled yet.
+struct FreePins {
+    gpio0: Option<GPIO0<'static>>,
+    gpio1: Option<GPIO1<'static>>,
+    gpio2: Option<GPIO2<'static>>,
+    gpio3: Option<GPIO3<'static>>,
+    gpio4: Option<GPIO4<'static>>,
+}
+
+impl FreePins {
+    /// Enables `pin`, or `None` if it is taken or not an ADC1 input.
+    fn enable(&mut self, adc_config: &mut AdcConfig<ADC1<'static>>, pin: u8, attenuation: HalAttenuation) -> Option<Pin> {
+        match pin {
+            0 => self.gpio0.take().map(|p| Pin::Gpio0(adc_config.enable_pin_with_cal::<_, Cal>(p, attenuation))),
+            1 => self.gpio1.take().map(|p| Pin::Gpio1(adc_config.enable_pin_with_cal::<_, Cal>(p, attenuation))),
+            2 => self.gpio2.take().map(|p| Pin::Gpio2(adc_config.enable_pin_with_cal::<_, Cal>(p, attenuation))),
+            3 => self.gpio3.take().map(|p| Pin::Gpio3(adc_config.enable_pin_with_cal::<_, Cal>(p, attenuation))),
+            4 => self.gpio4.take().map(|p| Pin::Gpio4(adc_config.enable_pin_with_cal::<_, Cal>(p, attenuation))),
+            _ => None,
+        }
+    }
+}
+
+/// Mean of `samples` conversions, in mV.
+async fn read_mv(adc: &mut Adc<'static, ADC1<'static>, Async>, pin: &mut Pin, samples: u32) -> f32 {
+    let mut sum_mv = 0u32;
+    for _ in 0..samples {
+        sum_mv += match pin {
+            Pin::Gpio0(pin) => adc.read_oneshot(pin).await,
+            Pin::Gpio1(pin) => adc.read_oneshot(pin).await,
+            Pin::Gpio2(pin) => adc.read_oneshot(pin).await,
+            Pin::Gpio3(pin) => adc.read_oneshot(pin).await,
+            Pin::Gpio4(pin) => adc.read_oneshot(pin).await,
+        } as u32;
+    }
+    sum_mv as f32 / samples as f32
+}
+
+fn hal_attenuation(attenuation: Attenuation) -> HalAttenuation {
+    match attenuation {
+        Attenuation::Db0 => HalAttenuation::_0dB,
+        Attenuation::Db2_5 => HalAttenuation::_2p5dB,
+        Attenuation::Db6 => HalAttenuation::_6dB,
+        Attenuation::Db11 => HalAttenuation::_11dB,
+    }
+}
+
+impl AdcSensor {
+    /// Enables the configured channels and the battery input. Invalid
+    /// channels, and a second channel on the same pin, are skipped with a log
+    /// message. The battery pin is enabled first.
+    pub fn new(inputs: AnalogInputs, configs: &[AdcChannelConfig], battery: &BatteryConfig) -> Self {
+        let mut adc_config = AdcConfig::new();
+        let mut pins = FreePins {
+            gpio0: Some(inputs.gpio0),
+            gpio1: Some(inputs.gpio1),
+            gpio2: Some(inputs.gpio2),
+            gpio3: Some(inputs.gpio3),
+            gpio4: Some(inputs.gpio4),
+        };
+
+        let battery = if battery.is_enabled() {
+            match pins.enable(&mut adc_config, battery.pin, HalAttenuation::_11dB) {
+                Some(pin) => {
+                    rprintln!("Battery monitoring on GPIO{} (divider {})", battery.pin, battery.divider);
+                    Some((pin, *battery))
+                }
+                None => {
+                    rprintln!("Battery monitoring: GPIO{} is not an ADC1 input", battery.pin);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let mut channels = Vec::new();
+        for (index, config) in configs.iter().enumerate() {
+            if let Err(e) = config.validate() {
+                rprintln!("ADC channel '{}': {:?}", config.name, e);
+                continue;
+            }
+            let Some(pin) = pins.enable(&mut adc_config, config.pin, hal_attenuation(config.attenuation)) else {
+                rprintln!("ADC channel '{}': GPIO{} already in use", config.name, config.pin);
+                continue;
+            };
+            rprintln!("ADC channel '{}' on GPIO{} ({:?})", config.name, config.pin, config.attenuation);
+            let _ = channels.push(Channel { pin, index: index as u8, config: config.clone() });
+        }
+
+        Self { adc: Adc::new(inputs.adc1, adc_config).into_async(), channels, battery }
+    }
+
+    /// Neither channels nor the battery to read.
+    pub fn is_empty(&self) -> bool {
+        self.channels.is_empty() && self.battery.is_none()
+    }
+
+    /// Whether any channel is published (the battery is not).
+    pub fn has_channels(&self) -> bool {
+        !self.channels.is_empty()
+    }
+
+    /// Measures the supply and records it in `battery`.
+    pub async fn read_battery(&mut self) -> Option<u32> {
+        let (pin, config) = self.battery.as_mut()?;
+        let mv = config.supply_mv(read_mv(&mut self.adc, pin, battery::OVERSAMPLE).await);
+        battery::record(config, mv);
+        Some(mv)
+    }
+
+    /// Indices (into `AppConfig::adc_channels`) of the channels that are read.
+    pub fn channel_indices(&self) -> impl Iterator<Item = u8> + '_ {
+        self.channels.iter().map(|c| c.index)
+    }
+}
+
+impl Sensor for AdcSensor {
+    fn kind(&self) -> SensorKind {
+        SensorKind::Adc
+    }
+
+    async fn read(&mut self, out: &mut Readings) -> Result<(), SensorError> {
+        let Self { adc, channels, .. } = self;
+        for channel in channels.iter_mut() {
+            let samples = channel.config.oversample.max(1) as u32;
+            let volts = read_mv(adc, &mut channel.pin, samples).await / 1000.0;
+            push(out, Quantity::Other(channel.index), channel.config.scale(volts))?;
+        }
+        self.read_battery().await;
+        Ok(())
+    }
+}
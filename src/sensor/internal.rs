@@ -0,0 +1,29 @@
+use esp_hal::tsens::TemperatureSensor;
+
+use super::{push, Quantity, Readings, Sensor, SensorError, SensorKind};
+use crate::calibration;
+
+/// The on-die temperature sensor, with the device calibration and
+/// self-heating compensation applied.
+pub struct InternalTemp {
+    sensor: TemperatureSensor<'static>,
+}
+
+impl InternalTemp {
+    pub fn new(sensor: TemperatureSensor<'static>) -> Self {
+        Self { sensor }
+    }
+}
+
+impl Sensor for InternalTemp {
+    fn kind(&self) -> SensorKind {
+        SensorKind::Internal
+    }
+
+    async fn read(&mut self, out: &mut Readings) -> Result<(), SensorError> {
+        let calibration = calibration::current();
+        let raw = self.sensor.get_temperature().to_celsius();
+        let value = calibration.apply(raw) - calibration.compensation(calibration::load());
+        push(out, Quantity::Temperature, value)
+    }
+}
@@ -0,0 +1,74 @@
+pub use firmware_core::sensor::analog;
+pub use firmware_core::sensor::{
+    push, Measurement, Quantity, Readings, Sensor, SensorError, SensorKind, MAX_CHANNELS, MAX_SENSORS,
+};
+#[cfg(feature = "bme280")]
+pub use firmware_core::sensor::bme280;
+#[cfg(feature = "ds18b20")]
+pub use firmware_core::sensor::{ds18b20, onewire};
+#[cfg(feature = "sht3x")]
+pub use firmware_core::sensor::sht3x;
+
+pub mod adc;
+pub mod internal;
+
+// --- Sensors ---
+// The sensor trait and the external drivers live in `firmware-core` (see
+// `firmware-core/src/sensor/`), where they are tested against
+// `embedded-hal-mock`. This module adds the sensors that need the chip (die
+// temperature, ADC) and the board wiring, and re-exports a driver only when
+// its cargo feature is enabled.
+
+// --- Board Wiring ---
+// The concrete types used on the board: both I2C sensors share I2C0, the
+// 1-Wire probe sits on its own open-drain pin.
+
+#[cfg(any(feature = "sht3x", feature = "bme280"))]
+pub type I2cBus = embassy_sync::mutex::Mutex<embassy_sync::blocking_mutex::raw::NoopRawMutex, esp_hal::i2c::master::I2c<'static, esp_hal::Async>>;
+
+#[cfg(any(feature = "sht3x", feature = "bme280"))]
+pub type SharedI2c = embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice<
+    'static,
+    embassy_sync::blocking_mutex::raw::NoopRawMutex,
+    esp_hal::i2c::master::I2c<'static, esp_hal::Async>,
+>;
+
+/// Any of the compiled-in sensors.
+pub enum AnySensor {
+    Internal(internal::InternalTemp),
+    Adc(adc::AdcSensor),
+    #[cfg(feature = "sht3x")]
+    Sht3x(sht3x::Sht3x<SharedI2c, embassy_time::Delay>),
+    #[cfg(feature = "bme280")]
+    Bme280(bme280::Bme280<SharedI2c, embassy_time::Delay>),
+    #[cfg(feature = "ds18b20")]
+    Ds18b20(ds18b20::Ds18b20<esp_hal::gpio::Flex<'static>, embassy_time::Delay>),
+}
+
+impl Sensor for AnySensor {
+    fn kind(&self) -> SensorKind {
+        match self {
+            Self::Internal(s) => s.kind(),
+            Self::Adc(s) => s.kind(),
+            #[cfg(feature = "sht3x")]
+            Self::Sht3x(s) => s.kind(),
+            #[cfg(feature = "bme280")]
+            Self::Bme280(s) => s.kind(),
+            #[cfg(feature = "ds18b20")]
+            Self::Ds18b20(s) => s.kind(),
+        }
+    }
+
+    async fn read(&mut self, out: &mut Readings) -> Result<(), SensorError> {
+        match self {
+            Self::Internal(s) => s.read(out).await,
+            Self::Adc(s) => s.read(out).await,
+            #[cfg(feature = "sht3x")]
+            Self::Sht3x(s) => s.read(out).await,
+            #[cfg(feature = "bme280")]
+            Self::Bme280(s) => s.read(out).await,
+            #[cfg(feature = "ds18b20")]
+            Self::Ds18b20(s) => s.read(out).await,
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use core::ptr::addr_of_mut;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::Duration;
+use rtt_target::rprintln;
+
+use crate::boot;
+use crate::buffer::{Sample, SAMPLE_BUF_SIZE};
+use crate::sntp;
+
+// --- Duty Cycle ---
+// With a sleep interval set, each boot is one cycle: wake from deep sleep on
+// the RTC timer, sample, connect, publish and sleep again. Deep sleep powers
+// down everything but the RTC, so what has to outlive a cycle is kept in RTC
+// memory: readings not published yet, the binary frame sequence number and
+// the wall clock. The queue is small; when it overflows, the oldest readings
+// move to the flash buffer.
+
+/// Readings kept in RTC memory.
+pub const QUEUE_LEN: usize = 32;
+
+/// A cycle that has not got its readings out by then sleeps anyway.
+pub const AWAKE_LIMIT: Duration = Duration::from_secs(60);
+
+/// Stay connected this long after publishing, so commands sent while the
+/// device slept (e.g. `set_config`) can be picked up. Each command extends it.
+pub const LISTEN_WINDOW: Duration = Duration::from_secs(3);
+
+/// Marks valid state; anything else in RTC memory is ignored.
+const STATE_MAGIC: u32 = 0x51EE_D0C5;
+
+/// Kept across deep sleep. Plain integers only, so any bit pattern left in
+/// RTC memory is a valid (if meaningless) value.
+#[repr(C)]
+struct State {
+    magic: u32,
+    /// Next binary frame sequence number.
+    seq: u32,
+    /// Unix time (ms) when the device went to sleep, 0 if unknown.
+    unix_ms: u64,
+    /// How long it slept.
+    sleep_ms: u64,
+    head: u32,
+    len: u32,
+    /// Postcard-encoded `Sample`s, oldest at `head`.
+    entries: [[u8; SAMPLE_BUF_SIZE]; QUEUE_LEN],
+}
+
+impl State {
+    const EMPTY: Self = Self {
+        magic: 0,
+        seq: 0,
+        unix_ms: 0,
+        sleep_ms: 0,
+        head: 0,
+        len: 0,
+        entries: [[0; SAMPLE_BUF_SIZE]; QUEUE_LEN],
+    };
+}
+
+#[esp_hal::ram(unstable(rtc_fast, persistent))]
+static mut STATE: State = State::EMPTY;
+
+// SAFETY: only integers and arrays of them, see `State`
+unsafe impl esp_hal::Persistable for State {}
+
+/// The RTC state, reset if it does not hold any yet (e.g. after power-on).
+/// Only used from the main executor, never across an await.
+fn state() -> &'static mut State {
+    let state = unsafe { &mut *addr_of_mut!(STATE) };
+    if state.magic != STATE_MAGIC || state.head as usize >= QUEUE_LEN || state.len as usize > QUEUE_LEN {
+        *state = State { magic: STATE_MAGIC, ..State::EMPTY };
+    }
+    state
+}
+
+/// Called at boot: after deep sleep, sets the wall clock from the time slept.
+/// Drops queued readings stamped with the uptime of an earlier boot (taken
+/// before the clock was ever set), like the flash buffer does after a reboot.
+pub fn wake() {
+    let state = state();
+    // Only right after sleeping; a later reset would restore a stale time
+    if state.unix_ms != 0 {
+        sntp::set_unix_time_ms(state.unix_ms + state.sleep_ms);
+        state.unix_ms = 0;
+    }
+    // Rotate through the queue once, putting back what is kept
+    for _ in 0..state.len {
+        let entry = state.entries[state.head as usize];
+        state.head = (state.head + 1) % QUEUE_LEN as u32;
+        state.len -= 1;
+        if decode(&entry).is_some_and(|sample| sample.epoch) {
+            let slot = (state.head as usize + state.len as usize) % QUEUE_LEN;
+            state.entries[slot] = entry;
+            state.len += 1;
+        }
+    }
+    if state.len > 0 {
+        rprintln!("Sleep: {} readings queued from earlier cycles", state.len);
+    }
+}
+
+fn decode(entry: &[u8; SAMPLE_BUF_SIZE]) -> Option<Sample> {
+    postcard::from_bytes(entry).ok()
+}
+
+/// Number of readings waiting in RTC memory.
+pub fn len() -> usize {
+    state().len as usize
+}
+
+/// Queues a reading. If the queue is full, the oldest one is returned so it
+/// can go to the flash buffer instead.
+pub fn push(sample: &Sample) -> Option<Sample> {
+    let mut entry = [0u8; SAMPLE_BUF_SIZE];
+    postcard::to_slice(sample, &mut entry).ok()?;
+    let evicted = if len() == QUEUE_LEN { pop() } else { None };
+    let state = state();
+    let slot = (state.head as usize + state.len as usize) % QUEUE_LEN;
+    state.entries[slot] = entry;
+    state.len += 1;
+    evicted
+}
+
+/// The oldest queued reading, without removing it. Entries that do not
+/// decode are discarded.
+pub fn peek() -> Option<Sample> {
+    loop {
+        let state = state();
+        if state.len == 0 {
+            return None;
+        }
+        match decode(&state.entries[state.head as usize]) {
+            Some(sample) => return Some(sample),
+            None => {
+                pop();
+            }
+        }
+    }
+}
+
+/// Removes and returns the oldest reading (call after it has been published).
+pub fn pop() -> Option<Sample> {
+    let state = state();
+    if state.len == 0 {
+        return None;
+    }
+    let sample = decode(&state.entries[state.head as usize]);
+    state.head = (state.head + 1) % QUEUE_LEN as u32;
+    state.len -= 1;
+    sample
+}
+
+/// Sequence number for the next binary frame.
+pub fn sequence() -> u32 {
+    state().seq
+}
+
+pub fn set_sequence(seq: u32) {
+    state().seq = seq;
+}
+
+static SLEEP: Signal<CriticalSectionRawMutex, Duration> = Signal::new();
+
+/// Ends the cycle: keeps the clock and asks the watchdog task, which owns the
+/// RTC, to sleep for `interval`. A cycle that gets this far counts as a good
+/// boot.
+pub fn request(interval: Duration) {
+    let state = state();
+    state.unix_ms = sntp::unix_time_ms().unwrap_or(0);
+    state.sleep_ms = interval.as_millis();
+    boot::mark_healthy();
+    rprintln!("Sleeping for {}s ({} readings queued)...", interval.as_secs(), state.len);
+    SLEEP.signal(interval);
+}
+
+/// Requests sleep and waits for it.
+pub async fn enter(interval: Duration) -> ! {
+    request(interval);
+    loop {
+        core::future::pending::<()>().await;
+    }
+}
+
+/// Waits for `request`.
+pub async fn requested() -> Duration {
+    SLEEP.wait().await
+}
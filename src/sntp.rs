@@ -0,0 +1,102 @@
+use core::cell::Cell;
+use critical_section::Mutex;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::Stack;
+use embassy_time::{with_timeout, Duration, Instant};
+use rtt_target::rprintln;
+
+use crate::net::resolve_host;
+
+// Building and checking the packets is hardware-independent (see `firmware-core/`)
+pub use firmware_core::sntp::{build_request, parse_response, SntpError, SNTP_PACKET_LEN};
+
+// --- SNTP Client & Wall Clock ---
+// We keep a single offset between `embassy_time::Instant` (uptime) and Unix
+// time. It is set by SNTP and refreshed periodically to correct drift.
+
+/// How often to resync once the clock is set.
+pub const SNTP_RESYNC_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How long to wait before retrying a failed sync.
+pub const SNTP_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+const SNTP_PORT: u16 = 123;
+const SNTP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Unix time (ms) minus uptime (ms), once synchronised.
+static UNIX_OFFSET_MS: Mutex<Cell<Option<u64>>> = Mutex::new(Cell::new(None));
+
+/// Current Unix time in milliseconds, or `None` before the first sync.
+pub fn unix_time_ms() -> Option<u64> {
+    to_unix_ms(Instant::now().as_millis())
+}
+
+/// Converts an uptime timestamp (ms) to Unix time (ms), if the clock is set.
+pub fn to_unix_ms(uptime_ms: u64) -> Option<u64> {
+    critical_section::with(|cs| UNIX_OFFSET_MS.borrow(cs).get()).map(|offset| offset + uptime_ms)
+}
+
+/// Sets the clock without SNTP, e.g. from the time slept in deep sleep.
+/// The next sync corrects it.
+pub fn set_unix_time_ms(unix_ms: u64) {
+    let offset = unix_ms.saturating_sub(Instant::now().as_millis());
+    critical_section::with(|cs| UNIX_OFFSET_MS.borrow(cs).set(Some(offset)));
+}
+
+/// Queries `server` once and updates the wall-clock offset.
+/// Half of the round trip is added to account for the reply's travel time.
+pub async fn sync(stack: Stack<'_>, server: &str) -> Result<u64, ()> {
+    let server_ip = resolve_host(stack, server).await?;
+
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0u8; 128];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0u8; 64];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    socket.bind(0).map_err(|_| ())?;
+
+    // Any value works as long as the reply echoes it; uptime never repeats
+    let nonce = Instant::now().as_ticks().max(1).to_be_bytes();
+    let mut packet = [0u8; SNTP_PACKET_LEN];
+    build_request(&mut packet, nonce);
+
+    let sent_at = Instant::now();
+    socket.send_to(&packet, (server_ip, SNTP_PORT)).await.map_err(|_| ())?;
+
+    let mut reply = [0u8; 128];
+    loop {
+        let (n, meta) = match with_timeout(SNTP_TIMEOUT, socket.recv_from(&mut reply)).await {
+            Ok(Ok(res)) => res,
+            Ok(Err(_)) => return Err(()),
+            Err(_) => {
+                rprintln!("SNTP: no reply from {}", server);
+                return Err(());
+            }
+        };
+        if meta.endpoint.addr != server_ip {
+            continue;
+        }
+
+        let received_at = Instant::now();
+        let server_ms = match parse_response(&reply[..n], nonce) {
+            Ok(ms) => ms,
+            // A late reply to an earlier request, or not meant for us
+            Err(SntpError::WrongOriginate) => continue,
+            Err(e) => {
+                rprintln!("SNTP: invalid reply from {}: {:?}", server, e);
+                return Err(());
+            }
+        };
+
+        let rtt_ms = (received_at - sent_at).as_millis();
+        let unix_ms = server_ms + rtt_ms / 2;
+        let offset = unix_ms - received_at.as_millis();
+
+        let previous = critical_section::with(|cs| UNIX_OFFSET_MS.borrow(cs).replace(Some(offset)));
+        match previous {
+            Some(prev) => rprintln!("SNTP: resynced, drift {} ms (rtt {} ms)", offset as i64 - prev as i64, rtt_ms),
+            None => rprintln!("SNTP: clock set to {} ms (rtt {} ms)", unix_ms, rtt_ms),
+        }
+        return Ok(unix_ms);
+    }
+}
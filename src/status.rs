@@ -0,0 +1,188 @@
+use core::cell::{Cell, RefCell};
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use critical_section::Mutex;
+use embassy_net::{Ipv4Address, Ipv4Cidr, Ipv6Cidr};
+use embassy_time::Instant;
+use heapless::Vec;
+use rtt_target::rprintln;
+
+// --- Shared Device Status ---
+// A small global snapshot that any task can update or read without owning the
+// network stack. Access goes through a critical section, so keep updates short.
+
+/// Where the DHCP client currently is, as seen from the application.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DhcpState {
+    /// Wi-Fi is not associated yet.
+    LinkDown,
+    /// Link is up, waiting for a lease.
+    Discovering,
+    /// A lease has been acquired and the interface is configured.
+    Bound,
+    /// No lease arrived within the timeout; Wi-Fi will re-associate.
+    TimedOut,
+}
+
+/// Details of the current DHCPv4 lease.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LeaseInfo {
+    pub address: Ipv4Cidr,
+    pub gateway: Option<Ipv4Address>,
+    pub dns_servers: Vec<Ipv4Address, 3>,
+    /// Cap on the lease time: leases are renewed at least this often. The
+    /// time the server granted is not exposed by embassy-net.
+    pub lease_cap_secs: u32,
+    /// Uptime (in seconds) at which the lease was acquired.
+    pub acquired_at_secs: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct NetStatus {
+    pub dhcp: DhcpState,
+    pub lease: Option<LeaseInfo>,
+    /// Global IPv6 address from SLAAC, if IPv6 is enabled and a router answered.
+    pub ipv6: Option<Ipv6Cidr>,
+    /// Number of DHCP timeouts since boot.
+    pub dhcp_timeouts: u32,
+}
+
+static NET_STATUS: Mutex<RefCell<NetStatus>> = Mutex::new(RefCell::new(NetStatus {
+    dhcp: DhcpState::LinkDown,
+    lease: None,
+    ipv6: None,
+    dhcp_timeouts: 0,
+}));
+
+/// Returns a copy of the current network status.
+pub fn net_status() -> NetStatus {
+    critical_section::with(|cs| NET_STATUS.borrow_ref(cs).clone())
+}
+
+/// Updates the DHCP state and logs the transition if it changed.
+pub fn set_dhcp_state(state: DhcpState) {
+    let previous = critical_section::with(|cs| {
+        let mut status = NET_STATUS.borrow_ref_mut(cs);
+        let previous = status.dhcp;
+        status.dhcp = state;
+        if state == DhcpState::TimedOut {
+            status.dhcp_timeouts += 1;
+        }
+        if state != DhcpState::Bound {
+            status.lease = None;
+        }
+        previous
+    });
+
+    if previous != state {
+        rprintln!("DHCP: {:?} -> {:?} (t={}s)", previous, state, Instant::now().as_secs());
+    }
+}
+
+/// Records a freshly acquired lease and marks DHCP as bound.
+pub fn set_lease(lease: LeaseInfo) {
+    critical_section::with(|cs| {
+        NET_STATUS.borrow_ref_mut(cs).lease = Some(lease);
+    });
+    set_dhcp_state(DhcpState::Bound);
+}
+
+/// Records the SLAAC address (or clears it).
+pub fn set_ipv6(address: Option<Ipv6Cidr>) {
+    critical_section::with(|cs| {
+        NET_STATUS.borrow_ref_mut(cs).ipv6 = address;
+    });
+}
+
+static WIFI_CONNECTED: AtomicBool = AtomicBool::new(false);
+
+/// Marks whether Wi-Fi is associated (and the radio therefore powered).
+pub fn set_wifi_connected(connected: bool) {
+    WIFI_CONNECTED.store(connected, Ordering::Relaxed);
+}
+
+pub fn wifi_connected() -> bool {
+    WIFI_CONNECTED.load(Ordering::Relaxed)
+}
+
+static MQTT_CONNECTED: AtomicBool = AtomicBool::new(false);
+
+/// Marks whether the MQTT session is currently usable for publishing.
+pub fn set_mqtt_connected(connected: bool) {
+    MQTT_CONNECTED.store(connected, Ordering::Relaxed);
+}
+
+pub fn mqtt_connected() -> bool {
+    MQTT_CONNECTED.load(Ordering::Relaxed)
+}
+
+static WIFI_CHANNEL: AtomicU8 = AtomicU8::new(0);
+
+/// Records the channel of the associated access point (0 = unknown).
+pub fn set_wifi_channel(channel: u8) {
+    WIFI_CHANNEL.store(channel, Ordering::Relaxed);
+}
+
+pub fn wifi_channel() -> Option<u8> {
+    match WIFI_CHANNEL.load(Ordering::Relaxed) {
+        0 => None,
+        channel => Some(channel),
+    }
+}
+
+/// Event counters since boot, reported in the health report.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Counters {
+    /// Wi-Fi re-associations after the first connect.
+    pub wifi_reconnects: u32,
+    /// MQTT sessions that were lost and had to be re-established.
+    pub mqtt_reconnects: u32,
+    /// Publishes that failed on the socket.
+    pub publish_failures: u32,
+}
+
+static COUNTERS: Mutex<Cell<Counters>> = Mutex::new(Cell::new(Counters {
+    wifi_reconnects: 0,
+    mqtt_reconnects: 0,
+    publish_failures: 0,
+}));
+
+pub fn counters() -> Counters {
+    critical_section::with(|cs| COUNTERS.borrow(cs).get())
+}
+
+/// Updates the counters, e.g. `status::count(|c| c.publish_failures += 1)`.
+pub fn count(update: impl FnOnce(&mut Counters)) {
+    critical_section::with(|cs| {
+        let counters = COUNTERS.borrow(cs);
+        let mut value = counters.get();
+        update(&mut value);
+        counters.set(value);
+    });
+}
+
+/// How long the last connects took, reported in the health report so the
+/// Wi-Fi power settings can be weighed against responsiveness.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Latency {
+    /// Association with the access point, retries included (0 = not yet).
+    pub wifi_connect_ms: u32,
+    /// From losing the broker (or from boot) until the MQTT session is back,
+    /// including Wi-Fi, DHCP and DNS (0 = not yet).
+    pub reconnect_ms: u32,
+}
+
+static LATENCY: Mutex<Cell<Latency>> = Mutex::new(Cell::new(Latency { wifi_connect_ms: 0, reconnect_ms: 0 }));
+
+pub fn latency() -> Latency {
+    critical_section::with(|cs| LATENCY.borrow(cs).get())
+}
+
+/// Updates the latencies, e.g. `status::record_latency(|l| l.reconnect_ms = ms)`.
+pub fn record_latency(update: impl FnOnce(&mut Latency)) {
+    critical_section::with(|cs| {
+        let latency = LATENCY.borrow(cs);
+        let mut value = latency.get();
+        update(&mut value);
+        latency.set(value);
+    });
+}
@@ -0,0 +1,176 @@
+use core::cell::Cell;
+use core::future::Future;
+use core::pin::pin;
+use core::ptr::addr_of_mut;
+use critical_section::Mutex;
+use embassy_futures::select::{select, Either};
+use embassy_time::{Duration, Instant, Timer};
+use esp_hal::peripherals::TIMG0;
+use esp_hal::rtc_cntl::sleep::TimerWakeupSource;
+use esp_hal::rtc_cntl::{Rtc, RwdtStage};
+use esp_hal::time::Duration as HalDuration;
+use esp_hal::timer::timg::{MwdtStage, Wdt};
+use rtt_target::rprintln;
+
+// --- Watchdogs ---
+// The hardware watchdogs only reset the device when nothing feeds them, which
+// a wedged task does not prevent: the executor keeps running and the feeding
+// task along with it. So tasks register here and check in regularly, and the
+// watchdogs are only fed while every registered task has checked in within
+// its deadline. A task that misses it is recorded in RTC memory, which
+// survives the reset, so the next boot can report it.
+
+/// How often the watchdog task checks the registry and feeds the watchdogs.
+pub const FEED_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Timer group watchdog: resets the system once feeding stops.
+const MWDT_TIMEOUT_SECS: u64 = 10;
+
+/// RTC watchdog, the backstop if the timer group watchdog does not fire.
+const RWDT_TIMEOUT_SECS: u64 = 20;
+
+/// Upper half of the RTC record, so garbage is not mistaken for a task.
+const RECORD_MAGIC: u32 = 0x5744_0000;
+
+/// Tasks watched by the watchdog.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Task {
+    /// The network stack runner.
+    Net,
+    /// The main loop: Wi-Fi, DHCP, MQTT connection and publishing.
+    Mqtt,
+    /// Sensor sampling.
+    Sampler,
+    /// The serial console.
+    Console,
+}
+
+const TASK_COUNT: usize = 4;
+
+impl Task {
+    const ALL: [Task; TASK_COUNT] = [Task::Net, Task::Mqtt, Task::Sampler, Task::Console];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Task::Net => "net",
+            Task::Mqtt => "mqtt",
+            Task::Sampler => "sampler",
+            Task::Console => "console",
+        }
+    }
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+/// Deadline and last check-in of a registered task.
+#[derive(Clone, Copy)]
+struct Entry {
+    deadline: Duration,
+    last_seen: Instant,
+}
+
+static REGISTRY: Mutex<Cell<[Option<Entry>; TASK_COUNT]>> = Mutex::new(Cell::new([None; TASK_COUNT]));
+
+/// Starts watching `task`, or changes its deadline. Counts as a check-in.
+pub fn register(task: Task, deadline: Duration) {
+    critical_section::with(|cs| {
+        let cell = REGISTRY.borrow(cs);
+        let mut entries = cell.get();
+        entries[task.index()] = Some(Entry { deadline, last_seen: Instant::now() });
+        cell.set(entries);
+    });
+}
+
+/// Records that `task` is making progress.
+pub fn check_in(task: Task) {
+    critical_section::with(|cs| {
+        let cell = REGISTRY.borrow(cs);
+        let mut entries = cell.get();
+        if let Some(entry) = &mut entries[task.index()] {
+            entry.last_seen = Instant::now();
+        }
+        cell.set(entries);
+    });
+}
+
+/// The first registered task that missed its deadline, if any.
+pub fn starved(now: Instant) -> Option<Task> {
+    let entries = critical_section::with(|cs| REGISTRY.borrow(cs).get());
+    Task::ALL.into_iter().find(|task| {
+        entries[task.index()].is_some_and(|e| now.saturating_duration_since(e.last_seen) > e.deadline)
+    })
+}
+
+/// Runs `fut` while checking in for `task`, for long operations that have
+/// their own timeouts (e.g. an OTA download).
+pub async fn keep_alive<F: Future>(task: Task, fut: F) -> F::Output {
+    let mut fut = pin!(fut);
+    loop {
+        check_in(task);
+        if let Either::First(output) = select(fut.as_mut(), Timer::after(FEED_INTERVAL)).await {
+            return output;
+        }
+    }
+}
+
+// --- Starvation Record ---
+
+#[esp_hal::ram(unstable(rtc_fast, persistent))]
+static mut STARVED_TASK: u32 = 0;
+
+fn write_record(value: u32) {
+    // Only touched by the watchdog task and once at boot
+    unsafe { addr_of_mut!(STARVED_TASK).write_volatile(value) };
+}
+
+/// The task that starved before the last reset, if that is why we reset.
+/// Clears the record, so it is reported once.
+pub fn take_starved_record() -> Option<Task> {
+    let record = unsafe { addr_of_mut!(STARVED_TASK).read_volatile() };
+    write_record(0);
+    if record & 0xFFFF_0000 != RECORD_MAGIC {
+        return None;
+    }
+    Task::ALL.get((record & 0xFFFF) as usize).copied()
+}
+
+// --- Hardware ---
+
+pub struct Watchdogs {
+    rtc: Rtc<'static>,
+    mwdt: Wdt<TIMG0<'static>>,
+}
+
+impl Watchdogs {
+    /// Arms both watchdogs. From here on they must be fed.
+    pub fn arm(mut rtc: Rtc<'static>, mut mwdt: Wdt<TIMG0<'static>>) -> Self {
+        mwdt.set_timeout(MwdtStage::Stage0, HalDuration::from_secs(MWDT_TIMEOUT_SECS));
+        mwdt.enable();
+        rtc.rwdt.set_timeout(RwdtStage::Stage0, HalDuration::from_secs(RWDT_TIMEOUT_SECS));
+        rtc.rwdt.enable();
+        rprintln!("Watchdogs armed ({}s / {}s)", MWDT_TIMEOUT_SECS, RWDT_TIMEOUT_SECS);
+        Self { rtc, mwdt }
+    }
+
+    pub fn feed(&mut self) {
+        self.mwdt.feed();
+        self.rtc.rwdt.feed();
+    }
+
+    /// Stops feeding for good: records `task` and waits for the reset.
+    pub fn starve(&mut self, task: Task) {
+        rprintln!("Watchdog: task '{}' missed its deadline, resetting...", task.name());
+        write_record(RECORD_MAGIC | task.index() as u32);
+    }
+
+    /// Disarms both watchdogs, which would otherwise reset the chip while it
+    /// sleeps, and sleeps until the RTC timer wakes it into a fresh boot.
+    pub fn sleep_deep(&mut self, duration: Duration) -> ! {
+        self.mwdt.disable();
+        self.rtc.rwdt.disable();
+        let timer = TimerWakeupSource::new(core::time::Duration::from_millis(duration.as_millis()));
+        self.rtc.sleep_deep(&[&timer])
+    }
+}
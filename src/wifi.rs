@@ -0,0 +1,68 @@
+use esp_radio::wifi::{Config, CountryInfo, PowerSaveMode, WifiController};
+use rtt_target::rprintln;
+
+use crate::config::AppConfig;
+
+// The radio settings and their ranges are hardware-independent (see `firmware-core/`)
+pub use firmware_core::wifi::{
+    is_valid_country, is_valid_listen_interval, is_valid_tx_power, PowerSave, MAX_LISTEN_INTERVAL, MAX_TX_POWER_DBM,
+    MIN_TX_POWER_DBM,
+};
+
+// --- Driver ---
+
+fn driver_mode(power_save: PowerSave) -> PowerSaveMode {
+    match power_save {
+        PowerSave::None => PowerSaveMode::None,
+        PowerSave::Min => PowerSaveMode::Minimum,
+        PowerSave::Max => PowerSaveMode::Maximum,
+    }
+}
+
+/// Driver configuration for `esp_radio::wifi::new`.
+pub fn driver_config(config: &AppConfig) -> Config {
+    let country = match config.wifi_country.as_bytes() {
+        &[a, b] if is_valid_country(&config.wifi_country) => [a, b],
+        _ => {
+            rprintln!("Invalid Wi-Fi country '{}', using 01", config.wifi_country);
+            *b"01"
+        }
+    };
+    Config::default().with_power_save_mode(driver_mode(config.wifi_power_save)).with_country_code(CountryInfo::from(country))
+}
+
+/// The settings `set_wifi` can change while running.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RadioSettings {
+    pub power_save: PowerSave,
+    pub tx_power_dbm: u8,
+}
+
+impl RadioSettings {
+    pub fn from_config(config: &AppConfig) -> Self {
+        Self { power_save: config.wifi_power_save, tx_power_dbm: config.wifi_tx_power_dbm }
+    }
+
+    /// Applies both settings. Only works once the driver has started.
+    pub fn apply(&self, wifi: &mut WifiController<'static>) -> Result<(), ()> {
+        if let Err(e) = wifi.set_power_saving(driver_mode(self.power_save)) {
+            rprintln!("Wi-Fi: setting power save '{}' failed: {:?}", self.power_save.name(), e);
+            return Err(());
+        }
+        set_max_tx_power(self.tx_power_dbm)?;
+        rprintln!("Wi-Fi: power save '{}', max TX power {} dBm", self.power_save.name(), self.tx_power_dbm);
+        Ok(())
+    }
+}
+
+/// Caps the TX power. esp-radio does not expose this, so it goes to the
+/// driver directly (in units of 0.25 dBm).
+fn set_max_tx_power(dbm: u8) -> Result<(), ()> {
+    let dbm = dbm.clamp(MIN_TX_POWER_DBM, MAX_TX_POWER_DBM);
+    let err = unsafe { esp_wifi_sys::include::esp_wifi_set_max_tx_power(dbm as i8 * 4) };
+    if err != 0 {
+        rprintln!("Wi-Fi: setting TX power {} dBm failed: {}", dbm, err);
+        return Err(());
+    }
+    Ok(())
+}
@@ -0,0 +1,153 @@
+#![no_std]
+
+use heapless::Vec;
+use serde::{Serialize, Deserialize};
+
+// --- Binary Telemetry Frame ---
+// Layout on the wire: one schema version byte, followed by a postcard-encoded
+// `TelemetryFrame`. Only ever append new `Quantity` variants or bump the
+// version; postcard encodes enum variants by index and structs by position.
+
+/// Version of the frame layout below.
+pub const SCHEMA_VERSION: u8 = 1;
+
+/// Maximum number of fields in one frame.
+pub const MAX_FIELDS: usize = 8;
+
+/// Largest encoded frame (version byte + worst-case postcard size).
+pub const MAX_FRAME_LEN: usize = 1 + 5 + 11 + 1 + MAX_FIELDS * 6;
+
+/// What a field measures. Units are fixed per quantity.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Quantity {
+    /// Degrees Celsius.
+    Temperature,
+    /// Percent relative humidity.
+    Humidity,
+    /// Hectopascal.
+    Pressure,
+    /// Volts.
+    Voltage,
+    /// dBm.
+    Rssi,
+    /// Application-defined channel, identified by number.
+    Other(u8),
+}
+
+impl Quantity {
+    /// Name used for the Influx field / JSON key.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Quantity::Temperature => "temperature",
+            Quantity::Humidity => "humidity",
+            Quantity::Pressure => "pressure",
+            Quantity::Voltage => "voltage",
+            Quantity::Rssi => "rssi",
+            Quantity::Other(_) => "other",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Field {
+    pub quantity: Quantity,
+    pub value: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TelemetryFrame {
+    /// Per-device sequence number, to spot gaps and duplicates.
+    pub seq: u32,
+    /// Unix time in milliseconds, `None` if the device clock was not set.
+    pub timestamp_ms: Option<u64>,
+    pub fields: Vec<Field, MAX_FIELDS>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DecodeError {
+    Empty,
+    /// The frame was written with a schema version this decoder does not know.
+    UnsupportedVersion(u8),
+    Malformed(postcard::Error),
+}
+
+/// Writes `frame` into `buf` and returns the used part.
+pub fn encode<'a>(frame: &TelemetryFrame, buf: &'a mut [u8]) -> Result<&'a mut [u8], postcard::Error> {
+    let (version, body) = buf.split_first_mut().ok_or(postcard::Error::SerializeBufferFull)?;
+    *version = SCHEMA_VERSION;
+    let len = postcard::to_slice(frame, body)?.len();
+    Ok(&mut buf[..1 + len])
+}
+
+/// Parses a frame, checking the schema version first.
+pub fn decode(bytes: &[u8]) -> Result<TelemetryFrame, DecodeError> {
+    let (version, body) = bytes.split_first().ok_or(DecodeError::Empty)?;
+    match *version {
+        SCHEMA_VERSION => postcard::from_bytes(body).map_err(DecodeError::Malformed),
+        other => Err(DecodeError::UnsupportedVersion(other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(timestamp_ms: Option<u64>, fields: &[Field]) -> TelemetryFrame {
+        TelemetryFrame { seq: 42, timestamp_ms, fields: Vec::from_slice(fields).unwrap() }
+    }
+
+    fn round_trip(frame: &TelemetryFrame) -> TelemetryFrame {
+        let mut buf = [0u8; MAX_FRAME_LEN];
+        let bytes = encode(frame, &mut buf).unwrap();
+        assert_eq!(bytes[0], SCHEMA_VERSION);
+        decode(bytes).unwrap()
+    }
+
+    #[test]
+    fn round_trips() {
+        let fields = [
+            Field { quantity: Quantity::Temperature, value: 23.4 },
+            Field { quantity: Quantity::Humidity, value: 48.5 },
+            Field { quantity: Quantity::Other(200), value: -1.0e-3 },
+        ];
+        for timestamp_ms in [None, Some(0), Some(1_767_225_600_000)] {
+            let frame = frame(timestamp_ms, &fields);
+            assert_eq!(round_trip(&frame), frame);
+        }
+    }
+
+    #[test]
+    fn worst_case_fits_max_frame_len() {
+        let fields = [Field { quantity: Quantity::Other(u8::MAX), value: f32::MIN }; MAX_FIELDS];
+        let frame = TelemetryFrame { seq: u32::MAX, timestamp_ms: Some(u64::MAX), fields: Vec::from_slice(&fields).unwrap() };
+        assert_eq!(round_trip(&frame), frame);
+    }
+
+    #[test]
+    fn non_finite_values_survive() {
+        let frame = frame(None, &[Field { quantity: Quantity::Pressure, value: f32::INFINITY }]);
+        assert_eq!(round_trip(&frame), frame);
+
+        let nan = round_trip(&self::frame(None, &[Field { quantity: Quantity::Voltage, value: f32::NAN }]));
+        assert!(nan.fields[0].value.is_nan());
+    }
+
+    #[test]
+    fn rejects_bad_frames() {
+        assert_eq!(decode(&[]), Err(DecodeError::Empty));
+        assert_eq!(decode(&[SCHEMA_VERSION + 1, 0, 0, 0]), Err(DecodeError::UnsupportedVersion(SCHEMA_VERSION + 1)));
+
+        let mut buf = [0u8; MAX_FRAME_LEN];
+        let bytes = encode(&frame(Some(5), &[Field { quantity: Quantity::Rssi, value: -61.0 }]), &mut buf).unwrap();
+        let len = bytes.len();
+        assert!(matches!(decode(&buf[..len - 1]), Err(DecodeError::Malformed(_))));
+    }
+
+    #[test]
+    fn small_buffer() {
+        let mut buf = [0u8; 4];
+        let frame = frame(Some(5), &[Field { quantity: Quantity::Rssi, value: -61.0 }]);
+        assert!(encode(&frame, &mut buf).is_err());
+        assert!(encode(&frame, &mut []).is_err());
+    }
+}
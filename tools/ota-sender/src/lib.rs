@@ -0,0 +1,158 @@
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+pub mod mqtt;
+
+// --- Chunked OTA Protocol ---
+// Mirrors `firmware-core/src/ota/chunk.rs`: `ota_begin` announces the image,
+// chunks go to `devices/<id>/ota/chunk` as a 4-byte big-endian chunk number
+// plus data, and the device answers every chunk on `devices/<id>/ota/ack`
+// with the number of the chunk it expects next.
+
+/// Largest chunk the firmware accepts.
+pub const MAX_CHUNK_SIZE: usize = 1024;
+
+pub fn chunk_topic(device_id: &str) -> String {
+    format!("devices/{}/ota/chunk", device_id)
+}
+
+pub fn ack_topic(device_id: &str) -> String {
+    format!("devices/{}/ota/ack", device_id)
+}
+
+pub fn begin_topic(device_id: &str) -> String {
+    format!("devices/{}/cmd/ota_begin", device_id)
+}
+
+pub fn begin_response_topic(device_id: &str) -> String {
+    format!("devices/{}/resp/ota_begin", device_id)
+}
+
+pub fn sha256_hex(image: &[u8]) -> String {
+    Sha256::digest(image).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn chunk_count(image_len: usize, chunk_size: usize) -> u32 {
+    image_len.div_ceil(chunk_size) as u32
+}
+
+/// Chunk `index` as published: chunk number followed by its part of the image.
+pub fn chunk_payload(image: &[u8], chunk_size: usize, index: u32) -> Vec<u8> {
+    let start = (index as usize * chunk_size).min(image.len());
+    let end = (start + chunk_size).min(image.len());
+    let mut payload = index.to_be_bytes().to_vec();
+    payload.extend_from_slice(&image[start..end]);
+    payload
+}
+
+pub fn begin_payload(request_id: &str, size: usize, sha256: &str, signature: &str, chunk_size: usize) -> String {
+    json!({
+        "id": request_id,
+        "size": size,
+        "sha256": sha256,
+        "signature": signature,
+        "chunk_size": chunk_size,
+    })
+    .to_string()
+}
+
+/// What the device said about the transfer.
+#[derive(Debug, PartialEq)]
+pub enum Reply {
+    /// Send this chunk next.
+    Next(u32),
+    /// The image was verified and the device is restarting into it.
+    Installed,
+    /// The device gave up; the error code from the firmware.
+    Error(String),
+}
+
+/// Parses an acknowledgement: `{"next":12}`, `{"next":42,"state":"installed"}`
+/// or `{"next":0,"error":".."}`.
+pub fn parse_ack(payload: &[u8]) -> Option<Reply> {
+    let ack: Value = serde_json::from_slice(payload).ok()?;
+    if let Some(error) = ack.get("error").and_then(Value::as_str) {
+        return Some(Reply::Error(error.to_string()));
+    }
+    if ack.get("state").and_then(Value::as_str) == Some("installed") {
+        return Some(Reply::Installed);
+    }
+    Some(Reply::Next(u32::try_from(ack.get("next")?.as_u64()?).ok()?))
+}
+
+/// Parses the response to `ota_begin`; `None` if it belongs to another request.
+pub fn parse_begin_response(payload: &[u8], request_id: &str) -> Option<Reply> {
+    let response: Value = serde_json::from_slice(payload).ok()?;
+    if response.get("id").and_then(Value::as_str) != Some(request_id) {
+        return None;
+    }
+    if response.get("ok").and_then(Value::as_bool) != Some(true) {
+        let error = response.get("error").and_then(Value::as_str).unwrap_or("unknown");
+        return Some(Reply::Error(error.to_string()));
+    }
+    let next = response.get("result")?.get("next")?.as_u64()?;
+    Some(Reply::Next(u32::try_from(next).ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn topics() {
+        assert_eq!(chunk_topic("esp32"), "devices/esp32/ota/chunk");
+        assert_eq!(ack_topic("esp32"), "devices/esp32/ota/ack");
+        assert_eq!(begin_topic("esp32"), "devices/esp32/cmd/ota_begin");
+        assert_eq!(begin_response_topic("esp32"), "devices/esp32/resp/ota_begin");
+    }
+
+    #[test]
+    fn sha256_as_hex() {
+        assert_eq!(sha256_hex(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    }
+
+    #[test]
+    fn splits_the_image_into_chunks() {
+        let image: Vec<u8> = (0..10).collect();
+        assert_eq!(chunk_count(image.len(), 4), 3);
+        assert_eq!(chunk_count(8, 4), 2);
+        assert_eq!(chunk_payload(&image, 4, 0), [0, 0, 0, 0, 0, 1, 2, 3]);
+        assert_eq!(chunk_payload(&image, 4, 1), [0, 0, 0, 1, 4, 5, 6, 7]);
+        // The last chunk is short
+        assert_eq!(chunk_payload(&image, 4, 2), [0, 0, 0, 2, 8, 9]);
+        // Past the end only the chunk number is left
+        assert_eq!(chunk_payload(&image, 4, 3), [0, 0, 0, 3]);
+        assert_eq!(chunk_payload(&image, 4, 0x0102_0304)[..4], [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn begin_payload_fields() {
+        let payload: Value = serde_json::from_str(&begin_payload("7-1", 2500, "9f86", "4c1e", 512)).unwrap();
+        assert_eq!(payload, json!({"id": "7-1", "size": 2500, "sha256": "9f86", "signature": "4c1e", "chunk_size": 512}));
+    }
+
+    #[test]
+    fn parses_acks() {
+        assert_eq!(parse_ack(br#"{"next":12}"#), Some(Reply::Next(12)));
+        assert_eq!(parse_ack(br#"{"next":3,"state":"installed"}"#), Some(Reply::Installed));
+        assert_eq!(parse_ack(br#"{"next":1,"error":"size_mismatch"}"#), Some(Reply::Error("size_mismatch".into())));
+        assert_eq!(parse_ack(br#"{"next":-1}"#), None);
+        assert_eq!(parse_ack(br#"{"next":4294967296}"#), None);
+        assert_eq!(parse_ack(b"{}"), None);
+        assert_eq!(parse_ack(b"not json"), None);
+    }
+
+    #[test]
+    fn parses_begin_responses() {
+        let resume = br#"{"id":"7-1","ok":true,"result":{"state":"receiving","next":42,"chunk_size":1024}}"#;
+        assert_eq!(parse_begin_response(resume, "7-1"), Some(Reply::Next(42)));
+        // An answer to an earlier attempt
+        assert_eq!(parse_begin_response(resume, "7-2"), None);
+
+        let refused = br#"{"id":"7-1","ok":false,"error":"ota_busy"}"#;
+        assert_eq!(parse_begin_response(refused, "7-1"), Some(Reply::Error("ota_busy".into())));
+        let no_code = br#"{"id":"7-1","ok":false}"#;
+        assert_eq!(parse_begin_response(no_code, "7-1"), Some(Reply::Error("unknown".into())));
+        assert_eq!(parse_begin_response(br#"{"id":"7-1","ok":true}"#, "7-1"), None);
+    }
+}
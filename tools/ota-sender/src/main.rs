@@ -0,0 +1,190 @@
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+use ota_sender::mqtt::Client;
+use ota_sender::*;
+
+// Sends a signed firmware image to a device over MQTT, e.g.:
+//
+//   ota-sender --host 127.0.0.1 --device esp32 --image app.bin --signature <hex>
+//
+// The signature is the Ed25519 signature over the image's SHA-256 digest
+// (see docs/DEPLOY.md). Lost acknowledgements and broker disconnects are
+// survived by asking the device where it is (`ota_begin` again) and resuming.
+
+/// How long to wait for an acknowledgement before asking the device again.
+const ACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Give up after this many attempts in a row without progress.
+const MAX_RETRIES: u32 = 10;
+
+struct Args {
+    host: String,
+    port: u16,
+    device: String,
+    image: String,
+    signature: String,
+    chunk_size: usize,
+}
+
+const USAGE: &str = "usage: ota-sender --host <broker> [--port 1883] --device <device_id> --image <app.bin> --signature <hex> [--chunk-size 1024]";
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        host: String::new(),
+        port: 1883,
+        device: String::new(),
+        image: String::new(),
+        signature: String::new(),
+        chunk_size: MAX_CHUNK_SIZE,
+    };
+    let mut iter = std::env::args().skip(1);
+    while let Some(flag) = iter.next() {
+        let value = iter.next().ok_or_else(|| format!("missing value for {}", flag))?;
+        match flag.as_str() {
+            "--host" => args.host = value,
+            "--port" => args.port = value.parse().map_err(|_| "invalid --port")?,
+            "--device" => args.device = value,
+            "--image" => args.image = value,
+            "--signature" => args.signature = value.trim().to_string(),
+            "--chunk-size" => args.chunk_size = value.parse().map_err(|_| "invalid --chunk-size")?,
+            _ => return Err(format!("unknown option {}", flag)),
+        }
+    }
+    if args.host.is_empty() || args.device.is_empty() || args.image.is_empty() || args.signature.is_empty() {
+        return Err("--host, --device, --image and --signature are required".to_string());
+    }
+    if args.chunk_size == 0 || args.chunk_size > MAX_CHUNK_SIZE {
+        return Err(format!("--chunk-size must be 1..={}", MAX_CHUNK_SIZE));
+    }
+    Ok(args)
+}
+
+/// Connects and subscribes to the device's answers.
+fn connect(args: &Args) -> std::io::Result<Client> {
+    let mut client = Client::connect(&format!("{}:{}", args.host, args.port), &format!("ota-sender-{}", std::process::id()))?;
+    client.subscribe(&ack_topic(&args.device), 1)?;
+    client.subscribe(&begin_response_topic(&args.device), 2)?;
+    Ok(client)
+}
+
+/// Announces the image and returns where the device wants to continue.
+fn begin(client: &mut Client, args: &Args, image: &[u8], sha256: &str, attempt: u32) -> std::io::Result<Option<Reply>> {
+    let request_id = format!("{}-{}", std::process::id(), attempt);
+    let payload = begin_payload(&request_id, image.len(), sha256, &args.signature, args.chunk_size);
+    client.publish(&begin_topic(&args.device), payload.as_bytes())?;
+
+    let response_topic = begin_response_topic(&args.device);
+    let deadline = Instant::now() + ACK_TIMEOUT;
+    while let Some(message) = client.next_message(deadline.saturating_duration_since(Instant::now()))? {
+        if message.topic == response_topic
+            && let Some(reply) = parse_begin_response(&message.payload, &request_id)
+        {
+            return Ok(Some(reply));
+        }
+    }
+    Ok(None)
+}
+
+/// Sends chunks from `next` on. Returns the device's last reply, or `None`
+/// if an acknowledgement did not arrive in time.
+fn send_chunks(client: &mut Client, args: &Args, image: &[u8], mut next: u32) -> std::io::Result<Option<Reply>> {
+    let count = chunk_count(image.len(), args.chunk_size);
+    let ack_topic = ack_topic(&args.device);
+    let chunk_topic = chunk_topic(&args.device);
+    let mut last_report = Instant::now();
+
+    loop {
+        client.publish(&chunk_topic, &chunk_payload(image, args.chunk_size, next))?;
+
+        let deadline = Instant::now() + ACK_TIMEOUT;
+        let reply = loop {
+            match client.next_message(deadline.saturating_duration_since(Instant::now()))? {
+                Some(message) if message.topic == ack_topic => {
+                    if let Some(reply) = parse_ack(&message.payload) {
+                        break reply;
+                    }
+                }
+                Some(_) => {}
+                None => return Ok(None),
+            }
+        };
+        match reply {
+            Reply::Next(n) => next = n,
+            other => return Ok(Some(other)),
+        }
+        if last_report.elapsed() > Duration::from_secs(2) || next >= count {
+            eprintln!("{}/{} chunks", next.min(count), count);
+            last_report = Instant::now();
+        }
+    }
+}
+
+/// One attempt: (re)connects if needed, asks the device where to continue
+/// and sends the remaining chunks. `resumed_at` is the furthest chunk the
+/// device has asked for so far.
+fn attempt(client: &mut Option<Client>, args: &Args, image: &[u8], sha256: &str, attempt: u32, resumed_at: &mut u32) -> std::io::Result<Option<Reply>> {
+    let client = match client {
+        Some(client) => client,
+        None => client.insert(connect(args)?),
+    };
+    match begin(client, args, image, sha256, attempt)? {
+        Some(Reply::Next(next)) => {
+            eprintln!("device expects chunk {}", next);
+            *resumed_at = (*resumed_at).max(next);
+            send_chunks(client, args, image, next)
+        }
+        reply => Ok(reply),
+    }
+}
+
+fn run(args: &Args) -> Result<(), String> {
+    let image = std::fs::read(&args.image).map_err(|e| format!("cannot read {}: {}", args.image, e))?;
+    if image.is_empty() {
+        return Err(format!("{} is empty", args.image));
+    }
+    let sha256 = sha256_hex(&image);
+    eprintln!("{}: {} bytes, sha256 {}", args.image, image.len(), sha256);
+
+    let mut client = None;
+    let mut failures = 0;
+    let mut resumed_at = 0;
+    for n in 1.. {
+        let before = resumed_at;
+        match attempt(&mut client, args, &image, &sha256, n, &mut resumed_at) {
+            Ok(Some(Reply::Installed)) => {
+                eprintln!("image installed, device is restarting");
+                return Ok(());
+            }
+            Ok(Some(Reply::Error(e))) => return Err(format!("device refused the update: {}", e)),
+            Ok(_) => eprintln!("no answer from the device, asking again"),
+            Err(e) => {
+                eprintln!("connection lost: {}", e);
+                client = None;
+                std::thread::sleep(Duration::from_secs(2));
+            }
+        }
+        failures = if resumed_at > before { 1 } else { failures + 1 };
+        if failures >= MAX_RETRIES {
+            break;
+        }
+    }
+    Err(format!("giving up after {} attempts without progress", MAX_RETRIES))
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
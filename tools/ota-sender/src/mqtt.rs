@@ -0,0 +1,284 @@
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+// --- Minimal MQTT 3.1.1 Client (QoS 0) ---
+// The same subset the firmware speaks: CONNECT, SUBSCRIBE, PUBLISH, PINGREQ.
+
+const KEEP_ALIVE_SECS: u16 = 60;
+
+pub struct Client {
+    stream: TcpStream,
+    /// Time of the last packet sent, for keep-alive pings.
+    last_sent: Instant,
+}
+
+/// An application message received on a subscribed topic.
+pub struct Message {
+    pub topic: String,
+    pub payload: Vec<u8>,
+}
+
+fn encode_remaining_length(mut len: usize, out: &mut Vec<u8>) {
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        out.push(byte);
+        if len == 0 {
+            return;
+        }
+    }
+}
+
+fn put_field(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+    out.extend_from_slice(bytes);
+}
+
+/// Builds a packet from its first byte and body.
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut out = vec![header];
+    encode_remaining_length(body.len(), &mut out);
+    out.extend_from_slice(body);
+    out
+}
+
+fn protocol_error(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, what.to_string())
+}
+
+impl Client {
+    pub fn connect(addr: &str, client_id: &str) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        let mut client = Self { stream, last_sent: Instant::now() };
+
+        let mut body = Vec::new();
+        put_field(&mut body, b"MQTT");
+        body.push(0x04); // Protocol Level 4 (v3.1.1)
+        body.push(0x02); // Clean Session
+        body.extend_from_slice(&KEEP_ALIVE_SECS.to_be_bytes());
+        put_field(&mut body, client_id.as_bytes());
+        client.send(&packet(0x10, &body))?;
+
+        let mut connack = [0u8; 4];
+        client.stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+        client.stream.read_exact(&mut connack)?;
+        if connack[0] != 0x20 || connack[3] != 0x00 {
+            return Err(protocol_error("connection refused by broker"));
+        }
+        Ok(client)
+    }
+
+    fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.stream.write_all(bytes)?;
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+
+    /// Subscribes with QoS 0. The SUBACK is skipped by `next_message`.
+    pub fn subscribe(&mut self, filter: &str, packet_id: u16) -> io::Result<()> {
+        let mut body = packet_id.to_be_bytes().to_vec();
+        put_field(&mut body, filter.as_bytes());
+        body.push(0x00);
+        self.send(&packet(0x82, &body))
+    }
+
+    pub fn publish(&mut self, topic: &str, payload: &[u8]) -> io::Result<()> {
+        let mut body = Vec::with_capacity(2 + topic.len() + payload.len());
+        put_field(&mut body, topic.as_bytes());
+        body.extend_from_slice(payload);
+        self.send(&packet(0x30, &body))
+    }
+
+    /// Waits up to `timeout` for the next PUBLISH. Other packets are skipped.
+    pub fn next_message(&mut self, timeout: Duration) -> io::Result<Option<Message>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.last_sent.elapsed() > Duration::from_secs(KEEP_ALIVE_SECS as u64 / 2) {
+                self.send(&[0xC0, 0x00])?;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+
+            // Only the wait for the first byte may time out; the rest follows right away
+            self.stream.set_read_timeout(Some(deadline - now))?;
+            let mut header = [0u8; 1];
+            match self.stream.read(&mut header) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(_) => {}
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => return Ok(None),
+                Err(e) => return Err(e),
+            }
+            self.stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+
+            let mut len = 0usize;
+            for shift in 0..4 {
+                let mut byte = [0u8; 1];
+                self.stream.read_exact(&mut byte)?;
+                len |= ((byte[0] & 0x7F) as usize) << (7 * shift);
+                if byte[0] & 0x80 == 0 {
+                    break;
+                }
+                if shift == 3 {
+                    return Err(protocol_error("malformed remaining length"));
+                }
+            }
+            let mut body = vec![0u8; len];
+            self.stream.read_exact(&mut body)?;
+
+            if header[0] >> 4 != 3 {
+                continue; // SUBACK, PINGRESP
+            }
+            if body.len() < 2 {
+                return Err(protocol_error("short PUBLISH"));
+            }
+            let topic_len = u16::from_be_bytes([body[0], body[1]]) as usize;
+            let mut idx = 2 + topic_len;
+            if header[0] & 0x06 != 0 {
+                idx += 2;
+            }
+            if idx > body.len() {
+                return Err(protocol_error("short PUBLISH"));
+            }
+            let topic = String::from_utf8_lossy(&body[2..2 + topic_len]).into_owned();
+            return Ok(Some(Message { topic, payload: body[idx..].to_vec() }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Reads one packet on the broker side: first byte and body.
+    fn read_packet(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+        let mut header = [0u8; 1];
+        stream.read_exact(&mut header).unwrap();
+        let mut len = 0usize;
+        for shift in 0..4 {
+            let mut byte = [0u8; 1];
+            stream.read_exact(&mut byte).unwrap();
+            len |= ((byte[0] & 0x7F) as usize) << (7 * shift);
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0u8; len];
+        stream.read_exact(&mut body).unwrap();
+        (header[0], body)
+    }
+
+    /// A broker that accepts one connection, answers the CONNECT with
+    /// `return_code` and then runs `script`.
+    fn broker(return_code: u8, script: impl FnOnce(TcpStream) + Send + 'static) -> (String, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let (header, body) = read_packet(&mut stream);
+            assert_eq!(header, 0x10);
+            assert_eq!(&body[..7], b"\x00\x04MQTT\x04");
+            stream.write_all(&[0x20, 0x02, 0x00, return_code]).unwrap();
+            script(stream);
+        });
+        (addr, handle)
+    }
+
+    fn remaining_length(len: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        encode_remaining_length(len, &mut out);
+        out
+    }
+
+    #[test]
+    fn encodes_remaining_length() {
+        assert_eq!(remaining_length(0), [0x00]);
+        assert_eq!(remaining_length(127), [0x7F]);
+        assert_eq!(remaining_length(128), [0x80, 0x01]);
+        assert_eq!(remaining_length(16_383), [0xFF, 0x7F]);
+        assert_eq!(remaining_length(16_384), [0x80, 0x80, 0x01]);
+        assert_eq!(remaining_length(2_097_151), [0xFF, 0xFF, 0x7F]);
+    }
+
+    #[test]
+    fn builds_packets() {
+        assert_eq!(packet(0xC0, &[]), [0xC0, 0x00]);
+        let publish = packet(0x30, &[0xAB; 200]);
+        assert_eq!(publish[..3], [0x30, 0xC8, 0x01]);
+        assert_eq!(publish.len(), 3 + 200);
+    }
+
+    #[test]
+    fn subscribes_and_publishes() {
+        let (addr, broker) = broker(0x00, |mut stream| {
+            assert_eq!(read_packet(&mut stream), (0x82, b"\x00\x05\x00\x03a/+\x00".to_vec()));
+            assert_eq!(read_packet(&mut stream), (0x30, b"\x00\x03a/bhi".to_vec()));
+        });
+        let mut client = Client::connect(&addr, "test").unwrap();
+        client.subscribe("a/+", 5).unwrap();
+        client.publish("a/b", b"hi").unwrap();
+        broker.join().unwrap();
+    }
+
+    #[test]
+    fn receives_messages_and_skips_the_rest() {
+        let (addr, broker) = broker(0x00, |mut stream| {
+            // SUBACK, PINGRESP, then a QoS 1 PUBLISH (with a packet id) and a QoS 0 one
+            stream.write_all(&[0x90, 0x03, 0x00, 0x01, 0x00]).unwrap();
+            stream.write_all(&[0xD0, 0x00]).unwrap();
+            stream.write_all(&[0x32, 0x08, 0x00, 0x01, b't', 0x00, 0x07, 1, 2, 3]).unwrap();
+            let mut large = vec![0x30];
+            large.extend(remaining_length(3 + 300));
+            large.extend([0x00, 0x01, b'u']);
+            large.extend([0x5A; 300]);
+            stream.write_all(&large).unwrap();
+        });
+        let mut client = Client::connect(&addr, "test").unwrap();
+
+        let message = client.next_message(Duration::from_secs(5)).unwrap().unwrap();
+        assert_eq!((message.topic.as_str(), message.payload.as_slice()), ("t", &[1, 2, 3][..]));
+        let message = client.next_message(Duration::from_secs(5)).unwrap().unwrap();
+        assert_eq!(message.topic, "u");
+        assert_eq!(message.payload, [0x5A; 300]);
+        broker.join().unwrap();
+    }
+
+    #[test]
+    fn times_out_without_messages() {
+        // Keeps the connection open, but says nothing
+        let (addr, broker) = broker(0x00, |stream| {
+            thread::sleep(Duration::from_millis(300));
+            drop(stream);
+        });
+        let mut client = Client::connect(&addr, "test").unwrap();
+        assert!(client.next_message(Duration::from_millis(50)).unwrap().is_none());
+        broker.join().unwrap();
+    }
+
+    #[test]
+    fn connection_refused_by_broker() {
+        // 0x05: not authorized
+        let (addr, broker) = broker(0x05, |_| {});
+        assert_eq!(Client::connect(&addr, "test").err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+        broker.join().unwrap();
+    }
+
+    #[test]
+    fn malformed_publish() {
+        let (addr, broker) = broker(0x00, |mut stream| {
+            // Topic length 9, but only 2 bytes follow
+            stream.write_all(&[0x30, 0x04, 0x00, 0x09, b'a', b'b']).unwrap();
+        });
+        let mut client = Client::connect(&addr, "test").unwrap();
+        assert_eq!(client.next_message(Duration::from_secs(5)).err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+        broker.join().unwrap();
+    }
+}
@@ -0,0 +1,103 @@
+use std::thread;
+use std::time::Duration;
+
+use ota_sender::mqtt::Client;
+use ota_sender::*;
+use serde_json::{json, Value};
+
+// --- Transfer Through a Real Broker ---
+// Needs a broker without authentication, e.g. `mosquitto -v` on this machine:
+//
+//   cargo test --target x86_64-unknown-linux-gnu -- --ignored
+//
+// `MQTT_BROKER` (host:port) points the test elsewhere. A thread stands in
+// for the device and answers like the firmware does (see
+// `firmware-core/src/ota/chunk.rs`).
+
+const DEVICE_ID: &str = "ota-sender-test";
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+fn broker() -> String {
+    std::env::var("MQTT_BROKER").unwrap_or_else(|_| "127.0.0.1:1883".to_string())
+}
+
+/// Receives an image announced with `ota_begin`; returns what arrived.
+fn device(mut client: Client) -> Vec<u8> {
+    let mut image = Vec::new();
+    let (mut size, mut chunk_size, mut next) = (0, 0, 0);
+    loop {
+        let message = client.next_message(TIMEOUT).unwrap().expect("sender went quiet");
+        if message.topic == begin_topic(DEVICE_ID) {
+            let begin: Value = serde_json::from_slice(&message.payload).unwrap();
+            size = begin["size"].as_u64().unwrap() as usize;
+            chunk_size = begin["chunk_size"].as_u64().unwrap() as usize;
+            let response = json!({"id": begin["id"], "ok": true, "result": {"state": "receiving", "next": next}});
+            client.publish(&begin_response_topic(DEVICE_ID), response.to_string().as_bytes()).unwrap();
+        } else if message.topic == chunk_topic(DEVICE_ID) {
+            let index = u32::from_be_bytes(message.payload[..4].try_into().unwrap());
+            if index == next {
+                image.extend_from_slice(&message.payload[4..]);
+                next += 1;
+            }
+            let ack = if next == chunk_count(size, chunk_size) {
+                json!({"next": next, "state": "installed"})
+            } else {
+                json!({"next": next})
+            };
+            client.publish(&ack_topic(DEVICE_ID), ack.to_string().as_bytes()).unwrap();
+            if ack.get("state").is_some() {
+                return image;
+            }
+        }
+    }
+}
+
+/// Waits for the next reply on `topic`.
+fn reply(client: &mut Client, topic: &str, parse: impl Fn(&[u8]) -> Option<Reply>) -> Reply {
+    loop {
+        let message = client.next_message(TIMEOUT).unwrap().expect("device went quiet");
+        if message.topic == topic
+            && let Some(reply) = parse(&message.payload)
+        {
+            return reply;
+        }
+    }
+}
+
+#[test]
+#[ignore = "needs an MQTT broker, see the top of this file"]
+fn sends_an_image_through_the_broker() {
+    let image: Vec<u8> = (0..2500u32).map(|i| (i * 7) as u8).collect();
+    let chunk_size = 1024;
+
+    let mut device_client = Client::connect(&broker(), "ota-sender-test-device").unwrap();
+    device_client.subscribe(&begin_topic(DEVICE_ID), 1).unwrap();
+    device_client.subscribe(&chunk_topic(DEVICE_ID), 2).unwrap();
+    let mut sender = Client::connect(&broker(), "ota-sender-test-sender").unwrap();
+    sender.subscribe(&ack_topic(DEVICE_ID), 1).unwrap();
+    sender.subscribe(&begin_response_topic(DEVICE_ID), 2).unwrap();
+    // Let both subscriptions take effect before anything is published
+    thread::sleep(Duration::from_millis(200));
+    let device = thread::spawn(move || device(device_client));
+
+    let begin = begin_payload("1", image.len(), &sha256_hex(&image), "00", chunk_size);
+    sender.publish(&begin_topic(DEVICE_ID), begin.as_bytes()).unwrap();
+    let response_topic = begin_response_topic(DEVICE_ID);
+    assert_eq!(reply(&mut sender, &response_topic, |p| parse_begin_response(p, "1")), Reply::Next(0));
+
+    // A chunk sent too early is answered with the one expected
+    let ack_topic = ack_topic(DEVICE_ID);
+    sender.publish(&chunk_topic(DEVICE_ID), &chunk_payload(&image, chunk_size, 1)).unwrap();
+    assert_eq!(reply(&mut sender, &ack_topic, parse_ack), Reply::Next(0));
+
+    let mut next = 0;
+    let outcome = loop {
+        sender.publish(&chunk_topic(DEVICE_ID), &chunk_payload(&image, chunk_size, next)).unwrap();
+        match reply(&mut sender, &ack_topic, parse_ack) {
+            Reply::Next(n) => next = n,
+            other => break other,
+        }
+    };
+    assert_eq!(outcome, Reply::Installed);
+    assert_eq!(device.join().unwrap(), image);
+}
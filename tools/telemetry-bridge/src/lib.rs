@@ -0,0 +1,135 @@
+use std::fmt::Write;
+
+use telemetry_schema::{DecodeError, Quantity, TelemetryFrame};
+
+// --- Frame -> Influx Line Protocol ---
+
+#[derive(Debug, PartialEq)]
+pub enum BridgeError {
+    /// The line is not `<topic> <hex payload>`.
+    BadLine,
+    BadHex,
+    Decode(DecodeError),
+}
+
+impl From<DecodeError> for BridgeError {
+    fn from(e: DecodeError) -> Self {
+        BridgeError::Decode(e)
+    }
+}
+
+/// Parses a hex string (as printed by `mosquitto_sub -F %x`).
+pub fn parse_hex(hex: &str) -> Result<Vec<u8>, BridgeError> {
+    if !hex.len().is_multiple_of(2) {
+        return Err(BridgeError::BadHex);
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| BridgeError::BadHex))
+        .collect()
+}
+
+fn field_name(quantity: &Quantity) -> String {
+    match quantity {
+        Quantity::Other(n) => format!("other_{}", n),
+        q => q.name().to_string(),
+    }
+}
+
+/// Escapes commas, equals signs and spaces in tag keys/values.
+fn escape_tag(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, ',' | '=' | ' ') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Formats a decoded frame as one line of Influx line protocol.
+/// The MQTT topic becomes the `topic` tag; the timestamp is omitted if the
+/// device clock was not set, so Influx stamps it on arrival.
+pub fn to_line_protocol(measurement: &str, topic: &str, frame: &TelemetryFrame) -> String {
+    let mut line = format!("{},topic={} seq={}u", escape_tag(measurement), escape_tag(topic), frame.seq);
+    for field in frame.fields.iter().filter(|f| f.value.is_finite()) {
+        let _ = write!(line, ",{}={}", field_name(&field.quantity), field.value);
+    }
+    if let Some(ts) = frame.timestamp_ms {
+        let _ = write!(line, " {}", ts as u128 * 1_000_000);
+    }
+    line
+}
+
+/// Translates one `<topic> <hex payload>` input line.
+pub fn translate_line(measurement: &str, line: &str) -> Result<String, BridgeError> {
+    let (topic, hex) = line.trim().rsplit_once(' ').ok_or(BridgeError::BadLine)?;
+    let bytes = parse_hex(hex)?;
+    let frame = telemetry_schema::decode(&bytes)?;
+    Ok(to_line_protocol(measurement, topic, &frame))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use telemetry_schema::{Field, MAX_FRAME_LEN, SCHEMA_VERSION};
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn encoded(frame: &TelemetryFrame) -> Vec<u8> {
+        let mut buf = [0u8; MAX_FRAME_LEN];
+        telemetry_schema::encode(frame, &mut buf).unwrap().to_vec()
+    }
+
+    fn frame(timestamp_ms: Option<u64>, fields: &[Field]) -> TelemetryFrame {
+        TelemetryFrame { seq: 5, timestamp_ms, fields: fields.iter().copied().collect() }
+    }
+
+    #[test]
+    fn translates_an_encoded_frame() {
+        let frame = frame(
+            Some(1_767_225_600_000),
+            &[
+                Field { quantity: Quantity::Temperature, value: 23.5 },
+                Field { quantity: Quantity::Rssi, value: -61.0 },
+                Field { quantity: Quantity::Other(3), value: 0.25 },
+            ],
+        );
+        let line = format!("sensors/temp {}\n", hex(&encoded(&frame)));
+        assert_eq!(
+            translate_line("telemetry", &line).unwrap(),
+            "telemetry,topic=sensors/temp seq=5u,temperature=23.5,rssi=-61,other_3=0.25 1767225600000000000"
+        );
+    }
+
+    #[test]
+    fn hex_round_trip() {
+        let bytes = encoded(&frame(None, &[Field { quantity: Quantity::Humidity, value: 48.5 }]));
+        assert_eq!(parse_hex(&hex(&bytes)).unwrap(), bytes);
+        assert_eq!(parse_hex(&hex(&bytes).to_uppercase()).unwrap(), bytes);
+    }
+
+    #[test]
+    fn no_timestamp_and_non_finite_fields() {
+        let frame = frame(
+            None,
+            &[Field { quantity: Quantity::Temperature, value: f32::NAN }, Field { quantity: Quantity::Voltage, value: 3.3 }],
+        );
+        let decoded = telemetry_schema::decode(&encoded(&frame)).unwrap();
+        assert_eq!(to_line_protocol("t", "a b,c", &decoded), r"t,topic=a\ b\,c seq=5u,voltage=3.3");
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        assert_eq!(translate_line("t", "no-payload"), Err(BridgeError::BadLine));
+        assert_eq!(translate_line("t", "sensors/temp 0"), Err(BridgeError::BadHex));
+        assert_eq!(translate_line("t", "sensors/temp zz"), Err(BridgeError::BadHex));
+        assert_eq!(
+            translate_line("t", &format!("sensors/temp {:02x}00", SCHEMA_VERSION + 1)),
+            Err(BridgeError::Decode(DecodeError::UnsupportedVersion(SCHEMA_VERSION + 1)))
+        );
+    }
+}
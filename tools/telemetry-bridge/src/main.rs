@@ -0,0 +1,31 @@
+use std::io::{self, BufRead, Write};
+
+use telemetry_bridge::translate_line;
+
+// Reads `<topic> <hex payload>` lines on stdin and writes Influx line protocol
+// to stdout, e.g.:
+//
+//   mosquitto_sub -h broker -t 'sensors/#' -F '%t %x' | telemetry-bridge
+//
+// The output can be fed to Telegraf (`inputs.execd`) or `influx write`.
+
+fn main() {
+    let measurement = std::env::args().nth(1).unwrap_or_else(|| "telemetry".to_string());
+
+    let stdin = io::stdin();
+    let mut stdout = io::stdout().lock();
+    for line in stdin.lock().lines() {
+        let Ok(line) = line else { break };
+        if line.trim().is_empty() {
+            continue;
+        }
+        match translate_line(&measurement, &line) {
+            Ok(out) => {
+                if writeln!(stdout, "{}", out).and_then(|_| stdout.flush()).is_err() {
+                    break;
+                }
+            }
+            Err(e) => eprintln!("skipping frame: {:?}", e),
+        }
+    }
+}